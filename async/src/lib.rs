@@ -0,0 +1,191 @@
+//! An async API for services that run on tokio. The engine reads pages with blocking I/O, so
+//! statements run on tokio's blocking thread pool instead of on the threads of the executor,
+//! and the rows of a query are streamed back as they're found.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use futures::StreamExt;
+//! use sqlite_starter_rust_async::AsyncConnection;
+//!
+//! let connection = AsyncConnection::open("sample.db").await?;
+//! let mut rows = connection.query("SELECT name FROM apples");
+//! while let Some(row) = rows.next().await {
+//!     println!("{}", row?.get::<String>(0)?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use futures_core::Stream;
+use sqlite_starter_rust::connection::{Connection, Row};
+use sqlite_starter_rust::output::OutputFormatter;
+use sqlite_starter_rust::record::{ColumnValue, Value};
+use tokio::sync::mpsc;
+use tokio::task;
+
+/// The rows a query finds before the stream is read. The query waits for the stream when
+/// there are more, so a slow reader doesn't make the rows pile up in memory.
+const BUFFERED_ROWS: usize = 256;
+
+/// A connection that can be shared by tasks. Its statements run one at a time, so a query
+/// holds the connection until its stream ends or is dropped.
+#[derive(Clone)]
+pub struct AsyncConnection {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl AsyncConnection {
+    pub async fn open(path: &str) -> Result<Self> {
+        let path = path.to_string();
+        let connection = task::spawn_blocking(move || Connection::open(&path)).await??;
+        Ok(Self::from(connection))
+    }
+
+    /// Runs a statement that doesn't return rows, such as INSERT.
+    pub async fn execute(&self, statement: &str) -> Result<()> {
+        let connection = self.connection.clone();
+        let statement = statement.to_string();
+        task::spawn_blocking(move || {
+            let mut connection = connection.lock().map_err(|_| poisoned())?;
+            connection.execute(&statement, &mut Discard)
+        })
+        .await?
+    }
+
+    /// Runs a query, whose rows can be read from the stream as soon as they're found. Dropping
+    /// the stream stops the query.
+    pub fn query(&self, statement: &str) -> RowStream {
+        let (sender, receiver) = mpsc::channel(BUFFERED_ROWS);
+        let connection = self.connection.clone();
+        let statement = statement.to_string();
+        task::spawn_blocking(move || {
+            let mut out = RowSender {
+                sender: sender.clone(),
+                columns: Arc::from([]),
+            };
+            let result = match connection.lock() {
+                Ok(mut connection) => connection.execute(&statement, &mut out),
+                Err(_) => Err(poisoned()),
+            };
+            // Nobody is left to tell about an error if the stream was dropped
+            if let Err(error) = result {
+                let _ = sender.blocking_send(Err(error));
+            }
+        });
+        RowStream { receiver }
+    }
+}
+
+impl From<Connection> for AsyncConnection {
+    fn from(connection: Connection) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(connection)),
+        }
+    }
+}
+
+/// The rows of a query run by [`AsyncConnection::query`], followed by its error if it fails.
+pub struct RowStream {
+    receiver: mpsc::Receiver<Result<Row>>,
+}
+
+impl Stream for RowStream {
+    type Item = Result<Row>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Sends the rows of a query to its [`RowStream`].
+struct RowSender {
+    sender: mpsc::Sender<Result<Row>>,
+    columns: Arc<[String]>,
+}
+
+impl OutputFormatter for RowSender {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        self.columns = columns.into();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        let row = Row::new(
+            self.columns.clone(),
+            values.iter().map(Value::from).collect(),
+        );
+        self.sender
+            .blocking_send(Ok(row))
+            .map_err(|_| anyhow!("query was cancelled"))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Ignores the rows of statements that are run for their effect.
+struct Discard;
+
+impl OutputFormatter for Discard {
+    fn begin(&mut self, _: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    fn row(&mut self, _: &[ColumnValue]) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn poisoned() -> anyhow::Error {
+    anyhow!("a statement panicked while it ran on the connection")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn stream_rows() {
+        let connection = AsyncConnection::open("../sample.db").await.unwrap();
+        let names = connection
+            .query("SELECT name FROM apples WHERE id > 2")
+            .map(|row| row.unwrap().get::<String>(0).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(names, ["Honeycrisp", "Golden Delicious"]);
+
+        let mut rows = connection.query("SELECT nope FROM apples");
+        assert!(rows.next().await.unwrap().is_err());
+        assert!(rows.next().await.is_none());
+
+        // A query stops when its stream is dropped, and the connection can be used again
+        drop(connection.query("SELECT * FROM apples"));
+        assert_eq!(connection.query("SELECT id FROM apples").count().await, 4);
+    }
+
+    #[tokio::test]
+    async fn execute_statements() {
+        let path = std::env::temp_dir().join(format!("async-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::copy("../sample.db", path).unwrap();
+        let connection = AsyncConnection::open(path).await.unwrap();
+        connection
+            .execute("INSERT INTO apples (name, color) VALUES ('Jazz', 'Red')")
+            .await
+            .unwrap();
+        assert_eq!(connection.query("SELECT id FROM apples").count().await, 5);
+        std::fs::remove_file(path).unwrap();
+        let _ = std::fs::remove_file(format!("{path}-journal"));
+    }
+}
@@ -0,0 +1,39 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use sqlite_starter_rust::connection::Connection;
+use sqlite_starter_rust::output::OutputMode;
+use sqlite_starter_rust_benches::fruits;
+
+const ROWS: usize = 1_000_000;
+
+/// Selects every row of a large table in list mode and writes it to /dev/null, once with a
+/// write per value and once through a buffer like the shell does.
+fn select_all(c: &mut Criterion) {
+    let path = fruits(ROWS).unwrap();
+    let mut connection = Connection::open(&path).unwrap();
+
+    let mut group = c.benchmark_group("select 1M rows");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS as u64));
+    let mut run = |name: &str, out: &mut dyn Write| {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut formatter = OutputMode::List.formatter(&mut *out, false);
+                connection
+                    .execute("SELECT * FROM fruits", formatter.as_mut())
+                    .unwrap();
+            })
+        });
+    };
+    run("unbuffered", &mut File::create("/dev/null").unwrap());
+    run(
+        "buffered",
+        &mut BufWriter::new(File::create("/dev/null").unwrap()),
+    );
+    group.finish();
+}
+
+criterion_group!(benches, select_all);
+criterion_main!(benches);
@@ -0,0 +1,70 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sqlite_starter_rust::connection::Connection;
+use sqlite_starter_rust_benches::{deep_index, execute, fruits, key, SIZES};
+
+/// Rows and key length of the deep index fixture. 200 byte keys fill index pages with about
+/// 17 entries, so 200k rows need an index of 5 levels.
+const DEEP_ROWS: usize = 200_000;
+const DEEP_KEY_LENGTH: usize = 200;
+
+fn open_fruits(rows: usize) -> Connection {
+    Connection::open(&fruits(rows).unwrap()).unwrap()
+}
+
+/// Reads every row of the table, but only a few of them match and are written out.
+fn full_scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("full scan");
+    group.sample_size(10);
+    for (name, rows) in SIZES {
+        let mut connection = open_fruits(rows);
+        group.throughput(Throughput::Elements(rows as u64));
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                execute(
+                    &mut connection,
+                    "SELECT name, color FROM fruits WHERE weight = 7",
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn indexed_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("indexed lookup");
+    for (name, rows) in SIZES {
+        let mut connection = open_fruits(rows);
+        let statement = format!("SELECT * FROM fruits WHERE name = 'fruit {}'", rows / 2);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| execute(&mut connection, &statement).unwrap())
+        });
+    }
+
+    let path = deep_index(DEEP_ROWS, DEEP_KEY_LENGTH).unwrap();
+    let mut connection = Connection::open(&path).unwrap();
+    let statement = format!(
+        "SELECT id FROM keys WHERE key = '{}'",
+        key(DEEP_ROWS / 2, DEEP_KEY_LENGTH)
+    );
+    group.bench_function(BenchmarkId::from_parameter("deep index"), |b| {
+        b.iter(|| execute(&mut connection, &statement).unwrap())
+    });
+    group.finish();
+}
+
+/// Walks every leaf page of the table, counting their cells without decoding the rows.
+fn count(c: &mut Criterion) {
+    let mut group = c.benchmark_group("count");
+    for (name, rows) in SIZES {
+        let mut connection = open_fruits(rows);
+        group.throughput(Throughput::Elements(rows as u64));
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| execute(&mut connection, "SELECT COUNT(*) FROM fruits").unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, full_scan, indexed_lookup, count);
+criterion_main!(benches);
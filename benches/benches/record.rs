@@ -0,0 +1,33 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use sqlite_starter_rust::record::{encode_record, Record, Value};
+
+/// Decodes records of a wide table, all of their values and only two of them.
+fn decode(c: &mut Criterion) {
+    let values = (0..50)
+        .map(|i| match i % 3 {
+            0 => Value::Integer(i * 1000),
+            1 => Value::Text(format!("value of column {i}")),
+            _ => Value::Real(i as f64 / 3.0),
+        })
+        .collect::<Vec<_>>();
+    let payload = encode_record(&values);
+
+    let mut group = c.benchmark_group("record decoding");
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+    group.bench_function("header only", |b| {
+        b.iter(|| Record::read(1, black_box(&payload)).unwrap())
+    });
+    group.bench_function("two of 50 columns", |b| {
+        b.iter(|| {
+            let record = Record::read(1, black_box(&payload)).unwrap();
+            (record.value(3), record.value(48))
+        })
+    });
+    group.bench_function("all 50 columns", |b| {
+        b.iter(|| Record::read(1, black_box(&payload)).unwrap().values())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
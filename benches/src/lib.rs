@@ -0,0 +1,100 @@
+//! Fixture databases for the benchmarks. They're generated with the crate's own INSERT
+//! support the first time they're needed, and kept in the temporary directory for later runs.
+
+use anyhow::Result;
+use sqlite_starter_rust::connection::Connection;
+use sqlite_starter_rust::output::OutputMode;
+
+/// Rows inserted by a single INSERT statement, and so in a single transaction.
+const BATCH_SIZE: usize = 10_000;
+/// Part of the file names, to be bumped whenever the content of the fixtures changes so that
+/// stale ones aren't reused.
+const FIXTURE_VERSION: u32 = 2;
+
+const COLORS: [&str; 5] = ["Red", "Green", "Yellow", "Orange", "Purple"];
+
+/// The table sizes the benchmarks compare, with a name for each.
+pub const SIZES: [(&str, usize); 3] = [("small", 100), ("10k", 10_000), ("1M", 1_000_000)];
+
+/// Returns the path of a database with a table `fruits (id INTEGER PRIMARY KEY, name TEXT,
+/// color TEXT, weight INTEGER)` of `rows` rows and the index `fruits_by_name`, creating it if
+/// it doesn't exist yet. The names are `fruit 0` to `fruit {rows - 1}`.
+pub fn fruits(rows: usize) -> Result<String> {
+    fixture(&format!("fruits-{rows}"), |connection| {
+        execute(
+            connection,
+            "CREATE TABLE fruits (id INTEGER PRIMARY KEY, name TEXT, color TEXT, weight INTEGER)",
+        )?;
+        insert_batches(connection, "fruits (name, color, weight)", rows, |i| {
+            format!("('fruit {i}', '{}', {})", COLORS[i % COLORS.len()], i % 500)
+        })?;
+        execute(connection, "CREATE INDEX fruits_by_name ON fruits (name)")
+    })
+}
+
+/// Returns the path of a database with a table `keys (id INTEGER PRIMARY KEY, key TEXT)` of
+/// `rows` rows and the index `keys_by_key`. The keys are `key_length` characters long, so few
+/// of them fit on a page and the index is much deeper than one over short values. [`key`]
+/// returns the key of a row.
+pub fn deep_index(rows: usize, key_length: usize) -> Result<String> {
+    fixture(&format!("deep-index-{rows}-{key_length}"), |connection| {
+        execute(
+            connection,
+            "CREATE TABLE keys (id INTEGER PRIMARY KEY, key TEXT)",
+        )?;
+        execute(connection, "CREATE INDEX keys_by_key ON keys (key)")?;
+        insert_batches(connection, "keys (key)", rows, |i| {
+            format!("('{}')", key(i, key_length))
+        })
+    })
+}
+
+/// The key of row `i` in [`deep_index`]. Keys share a long prefix, so that comparisons have
+/// to look at the whole key.
+pub fn key(i: usize, key_length: usize) -> String {
+    format!("{:x>width$}", i, width = key_length)
+}
+
+/// Creates the fixture `name` with `build` unless it exists. The database is built under a
+/// different name first, so that an interrupted run doesn't leave an incomplete fixture.
+fn fixture(name: &str, build: impl FnOnce(&mut Connection) -> Result<()>) -> Result<String> {
+    let path = std::env::temp_dir().join(format!(
+        "sqlite-starter-rust-bench-{name}-v{FIXTURE_VERSION}.db"
+    ));
+    if !path.exists() {
+        let partial = path.with_extension("partial");
+        let _ = std::fs::remove_file(&partial);
+        let mut connection = Connection::create(partial.to_str().unwrap())?;
+        build(&mut connection)?;
+        drop(connection);
+        let _ = std::fs::remove_file(format!("{}-journal", partial.display()));
+        std::fs::rename(partial, &path)?;
+    }
+    Ok(path.to_str().unwrap().to_string())
+}
+
+/// Inserts `rows` rows into `target`, a table name with an optional column list. `values`
+/// returns the parenthesized values of row `i`.
+fn insert_batches(
+    connection: &mut Connection,
+    target: &str,
+    rows: usize,
+    values: impl Fn(usize) -> String,
+) -> Result<()> {
+    for start in (0..rows).step_by(BATCH_SIZE) {
+        let batch = (start..rows.min(start + BATCH_SIZE))
+            .map(&values)
+            .collect::<Vec<_>>();
+        execute(
+            connection,
+            &format!("INSERT INTO {target} VALUES {}", batch.join(", ")),
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs a statement and discards its results.
+pub fn execute(connection: &mut Connection, statement: &str) -> Result<()> {
+    let mut out = OutputMode::List.formatter(std::io::sink(), false);
+    connection.execute(statement, out.as_mut())
+}
@@ -0,0 +1,20 @@
+//! Writes a table of a database to a Parquet file:
+//!
+//! ```sh
+//! sqlite-to-parquet sample.db apples apples.parquet
+//! ```
+
+use anyhow::{bail, Result};
+use sqlite_starter_rust::connection::Connection;
+use sqlite_starter_rust_export::write_parquet;
+
+fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [database, table, output] = args.as_slice() else {
+        bail!("Usage: sqlite-to-parquet DATABASE TABLE OUTPUT");
+    };
+    let mut connection = Connection::open(database)?;
+    let rows = write_parquet(&mut connection, table, output)?;
+    eprintln!("{rows} rows written to {output}");
+    Ok(())
+}
@@ -0,0 +1,341 @@
+//! Exports whole tables of a database as Arrow record batches, and with the `parquet` feature
+//! as Parquet files, for analytics tools that don't read SQLite files. Scans can be limited to
+//! some of the columns, like query engines push projections down to their table sources, so
+//! that the values of the other columns are never decoded. With the `datafusion` feature,
+//! [`table_provider::SqliteTable`] lets DataFusion query tables this way.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use arrow_array::builder::{BinaryBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, NullArray, RecordBatch, RecordBatchOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use sqlite_starter_rust::connection::Connection;
+use sqlite_starter_rust::record::{format_real, Value};
+
+#[cfg(feature = "datafusion")]
+pub mod table_provider;
+
+/// The number of rows in each record batch but the last.
+pub const BATCH_ROWS: usize = 8192;
+
+/// The Arrow type of a column with the given values. A column can hold values of any storage
+/// class, whatever its declared type, so this is the narrowest type that all of them convert
+/// to: Int64 for integers, Float64 once there are reals, Utf8 once there is text and Binary
+/// once there are blobs. NULLs fit every type, and a column of only NULLs has the Null type.
+pub fn data_type<'a>(values: impl IntoIterator<Item = &'a Value>) -> DataType {
+    let rank = |value: &Value| match value {
+        Value::Null => 0,
+        Value::Integer(_) => 1,
+        Value::Real(_) => 2,
+        Value::Text(_) => 3,
+        Value::Blob(_) => 4,
+    };
+    match values.into_iter().map(rank).max().unwrap_or(0) {
+        0 => DataType::Null,
+        1 => DataType::Int64,
+        2 => DataType::Float64,
+        3 => DataType::Utf8,
+        _ => DataType::Binary,
+    }
+}
+
+/// Scans a table and returns its schema and its rows as record batches of [`BATCH_ROWS`] rows.
+/// With a projection, only the columns at the given positions are read, in that order.
+pub fn table_batches(
+    connection: &mut Connection,
+    table: &str,
+    projection: Option<&[usize]>,
+) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    let (names, rows) = read_rows(connection, table, projection, None)?;
+    let schema = infer_schema(names, &rows);
+    let batches = batches(&schema, &rows)?;
+    Ok((schema, batches))
+}
+
+/// The schema of a table, which takes a scan of all of its values.
+pub fn table_schema(connection: &mut Connection, table: &str) -> Result<SchemaRef> {
+    let (names, rows) = read_rows(connection, table, None, None)?;
+    Ok(infer_schema(names, &rows))
+}
+
+/// The names and the values of the columns of a table at the given positions, or of all of
+/// them, in at most `limit` rows.
+fn read_rows(
+    connection: &mut Connection,
+    table: &str,
+    projection: Option<&[usize]>,
+    limit: Option<usize>,
+) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    let columns = &connection.database().schema().table(table)?.columns;
+    let names = match projection {
+        Some(projection) => projection
+            .iter()
+            .map(|&i| match columns.get(i) {
+                Some(column) => Ok(column.name.clone()),
+                None => bail!("{table} has no column {i}"),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => columns.iter().map(|column| column.name.clone()).collect(),
+    };
+    // Rows are still counted without any columns, as for COUNT(*)
+    let selected = if names.is_empty() {
+        vec![quote(&columns[0].name)]
+    } else {
+        names.iter().map(|name| quote(name)).collect()
+    };
+    let query = format!("SELECT {} FROM {}", selected.join(", "), quote(table));
+    let mut statement = connection.prepare(&query)?;
+    // There is no LIMIT clause, but a single page stops the scan after its rows
+    let rows = match limit {
+        Some(0) => vec![],
+        Some(limit) => statement
+            .query_paged(limit)?
+            .map(|row| row.values().to_vec())
+            .collect(),
+        None => statement
+            .query()?
+            .map(|row| row.values().to_vec())
+            .collect(),
+    };
+    Ok((names, rows))
+}
+
+/// A schema with the narrowest type of each column, as chosen by [`data_type`].
+fn infer_schema(names: Vec<String>, rows: &[Vec<Value>]) -> SchemaRef {
+    let fields = names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| Field::new(name, data_type(rows.iter().map(|row| &row[i])), true))
+        .collect::<Vec<_>>();
+    Arc::new(Schema::new(fields))
+}
+
+/// Splits rows into record batches of [`BATCH_ROWS`] rows with the given schema.
+fn batches(schema: &SchemaRef, rows: &[Vec<Value>]) -> Result<Vec<RecordBatch>> {
+    rows.chunks(BATCH_ROWS)
+        .map(|rows| {
+            let columns = schema
+                .fields()
+                .iter()
+                .enumerate()
+                .map(|(i, field)| column(field.data_type(), rows.iter().map(|row| &row[i])))
+                .collect();
+            let options = RecordBatchOptions::new().with_row_count(Some(rows.len()));
+            Ok(RecordBatch::try_new_with_options(
+                schema.clone(),
+                columns,
+                &options,
+            )?)
+        })
+        .collect()
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Builds an array of the given type, as chosen by [`data_type`], from the values of a column.
+fn column<'a>(data_type: &DataType, values: impl ExactSizeIterator<Item = &'a Value>) -> ArrayRef {
+    match data_type {
+        DataType::Int64 => {
+            let mut builder = Int64Builder::with_capacity(values.len());
+            for value in values {
+                match value {
+                    Value::Integer(n) => builder.append_value(*n),
+                    _ => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Float64 => {
+            let mut builder = Float64Builder::with_capacity(values.len());
+            for value in values {
+                match value {
+                    Value::Integer(n) => builder.append_value(*n as f64),
+                    Value::Real(n) => builder.append_value(*n),
+                    _ => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Utf8 => {
+            let mut builder = StringBuilder::new();
+            for value in values {
+                match text(value) {
+                    Some(text) => builder.append_value(text),
+                    None => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Binary => {
+            let mut builder = BinaryBuilder::new();
+            for value in values {
+                match value {
+                    Value::Blob(blob) => builder.append_value(blob),
+                    value => match text(value) {
+                        Some(text) => builder.append_value(text),
+                        None => builder.append_null(),
+                    },
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        _ => Arc::new(NullArray::new(values.len())),
+    }
+}
+
+/// A value converted to text like SQLite's CAST, or `None` for NULL.
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Integer(n) => Some(n.to_string()),
+        Value::Real(n) => Some(format_real(*n)),
+        Value::Text(text) => Some(text.clone()),
+        Value::Blob(blob) => Some(String::from_utf8_lossy(blob).into_owned()),
+    }
+}
+
+/// Reads the columns at the given positions of a table, or all of them, into record batches
+/// with `schema`, which was read before with [`table_schema`] and projected the same way.
+/// Values that don't fit the type of their column, like reals written to an `Int64` column
+/// since, become NULL.
+#[cfg(feature = "datafusion")]
+fn projected_batches(
+    connection: &mut Connection,
+    table: &str,
+    projection: Option<&[usize]>,
+    limit: Option<usize>,
+    schema: &SchemaRef,
+) -> Result<Vec<RecordBatch>> {
+    let (_, rows) = read_rows(connection, table, projection, limit)?;
+    batches(schema, &rows)
+}
+
+/// Writes a table to a Parquet file, and returns the number of rows written.
+#[cfg(feature = "parquet")]
+pub fn write_parquet(connection: &mut Connection, table: &str, path: &str) -> Result<usize> {
+    use parquet::arrow::ArrowWriter;
+
+    let (schema, batches) = table_batches(connection, table, None)?;
+    let mut writer = ArrowWriter::try_new(std::fs::File::create(path)?, schema, None)?;
+    for batch in &batches {
+        writer.write(batch)?;
+    }
+    writer.close()?;
+    Ok(batches.iter().map(RecordBatch::num_rows).sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Array, Int64Array, StringArray};
+
+    #[test]
+    fn map_storage_classes() {
+        assert_eq!(
+            data_type(&[Value::Null, Value::Integer(1)]),
+            DataType::Int64
+        );
+        assert_eq!(
+            data_type(&[Value::Integer(1), Value::Real(1.5)]),
+            DataType::Float64
+        );
+        assert_eq!(
+            data_type(&[Value::Real(1.5), Value::Text("a".to_string())]),
+            DataType::Utf8
+        );
+        assert_eq!(
+            data_type(&[Value::Blob(vec![1]), Value::Text("a".to_string())]),
+            DataType::Binary
+        );
+        assert_eq!(data_type(&[Value::Null]), DataType::Null);
+        assert_eq!(data_type(&[]), DataType::Null);
+
+        let array = column(
+            &DataType::Utf8,
+            [Value::Real(2.0), Value::Null, Value::Integer(3)].iter(),
+        );
+        let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(array.value(0), "2.0");
+        assert!(array.is_null(1));
+        assert_eq!(array.value(2), "3");
+    }
+
+    #[test]
+    fn sample_batches() {
+        let mut connection = Connection::open("../sample.db").unwrap();
+        let (schema, batches) = table_batches(&mut connection, "apples", None).unwrap();
+        assert_eq!(
+            schema
+                .fields()
+                .iter()
+                .map(|field| (field.name().as_str(), field.data_type().clone()))
+                .collect::<Vec<_>>(),
+            [
+                ("id", DataType::Int64),
+                ("name", DataType::Utf8),
+                ("color", DataType::Utf8),
+            ]
+        );
+        assert_eq!(batches.len(), 1);
+        let ids = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(ids.values(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn project_columns() {
+        let mut connection = Connection::open("../sample.db").unwrap();
+        let (schema, batches) = table_batches(&mut connection, "apples", Some(&[2, 0])).unwrap();
+        assert_eq!(
+            schema
+                .fields()
+                .iter()
+                .map(|field| field.name().as_str())
+                .collect::<Vec<_>>(),
+            ["color", "id"]
+        );
+        let colors = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(colors.value(0), "Light Green");
+
+        let (schema, batches) = table_batches(&mut connection, "apples", Some(&[])).unwrap();
+        assert!(schema.fields().is_empty());
+        assert_eq!(batches[0].num_rows(), 4);
+
+        assert_eq!(
+            table_batches(&mut connection, "apples", Some(&[3]))
+                .unwrap_err()
+                .to_string(),
+            "apples has no column 3"
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_round_trip() {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let path = std::env::temp_dir().join("sqlite-export-parquet_round_trip.parquet");
+        let path = path.to_str().unwrap();
+        let mut connection = Connection::open("../sample.db").unwrap();
+        assert_eq!(write_parquet(&mut connection, "apples", path).unwrap(), 4);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        let (_, expected) = table_batches(&mut connection, "apples", None).unwrap();
+        assert_eq!(batches, expected);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! A [DataFusion](https://datafusion.apache.org) table provider, so that SQL engines built on
+//! it can query the tables of database files directly:
+//!
+//! ```no_run
+//! # async fn example() -> datafusion::error::Result<()> {
+//! use std::sync::Arc;
+//!
+//! use datafusion::prelude::SessionContext;
+//! use sqlite_starter_rust_export::table_provider::SqliteTable;
+//!
+//! let context = SessionContext::new();
+//! let apples = SqliteTable::try_new("sample.db", "apples").unwrap();
+//! context.register_table("apples", Arc::new(apples))?;
+//! context.sql("SELECT color, COUNT(*) FROM apples GROUP BY color").await?.show().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Projections and limits are pushed down to the scan, so only the columns a query uses are
+//! decoded and reading stops after the rows it needs. Filters are left to DataFusion.
+
+use std::any::Any;
+use std::sync::Arc;
+
+use arrow_schema::SchemaRef;
+use async_trait::async_trait;
+use datafusion::catalog::{Session, TableProvider};
+use datafusion::datasource::memory::MemorySourceConfig;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::logical_expr::{Expr, TableType};
+use datafusion::physical_plan::ExecutionPlan;
+use sqlite_starter_rust::connection::Connection;
+
+use crate::{projected_batches, table_schema};
+
+/// A table of a database file. Each scan opens the file again, so it sees the changes made
+/// since, but the schema is read once, when the table is opened.
+#[derive(Debug)]
+pub struct SqliteTable {
+    path: String,
+    table: String,
+    schema: SchemaRef,
+}
+
+impl SqliteTable {
+    /// Opens the table called `table` in the database at `path`. The Arrow types of the columns
+    /// depend on their values, so this scans the whole table once.
+    pub fn try_new(path: &str, table: &str) -> anyhow::Result<Self> {
+        let mut connection = Connection::open(path)?;
+        let schema = table_schema(&mut connection, table)?;
+        Ok(Self {
+            path: path.to_string(),
+            table: table.to_string(),
+            schema,
+        })
+    }
+}
+
+#[async_trait]
+impl TableProvider for SqliteTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let schema = match projection {
+            Some(projection) => Arc::new(self.schema.project(projection)?),
+            None => self.schema.clone(),
+        };
+        let path = self.path.clone();
+        let table = self.table.clone();
+        let projection = projection.cloned();
+        let batches_schema = schema.clone();
+        // Reading the file blocks, so it's kept off the threads that drive the query
+        let batches = tokio::task::spawn_blocking(move || {
+            let mut connection = Connection::open(&path)?;
+            projected_batches(
+                &mut connection,
+                &table,
+                projection.as_deref(),
+                limit,
+                &batches_schema,
+            )
+        })
+        .await
+        .map_err(|error| DataFusionError::External(error.into()))?
+        .map_err(|error| DataFusionError::External(error.into()))?;
+        Ok(MemorySourceConfig::try_new_exec(&[batches], schema, None)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::prelude::SessionContext;
+
+    async fn query(sql: &str) -> String {
+        let context = SessionContext::new();
+        let apples = SqliteTable::try_new("../sample.db", "apples").unwrap();
+        context.register_table("apples", Arc::new(apples)).unwrap();
+        let batches = context.sql(sql).await.unwrap().collect().await.unwrap();
+        pretty_format_batches(&batches).unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn query_a_table() {
+        assert_eq!(
+            query("SELECT name, id FROM apples WHERE id > 2 ORDER BY id").await,
+            "+------------------+----+
+| name             | id |
++------------------+----+
+| Honeycrisp       | 3  |
+| Golden Delicious | 4  |
++------------------+----+"
+        );
+        assert_eq!(
+            query("SELECT COUNT(*) AS n FROM apples").await,
+            "+---+
+| n |
++---+
+| 4 |
++---+"
+        );
+    }
+
+    #[tokio::test]
+    async fn push_down_projections_and_limits() {
+        let context = SessionContext::new();
+        let apples = SqliteTable::try_new("../sample.db", "apples").unwrap();
+        let projection = vec![2];
+        let plan = apples
+            .scan(&context.state(), Some(&projection), &[], Some(1))
+            .await
+            .unwrap();
+        assert_eq!(plan.schema().field(0).name(), "color");
+        let batches = datafusion::physical_plan::collect(plan, context.task_ctx())
+            .await
+            .unwrap();
+        assert_eq!(
+            pretty_format_batches(&batches).unwrap().to_string(),
+            "+-------------+
+| color       |
++-------------+
+| Light Green |
++-------------+"
+        );
+    }
+}
@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::page::{Cell, Page};
+use sqlite_starter_rust::record::Record;
+
+// The first byte picks whether the page follows the database header, like page 1 does. The
+// rest is the page, which uses all of its bytes.
+fuzz_target!(|data: &[u8]| {
+    let Some((&first, page)) = data.split_first() else {
+        return;
+    };
+    let offset = if first & 1 == 1 { 100 } else { 0 };
+    let Ok(page) = Page::parse(page.to_vec(), page.len() + offset as usize, offset) else {
+        return;
+    };
+    for cell in page.cells() {
+        if let Cell::LeafTable {
+            rowid,
+            payload,
+            overflow_page: 0,
+            ..
+        } = cell
+        {
+            let _ = Record::read(rowid, payload);
+        }
+    }
+});
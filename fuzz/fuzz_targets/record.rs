@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::record::{encode_record, Record};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(record) = Record::read(1, data) {
+        // Whatever was read has to survive being written and read again
+        let encoded = encode_record(&record.to_owned_record().values);
+        let reread = Record::read(1, &encoded).expect("encoded record is valid");
+        assert_eq!(encode_record(&reread.to_owned_record().values), encoded);
+    }
+});
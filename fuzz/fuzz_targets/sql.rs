@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::sql;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = sql::parse(data);
+});
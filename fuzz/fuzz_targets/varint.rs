@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::varint;
+
+fuzz_target!(|data: &[u8]| {
+    let (value, len) = varint::read(data);
+    assert!(len <= data.len().min(9));
+    let encoded = varint::write(value);
+    assert_eq!(varint::read(&encoded), (value, encoded.len()));
+});
@@ -0,0 +1,26 @@
+//! Runs queries against a database on a web server, and prints their rows like the main
+//! binary does in list mode:
+//!
+//! ```sh
+//! sqlite-http https://example.com/data.db "SELECT COUNT(*) FROM apples"
+//! ```
+
+use std::io::stdout;
+
+use anyhow::{bail, Result};
+use sqlite_starter_rust::output::OutputMode;
+use sqlite_starter_rust_http::open;
+
+fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let Some((url, statements)) = args.split_first() else {
+        bail!("Usage: sqlite-http URL STATEMENT...");
+    };
+
+    let mut connection = open(url)?;
+    for statement in statements {
+        let mut out = OutputMode::default().formatter(stdout(), false);
+        connection.execute(statement, out.as_mut())?;
+    }
+    Ok(())
+}
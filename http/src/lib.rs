@@ -0,0 +1,222 @@
+//! Reading databases from web servers with HTTP range requests, so that a query only downloads
+//! the pages it reads instead of the whole file. Static file servers support range requests,
+//! so a database can be queried where it's hosted.
+//!
+//! Requests are made with [`ureq`], which speaks `https://` with rustls and keeps the
+//! connection to the server open between them, so that reading a page costs one round trip.
+
+use std::io::{self, ErrorKind};
+
+use anyhow::{bail, Result};
+use sqlite_starter_rust::connection::Connection;
+use sqlite_starter_rust::pager::RemoteFile;
+use ureq::http::{Response, StatusCode};
+use ureq::{Agent, Body};
+
+/// A file on a web server, read with range requests.
+#[derive(Debug)]
+pub struct HttpFile {
+    /// Pools the connections to the server, so that requests reuse them
+    agent: Agent,
+    url: String,
+    size: u64,
+    requests: usize,
+}
+
+impl HttpFile {
+    /// Checks that the server supports range requests for the file at `url`, and gets its size.
+    pub fn open(url: &str) -> Result<Self> {
+        let mut file = Self {
+            agent: Agent::new_with_defaults(),
+            url: url.to_string(),
+            size: 0,
+            requests: 0,
+        };
+        // The size of the file is part of the Content-Range header of any range response
+        let mut response = file.get(0, 1)?;
+        // Read the rest of the response, so that the connection can be reused
+        response.body_mut().read_to_vec()?;
+        match total_size(&response) {
+            Some(size) if response.status() == StatusCode::PARTIAL_CONTENT => file.size = size,
+            _ => bail!("{url}: the server doesn't support range requests"),
+        }
+        Ok(file)
+    }
+
+    /// The number of requests made so far, including the one for the size of the file.
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// Requests `len` bytes at `position`.
+    fn get(&mut self, position: u64, len: usize) -> Result<Response<Body>, ureq::Error> {
+        let end = position + len as u64 - 1;
+        self.requests += 1;
+        self.agent
+            .get(&self.url)
+            .header("Range", format!("bytes={position}-{end}"))
+            .call()
+    }
+}
+
+impl RemoteFile for HttpFile {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<()> {
+        if position + buf.len() as u64 > self.size {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let mut response = self
+            .get(position, buf.len())
+            .map_err(|error| io::Error::new(ErrorKind::Other, error))?;
+        let body = response
+            .body_mut()
+            .read_to_vec()
+            .map_err(|error| io::Error::new(ErrorKind::Other, error))?;
+        // A server that ignores the range sends the whole file
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(io::Error::new(
+                ErrorKind::Other,
+                format!("{}: the server doesn't support range requests", self.url),
+            ));
+        }
+        if body.len() != buf.len() {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        buf.copy_from_slice(&body);
+        Ok(())
+    }
+}
+
+/// The size of the whole file, from a Content-Range header like `bytes 0-1/4096`.
+fn total_size(response: &Response<Body>) -> Option<u64> {
+    let range = response.headers().get("Content-Range")?.to_str().ok()?;
+    range.rsplit('/').next()?.parse().ok()
+}
+
+/// Opens a connection to the database at an `http://` or `https://` URL.
+pub fn open(url: &str) -> Result<Connection> {
+    Connection::remote(url, Box::new(HttpFile::open(url)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlite_starter_rust::output::JsonLines;
+    use std::io::{prelude::*, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Serves `content` on a local port, to range requests unless `ranges` is false, and
+    /// returns its URL and the number of connections that were made.
+    fn serve(content: Vec<u8>, ranges: bool) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/sample.db", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let content = content.clone();
+                std::thread::spawn(move || respond(stream.unwrap(), &content, ranges));
+            }
+        });
+        (url, connections)
+    }
+
+    /// Answers the requests on a connection until the client closes it.
+    fn respond(mut stream: TcpStream, content: &[u8], ranges: bool) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        loop {
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return;
+                }
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("range") {
+                        range = value.trim().strip_prefix("bytes=").map(str::to_string);
+                    }
+                }
+            }
+            let (start, end) = match range.filter(|_| ranges) {
+                Some(range) => {
+                    let (start, end) = range.split_once('-').unwrap();
+                    (start.parse().unwrap(), end.parse::<usize>().unwrap())
+                }
+                None => (0, content.len() - 1),
+            };
+            let body = &content[start..=end.min(content.len() - 1)];
+            let head = if ranges {
+                format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {start}-{end}/{}\r\n\r\n",
+                    body.len(),
+                    content.len()
+                )
+            } else {
+                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len())
+            };
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+        }
+    }
+
+    #[test]
+    fn query_over_http() {
+        let (url, connections) = serve(std::fs::read("../sample.db").unwrap(), true);
+        let mut conn = open(&url).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT name FROM apples WHERE id = 3")
+            .unwrap();
+        let names = stmt
+            .query()
+            .unwrap()
+            .map(|row| row.get(0).unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(names, ["Honeycrisp"]);
+        assert_eq!(
+            conn.execute(
+                "INSERT INTO apples (name) VALUES ('Jazz')",
+                &mut JsonLines::new(vec![]),
+            )
+            .unwrap_err()
+            .to_string(),
+            "attempt to write a readonly database"
+        );
+        // All requests went over the same connection
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn reuse_connections() {
+        let (url, connections) = serve(std::fs::read("../sample.db").unwrap(), true);
+        let mut file = HttpFile::open(&url).unwrap();
+        let mut page = vec![0; 4096];
+        file.read_at(0, &mut page).unwrap();
+        assert_eq!(&page[..16], b"SQLite format 3\0");
+        file.read_at(4096, &mut page).unwrap();
+        assert_eq!(file.requests(), 3);
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            file.read_at(file.size(), &mut page).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn require_range_requests() {
+        let (url, _) = serve(std::fs::read("../sample.db").unwrap(), false);
+        assert_eq!(
+            HttpFile::open(&url).unwrap_err().to_string(),
+            format!("{url}: the server doesn't support range requests")
+        );
+    }
+}
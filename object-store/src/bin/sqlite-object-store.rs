@@ -0,0 +1,39 @@
+//! Runs queries against a database in an object store, and prints their rows like the main
+//! binary does in list mode:
+//!
+//! ```sh
+//! sqlite-object-store --prefetch 64 s3://bucket/data.db "SELECT COUNT(*) FROM apples"
+//! ```
+
+use std::io::stdout;
+
+use anyhow::{bail, Result};
+use sqlite_starter_rust::output::OutputMode;
+use sqlite_starter_rust_object_store::{open, DEFAULT_PREFETCH};
+
+fn main() -> Result<()> {
+    let mut prefetch = DEFAULT_PREFETCH;
+    let mut positional = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--prefetch" => {
+                prefetch = args
+                    .next()
+                    .and_then(|pages| pages.parse().ok())
+                    .ok_or(anyhow::anyhow!("--prefetch needs a number of pages"))?;
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let Some((url, statements)) = positional.split_first() else {
+        bail!("Usage: sqlite-object-store [--prefetch PAGES] URL STATEMENT...");
+    };
+
+    let mut connection = open(url, prefetch)?;
+    for statement in statements {
+        let mut out = OutputMode::default().formatter(stdout(), false);
+        connection.execute(statement, out.as_mut())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! Querying databases in object stores like S3, Google Cloud Storage and Azure Blob Storage
+//! with the `object_store` crate. The databases are read-only, and their pages are fetched
+//! with range requests when they're read.
+//!
+//! Every request has a latency that's much larger than the time it takes to transfer a page,
+//! so each request prefetches the pages after the one that is read. That's where the next
+//! leaves of a table scan usually are, in a database that was written in order or vacuumed.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use sqlite_starter_rust::connection::Connection;
+use sqlite_starter_rust::pager::RemoteFile;
+use tokio::runtime::Runtime;
+use url::Url;
+
+/// The number of pages each request prefetches by default.
+pub const DEFAULT_PREFETCH: usize = 16;
+
+/// A database file in an object store.
+#[derive(Debug)]
+pub struct ObjectStoreFile {
+    store: Arc<dyn ObjectStore>,
+    location: Path,
+    size: u64,
+    prefetch: usize,
+    /// Runs the requests of the store, which are async, for the reads of the pager, which
+    /// aren't.
+    runtime: Runtime,
+    /// The fetched ranges by their start. They're kept as long as the file is open, so no
+    /// part of the file is fetched twice.
+    cache: BTreeMap<u64, Bytes>,
+    requests: usize,
+}
+
+impl ObjectStoreFile {
+    /// Opens the object at `location`. Each request fetches `prefetch` more pages than the
+    /// page that is read.
+    pub fn open(store: Arc<dyn ObjectStore>, location: Path, prefetch: usize) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let size = runtime.block_on(store.head(&location))?.size;
+        Ok(Self {
+            store,
+            location,
+            size,
+            prefetch,
+            runtime,
+            cache: BTreeMap::new(),
+            requests: 0,
+        })
+    }
+
+    /// Opens the object at a URL like `s3://bucket/data.db`, `gs://bucket/data.db` or
+    /// `az://container/data.db`. The credentials and other options of the store are read from
+    /// the environment variables that `object_store` knows, like `AWS_ACCESS_KEY_ID` and
+    /// `AWS_REGION`.
+    pub fn open_url(url: &str, prefetch: usize) -> Result<Self> {
+        let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, location) = object_store::parse_url_opts(&Url::parse(url)?, options)?;
+        Self::open(store.into(), location, prefetch)
+    }
+
+    /// The number of requests made so far, besides the one for the size of the object.
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// The cached bytes from `position` on, if a fetched range contains them.
+    fn cached(&self, position: u64, len: usize) -> Option<&[u8]> {
+        let (start, bytes) = self.cache.range(..=position).next_back()?;
+        let offset = (position - start) as usize;
+        bytes.get(offset..offset + len)
+    }
+}
+
+impl RemoteFile for ObjectStoreFile {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<()> {
+        let end = position + buf.len() as u64;
+        if end > self.size {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        if self.cached(position, buf.len()).is_none() {
+            let prefetch_end = end + (buf.len() * self.prefetch) as u64;
+            let range = position..prefetch_end.min(self.size);
+            let bytes = self
+                .runtime
+                .block_on(self.store.get_range(&self.location, range))
+                .map_err(|error| io::Error::new(ErrorKind::Other, error))?;
+            self.requests += 1;
+            self.cache.insert(position, bytes);
+        }
+        buf.copy_from_slice(self.cached(position, buf.len()).expect("range was fetched"));
+        Ok(())
+    }
+}
+
+/// Opens a connection to the database at a URL, see [`ObjectStoreFile::open_url`].
+pub fn open(url: &str, prefetch: usize) -> Result<Connection> {
+    Connection::remote(url, Box::new(ObjectStoreFile::open_url(url, prefetch)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use object_store::PutPayload;
+
+    fn sample(prefetch: usize) -> ObjectStoreFile {
+        let store = Arc::new(InMemory::new());
+        let location = Path::from("sample.db");
+        let content = std::fs::read("../sample.db").unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime
+            .block_on(store.put(&location, PutPayload::from(content)))
+            .unwrap();
+        ObjectStoreFile::open(store, location, prefetch).unwrap()
+    }
+
+    #[test]
+    fn prefetch_pages() {
+        let mut file = sample(1);
+        let mut page = vec![0; 4096];
+        file.read_at(0, &mut page).unwrap();
+        assert_eq!(&page[..16], b"SQLite format 3\0");
+        // The second page came with the first one
+        file.read_at(4096, &mut page).unwrap();
+        assert_eq!(file.requests(), 1);
+        file.read_at(3 * 4096, &mut page).unwrap();
+        assert_eq!(file.requests(), 2);
+        assert_eq!(
+            file.read_at(4 * 4096, &mut page).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn query_object() {
+        let file = sample(DEFAULT_PREFETCH);
+        let mut conn = Connection::remote("memory:///sample.db", Box::new(file)).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT name FROM apples WHERE id = 1")
+            .unwrap();
+        let names = stmt
+            .query()
+            .unwrap()
+            .map(|row| row.get(0).unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(names, ["Granny Smith"]);
+    }
+}
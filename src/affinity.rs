@@ -0,0 +1,346 @@
+use crate::record::{format_real, ColumnValue};
+use crate::sql::Literal;
+
+/// The preferred storage class of a column, derived from its declared type.
+///
+/// See https://www.sqlite.org/datatype3.html#type_affinity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+}
+
+impl Affinity {
+    /// Determines the affinity using the rules of section 3.1, which are applied in order.
+    pub fn from_declared_type(ty: Option<&str>) -> Self {
+        let Some(ty) = ty else {
+            return Self::Blob;
+        };
+        let ty = ty.to_ascii_uppercase();
+
+        if ty.contains("INT") {
+            Self::Integer
+        } else if ty.contains("CHAR") || ty.contains("CLOB") || ty.contains("TEXT") {
+            Self::Text
+        } else if ty.contains("BLOB") || ty.is_empty() {
+            Self::Blob
+        } else if ty.contains("REAL") || ty.contains("FLOA") || ty.contains("DOUB") {
+            Self::Real
+        } else {
+            Self::Numeric
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Self::Numeric | Self::Integer | Self::Real)
+    }
+
+    /// Converts a literal that is compared to or stored in a column with this affinity,
+    /// following sections 3 and 4.2: numeric columns turn well-formed numeric text into numbers
+    /// and text columns turn numbers into text. REAL columns hold integers as reals, while
+    /// INTEGER and NUMERIC columns hold reals without a fractional part as integers. Blob
+    /// columns leave the literal as is.
+    pub fn apply(&self, literal: &Literal) -> Literal {
+        match (self, literal) {
+            (affinity, Literal::Text(text)) if affinity.is_numeric() => {
+                let trimmed = text.trim();
+                let number = if let Ok(n) = trimmed.parse::<i64>() {
+                    Literal::Integer(n)
+                } else if let Some(n) = parse_real(trimmed) {
+                    Literal::Real(n)
+                } else {
+                    return literal.clone();
+                };
+                self.apply(&number)
+            }
+            (Self::Real, Literal::Integer(n)) => Literal::Real(*n as f64),
+            (Self::Integer | Self::Numeric, Literal::Real(n)) if fits_integer(*n) => {
+                Literal::Integer(*n as i64)
+            }
+            (Self::Text, Literal::Integer(n)) => Literal::Text(n.to_string()),
+            (Self::Text, Literal::Real(n)) => Literal::Text(format_real(*n)),
+            _ => literal.clone(),
+        }
+    }
+
+    /// Converts a value like `CAST(value AS type)` does for a type with this affinity. Unlike
+    /// [`Affinity::apply`], the conversion always happens: numbers become text or blobs, and
+    /// text and blobs become the number they start with, or 0 if they don't start with one.
+    /// NULL stays NULL.
+    ///
+    /// See https://www.sqlite.org/lang_expr.html#castexpr
+    pub fn cast<'a>(&self, value: ColumnValue<'a>) -> ColumnValue<'a> {
+        match (self, value) {
+            (_, ColumnValue::Null) => ColumnValue::Null,
+            (Self::Text, ColumnValue::Text(text) | ColumnValue::Blob(text)) => {
+                ColumnValue::Text(text)
+            }
+            (Self::Blob, ColumnValue::Text(bytes) | ColumnValue::Blob(bytes)) => {
+                ColumnValue::Blob(bytes)
+            }
+            (Self::Text, number) => ColumnValue::Text(number_text(&number).into_bytes().into()),
+            (Self::Blob, number) => ColumnValue::Blob(number_text(&number).into_bytes().into()),
+            (Self::Integer, ColumnValue::Text(text) | ColumnValue::Blob(text)) => {
+                ColumnValue::I64(leading_integer(&String::from_utf8_lossy(&text)))
+            }
+            (affinity, ColumnValue::Text(text) | ColumnValue::Blob(text)) => {
+                let text = String::from_utf8_lossy(&text);
+                let prefix = numeric_prefix(text.trim_start());
+                let real = prefix.parse().unwrap_or(0.0);
+                match (affinity, prefix.parse()) {
+                    (Self::Real, _) => ColumnValue::F64(real),
+                    (_, Ok(n)) => ColumnValue::I64(n),
+                    (_, Err(_)) if fits_integer(real) => ColumnValue::I64(real as i64),
+                    (_, Err(_)) => ColumnValue::F64(real),
+                }
+            }
+            (Self::Real, ColumnValue::F64(n)) => ColumnValue::F64(n),
+            (Self::Real, number) => ColumnValue::F64(number.as_i64().unwrap_or_default() as f64),
+            // Reals are truncated, saturating at the limits of i64
+            (Self::Integer, ColumnValue::F64(n)) => ColumnValue::I64(n as i64),
+            (_, number) => number,
+        }
+    }
+
+    /// Converts a value read from a record of a column with this affinity. REAL columns store
+    /// reals without a fractional part as integers to save space, and read them back as reals.
+    pub fn read<'a>(&self, value: ColumnValue<'a>) -> ColumnValue<'a> {
+        match (self, value.as_i64()) {
+            (Self::Real, Some(n)) => ColumnValue::F64(n as f64),
+            _ => value,
+        }
+    }
+}
+
+/// Whether a real has no fractional part and is in the range of i64.
+fn fits_integer(real: f64) -> bool {
+    real.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(&real)
+}
+
+/// The text of a number, as a CAST to TEXT or BLOB writes it.
+fn number_text(number: &ColumnValue) -> String {
+    match number {
+        ColumnValue::F64(n) => format_real(*n),
+        number => number.as_i64().unwrap_or_default().to_string(),
+    }
+}
+
+/// The integer `text` starts with after leading spaces, or 0 if it doesn't start with one.
+/// Integers that don't fit in i64 saturate.
+fn leading_integer(text: &str) -> i64 {
+    let text = text.trim_start();
+    let sign = usize::from(matches!(text.as_bytes().first(), Some(b'+' | b'-')));
+    let digits = text[sign..].bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 {
+        return 0;
+    }
+    text[..sign + digits]
+        .parse()
+        .unwrap_or(if text.starts_with('-') {
+            i64::MIN
+        } else {
+            i64::MAX
+        })
+}
+
+/// The longest prefix of `text` that is a number: an optional sign, digits with an optional
+/// decimal point and an optional exponent.
+fn numeric_prefix(text: &str) -> &str {
+    let bytes = text.as_bytes();
+    let digits = |position: usize| {
+        position
+            + bytes[position..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_digit())
+                .count()
+    };
+    let mut end = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let start = end;
+    end = digits(end);
+    if bytes.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    // A sign or a decimal point alone isn't a number
+    if !bytes[start..end].iter().any(u8::is_ascii_digit) {
+        return "";
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let exponent = end + 1 + usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        if digits(exponent) > exponent {
+            end = digits(exponent);
+        }
+    }
+    &text[..end]
+}
+
+/// Parses text that looks like an SQL real literal. Rust also accepts "inf" and "NaN", which
+/// SQLite doesn't consider numeric.
+pub fn parse_real(text: &str) -> Option<f64> {
+    let looks_numeric = !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+    if looks_numeric {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn affinity_from_declared_type() {
+        assert_eq!(
+            Affinity::from_declared_type(Some("INTEGER")),
+            Affinity::Integer
+        );
+        assert_eq!(
+            Affinity::from_declared_type(Some("BIGINT")),
+            Affinity::Integer
+        );
+        assert_eq!(
+            Affinity::from_declared_type(Some("VARCHAR(255)")),
+            Affinity::Text
+        );
+        assert_eq!(Affinity::from_declared_type(Some("CLOB")), Affinity::Text);
+        assert_eq!(Affinity::from_declared_type(Some("BLOB")), Affinity::Blob);
+        assert_eq!(Affinity::from_declared_type(None), Affinity::Blob);
+        assert_eq!(Affinity::from_declared_type(Some("DOUBLE")), Affinity::Real);
+        assert_eq!(
+            Affinity::from_declared_type(Some("DECIMAL(10,5)")),
+            Affinity::Numeric
+        );
+        // "INT" wins over "CHAR" and "FLOATING POINT" contains "INT"
+        assert_eq!(
+            Affinity::from_declared_type(Some("CHARINT")),
+            Affinity::Integer
+        );
+        assert_eq!(
+            Affinity::from_declared_type(Some("FLOATING POINT")),
+            Affinity::Integer
+        );
+    }
+
+    #[test]
+    fn numeric_affinity_converts_numeric_text() {
+        let text = |s: &str| Literal::Text(s.to_string());
+        assert_eq!(Affinity::Integer.apply(&text("5")), Literal::Integer(5));
+        assert_eq!(Affinity::Numeric.apply(&text(" 2.5 ")), Literal::Real(2.5));
+        assert_eq!(Affinity::Real.apply(&text("abc")), text("abc"));
+        assert_eq!(Affinity::Integer.apply(&text("inf")), text("inf"));
+    }
+
+    #[test]
+    fn text_affinity_converts_numbers() {
+        assert_eq!(
+            Affinity::Text.apply(&Literal::Integer(3)),
+            Literal::Text("3".to_string())
+        );
+        assert_eq!(
+            Affinity::Text.apply(&Literal::Real(3.0)),
+            Literal::Text("3.0".to_string())
+        );
+        assert_eq!(
+            Affinity::Blob.apply(&Literal::Integer(3)),
+            Literal::Integer(3)
+        );
+        assert_eq!(
+            Affinity::Text.apply(&Literal::Real(1e20)),
+            Literal::Text("1.0e+20".to_string())
+        );
+    }
+
+    #[test]
+    fn cast_values() {
+        let cast = |affinity: Affinity, value: ColumnValue<'static>| {
+            let value = affinity.cast(value);
+            (value.type_name(), value.to_string())
+        };
+        let text = |text: &'static str| ColumnValue::Text(text.as_bytes().into());
+        assert_eq!(
+            cast(Affinity::Integer, text(" 12abc")),
+            ("integer", "12".into())
+        );
+        assert_eq!(
+            cast(Affinity::Integer, text("1e20")),
+            ("integer", "1".into())
+        );
+        assert_eq!(
+            cast(Affinity::Integer, text("9223372036854775808")),
+            ("integer", i64::MAX.to_string())
+        );
+        assert_eq!(
+            cast(Affinity::Integer, ColumnValue::F64(-1.9)),
+            ("integer", "-1".into())
+        );
+        assert_eq!(
+            cast(Affinity::Integer, ColumnValue::F64(1e30)),
+            ("integer", i64::MAX.to_string())
+        );
+        assert_eq!(
+            cast(Affinity::Real, text("-.5e-1x")),
+            ("real", "-0.05".into())
+        );
+        assert_eq!(cast(Affinity::Real, text("abc")), ("real", "0.0".into()));
+        assert_eq!(
+            cast(Affinity::Real, ColumnValue::I8(3)),
+            ("real", "3.0".into())
+        );
+        // NUMERIC keeps reals as they are, but text without a fraction becomes an integer
+        assert_eq!(
+            cast(Affinity::Numeric, text("3.0e0")),
+            ("integer", "3".into())
+        );
+        assert_eq!(
+            cast(Affinity::Numeric, text("1e20")),
+            ("real", "1.0e+20".into())
+        );
+        assert_eq!(cast(Affinity::Numeric, text(".5")), ("real", "0.5".into()));
+        assert_eq!(
+            cast(Affinity::Numeric, ColumnValue::F64(3.0)),
+            ("real", "3.0".into())
+        );
+        assert_eq!(
+            cast(Affinity::Text, ColumnValue::F64(1e30)),
+            ("text", "1.0e+30".into())
+        );
+        assert_eq!(
+            cast(Affinity::Text, ColumnValue::Blob(b"12"[..].into())),
+            ("text", "12".into())
+        );
+        assert_eq!(
+            cast(Affinity::Blob, ColumnValue::I64(7)),
+            ("blob", "X'37'".into())
+        );
+        assert_eq!(
+            cast(Affinity::Integer, ColumnValue::Null),
+            ("null", "NULL".into())
+        );
+    }
+
+    #[test]
+    fn real_columns_read_integers_as_reals() {
+        assert!(matches!(
+            Affinity::Real.read(ColumnValue::I8(3)),
+            ColumnValue::F64(n) if n == 3.0
+        ));
+        assert!(matches!(
+            Affinity::Real.read(ColumnValue::Zero),
+            ColumnValue::F64(n) if n == 0.0
+        ));
+        assert!(matches!(
+            Affinity::Numeric.read(ColumnValue::I8(3)),
+            ColumnValue::I8(3)
+        ));
+        assert!(matches!(
+            Affinity::Real.read(ColumnValue::Text(b"3"[..].into())),
+            ColumnValue::Text(text) if *text == b"3"[..]
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+//! The databases of a connection: the main database, the in-memory database of temporary
+//! tables and the ones added with `ATTACH`. Each database has its own pager and schema, so a
+//! page is identified by the database it's in and its page number, and a table by its schema
+//! name and table name.
+
+use std::ops::RangeInclusive;
+
+use anyhow::{bail, Result};
+
+use crate::collation::CollationFunction;
+use crate::database::Database;
+use crate::error::Error;
+use crate::functions::UserFunction;
+
+/// The schema name of the database a connection was opened with.
+pub const MAIN_SCHEMA: &str = "main";
+/// The schema name of the database that holds temporary tables.
+pub const TEMP_SCHEMA: &str = "temp";
+
+#[derive(Debug)]
+pub struct Catalog {
+    main: Database,
+    /// Created in memory when the first temporary table is
+    temp: Option<Database>,
+    /// Attached databases by schema name, in the order they were attached
+    attached: Vec<(String, Database)>,
+}
+
+/// Where a database is kept in the catalog.
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Main,
+    Temp,
+    Attached(usize),
+}
+
+impl Catalog {
+    pub fn new(main: Database) -> Self {
+        Self {
+            main,
+            temp: None,
+            attached: vec![],
+        }
+    }
+
+    pub fn main(&self) -> &Database {
+        &self.main
+    }
+
+    pub fn main_mut(&mut self) -> &mut Database {
+        &mut self.main
+    }
+
+    /// Opens the database at `path` as `schema`. Like sqlite3, a file that doesn't exist yet is
+    /// created.
+    pub fn attach(&mut self, path: &str, schema: &str) -> Result<()> {
+        if self.slot(schema).is_some() {
+            return Err(Error::DatabaseInUse(schema.to_string()).into());
+        }
+        let mut database = if std::path::Path::new(path).exists() {
+            Database::open(path)?
+        } else {
+            Database::create(path)?
+        };
+        database.threads = self.main.threads;
+        database.functions = self.main.functions.clone();
+        database.collations = self.main.collations.clone();
+        self.attached.push((schema.to_string(), database));
+        Ok(())
+    }
+
+    /// Adds a function that the queries of all databases can call, see
+    /// [`Functions::add`](crate::functions::Functions::add).
+    pub fn add_function(
+        &mut self,
+        name: &str,
+        arguments: RangeInclusive<usize>,
+        function: UserFunction,
+    ) {
+        let databases = std::iter::once(&mut self.main)
+            .chain(self.temp.as_mut())
+            .chain(self.attached.iter_mut().map(|(_, database)| database));
+        for database in databases {
+            database
+                .functions
+                .add(name, arguments.clone(), function.clone());
+        }
+    }
+
+    /// Adds a collation that the columns and indexes of all databases can name, see
+    /// [`Collations::add`](crate::collation::Collations::add).
+    pub fn add_collation(&mut self, name: &str, compare: CollationFunction) {
+        let databases = std::iter::once(&mut self.main)
+            .chain(self.temp.as_mut())
+            .chain(self.attached.iter_mut().map(|(_, database)| database));
+        for database in databases {
+            database.collations.add(name, compare.clone());
+        }
+    }
+
+    pub fn detach(&mut self, schema: &str) -> Result<(), Error> {
+        match self.slot(schema) {
+            Some(Slot::Attached(position)) => {
+                self.attached.remove(position);
+                Ok(())
+            }
+            Some(Slot::Main | Slot::Temp) => Err(Error::CannotDetach(schema.to_string())),
+            None => Err(Error::NoSuchDatabase(schema.to_string())),
+        }
+    }
+
+    /// The schema names and databases: the main database, the temporary one if a temporary
+    /// table was created, and the attached ones.
+    pub fn databases(&self) -> impl Iterator<Item = (&str, &Database)> {
+        std::iter::once((MAIN_SCHEMA, &self.main))
+            .chain(self.temp.iter().map(|temp| (TEMP_SCHEMA, temp)))
+            .chain(
+                self.attached
+                    .iter()
+                    .map(|(schema, database)| (schema.as_str(), database)),
+            )
+    }
+
+    /// The database with the given schema name, or the main database without one. This is where
+    /// CREATE statements add tables and indexes.
+    pub fn database(&mut self, schema: Option<&str>) -> Result<&mut Database> {
+        let slot = match schema {
+            Some(schema) => self
+                .slot(schema)
+                .ok_or_else(|| Error::UnknownDatabase(schema.to_string()))?,
+            None => Slot::Main,
+        };
+        self.get(slot)
+    }
+
+    /// The database a CREATE TABLE statement adds its table to. Temporary tables go to the
+    /// temporary database, and they can't name another one.
+    pub fn database_for_table(
+        &mut self,
+        schema: Option<&str>,
+        temporary: bool,
+    ) -> Result<&mut Database> {
+        match schema {
+            Some(schema) if temporary && !schema.eq_ignore_ascii_case(TEMP_SCHEMA) => {
+                bail!("temporary table name must be unqualified")
+            }
+            None if temporary => self.get(Slot::Temp),
+            schema => self.database(schema),
+        }
+    }
+
+    /// The database a CREATE INDEX statement adds its index to. Without a schema name, that's
+    /// the temporary database for temporary tables and the main database otherwise.
+    pub fn database_for_index(
+        &mut self,
+        schema: Option<&str>,
+        table: &str,
+    ) -> Result<&mut Database> {
+        let temporary = self
+            .temp
+            .as_ref()
+            .map_or(false, |temp| temp.schema.find_table(table).is_some());
+        match schema {
+            None if temporary => self.get(Slot::Temp),
+            schema => self.database(schema),
+        }
+    }
+
+    /// The database that holds `table`, which may also be a view or a virtual table. Without a schema name, the databases are searched
+    /// like SQLite does: temporary tables first, then the main database and then the attached
+    /// ones in the order they were attached.
+    pub fn database_of(&mut self, schema: Option<&str>, table: &str) -> Result<&mut Database> {
+        let slot = match schema {
+            Some(schema) => self
+                .slot(schema)
+                .ok_or_else(|| Error::NoSuchTable(format!("{schema}.{table}")))?,
+            None => [Slot::Temp, Slot::Main]
+                .into_iter()
+                .chain((0..self.attached.len()).map(Slot::Attached))
+                .find(|&slot| {
+                    self.find(slot).map_or(false, |database| {
+                        database.schema.find_table(table).is_some()
+                            || database.schema.find_view(table).is_some()
+                            || database.schema.find_virtual_table(table).is_some()
+                    })
+                })
+                .unwrap_or(Slot::Main),
+        };
+        self.get(slot)
+    }
+
+    /// The database in a slot. The temporary database is created on first use.
+    fn get(&mut self, slot: Slot) -> Result<&mut Database> {
+        Ok(match slot {
+            Slot::Main => &mut self.main,
+            Slot::Temp => match &mut self.temp {
+                Some(temp) => temp,
+                temp => {
+                    let mut database = Database::memory()?;
+                    database.functions = self.main.functions.clone();
+                    database.collations = self.main.collations.clone();
+                    temp.insert(database)
+                }
+            },
+            Slot::Attached(position) => &mut self.attached[position].1,
+        })
+    }
+
+    fn find(&self, slot: Slot) -> Option<&Database> {
+        match slot {
+            Slot::Main => Some(&self.main),
+            Slot::Temp => self.temp.as_ref(),
+            Slot::Attached(position) => Some(&self.attached[position].1),
+        }
+    }
+
+    /// The slot of the database with the given schema name. Schema names ignore case.
+    fn slot(&self, schema: &str) -> Option<Slot> {
+        if schema.eq_ignore_ascii_case(MAIN_SCHEMA) {
+            return Some(Slot::Main);
+        }
+        if schema.eq_ignore_ascii_case(TEMP_SCHEMA) {
+            return Some(Slot::Temp);
+        }
+        self.attached
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(schema))
+            .map(Slot::Attached)
+    }
+}
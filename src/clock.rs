@@ -0,0 +1,34 @@
+//! The current time, for `datetime('now')` and the nonces of rollback journals.
+//!
+//! `wasm32-unknown-unknown` has no clock, and `SystemTime::now()` panics there, so embedders
+//! on such targets replace the clock with [`set_clock`], e.g. with JavaScript's `Date.now()`.
+//! Until they do, the time there is the Unix epoch.
+
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// The function that [`now`] asks for the time.
+static CLOCK: Mutex<fn() -> Duration> = Mutex::new(system_clock);
+
+/// Replaces the clock with `clock`, which returns the time since 1970-01-01 00:00:00 UTC.
+pub fn set_clock(clock: fn() -> Duration) {
+    *CLOCK.lock().unwrap_or_else(PoisonError::into_inner) = clock;
+}
+
+/// The time since 1970-01-01 00:00:00 UTC.
+pub fn now() -> Duration {
+    let clock = *CLOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    clock()
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn system_clock() -> Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn system_clock() -> Duration {
+    Duration::ZERO
+}
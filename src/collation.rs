@@ -0,0 +1,145 @@
+//! Collating sequences, which order text values. Columns and indexes name theirs with `COLLATE`,
+//! and comparisons of text use the one of the column they compare.
+//!
+//! See https://www.sqlite.org/datatype3.html#collation
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::record::ColumnValue;
+
+/// A collation added with [`Connection::create_collation`].
+///
+/// [`Connection::create_collation`]: crate::connection::Connection::create_collation
+pub type CollationFunction = Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub enum Collation {
+    /// Compares the bytes of text
+    #[default]
+    Binary,
+    /// Like BINARY, with the ASCII letters in lower case
+    NoCase,
+    /// Like BINARY, without trailing spaces
+    RTrim,
+    User(CollationFunction),
+}
+
+impl Collation {
+    /// Orders two values like [`ColumnValue::compare`], except that text is ordered by the
+    /// collation.
+    pub fn compare(&self, a: &ColumnValue, b: &ColumnValue) -> Ordering {
+        match (self, a, b) {
+            (Collation::Binary, _, _) => a.compare(b),
+            (collation, ColumnValue::Text(a), ColumnValue::Text(b)) => collation.compare_text(a, b),
+            _ => a.compare(b),
+        }
+    }
+
+    fn compare_text(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => a
+                .iter()
+                .map(u8::to_ascii_lowercase)
+                .cmp(b.iter().map(u8::to_ascii_lowercase)),
+            Collation::RTrim => trim_end_spaces(a).cmp(trim_end_spaces(b)),
+            Collation::User(function) => {
+                function(&String::from_utf8_lossy(a), &String::from_utf8_lossy(b))
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Collation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Collation::Binary => "Binary",
+            Collation::NoCase => "NoCase",
+            Collation::RTrim => "RTrim",
+            Collation::User(_) => "User",
+        })
+    }
+}
+
+fn trim_end_spaces(text: &[u8]) -> &[u8] {
+    let end = text
+        .iter()
+        .rposition(|&c| c != b' ')
+        .map_or(0, |last| last + 1);
+    &text[..end]
+}
+
+/// Whether two collation names, `None` for the default, name the same collation. Names ignore
+/// case.
+pub fn same_name(a: Option<&str>, b: Option<&str>) -> bool {
+    a.unwrap_or("BINARY")
+        .eq_ignore_ascii_case(b.unwrap_or("BINARY"))
+}
+
+/// The collations columns and indexes can name: BINARY, NOCASE, RTRIM and the ones added to a
+/// connection. Like in SQLite, added collations take precedence over built-in ones with the
+/// same name.
+#[derive(Debug, Clone, Default)]
+pub struct Collations {
+    /// By lower case name
+    user: HashMap<String, Collation>,
+}
+
+impl Collations {
+    /// Adds a collation, replacing an added collation with the same name.
+    pub fn add(&mut self, name: &str, function: CollationFunction) {
+        self.user
+            .insert(name.to_ascii_lowercase(), Collation::User(function));
+    }
+
+    /// The collation called `name`, or BINARY without a name.
+    pub fn find(&self, name: Option<&str>) -> Result<Collation, Error> {
+        let Some(name) = name else {
+            return Ok(Collation::Binary);
+        };
+        if let Some(collation) = self.user.get(&name.to_ascii_lowercase()) {
+            return Ok(collation.clone());
+        }
+        match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Ok(Collation::Binary),
+            "NOCASE" => Ok(Collation::NoCase),
+            "RTRIM" => Ok(Collation::RTrim),
+            _ => Err(Error::NoSuchCollation(name.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_with_collations() {
+        let text = |text: &'static str| ColumnValue::Text(text.as_bytes().into());
+        let collations = Collations::default();
+        let nocase = collations.find(Some("nocase")).unwrap();
+        assert_eq!(
+            nocase.compare(&text("Apple"), &text("aPPLE")),
+            Ordering::Equal
+        );
+        assert_eq!(nocase.compare(&text("a"), &text("B")), Ordering::Less);
+        assert_eq!(
+            Collation::Binary.compare(&text("a"), &text("B")),
+            Ordering::Greater
+        );
+        let rtrim = collations.find(Some("RTRIM")).unwrap();
+        assert_eq!(rtrim.compare(&text("x  "), &text("x")), Ordering::Equal);
+        // Only text is collated
+        assert_eq!(
+            nocase.compare(&ColumnValue::I64(1), &text("1")),
+            Ordering::Less
+        );
+        assert_eq!(
+            collations.find(Some("de_DE")).err(),
+            Some(Error::NoSuchCollation("de_DE".to_string()))
+        );
+    }
+}
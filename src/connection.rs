@@ -0,0 +1,3350 @@
+//! A small rusqlite-like API to read databases from other programs.
+//!
+//! ```no_run
+//! use sqlite_starter_rust::connection::Connection;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let mut conn = Connection::open("sample.db")?;
+//! let mut stmt = conn.prepare("SELECT id, name FROM apples")?;
+//! for row in stmt.query()? {
+//!     let id: i64 = row.get(0)?;
+//!     let name: &str = row.get("name")?;
+//!     println!("{}: {}", id, name);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::catalog::Catalog;
+use crate::collation::Collations;
+use crate::csv;
+use crate::database::{Database, RowCount};
+use crate::dbstat::{self, SpaceUsage};
+use crate::error::Error;
+use crate::explain::Program;
+use crate::expr::Expression;
+use crate::fts::FtsTable;
+use crate::functions::Functions;
+use crate::join::{self, JoinColumns, DEFAULT_JOIN_MEMORY_LIMIT};
+use crate::output::OutputFormatter;
+use crate::pager::RemoteFile;
+use crate::record::ColumnValue;
+pub use crate::record::Value;
+use crate::rowset::Rowset;
+use crate::sql::{
+    self, CommonTableExpression, CompoundOperator, Expr, InsertStatement, Literal, Parameter,
+    ResultColumn, SQLCommand, SelectFields, SelectStatement, TableSource,
+};
+use crate::sqlite_schema::View;
+use crate::window::{self, WindowedQuery};
+
+/// Computes the values of a subquery that is used as an expression.
+type SubqueryValues<'a> = dyn FnMut(&mut Connection, &SelectStatement) -> Result<Vec<Literal>> + 'a;
+
+/// The value of a column of the outer query that a correlated subquery reads, by name.
+type OuterValue<'a> = dyn FnMut(&str) -> Result<Expr> + 'a;
+
+/// The names the columns of a query can be referred to by, which tell the columns of a
+/// subquery's own table from the ones of the query it's in.
+struct Scope {
+    /// The alias or the name of the table
+    qualifier: Option<String>,
+    /// The names of the columns, unless they're only known once the query has run
+    columns: Option<Vec<String>>,
+}
+
+impl Scope {
+    fn is_named(&self, name: &str) -> bool {
+        self.qualifier
+            .as_deref()
+            .map_or(false, |qualifier| qualifier.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the table may have the column. Tables whose columns aren't known may have any.
+    fn has_column(&self, name: &str) -> bool {
+        self.columns.as_ref().map_or(true, |columns| {
+            columns
+                .iter()
+                .any(|column| column.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+/// A common table expression of an explained query
+#[derive(Clone)]
+struct ExplainedCte {
+    name: String,
+    /// The rowset its rows go into
+    rowset: String,
+    /// The names of its columns, if its definition lists them
+    columns: Vec<String>,
+}
+
+/// What the name of a table in a FROM clause reads, see [`Connection::resolve_source`].
+enum Source {
+    /// The rows of a common table expression of the running query
+    Cte(Arc<Rowset>),
+    /// A common table expression of the explained query
+    ExplainedCte(ExplainedCte),
+    View(View),
+    Fts(FtsTable),
+    /// A table of a database
+    Table,
+}
+
+/// The name the rows of a query with window functions are read with, which can't be written
+/// without quotes.
+const WINDOW_ROWS: &str = "window rows";
+
+/// The name the rows of a join are read with, which can't be written without quotes.
+const JOIN_ROWS: &str = "join rows";
+
+/// How many times the step of a recursive common table expression can run by default.
+pub const DEFAULT_RECURSION_LIMIT: usize = 1000;
+
+pub struct Connection {
+    catalog: Catalog,
+    /// The views whose SELECTs are running, to catch views that are defined in terms of
+    /// themselves
+    views: Vec<String>,
+    /// The rows of the common table expressions of the running query, innermost last
+    ctes: Vec<(String, Arc<Rowset>)>,
+    /// How many times the step of a recursive common table expression can run
+    recursion_limit: usize,
+    /// How many bytes of rows the right side of a join can have before it's partitioned into
+    /// temporary files
+    join_memory_limit: usize,
+    /// The rowsets the common table expressions of the explained query go into
+    explained_ctes: Vec<ExplainedCte>,
+}
+
+impl Connection {
+    fn new(catalog: Catalog) -> Self {
+        Self {
+            catalog,
+            views: vec![],
+            ctes: vec![],
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            join_memory_limit: DEFAULT_JOIN_MEMORY_LIMIT,
+            explained_ctes: vec![],
+        }
+    }
+
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(Self::new(Catalog::new(Database::open(path)?)))
+    }
+
+    /// Opens the content of a database file that was read into memory. Changes aren't written
+    /// anywhere, and are gone when the connection is dropped.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        Ok(Self::new(Catalog::new(Database::from_bytes(bytes)?)))
+    }
+
+    /// Opens a database file that isn't on the local file system for reading, like an object
+    /// in a cloud store. `path` is how `.databases` lists it.
+    pub fn remote(path: &str, file: Box<dyn RemoteFile>) -> Result<Self> {
+        Ok(Self::new(Catalog::new(Database::remote(path, file)?)))
+    }
+
+    /// Creates a new database file, which fails if the file exists.
+    pub fn create(path: &str) -> Result<Self> {
+        Ok(Self::new(Catalog::new(Database::create(path)?)))
+    }
+
+    /// The main database, which the connection was opened with.
+    pub fn database(&self) -> &Database {
+        self.catalog.main()
+    }
+
+    /// The main database and the attached ones.
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    /// Sets the number of threads that full table scans decode pages on. Rows are returned in
+    /// the same order with any number of threads.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.catalog.main_mut().threads = threads.max(1);
+    }
+
+    /// Sets how many times the step of a `WITH RECURSIVE` query can run before the query fails.
+    /// Without LIMIT, this is what stops queries that never run out of new rows.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+
+    /// Sets how many bytes of rows the right side of a join can have before the join writes
+    /// the rows of both sides to temporary files, and joins them a part at a time.
+    pub fn set_join_memory_limit(&mut self, bytes: usize) {
+        self.join_memory_limit = bytes;
+    }
+
+    /// Adds a function that SQL can call with `arguments` arguments, like rusqlite's function of
+    /// the same name. It gets the values of the arguments, which [`FromValue`] converts to Rust
+    /// types, and an error it returns fails the statement that calls it. A function with the
+    /// name of another one replaces it, even a built-in one.
+    pub fn create_scalar_function<F>(
+        &mut self,
+        name: &str,
+        arguments: RangeInclusive<usize>,
+        function: F,
+    ) where
+        F: Fn(&[Value]) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.catalog
+            .add_function(name, arguments, Arc::new(function));
+    }
+
+    /// Adds a collation that columns and indexes can name with `COLLATE`, like rusqlite's
+    /// function of the same name. It orders text values, and has to be added before querying a
+    /// table that uses it. A collation with the name of another one replaces it, even a built-in
+    /// one.
+    pub fn create_collation<F>(&mut self, name: &str, compare: F)
+    where
+        F: Fn(&str, &str) -> Ordering + Send + Sync + 'static,
+    {
+        self.catalog.add_collation(name, Arc::new(compare));
+    }
+
+    /// The functions queries can call besides the built-in ones.
+    fn functions(&self) -> &Functions {
+        &self.catalog.main().functions
+    }
+
+    /// The collations columns and indexes can name besides the built-in ones.
+    fn collations(&self) -> &Collations {
+        &self.catalog.main().collations
+    }
+
+    /// Moves the content of the write-ahead log into the database file, see
+    /// [`Database::checkpoint`].
+    pub fn checkpoint(&mut self) -> Result<usize> {
+        self.catalog.main_mut().checkpoint()
+    }
+
+    /// Describes a page of the main database, see [`Database::inspect_page`].
+    pub fn inspect_page(&mut self, number: u32) -> Result<Vec<String>> {
+        self.catalog.main_mut().inspect_page(number)
+    }
+
+    /// A hexdump of a page of the main database, see [`Database::hexdump_page`].
+    pub fn hexdump_page(&mut self, number: u32) -> Result<Vec<String>> {
+        self.catalog.main_mut().hexdump_page(number)
+    }
+
+    /// The space used by each table and index of the main database, see
+    /// [`dbstat::space_usage`].
+    pub fn space_usage(&mut self) -> Result<Vec<SpaceUsage>> {
+        dbstat::space_usage(self.catalog.main_mut())
+    }
+
+    /// Imports the records of a CSV file into a table, like `.import` of sqlite3, and returns
+    /// the number of rows added. A table that doesn't exist yet is created with the columns
+    /// named in the first record, and with types that fit the values of the other records.
+    /// Empty values of numeric columns are NULL. Otherwise, every record is a row, whose values
+    /// get the affinity of the table's columns.
+    pub fn import_csv(&mut self, path: &str, table: &str) -> Result<usize> {
+        let mut records = csv::parse(&std::fs::read_to_string(path)?)?.into_iter();
+        let exists = self
+            .catalog
+            .database_of(None, table)?
+            .schema
+            .find_table(table)
+            .is_some();
+        // Records are numbered from 1 in errors, counting the header
+        let mut first = 1;
+        let mut numeric = vec![];
+        if !exists {
+            let Some(header) = records.next() else {
+                bail!("{path}: empty file");
+            };
+            first += 1;
+            let rows = records.as_slice();
+            let columns = header
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    let values = rows.iter().filter_map(|row| row.get(i).map(String::as_str));
+                    let column_type = csv::column_type(values);
+                    numeric.push(column_type != "TEXT");
+                    format!("{} {column_type}", quote_identifier(name))
+                })
+                .collect::<Vec<_>>();
+            let create = format!(
+                "CREATE TABLE {} ({})",
+                quote_identifier(table),
+                columns.join(", ")
+            );
+            self.execute(&create, &mut RowCollector::default())?;
+        }
+
+        let database = self.catalog.database_of(None, table)?;
+        let width = database.schema.table(table)?.columns.len();
+        let rows = records
+            .enumerate()
+            .map(|(i, record)| {
+                if record.len() != width {
+                    bail!(
+                        "{path}: record {} has {} values but the table has {width} columns",
+                        first + i,
+                        record.len()
+                    );
+                }
+                Ok(record
+                    .into_iter()
+                    .enumerate()
+                    .map(|(column, value)| match numeric.get(column) {
+                        Some(true) if value.is_empty() => Expr::Literal(Literal::Null),
+                        _ => Expr::Literal(Literal::Text(value)),
+                    })
+                    .collect())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let count = rows.len();
+        if count > 0 {
+            database.insert(&InsertStatement {
+                schema: None,
+                table: table.to_string(),
+                columns: vec![],
+                rows,
+            })?;
+        }
+        Ok(count)
+    }
+
+    /// Parses a single statement so that it can be run with [`Statement::query`]. The statement
+    /// may contain `?`, `?NNN`, `:name`, `@name` and `$name` parameters.
+    pub fn prepare(&mut self, statement: &str) -> Result<Statement<'_>> {
+        let mut command = parse_statement(statement)?;
+        let parameter_names = number_parameters(&mut command);
+        Ok(Statement {
+            connection: self,
+            sql: statement.to_string(),
+            command,
+            bindings: vec![Literal::Null; parameter_names.len()],
+            parameter_names,
+        })
+    }
+
+    /// Runs a single statement and writes its results to `out`.
+    pub fn execute(&mut self, statement: &str, out: &mut dyn OutputFormatter) -> Result<()> {
+        let command = parse_statement(statement)?;
+        self.run(&command, statement, out)
+    }
+
+    /// Runs the statements of a script in order, like a migration file, and writes their
+    /// results to `out`. The whole script is parsed first, so a syntax error runs nothing. A
+    /// statement that fails stops the script, and the ones before it stay applied.
+    pub fn execute_script(&mut self, script: &str, out: &mut dyn OutputFormatter) -> Result<()> {
+        for (number, (sql, command)) in sql::parse_script(script)?.iter().enumerate() {
+            self.run(command, sql, out)
+                .map_err(|e| anyhow::anyhow!("Error in statement {}: {}", number + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Runs a parsed statement. `sql` is its source, which CREATE statements store.
+    fn run(
+        &mut self,
+        command: &SQLCommand,
+        sql: &str,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<()> {
+        let catalog = &mut self.catalog;
+        match command {
+            SQLCommand::Select(select) => self.select(select, out),
+            SQLCommand::Pragma(pragma) => catalog.main_mut().pragma(pragma, out),
+            SQLCommand::Insert(insert) => catalog
+                .database_of(insert.schema.as_deref(), &insert.table)?
+                .insert(insert),
+            SQLCommand::CreateTable(create) => catalog
+                .database_for_table(create.schema.as_deref(), create.temporary)?
+                .create_table(create, sql),
+            SQLCommand::CreateIndex(create) => catalog
+                .database_for_index(create.schema.as_deref(), &create.table)?
+                .create_index(create, sql),
+            SQLCommand::CreateView(create) => catalog
+                .database_for_table(create.schema.as_deref(), create.temporary)?
+                .create_view(create, sql),
+            SQLCommand::CreateVirtualTable(create) => catalog
+                .database_for_table(create.schema.as_deref(), false)?
+                .create_virtual_table(create),
+            SQLCommand::Vacuum => catalog.main_mut().vacuum(),
+            SQLCommand::Analyze(name) => catalog.main_mut().analyze(name.as_deref(), out),
+            SQLCommand::Attach { path, schema } => catalog.attach(path, schema),
+            SQLCommand::Detach(schema) => Ok(catalog.detach(schema)?),
+            SQLCommand::Explain(select) => {
+                let mut program = Program::default();
+                self.explain(select, &mut program, None)?;
+                program.push("Halt", "");
+                program.write(out)
+            }
+        }
+    }
+
+    /// Runs a query. Subqueries in FROM, views and the SELECTs of a UNION are run first, and
+    /// their rows are kept in memory for the outer query to read.
+    fn select(&mut self, select: &SelectStatement, out: &mut dyn OutputFormatter) -> Result<()> {
+        if let SelectStatement::Fields(command) = select {
+            if let Some(windowed) = window::plan(command)? {
+                return self.select_windowed(command, &windowed, out);
+            }
+        }
+        match select {
+            SelectStatement::Count {
+                schema,
+                table,
+                where_clause: Some(where_clause),
+            } => {
+                let command = SelectFields {
+                    fields: vec![],
+                    from: TableSource::Table {
+                        schema: schema.clone(),
+                        name: table.clone(),
+                    },
+                    alias: None,
+                    where_clause: Some(where_clause.clone()),
+                };
+                let count = if self.is_correlated(&command)? {
+                    let mut rows = RowCount::default();
+                    self.select_correlated(&command, &mut rows)?;
+                    rows.0
+                } else {
+                    let command = self.run_subqueries(&command)?;
+                    self.count(schema.as_deref(), table, &command)?
+                };
+                out.begin(&["count(*)".to_string()])?;
+                out.row(&[ColumnValue::I64(count as i64)])?;
+                out.finish()
+            }
+            SelectStatement::Count {
+                schema,
+                table,
+                where_clause: None,
+            } => {
+                let count = match self.resolve_source(schema.as_deref(), table)? {
+                    Source::Cte(rows) => rows.len(),
+                    Source::View(view) => self.view_rows(&view)?.len(),
+                    Source::Fts(fts) => self.fts_rows(schema.as_deref(), &fts)?.len(),
+                    Source::Table | Source::ExplainedCte(_) => self
+                        .catalog
+                        .database_of(schema.as_deref(), table)?
+                        .count_rows(table)?,
+                };
+                out.begin(&["count(*)".to_string()])?;
+                out.row(&[ColumnValue::I64(count as i64)])?;
+                out.finish()
+            }
+            SelectStatement::Fields(command) if matches!(command.from, TableSource::Join(_)) => {
+                self.select_join(command, out)
+            }
+            SelectStatement::Fields(command) if self.is_correlated(command)? => {
+                self.select_correlated(command, out)
+            }
+            SelectStatement::Fields(command) => {
+                let command = self.run_subqueries(command)?;
+                match &command.from {
+                    TableSource::Table { schema, name } => {
+                        match self.resolve_source(schema.as_deref(), name)? {
+                            Source::Cte(rows) => {
+                                rows.select(&command, self.functions(), self.collations(), out)
+                            }
+                            Source::View(view) => self.view_rows(&view)?.select(
+                                &command,
+                                self.functions(),
+                                self.collations(),
+                                out,
+                            ),
+                            Source::Fts(fts) => self.fts_rows(schema.as_deref(), &fts)?.select(
+                                &fts.expand(&command),
+                                self.functions(),
+                                self.collations(),
+                                out,
+                            ),
+                            Source::Table | Source::ExplainedCte(_) => self
+                                .catalog
+                                .database_of(schema.as_deref(), name)?
+                                .select_fields(&command, out),
+                        }
+                    }
+                    TableSource::Subquery(subquery) => {
+                        let mut rowset = Rowset::default();
+                        self.select(subquery, &mut rowset)?;
+                        rowset.select(&command, self.functions(), self.collations(), out)
+                    }
+                    TableSource::Join(_) => unreachable!("joins are read by select_join"),
+                }
+            }
+            SelectStatement::Compound { op, left, right } => {
+                let mut rows = Rowset::default();
+                self.select(left, &mut rows)?;
+                let mut right_rows = Rowset::default();
+                self.select(right, &mut right_rows)?;
+                if rows.width() != right_rows.width() {
+                    bail!(
+                        "SELECTs to the left and right of {op} do not have the same number of \
+                         result columns"
+                    );
+                }
+                rows.append(right_rows);
+                if *op == CompoundOperator::Union {
+                    rows.dedup()?;
+                }
+                rows.write(out)
+            }
+            SelectStatement::With {
+                recursive,
+                ctes,
+                select,
+            } => self.with_ctes(*recursive, ctes, |connection| {
+                connection.select(select, out)
+            }),
+        }
+    }
+
+    /// Runs the queries of common table expressions and then `f`, for which their rows can be
+    /// read like tables. Each can read the ones before it, and with `recursive`, itself.
+    fn with_ctes<T>(
+        &mut self,
+        recursive: bool,
+        ctes: &[CommonTableExpression],
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let depth = self.ctes.len();
+        let run = |connection: &mut Self| {
+            for cte in ctes {
+                let rows = match recursive.then(|| cte.recursion()).transpose()?.flatten() {
+                    Some((op, initial, step)) => {
+                        connection.select_recursive(cte, op, initial, step)?
+                    }
+                    None => {
+                        let mut rows = Rowset::default();
+                        connection.select(&cte.select, &mut rows)?;
+                        rows.rename_columns(&cte.name, &cte.columns)?;
+                        rows
+                    }
+                };
+                connection.ctes.push((cte.name.clone(), Arc::new(rows)));
+            }
+            f(connection)
+        };
+        let result = run(self);
+        self.ctes.truncate(depth);
+        result
+    }
+
+    /// Runs a query whose results call window functions: first the query for the rows they
+    /// read, and then the results over those rows and the values of the window functions.
+    fn select_windowed(
+        &mut self,
+        command: &SelectFields,
+        windowed: &WindowedQuery,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<()> {
+        let mut rows = Rowset::default();
+        self.select(&SelectStatement::Fields(windowed.rows.clone()), &mut rows)?;
+        let rows = windowed.apply(&rows)?;
+        let select = SelectFields {
+            fields: windowed.results(&rows.column_names()),
+            from: TableSource::Table {
+                schema: None,
+                name: WINDOW_ROWS.to_string(),
+            },
+            alias: command.qualifier().map(str::to_string),
+            where_clause: None,
+        };
+        // The results read the rows like the ones of a common table expression
+        self.ctes.push((WINDOW_ROWS.to_string(), Arc::new(rows)));
+        let result = self.select(&SelectStatement::Fields(select), out);
+        self.ctes.pop();
+        result
+    }
+
+    /// Runs a query of joined tables. The rows of the join are read like the rows of a common
+    /// table expression, with the columns called `table.column`, and the parts of ON conditions
+    /// the join doesn't check in the WHERE clause.
+    fn select_join(&mut self, command: &SelectFields, out: &mut dyn OutputFormatter) -> Result<()> {
+        let mut conditions = vec![];
+        let (rows, columns) = self.join_rows(&command.from, None, &mut conditions)?;
+        if let Some(where_clause) = &command.where_clause {
+            conditions.push(columns.resolve(where_clause)?);
+        }
+        let where_clause = conditions.into_iter().reduce(|left, right| Expr::Binary {
+            op: sql::BinaryOperator::And,
+            left: Box::new(left),
+            right: Box::new(right),
+        });
+        let select = SelectFields {
+            fields: columns.resolve_results(&command.fields)?,
+            from: TableSource::Table {
+                schema: None,
+                name: JOIN_ROWS.to_string(),
+            },
+            alias: None,
+            where_clause,
+        };
+        self.ctes.push((JOIN_ROWS.to_string(), Arc::new(rows)));
+        let result = self.select(&SelectStatement::Fields(select), out);
+        self.ctes.pop();
+        result
+    }
+
+    /// Reads the rows of a table source of a join, called `alias` if it has one. The parts of
+    /// ON conditions that the join doesn't check are added to `conditions`.
+    fn join_rows(
+        &mut self,
+        source: &TableSource,
+        alias: Option<&str>,
+        conditions: &mut Vec<Expr>,
+    ) -> Result<(Rowset, JoinColumns)> {
+        let TableSource::Join(join) = source else {
+            let select = SelectFields {
+                fields: vec![ResultColumn::All],
+                from: source.clone(),
+                alias: alias.map(str::to_string),
+                where_clause: None,
+            };
+            let mut rows = Rowset::default();
+            self.select(&SelectStatement::Fields(select), &mut rows)?;
+            let qualifier = match source {
+                TableSource::Table { name, .. } => Some(alias.unwrap_or(name)),
+                _ => alias,
+            };
+            let columns = JoinColumns::new(qualifier, &rows.column_names());
+            rows.rename_columns(JOIN_ROWS, &columns.names())?;
+            return Ok((rows, columns));
+        };
+        let (left, left_columns) =
+            self.join_rows(&join.left, join.left_alias.as_deref(), conditions)?;
+        let (right, right_columns) =
+            self.join_rows(&join.right, join.right_alias.as_deref(), conditions)?;
+        let columns = left_columns.join(&right_columns);
+        let (keys, rest) = match &join.on {
+            Some(on) => join::split_condition(
+                &columns.resolve(on)?,
+                &left.column_names(),
+                &right.column_names(),
+            ),
+            None => (vec![], None),
+        };
+        conditions.extend(rest);
+        let mut rows = Rowset::default();
+        join::hash_join(
+            &left,
+            &right,
+            &keys,
+            self.functions(),
+            self.collations(),
+            self.join_memory_limit,
+            &mut rows,
+        )?;
+        Ok((rows, columns))
+    }
+
+    /// Runs a recursive common table expression, `initial UNION [ALL] step`. The step runs
+    /// with the rows the last run added as the expression's rows, starting with the ones of
+    /// `initial`, until it adds none.
+    fn select_recursive(
+        &mut self,
+        cte: &CommonTableExpression,
+        op: CompoundOperator,
+        initial: &SelectStatement,
+        step: &SelectStatement,
+    ) -> Result<Rowset> {
+        let mut rows = Rowset::default();
+        self.select(initial, &mut rows)?;
+        rows.rename_columns(&cte.name, &cte.columns)?;
+        if op == CompoundOperator::Union {
+            rows.dedup()?;
+        }
+        let columns = rows.column_names();
+        let mut added = rows.clone();
+        let mut steps = 0;
+        while !added.is_empty() {
+            if steps == self.recursion_limit {
+                return Err(Error::RecursionLimit {
+                    name: cte.name.clone(),
+                    limit: self.recursion_limit,
+                }
+                .into());
+            }
+            steps += 1;
+            self.ctes.push((cte.name.clone(), Arc::new(added)));
+            let mut step_rows = Rowset::default();
+            let result = self.select(step, &mut step_rows);
+            self.ctes.pop();
+            result?;
+            if step_rows.width() != rows.width() {
+                bail!(
+                    "SELECTs to the left and right of {op} do not have the same number of \
+                     result columns"
+                );
+            }
+            step_rows.rename_columns(&cte.name, &columns)?;
+            if op == CompoundOperator::Union {
+                step_rows.remove_seen(&rows)?;
+            }
+            rows.append(step_rows.clone());
+            added = step_rows;
+        }
+        Ok(rows)
+    }
+
+    /// The rows of the common table expression a query reads, if it names one. They take
+    /// precedence over tables and views, and can't be qualified with a schema.
+    fn find_cte(&self, schema: Option<&str>, name: &str) -> Option<Arc<Rowset>> {
+        if schema.is_some() {
+            return None;
+        }
+        self.ctes
+            .iter()
+            .rev()
+            .find(|(cte, _)| cte.eq_ignore_ascii_case(name))
+            .map(|(_, rows)| rows.clone())
+    }
+
+    /// The common table expression an explained query reads, if it names one.
+    fn find_explained_cte(&self, schema: Option<&str>, name: &str) -> Option<&ExplainedCte> {
+        if schema.is_some() {
+            return None;
+        }
+        self.explained_ctes
+            .iter()
+            .rev()
+            .find(|cte| cte.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Counts the rows of a table, view or FTS5 table that match the condition of `command`,
+    /// whose subqueries have run.
+    fn count(
+        &mut self,
+        schema: Option<&str>,
+        table: &str,
+        command: &SelectFields,
+    ) -> Result<usize> {
+        Ok(match self.resolve_source(schema, table)? {
+            Source::Cte(rows) => {
+                let mut count = RowCount::default();
+                rows.select(command, self.functions(), self.collations(), &mut count)?;
+                count.0
+            }
+            Source::View(view) => {
+                let mut rows = RowCount::default();
+                self.view_rows(&view)?.select(
+                    command,
+                    self.functions(),
+                    self.collations(),
+                    &mut rows,
+                )?;
+                rows.0
+            }
+            Source::Fts(fts) => {
+                let mut rows = RowCount::default();
+                self.fts_rows(schema, &fts)?.select(
+                    &fts.expand(command),
+                    self.functions(),
+                    self.collations(),
+                    &mut rows,
+                )?;
+                rows.0
+            }
+            Source::Table | Source::ExplainedCte(_) => {
+                self.catalog.database_of(schema, table)?.count(command)?
+            }
+        })
+    }
+
+    /// Resolves the name of a table in a FROM clause. Common table expressions take
+    /// precedence over views, which take precedence over FTS5 tables and tables.
+    fn resolve_source(&mut self, schema: Option<&str>, name: &str) -> Result<Source> {
+        if let Some(rows) = self.find_cte(schema, name) {
+            return Ok(Source::Cte(rows));
+        }
+        if let Some(cte) = self.find_explained_cte(schema, name) {
+            return Ok(Source::ExplainedCte(cte.clone()));
+        }
+        if let Some(view) = self.find_view(schema, name)? {
+            return Ok(Source::View(view));
+        }
+        if let Some(fts) = self.find_fts_table(schema, name)? {
+            return Ok(Source::Fts(fts));
+        }
+        Ok(Source::Table)
+    }
+
+    /// The view a query reads from, if it names a view rather than a table.
+    fn find_view(&mut self, schema: Option<&str>, name: &str) -> Result<Option<View>> {
+        let database = self.catalog.database_of(schema, name)?;
+        Ok(database.schema.find_view(name).cloned())
+    }
+
+    /// Runs the SELECT of a view, and returns its rows under the column names of the view.
+    fn view_rows(&mut self, view: &View) -> Result<Rowset> {
+        let mut rows = Rowset::default();
+        self.in_view(view, |connection| {
+            connection.select(&view.select, &mut rows)
+        })?;
+        rows.rename_columns(&view.name, &view.columns)?;
+        Ok(rows)
+    }
+
+    /// The FTS5 table a query reads from, if it names one. Other virtual tables can't be read.
+    fn find_fts_table(&mut self, schema: Option<&str>, name: &str) -> Result<Option<FtsTable>> {
+        let database = self.catalog.database_of(schema, name)?;
+        database
+            .schema
+            .find_virtual_table(name)
+            .map(FtsTable::new)
+            .transpose()
+    }
+
+    /// Reads the rows of an FTS5 table from its content table, which is in the same database.
+    fn fts_rows(&mut self, schema: Option<&str>, fts: &FtsTable) -> Result<Rowset> {
+        let mut rows = Rowset::default();
+        self.select(&fts.content_query(schema)?, &mut fts.documents(&mut rows))?;
+        Ok(rows)
+    }
+
+    /// Adds the steps of the SELECT of a view to `program`, and returns the rowset its rows go
+    /// into. A view runs like a subquery.
+    fn explain_view(&mut self, view: &View, program: &mut Program) -> Result<String> {
+        let rowset = program.open_rowset();
+        self.in_view(view, |connection| {
+            connection.explain(&view.select, program, Some(&rowset))
+        })?;
+        Ok(rowset)
+    }
+
+    /// Adds the steps that read an FTS5 table to `program`, and returns the rowset its rows go
+    /// into. It runs like a subquery of its content table.
+    fn explain_fts(
+        &mut self,
+        schema: Option<&str>,
+        fts: &FtsTable,
+        program: &mut Program,
+    ) -> Result<String> {
+        let rowset = program.open_rowset();
+        let content = fts.content_query(schema)?;
+        self.explain(&content, program, Some(&rowset))?;
+        Ok(rowset)
+    }
+
+    /// Runs `f` for the SELECT of a view, unless the view is already running, which means
+    /// that it's defined in terms of itself and would never finish.
+    fn in_view<T>(&mut self, view: &View, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.views.contains(&view.name) {
+            bail!("view {} is circularly defined", view.name);
+        }
+        self.views.push(view.name.clone());
+        // The view can't read the common table expressions of the query that reads it
+        let ctes = std::mem::take(&mut self.ctes);
+        let explained_ctes = std::mem::take(&mut self.explained_ctes);
+        let result = f(self);
+        self.ctes = ctes;
+        self.explained_ctes = explained_ctes;
+        self.views.pop();
+        result
+    }
+
+    /// Runs a page of at most `page_size` rows of a query, starting at `start`. Queries that scan
+    /// a single table continue the scan after the rowid of the last row of the previous page.
+    /// Other queries run again, skipping the rows of the previous pages. Returns where the next
+    /// page starts, unless there are no more rows.
+    fn select_page(
+        &mut self,
+        command: &SQLCommand,
+        sql: &str,
+        start: Option<PagePosition>,
+        page_size: usize,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<Option<PagePosition>> {
+        match (command, start) {
+            (
+                SQLCommand::Select(SelectStatement::Fields(select)),
+                None | Some(PagePosition::After(_)),
+            ) if self.scans_table(select)? => {
+                let select = self.run_subqueries(select)?;
+                let TableSource::Table { schema, name } = &select.from else {
+                    unreachable!("only tables are scanned")
+                };
+                let after = match start {
+                    Some(PagePosition::After(rowid)) => Some(rowid),
+                    _ => None,
+                };
+                let last = self
+                    .catalog
+                    .database_of(schema.as_deref(), name)?
+                    .select_page(&select, after, page_size, out)?;
+                Ok(last.map(PagePosition::After))
+            }
+            (command, None | Some(PagePosition::Skip(_))) => {
+                let skip = match start {
+                    Some(PagePosition::Skip(rows)) => rows,
+                    _ => 0,
+                };
+                let mut page = PageWriter {
+                    out,
+                    skip,
+                    remaining: page_size,
+                    more: false,
+                };
+                self.run(command, sql, &mut page)?;
+                Ok(page.more.then_some(PagePosition::Skip(skip + page_size)))
+            }
+            // The token of a query that scanned a table, which doesn't anymore
+            _ => Err(Error::InvalidContinuationToken.into()),
+        }
+    }
+
+    /// Whether a query reads its rows by scanning a table of a database in rowid order.
+    fn scans_table(&mut self, select: &SelectFields) -> Result<bool> {
+        let TableSource::Table { schema, name } = &select.from else {
+            return Ok(false);
+        };
+        Ok(
+            matches!(self.resolve_source(schema.as_deref(), name)?, Source::Table)
+                && window::plan(select)?.is_none()
+                && !self.is_correlated(select)?,
+        )
+    }
+
+    /// Adds the steps [`Connection::select`] takes to run a query to `program`, without running
+    /// it. The result rows are returned, or added to the rowset `into`.
+    fn explain(
+        &mut self,
+        select: &SelectStatement,
+        program: &mut Program,
+        into: Option<&str>,
+    ) -> Result<()> {
+        if let SelectStatement::Fields(command) = select {
+            if let Some(windowed) = window::plan(command)? {
+                return self.explain_windowed(command, &windowed, program, into);
+            }
+        }
+        match select {
+            SelectStatement::Count {
+                schema,
+                table,
+                where_clause: Some(where_clause),
+            } => {
+                let command = SelectFields {
+                    fields: vec![],
+                    from: TableSource::Table {
+                        schema: schema.clone(),
+                        name: table.clone(),
+                    },
+                    alias: None,
+                    where_clause: Some(where_clause.clone()),
+                };
+                let planned = self.explain_subqueries(&command, program)?;
+                let rowset = match self.resolve_source(schema.as_deref(), table)? {
+                    Source::ExplainedCte(cte) => cte.rowset,
+                    Source::View(view) => self.explain_view(&view, program)?,
+                    Source::Fts(fts) => self.explain_fts(schema.as_deref(), &fts, program)?,
+                    Source::Table | Source::Cte(_) => {
+                        return self
+                            .catalog
+                            .database_of(schema.as_deref(), table)?
+                            .explain_count(&planned, Some(where_clause), program, into);
+                    }
+                };
+                program.push("Scan", rowset);
+                program.push("Filter", where_clause.to_string());
+                program.push("Count", "rows that match");
+                program.result_row(&["count(*)".to_string()], into);
+            }
+            SelectStatement::Count {
+                schema,
+                table,
+                where_clause: None,
+            } => {
+                let rowset = match self.resolve_source(schema.as_deref(), table)? {
+                    Source::ExplainedCte(cte) => cte.rowset,
+                    Source::View(view) => self.explain_view(&view, program)?,
+                    Source::Fts(fts) => self.explain_fts(schema.as_deref(), &fts, program)?,
+                    Source::Table | Source::Cte(_) => {
+                        let database = self.catalog.database_of(schema.as_deref(), table)?;
+                        let row = database.schema.table(table)?;
+                        program.push(
+                            "OpenRead",
+                            format!("table {} (root page {})", row.name, row.rootpage),
+                        );
+                        program.push("Count", format!("cells on the leaf pages of {}", row.name));
+                        program.result_row(&["count(*)".to_string()], into);
+                        return Ok(());
+                    }
+                };
+                program.push("Count", format!("rows of {rowset}"));
+                program.result_row(&["count(*)".to_string()], into);
+            }
+            // Like the rows of a join are read by select_join, without knowing their columns
+            SelectStatement::Fields(command) if matches!(command.from, TableSource::Join(_)) => {
+                let rowset = self.explain_join(&command.from, None, program)?;
+                let select = SelectFields {
+                    from: TableSource::Table {
+                        schema: None,
+                        name: JOIN_ROWS.to_string(),
+                    },
+                    alias: None,
+                    ..command
+                        .map_expressions(&mut |expr| Ok::<_, Error>(join::qualified_names(expr)))?
+                };
+                self.explained_ctes.push(ExplainedCte {
+                    name: JOIN_ROWS.to_string(),
+                    rowset,
+                    columns: vec![],
+                });
+                let result = self.explain(&SelectStatement::Fields(select), program, into);
+                self.explained_ctes.pop();
+                result?;
+            }
+            SelectStatement::Fields(command) => {
+                let planned = self.explain_subqueries(command, program)?;
+                let where_clause = command.where_clause.as_ref();
+                let rowset = match &command.from {
+                    TableSource::Table { schema, name } => {
+                        match self.resolve_source(schema.as_deref(), name)? {
+                            Source::ExplainedCte(cte) => cte.rowset,
+                            Source::View(view) => self.explain_view(&view, program)?,
+                            Source::Fts(fts) => {
+                                self.explain_fts(schema.as_deref(), &fts, program)?
+                            }
+                            Source::Table | Source::Cte(_) => {
+                                return self
+                                    .catalog
+                                    .database_of(schema.as_deref(), name)?
+                                    .explain_select(&planned, where_clause, program, into);
+                            }
+                        }
+                    }
+                    TableSource::Subquery(subquery) => {
+                        let rowset = program.open_rowset();
+                        self.explain(subquery, program, Some(&rowset))?;
+                        rowset
+                    }
+                    TableSource::Join(_) => unreachable!("joins are explained on their own"),
+                };
+                program.push("Scan", rowset);
+                if let Some(where_clause) = where_clause {
+                    program.push("Filter", where_clause.to_string());
+                }
+                let columns = command
+                    .fields
+                    .iter()
+                    .map(|field| match field {
+                        ResultColumn::All => "*".to_string(),
+                        ResultColumn::Expr { alias, text, .. } => {
+                            alias.clone().unwrap_or_else(|| text.clone())
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                program.result_row(&columns, into);
+            }
+            SelectStatement::Compound { op, left, right } => {
+                let rows = program.open_rowset();
+                self.explain(left, program, Some(&rows))?;
+                let right_rows = program.open_rowset();
+                self.explain(right, program, Some(&right_rows))?;
+                program.push("Append", format!("{right_rows} to {rows}"));
+                if *op == CompoundOperator::Union {
+                    program.push("Distinct", format!("{rows}, sorting its rows"));
+                }
+                program.push("Scan", rows);
+                program.result_row(&["*".to_string()], into);
+            }
+            // The rows of each common table expression go into a rowset first
+            SelectStatement::With {
+                recursive,
+                ctes,
+                select,
+            } => {
+                let depth = self.explained_ctes.len();
+                let mut explain = |connection: &mut Self| {
+                    for cte in ctes {
+                        let rowset = program.open_rowset();
+                        let explained = ExplainedCte {
+                            name: cte.name.clone(),
+                            rowset: rowset.clone(),
+                            columns: cte.columns.clone(),
+                        };
+                        match recursive.then(|| cte.recursion()).transpose()?.flatten() {
+                            // The step reads the rows the last run added
+                            Some((op, initial, step)) => {
+                                connection.explain(initial, program, Some(&rowset))?;
+                                let added = program.open_rowset();
+                                connection.explained_ctes.push(ExplainedCte {
+                                    rowset: added.clone(),
+                                    ..explained.clone()
+                                });
+                                connection.explain(step, program, Some(&added))?;
+                                connection.explained_ctes.pop();
+                                if op == CompoundOperator::Union {
+                                    program.push(
+                                        "Distinct",
+                                        format!("{added}, without the rows of {rowset}"),
+                                    );
+                                }
+                                program.push("Append", format!("{added} to {rowset}"));
+                                program.push(
+                                    "Recurse",
+                                    format!("until {added} is empty, reading it as {}", cte.name),
+                                );
+                            }
+                            None => connection.explain(&cte.select, program, Some(&rowset))?,
+                        }
+                        connection.explained_ctes.push(explained);
+                    }
+                    connection.explain(select, program, into)
+                };
+                let result = explain(self);
+                self.explained_ctes.truncate(depth);
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the steps of a query whose results call window functions to `program`. The results
+    /// read the rows and the values of the window functions like the rows of a common table
+    /// expression.
+    fn explain_windowed(
+        &mut self,
+        command: &SelectFields,
+        windowed: &WindowedQuery,
+        program: &mut Program,
+        into: Option<&str>,
+    ) -> Result<()> {
+        let rowset = program.open_rowset();
+        self.explain(
+            &SelectStatement::Fields(windowed.rows.clone()),
+            program,
+            Some(&rowset),
+        )?;
+        for call in &windowed.calls {
+            program.push("Window", format!("{} of {rowset}", call.text));
+        }
+        let select = SelectFields {
+            fields: windowed.fields.clone(),
+            from: TableSource::Table {
+                schema: None,
+                name: WINDOW_ROWS.to_string(),
+            },
+            alias: command.qualifier().map(str::to_string),
+            where_clause: None,
+        };
+        self.explained_ctes.push(ExplainedCte {
+            name: WINDOW_ROWS.to_string(),
+            rowset,
+            columns: vec![],
+        });
+        let result = self.explain(&SelectStatement::Fields(select), program, into);
+        self.explained_ctes.pop();
+        result
+    }
+
+    /// Adds the steps [`Connection::join_rows`] takes to read the rows of a table source of a
+    /// join to `program`. Returns the rowset they go into.
+    fn explain_join(
+        &mut self,
+        source: &TableSource,
+        alias: Option<&str>,
+        program: &mut Program,
+    ) -> Result<String> {
+        let TableSource::Join(join) = source else {
+            let select = SelectFields {
+                fields: vec![ResultColumn::All],
+                from: source.clone(),
+                alias: alias.map(str::to_string),
+                where_clause: None,
+            };
+            let rowset = program.open_rowset();
+            self.explain(&SelectStatement::Fields(select), program, Some(&rowset))?;
+            return Ok(rowset);
+        };
+        let left = self.explain_join(&join.left, join.left_alias.as_deref(), program)?;
+        let right = self.explain_join(&join.right, join.right_alias.as_deref(), program)?;
+        let rowset = program.open_rowset();
+        match &join.on {
+            Some(on) => {
+                let step = if join::has_equality(on) {
+                    "HashJoin"
+                } else {
+                    "NestedLoop"
+                };
+                program.push(step, format!("{left} with {right} on {on} into {rowset}"));
+            }
+            None => program.push("NestedLoop", format!("{left} with {right} into {rowset}")),
+        }
+        Ok(rowset)
+    }
+
+    /// Adds the steps of the subqueries in the expressions of a query to `program`, in the
+    /// order [`Connection::run_subqueries`] runs them. Returns the query with placeholders for
+    /// their results, which plans like the query with the results would.
+    fn explain_subqueries(
+        &mut self,
+        select: &SelectFields,
+        program: &mut Program,
+    ) -> Result<SelectFields> {
+        // Correlated subqueries are planned with NULL for the columns of the row they run for
+        let outer = self.scope(select)?;
+        let mut correlated = false;
+        let select = self.bind_outer(select, &outer, &mut |_| {
+            correlated = true;
+            Ok(Expr::Literal(Literal::Null))
+        })?;
+        if correlated {
+            let rows = select.qualifier().unwrap_or("the subquery");
+            program.push(
+                "Correlated",
+                format!("subqueries run for each row of {rows}"),
+            );
+        }
+        self.map_subqueries(&select, &mut |connection, subquery| {
+            let rowset = program.open_rowset();
+            connection.explain(subquery, program, Some(&rowset))?;
+            Ok(vec![])
+        })
+    }
+
+    /// Replaces the subqueries in the expressions of a query with their results. They don't
+    /// depend on the rows of the query, so each of them only has to run once.
+    fn run_subqueries(&mut self, select: &SelectFields) -> Result<SelectFields> {
+        self.map_subqueries(select, &mut |connection, subquery| {
+            connection.subquery_values(subquery)
+        })
+    }
+
+    /// Replaces the subqueries in the expressions of a query with the values `f` returns for
+    /// them, in the order they appear in the query. The columns of the query are unqualified,
+    /// so that the query can be resolved against its table.
+    fn map_subqueries(
+        &mut self,
+        select: &SelectFields,
+        f: &mut SubqueryValues,
+    ) -> Result<SelectFields> {
+        let select = select.map_expressions(&mut |expr| self.resolve_subqueries(expr, f))?;
+        Ok(select.unqualify_columns()?)
+    }
+
+    fn resolve_subqueries(&mut self, expr: &Expr, f: &mut SubqueryValues) -> Result<Expr> {
+        Ok(match expr {
+            Expr::Subquery(subquery) => {
+                let values = f(self, subquery)?;
+                Expr::Literal(values.into_iter().next().unwrap_or(Literal::Null))
+            }
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => Expr::InValues {
+                expr: Box::new(self.resolve_subqueries(expr, f)?),
+                values: f(self, subquery)?,
+                negated: *negated,
+            },
+            // Any value will do, the rows only have to be counted
+            Expr::Exists(subquery) => {
+                let rows = SelectStatement::Fields(SelectFields {
+                    fields: vec![ResultColumn::Expr {
+                        expr: Expr::Literal(Literal::Integer(1)),
+                        alias: None,
+                        text: "1".to_string(),
+                    }],
+                    from: TableSource::Subquery(subquery.clone()),
+                    alias: None,
+                    where_clause: None,
+                });
+                let exists = !f(self, &rows)?.is_empty();
+                Expr::Literal(Literal::Integer(exists as i64))
+            }
+            expr => expr.map_children(&mut |expr| self.resolve_subqueries(expr, f))?,
+        })
+    }
+
+    /// Runs a subquery that is used as an expression.
+    fn subquery_values(&mut self, subquery: &SelectStatement) -> Result<Vec<Literal>> {
+        let mut rowset = Rowset::default();
+        self.select(subquery, &mut rowset)?;
+        rowset.values()
+    }
+
+    /// Whether subqueries in the expressions of a query read columns of the query's rows.
+    fn is_correlated(&mut self, select: &SelectFields) -> Result<bool> {
+        let outer = self.scope(select)?;
+        let mut correlated = false;
+        self.bind_outer(select, &outer, &mut |_| {
+            correlated = true;
+            Ok(Expr::Literal(Literal::Null))
+        })?;
+        Ok(correlated)
+    }
+
+    /// Runs a query whose subqueries read columns of its rows. The rows are read first, and
+    /// for each of them the subqueries run with the values of its columns.
+    fn select_correlated(
+        &mut self,
+        select: &SelectFields,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<()> {
+        let (rows, select) = match &select.from {
+            TableSource::Table { schema, name } => {
+                match self.resolve_source(schema.as_deref(), name)? {
+                    Source::Cte(rows) => (rows, select.clone()),
+                    Source::View(view) => (Arc::new(self.view_rows(&view)?), select.clone()),
+                    Source::Fts(fts) => {
+                        let rows = self.fts_rows(schema.as_deref(), &fts)?;
+                        (Arc::new(rows), fts.expand(select))
+                    }
+                    Source::Table | Source::ExplainedCte(_) => {
+                        let database = self.catalog.database_of(schema.as_deref(), name)?;
+                        let table = database.schema.table(name)?.clone();
+                        let rows = Rowset::from_table(table, database.scan(name)?)?;
+                        (Arc::new(rows), select.clone())
+                    }
+                }
+            }
+            TableSource::Subquery(subquery) => {
+                let mut rows = Rowset::default();
+                self.select(subquery, &mut rows)?;
+                (Arc::new(rows), select.clone())
+            }
+            TableSource::Join(_) => return self.select_join(select, out),
+        };
+        let outer = Scope {
+            qualifier: select.qualifier().map(str::to_string),
+            columns: Some(rows.column_names()),
+        };
+        // The names of the results don't depend on the values of subqueries
+        let names = self.map_subqueries(&select, &mut |_, _| Ok(vec![]))?;
+        let functions = self.functions().clone();
+        let collations = self.collations().clone();
+        rows.select_each(
+            &names,
+            &mut |table, record| {
+                let bound = self.bind_outer(&select, &outer, &mut |column| {
+                    let column = Expr::Column(column.to_string());
+                    let value = Value::from(&Expression::new(&column, table)?.evaluate(record)?);
+                    Ok(Expr::Literal(Literal::from(value)))
+                })?;
+                self.run_subqueries(&bound)
+            },
+            &functions,
+            &collations,
+            out,
+        )
+    }
+
+    /// The table or view a query reads, as far as it's known without running the query.
+    fn scope(&mut self, select: &SelectFields) -> Result<Scope> {
+        let columns = match &select.from {
+            TableSource::Table { schema, name } => {
+                match self.resolve_source(schema.as_deref(), name)? {
+                    Source::Cte(rows) => Some(rows.column_names()),
+                    Source::ExplainedCte(cte) => (!cte.columns.is_empty()).then_some(cte.columns),
+                    Source::View(view) => (!view.columns.is_empty()).then_some(view.columns),
+                    Source::Fts(_) | Source::Table => {
+                        let database = self.catalog.database_of(schema.as_deref(), name)?;
+                        database.schema.find_table(name).map(|table| {
+                            table
+                                .columns
+                                .iter()
+                                .map(|column| column.name.clone())
+                                .collect()
+                        })
+                    }
+                }
+            }
+            TableSource::Subquery(_) | TableSource::Join(_) => None,
+        };
+        Ok(Scope {
+            qualifier: select.qualifier().map(str::to_string),
+            columns,
+        })
+    }
+
+    /// Replaces the columns of `outer` that the subqueries of a query read with the results of
+    /// `value`, which makes the subqueries independent of the rows of `outer`.
+    fn bind_outer(
+        &mut self,
+        select: &SelectFields,
+        outer: &Scope,
+        value: &mut OuterValue,
+    ) -> Result<SelectFields> {
+        select.map_expressions(&mut |expr| self.bind_columns(expr, &mut vec![], outer, value))
+    }
+
+    /// Binds the columns of `outer` in an expression of a subquery, whose own tables and
+    /// the ones of the queries between it and `outer` are `inner`, innermost last. Their
+    /// columns take precedence over the ones of `outer`. In the outermost query, `inner` is
+    /// empty and only its subqueries are bound.
+    fn bind_columns(
+        &mut self,
+        expr: &Expr,
+        inner: &mut Vec<Scope>,
+        outer: &Scope,
+        value: &mut OuterValue,
+    ) -> Result<Expr> {
+        let is_subquery = !inner.is_empty();
+        Ok(match expr {
+            Expr::QualifiedColumn { table, column }
+                if is_subquery
+                    && !inner.iter().any(|scope| scope.is_named(table))
+                    && outer.is_named(table) =>
+            {
+                value(column)?
+            }
+            Expr::Column(column)
+                if is_subquery
+                    && !inner.iter().any(|scope| scope.has_column(column))
+                    && outer.has_column(column) =>
+            {
+                value(column)?
+            }
+            Expr::Subquery(subquery) => Expr::Subquery(Box::new(
+                self.bind_statement(subquery, inner, outer, value)?,
+            )),
+            Expr::Exists(subquery) => Expr::Exists(Box::new(
+                self.bind_statement(subquery, inner, outer, value)?,
+            )),
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => Expr::InSubquery {
+                expr: Box::new(self.bind_columns(expr, inner, outer, value)?),
+                subquery: Box::new(self.bind_statement(subquery, inner, outer, value)?),
+                negated: *negated,
+            },
+            expr => expr.map_children(&mut |expr| self.bind_columns(expr, inner, outer, value))?,
+        })
+    }
+
+    /// Binds the columns of `outer` in the expressions of a subquery, see
+    /// [`Connection::bind_columns`].
+    fn bind_statement(
+        &mut self,
+        select: &SelectStatement,
+        inner: &mut Vec<Scope>,
+        outer: &Scope,
+        value: &mut OuterValue,
+    ) -> Result<SelectStatement> {
+        let mut bind = |connection: &mut Self, select: &SelectFields| {
+            inner.push(connection.scope(select)?);
+            let bound = select
+                .map_expressions(&mut |expr| connection.bind_columns(expr, inner, outer, value));
+            inner.pop();
+            bound
+        };
+        Ok(match select {
+            SelectStatement::Fields(select) => SelectStatement::Fields(bind(self, select)?),
+            SelectStatement::Count {
+                schema,
+                table,
+                where_clause,
+            } => {
+                let bound = bind(
+                    self,
+                    &SelectFields {
+                        fields: vec![],
+                        from: TableSource::Table {
+                            schema: schema.clone(),
+                            name: table.clone(),
+                        },
+                        alias: None,
+                        where_clause: where_clause.clone(),
+                    },
+                )?;
+                SelectStatement::Count {
+                    schema: schema.clone(),
+                    table: table.clone(),
+                    where_clause: bound.where_clause,
+                }
+            }
+            SelectStatement::Compound { op, left, right } => SelectStatement::Compound {
+                op: *op,
+                left: Box::new(self.bind_statement(left, inner, outer, value)?),
+                right: Box::new(self.bind_statement(right, inner, outer, value)?),
+            },
+            SelectStatement::With {
+                recursive,
+                ctes,
+                select,
+            } => SelectStatement::With {
+                recursive: *recursive,
+                ctes: ctes
+                    .iter()
+                    .map(|cte| {
+                        Ok(CommonTableExpression {
+                            name: cte.name.clone(),
+                            columns: cte.columns.clone(),
+                            select: self.bind_statement(&cte.select, inner, outer, value)?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+                select: Box::new(self.bind_statement(select, inner, outer, value)?),
+            },
+        })
+    }
+}
+
+fn parse_statement(statement: &str) -> Result<SQLCommand, Error> {
+    sql::parse_statement(statement).map_err(Error::Syntax)
+}
+
+/// An identifier in double quotes, so that any name can be used in a statement.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Replaces the parameters of a statement with [`Parameter::Numbered`] ones, numbered like
+/// SQLite does. Returns the name of each parameter by number, or `None` if it has no name.
+fn number_parameters(command: &mut SQLCommand) -> Vec<Option<String>> {
+    let mut names: Vec<Option<String>> = vec![];
+    let mut number = |parameter: &Parameter| {
+        let index = match parameter {
+            Parameter::Anonymous => {
+                names.push(None);
+                names.len()
+            }
+            Parameter::Numbered(index) => {
+                if names.len() < *index {
+                    names.resize(*index, None);
+                }
+                *index
+            }
+            Parameter::Named(name) => match names.iter().position(|n| n.as_ref() == Some(name)) {
+                Some(position) => position + 1,
+                None => {
+                    names.push(Some(name.clone()));
+                    names.len()
+                }
+            },
+        };
+        Expr::Parameter(Parameter::Numbered(index))
+    };
+
+    *command = command.map_parameters(&mut number);
+    names
+}
+
+/// A parsed statement that can be run any number of times, with different parameter values.
+pub struct Statement<'conn> {
+    connection: &'conn mut Connection,
+    sql: String,
+    command: SQLCommand,
+    parameter_names: Vec<Option<String>>,
+    bindings: Vec<Literal>,
+}
+
+impl<'conn> Statement<'conn> {
+    /// The largest parameter number.
+    pub fn parameter_count(&self) -> usize {
+        self.parameter_names.len()
+    }
+
+    /// The number of a named parameter, including its prefix like `:name`.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.parameter_names
+            .iter()
+            .position(|n| n.as_deref() == Some(name))
+            .map(|position| position + 1)
+    }
+
+    /// Sets the value of the parameter with the given number, starting at 1.
+    pub fn bind(&mut self, index: usize, value: impl Into<Literal>) -> Result<(), Error> {
+        let binding = index
+            .checked_sub(1)
+            .and_then(|i| self.bindings.get_mut(i))
+            .ok_or(Error::InvalidParameterIndex(index))?;
+        *binding = value.into();
+        Ok(())
+    }
+
+    pub fn bind_name(&mut self, name: &str, value: impl Into<Literal>) -> Result<(), Error> {
+        let index = self
+            .parameter_index(name)
+            .ok_or_else(|| Error::NoSuchParameter(name.to_string()))?;
+        self.bind(index, value)
+    }
+
+    /// Resets all parameters to NULL.
+    pub fn clear_bindings(&mut self) {
+        self.bindings.fill(Literal::Null);
+    }
+
+    /// Runs the statement with the bound parameters and collects the resulting rows.
+    pub fn query(&mut self) -> Result<Rows> {
+        let command = self.bound_command();
+        let mut collector = RowCollector::default();
+        self.connection.run(&command, &self.sql, &mut collector)?;
+        Ok(Rows {
+            columns: collector.columns,
+            rows: collector.rows.into_iter(),
+        })
+    }
+
+    /// Runs a query with the bound parameters for its first `page_size` rows. The
+    /// [`Page::token`] of a page that isn't the last resumes the query after its rows with
+    /// [`Statement::resume_paged`], from any statement with the same SQL. Queries that scan a
+    /// table resume by seeking to the next rowid, so later pages don't read the rows of the
+    /// earlier ones again.
+    pub fn query_paged(&mut self, page_size: usize) -> Result<Page> {
+        self.query_page(None, page_size)
+    }
+
+    /// Runs the page of at most `page_size` rows after the page that returned `token`.
+    pub fn resume_paged(&mut self, token: &str, page_size: usize) -> Result<Page> {
+        let start = decode_token(&self.sql, token)?;
+        self.query_page(Some(start), page_size)
+    }
+
+    fn query_page(&mut self, start: Option<PagePosition>, page_size: usize) -> Result<Page> {
+        if page_size == 0 {
+            bail!("pages need at least one row");
+        }
+        let command = self.bound_command();
+        let mut collector = RowCollector::default();
+        let next =
+            self.connection
+                .select_page(&command, &self.sql, start, page_size, &mut collector)?;
+        Ok(Page {
+            rows: Rows {
+                columns: collector.columns,
+                rows: collector.rows.into_iter(),
+            },
+            token: next.map(|next| encode_token(&self.sql, next)),
+        })
+    }
+
+    /// Runs a statement that doesn't return rows, such as INSERT, with the bound parameters.
+    pub fn execute(&mut self) -> Result<()> {
+        self.query().map(|_| ())
+    }
+
+    /// The parsed statement with its parameters replaced by their values.
+    fn bound_command(&self) -> SQLCommand {
+        self.command
+            .map_parameters(&mut |parameter| match parameter {
+                Parameter::Numbered(index) => Expr::Literal(self.bindings[index - 1].clone()),
+                _ => unreachable!("parameters are numbered when preparing"),
+            })
+    }
+}
+
+/// Gathers the rows written by a query instead of printing them.
+struct RowCollector {
+    columns: Arc<[String]>,
+    rows: Vec<Row>,
+}
+
+impl Default for RowCollector {
+    fn default() -> Self {
+        Self {
+            columns: Arc::from(vec![]),
+            rows: vec![],
+        }
+    }
+}
+
+impl OutputFormatter for RowCollector {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        self.columns = columns.into();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        self.rows.push(Row {
+            columns: self.columns.clone(),
+            values: values.iter().map(Value::from).collect(),
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the rows of a page of a query, after skipping the ones of the previous pages.
+struct PageWriter<'a> {
+    out: &'a mut dyn OutputFormatter,
+    skip: usize,
+    remaining: usize,
+    /// Whether there are rows after the page
+    more: bool,
+}
+
+impl OutputFormatter for PageWriter<'_> {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        self.out.begin(columns)
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        if self.skip > 0 {
+            self.skip -= 1;
+        } else if self.remaining > 0 {
+            self.remaining -= 1;
+            self.out.row(values)?;
+        } else {
+            self.more = true;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.finish()
+    }
+}
+
+/// Where the next page of a query starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PagePosition {
+    /// After the row with this rowid of the table the query scans
+    After(i64),
+    /// After this many rows of a query that doesn't scan a table
+    Skip(usize),
+}
+
+/// The version of the format of continuation tokens.
+const TOKEN_VERSION: u8 = 1;
+
+/// Encodes where the next page of the query `sql` starts as a token of hex digits: the
+/// version, the kind of position, a hash of the SQL and the rowid or the number of rows.
+fn encode_token(sql: &str, position: PagePosition) -> String {
+    let (kind, value) = match position {
+        PagePosition::After(rowid) => (0, rowid as u64),
+        PagePosition::Skip(rows) => (1, rows as u64),
+    };
+    let mut bytes = vec![TOKEN_VERSION, kind];
+    bytes.extend(fingerprint(sql).to_be_bytes());
+    bytes.extend(value.to_be_bytes());
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Decodes a token of [`encode_token`], which has to be one of the query `sql`.
+fn decode_token(sql: &str, token: &str) -> Result<PagePosition, Error> {
+    let bytes = (0..token.len())
+        .step_by(2)
+        .map(|i| {
+            token
+                .get(i..i + 2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(Error::InvalidContinuationToken)?;
+    let [version, kind, rest @ ..] = bytes.as_slice() else {
+        return Err(Error::InvalidContinuationToken);
+    };
+    let (Ok(hash), Ok(value)) = (
+        <[u8; 8]>::try_from(rest.get(..8).unwrap_or_default()),
+        <[u8; 8]>::try_from(rest.get(8..).unwrap_or_default()),
+    ) else {
+        return Err(Error::InvalidContinuationToken);
+    };
+    if *version != TOKEN_VERSION || u64::from_be_bytes(hash) != fingerprint(sql) {
+        return Err(Error::InvalidContinuationToken);
+    }
+    let value = u64::from_be_bytes(value);
+    match kind {
+        0 => Ok(PagePosition::After(value as i64)),
+        1 => Ok(PagePosition::Skip(value as usize)),
+        _ => Err(Error::InvalidContinuationToken),
+    }
+}
+
+/// The 64-bit FNV-1a hash of a query, which unlike the hasher of the standard library is the
+/// same in every build, so tokens keep working across releases.
+fn fingerprint(sql: &str) -> u64 {
+    sql.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A page of the rows of a query, see [`Statement::query_paged`].
+pub struct Page {
+    rows: Rows,
+    token: Option<String>,
+}
+
+impl Page {
+    pub fn column_names(&self) -> &[String] {
+        self.rows.column_names()
+    }
+
+    /// The token that resumes the query after this page, or `None` for the last page.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
+
+impl Iterator for Page {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
+}
+
+/// The rows returned by a query.
+pub struct Rows {
+    columns: Arc<[String]>,
+    rows: std::vec::IntoIter<Row>,
+}
+
+impl Rows {
+    pub fn column_names(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+impl Iterator for Rows {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    columns: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    /// A row of a query that's run with another [`OutputFormatter`] than the one of
+    /// [`Statement::query`]. The columns are shared by all rows of the query.
+    pub fn new(columns: Arc<[String]>, values: Vec<Value>) -> Self {
+        Self { columns, values }
+    }
+
+    /// The value of a column, by position or by name, converted to `T`.
+    pub fn get<'a, T: FromValue<'a>>(&'a self, index: impl RowIndex) -> Result<T, Error> {
+        let index = index.index(&self.columns)?;
+        T::from_value(&self.values[index])
+    }
+
+    pub fn column_names(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+}
+
+/// Identifies a column of a [`Row`].
+pub trait RowIndex {
+    fn index(&self, columns: &[String]) -> Result<usize, Error>;
+}
+
+impl RowIndex for usize {
+    fn index(&self, columns: &[String]) -> Result<usize, Error> {
+        if *self < columns.len() {
+            Ok(*self)
+        } else {
+            Err(Error::InvalidColumnIndex(*self))
+        }
+    }
+}
+
+impl RowIndex for &str {
+    fn index(&self, columns: &[String]) -> Result<usize, Error> {
+        columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(self))
+            .ok_or_else(|| Error::NoSuchColumn(self.to_string()))
+    }
+}
+
+/// Conversion of a [`Value`] into a Rust type for [`Row::get`].
+pub trait FromValue<'a>: Sized {
+    fn from_value(value: &'a Value) -> Result<Self, Error>;
+}
+
+fn invalid_type(expected: &'static str, value: &Value) -> Error {
+    Error::InvalidType {
+        expected,
+        found: value.type_name(),
+    }
+}
+
+impl<'a> FromValue<'a> for i64 {
+    fn from_value(value: &'a Value) -> Result<Self, Error> {
+        match value {
+            Value::Integer(n) => Ok(*n),
+            value => Err(invalid_type("integer", value)),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for f64 {
+    fn from_value(value: &'a Value) -> Result<Self, Error> {
+        match value {
+            Value::Real(n) => Ok(*n),
+            Value::Integer(n) => Ok(*n as f64),
+            value => Err(invalid_type("real", value)),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for &'a str {
+    fn from_value(value: &'a Value) -> Result<Self, Error> {
+        match value {
+            Value::Text(text) => Ok(text),
+            value => Err(invalid_type("text", value)),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for String {
+    fn from_value(value: &'a Value) -> Result<Self, Error> {
+        <&str>::from_value(value).map(String::from)
+    }
+}
+
+impl<'a> FromValue<'a> for &'a [u8] {
+    fn from_value(value: &'a Value) -> Result<Self, Error> {
+        match value {
+            Value::Blob(blob) => Ok(blob),
+            value => Err(invalid_type("blob", value)),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for Vec<u8> {
+    fn from_value(value: &'a Value) -> Result<Self, Error> {
+        <&[u8]>::from_value(value).map(<[u8]>::to_vec)
+    }
+}
+
+impl<'a> FromValue<'a> for Value {
+    fn from_value(value: &'a Value) -> Result<Self, Error> {
+        Ok(value.clone())
+    }
+}
+
+/// NULL becomes `None`, other values are converted to `T`.
+impl<'a, T: FromValue<'a>> FromValue<'a> for Option<T> {
+    fn from_value(value: &'a Value) -> Result<Self, Error> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::affinity::Affinity;
+    use crate::test_support::TempDatabase;
+
+    fn row() -> Row {
+        Row {
+            columns: Arc::from(["id", "name", "score", "note"].map(String::from).to_vec()),
+            values: vec![
+                Value::Integer(1),
+                Value::Text("apple".to_string()),
+                Value::Real(2.5),
+                Value::Null,
+            ],
+        }
+    }
+
+    #[test]
+    fn get_by_index_and_name() {
+        let row = row();
+
+        assert_eq!(row.get::<i64>(0), Ok(1));
+        assert_eq!(row.get::<&str>("name"), Ok("apple"));
+        assert_eq!(row.get::<String>("NAME"), Ok("apple".to_string()));
+        assert_eq!(row.get::<f64>("score"), Ok(2.5));
+        assert_eq!(row.get::<f64>("id"), Ok(1.0));
+        assert_eq!(row.get::<Option<i64>>("note"), Ok(None));
+        assert_eq!(row.get::<Option<i64>>(0), Ok(Some(1)));
+    }
+
+    #[test]
+    fn query_sample_database() {
+        let mut conn = Connection::open("sample.db").unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id, name FROM apples WHERE color = 'Yellow'")
+            .unwrap();
+        let rows = stmt.query().unwrap();
+        assert_eq!(rows.column_names(), ["id", "name"]);
+
+        let rows = rows
+            .map(|row| (row.get(0).unwrap(), row.get("name").unwrap()))
+            .collect::<Vec<(i64, String)>>();
+        assert_eq!(rows, vec![(4, "Golden Delicious".to_string())]);
+    }
+
+    #[test]
+    fn paged_queries() {
+        let database_file = TempDatabase::sample("paged_queries");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        let rows = (5..=300)
+            .map(|id| {
+                let color = if id % 3 == 0 { "Red" } else { "Green" };
+                format!("({id}, 'apple {id} {}', '{color}')", "x".repeat(100))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        conn.execute(
+            &format!("INSERT INTO apples VALUES {rows}"),
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        // Reads every page of a query, checking that each page but the last is full
+        let pages = |conn: &mut Connection, sql: &str, page_size: usize| {
+            let mut stmt = conn.prepare(sql).unwrap();
+            let mut page = stmt.query_paged(page_size).unwrap();
+            let mut rows = vec![];
+            loop {
+                let token = page.token().map(str::to_string);
+                let page_rows = page
+                    .by_ref()
+                    .map(|row| row.values().to_vec())
+                    .collect::<Vec<_>>();
+                let Some(token) = token else {
+                    rows.extend(page_rows);
+                    return rows;
+                };
+                assert_eq!(page_rows.len(), page_size);
+                rows.extend(page_rows);
+                page = stmt.resume_paged(&token, page_size).unwrap();
+            }
+        };
+        let all = |conn: &mut Connection, sql: &str| {
+            conn.prepare(sql)
+                .unwrap()
+                .query()
+                .unwrap()
+                .map(|row| row.values().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        // Pages of a table scan, and of a subquery, which runs again for each page
+        for sql in [
+            "SELECT id, name FROM apples WHERE color = 'Red'",
+            "SELECT * FROM apples",
+            "SELECT name FROM (SELECT name, color FROM apples) WHERE color = 'Green'",
+        ] {
+            let rows = all(&mut conn, sql);
+            assert!(rows.len() > 90, "{sql}");
+            assert_eq!(pages(&mut conn, sql, 7), rows, "{sql}");
+            assert_eq!(pages(&mut conn, sql, rows.len()), rows, "{sql}");
+        }
+        let mut stmt = conn.prepare("SELECT id FROM apples WHERE id = 1").unwrap();
+        let page = stmt.query_paged(10).unwrap();
+        assert_eq!(page.column_names(), ["id"]);
+        assert_eq!(page.token(), None);
+
+        // The next page starts after the last row of the table, even if rows were added
+        let sql = "SELECT id FROM apples WHERE id > 295";
+        let token = conn
+            .prepare(sql)
+            .unwrap()
+            .query_paged(3)
+            .unwrap()
+            .token()
+            .unwrap()
+            .to_string();
+        assert_eq!(decode_token(sql, &token).unwrap(), PagePosition::After(298));
+        conn.execute(
+            "INSERT INTO apples VALUES (400, 'Jazz', 'Red')",
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        let mut stmt = conn.prepare(sql).unwrap();
+        let page = stmt.resume_paged(&token, 3).unwrap();
+        assert_eq!(page.token(), None);
+        assert_eq!(
+            page.map(|row| row.get::<i64>(0).unwrap())
+                .collect::<Vec<_>>(),
+            [299, 300, 400]
+        );
+
+        // Tokens only resume the query they're of
+        let mut stmt = conn.prepare("SELECT id FROM apples").unwrap();
+        for token in [token.as_str(), "", "01", "zz", &token[..token.len() - 2]] {
+            assert_eq!(
+                stmt.resume_paged(token, 5).err().unwrap().to_string(),
+                "invalid continuation token"
+            );
+        }
+        assert_eq!(
+            stmt.query_paged(0).err().unwrap().to_string(),
+            "pages need at least one row"
+        );
+    }
+
+    #[test]
+    fn insert_rows() {
+        let database_file = TempDatabase::sample("insert_rows");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        let mut stmt = conn
+            .prepare("INSERT INTO apples (name, color) VALUES ('Jazz', 'Red'), (?, ?)")
+            .unwrap();
+        stmt.bind(1, "Gala").unwrap();
+        stmt.bind(2, None::<&str>).unwrap();
+        stmt.execute().unwrap();
+
+        // Reopening reads the rows back from the file
+        let mut conn = Connection::open(path).unwrap();
+        let mut stmt = conn
+            .prepare("INSERT INTO apples VALUES (10, 'Envy', 'Red')")
+            .unwrap();
+        stmt.execute().unwrap();
+        assert_eq!(
+            stmt.execute().unwrap_err().to_string(),
+            "UNIQUE constraint failed: apples.id"
+        );
+
+        let mut stmt = conn
+            .prepare("SELECT id, name, color FROM apples WHERE id > 4")
+            .unwrap();
+        let rows = stmt
+            .query()
+            .unwrap()
+            .map(|row| {
+                (
+                    row.get(0).unwrap(),
+                    row.get(1).unwrap(),
+                    row.get(2).unwrap(),
+                )
+            })
+            .collect::<Vec<(i64, String, Option<String>)>>();
+        assert_eq!(
+            rows,
+            vec![
+                (5, "Jazz".to_string(), Some("Red".to_string())),
+                (6, "Gala".to_string(), None),
+                (10, "Envy".to_string(), Some("Red".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn execute_scripts() {
+        let database_file = TempDatabase::sample("execute_scripts");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        let mut out = RowCollector::default();
+        conn.execute_script(
+            "-- notes\nCREATE TABLE notes (id integer primary key, body text);\n\
+             INSERT INTO notes (body) VALUES ('a;b');\n\
+             INSERT INTO notes (body) VALUES ('c'); -- the last one",
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            conn.database().schema.table("notes").unwrap().columns.len(),
+            2
+        );
+
+        let error = conn
+            .execute_script(
+                "INSERT INTO notes (body) VALUES ('d');\n\
+                 SELECT missing FROM notes;\n\
+                 INSERT INTO notes (body) VALUES ('e');",
+                &mut out,
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Error in statement 2: no such column: missing"
+        );
+        assert_eq!(
+            conn.execute_script("INSERT INTO notes (body) VALUES ('f'); SELEKT", &mut out)
+                .unwrap_err()
+                .to_string(),
+            "syntax error in statement 2 near 'SELEKT' at offset 0, expected PRAGMA, CREATE, \
+             SELECT, INSERT, VACUUM, ANALYZE, ATTACH, DETACH or EXPLAIN"
+        );
+
+        let mut stmt = conn.prepare("SELECT body FROM notes").unwrap();
+        let bodies = stmt
+            .query()
+            .unwrap()
+            .map(|row| row.get(0).unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(bodies, ["a;b", "c", "d"]);
+    }
+
+    #[test]
+    fn explain_queries() {
+        let database_file = TempDatabase::sample("explain_queries");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        let explain = |conn: &mut Connection, query: &str| {
+            conn.prepare(&format!("EXPLAIN {query}"))
+                .unwrap()
+                .query()
+                .unwrap()
+                .map(|row| {
+                    format!(
+                        "{} {}",
+                        row.get::<String>(1).unwrap(),
+                        row.get::<String>(2).unwrap()
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            explain(
+                &mut conn,
+                "SELECT name FROM apples WHERE color = 'Yellow' OR id > 3"
+            ),
+            [
+                "OpenRead table apples (root page 2)",
+                "Scan apples",
+                "Column [1, 2] (name, color)",
+                "Filter color = 'Yellow' OR id > 3",
+                "ResultRow name",
+                "Halt ",
+            ]
+        );
+        assert_eq!(
+            explain(&mut conn, "SELECT x FROM (SELECT name AS x FROM apples) WHERE x IN (SELECT name FROM oranges)"),
+            [
+                "OpenEphemeral rowset 1",
+                "OpenRead table oranges (root page 4)",
+                "Scan oranges",
+                "Column [1] (name)",
+                "ResultRow name into rowset 1",
+                "OpenEphemeral rowset 2",
+                "OpenRead table apples (root page 2)",
+                "Scan apples",
+                "Column [1] (name)",
+                "ResultRow x into rowset 2",
+                "Scan rowset 2",
+                "Filter x IN (SELECT ...)",
+                "ResultRow x",
+                "Halt ",
+            ]
+        );
+        assert_eq!(
+            explain(
+                &mut conn,
+                "SELECT COUNT(*) FROM apples WHERE color = 'Yellow'"
+            ),
+            [
+                "OpenRead table apples (root page 2)",
+                "Scan apples",
+                "Column [2] (color)",
+                "Filter color = 'Yellow'",
+                "Count rows that match",
+                "ResultRow count(*)",
+                "Halt ",
+            ]
+        );
+
+        let mut out = RowCollector::default();
+        conn.execute("CREATE INDEX idx_color ON apples (color)", &mut out)
+            .unwrap();
+        assert_eq!(
+            explain(&mut conn, "SELECT name FROM apples WHERE color = 'Yellow'"),
+            [
+                "OpenRead index idx_color (root page 5)",
+                "SeekIndex color = 'Yellow'",
+                "IdxRowid collect the rowids, sorted and without duplicates",
+                "OpenRead table apples (root page 2)",
+                "SeekRowid each collected rowid in apples",
+                "Column [1] (name)",
+                "ResultRow name",
+                "Halt ",
+            ]
+        );
+        assert_eq!(
+            explain(
+                &mut conn,
+                "SELECT id, color FROM apples WHERE color = 'Yellow'"
+            ),
+            [
+                "OpenRead index idx_color (root page 5)",
+                "SeekIndex color = 'Yellow'",
+                "IdxColumn [1, 0] (rowid, color)",
+                "ResultRow id, color",
+                "Halt ",
+            ]
+        );
+        assert_eq!(
+            explain(
+                &mut conn,
+                "SELECT COUNT(*) FROM apples WHERE color = 'Yellow'"
+            ),
+            [
+                "OpenRead index idx_color (root page 5)",
+                "SeekIndex color = 'Yellow'",
+                "Count entries of idx_color that match",
+                "ResultRow count(*)",
+                "Halt ",
+            ]
+        );
+    }
+
+    #[test]
+    fn count_matching_rows() {
+        let database_file = TempDatabase::sample("count_matching_rows");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        let mut out = RowCollector::default();
+        for sql in [
+            "INSERT INTO apples (name, color) VALUES ('Gala', 'Red'), ('Jazz', NULL)",
+            "CREATE INDEX idx_color ON apples (color)",
+            "CREATE VIEW reds AS SELECT name FROM apples WHERE color = 'Red'",
+        ] {
+            conn.execute(sql, &mut out).unwrap();
+        }
+        let mut count = |sql: &str| -> i64 {
+            conn.prepare(sql)
+                .unwrap()
+                .query()
+                .unwrap()
+                .map(|row| row.get(0).unwrap())
+                .next()
+                .unwrap()
+        };
+        assert_eq!(count("SELECT COUNT(*) FROM apples"), 6);
+        assert_eq!(count("SELECT COUNT(*) FROM apples WHERE color = 'Red'"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM apples WHERE color = 'Blue'"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM apples WHERE color IS NULL"), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM apples WHERE id > 2"), 4);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM apples WHERE id IN (SELECT id FROM apples WHERE id < 3)"),
+            2
+        );
+        assert_eq!(count("SELECT COUNT(*) FROM reds WHERE name = 'Gala'"), 1);
+
+        // Every leaf of a table with interior pages is counted
+        let rows = (0..3000)
+            .map(|i| format!("('apple {i}', 'Green')"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        conn.execute(
+            &format!("INSERT INTO apples (name, color) VALUES {rows}"),
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        let mut count = |sql: &str| -> i64 {
+            conn.prepare(sql)
+                .unwrap()
+                .query()
+                .unwrap()
+                .map(|row| row.get(0).unwrap())
+                .next()
+                .unwrap()
+        };
+        assert_eq!(count("SELECT COUNT(*) FROM apples"), 3006);
+        assert_eq!(
+            count("SELECT id FROM apples WHERE id = (SELECT COUNT(*) FROM apples)"),
+            3006
+        );
+    }
+
+    #[test]
+    fn user_functions() {
+        let mut conn = Connection::from_bytes(std::fs::read("sample.db").unwrap()).unwrap();
+        conn.create_scalar_function("double", 1..=1, |args| {
+            Ok(Value::Integer(i64::from_value(&args[0])? * 2))
+        });
+        conn.create_scalar_function("fail", 0..=1, |_| {
+            Err(Error::UserFunction("no luck".to_string()))
+        });
+        conn.execute(
+            "CREATE VIEW ids AS SELECT id FROM apples",
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        let mut query = |sql: &str| -> Result<Vec<i64>> {
+            conn.prepare(sql)?
+                .query()?
+                .map(|row| Ok(row.get(0)?))
+                .collect()
+        };
+        assert_eq!(
+            query("SELECT DOUBLE(id) FROM apples WHERE double(id) > 4").unwrap(),
+            [6, 8]
+        );
+        assert_eq!(
+            query("SELECT double(id) FROM ids WHERE id = 1").unwrap(),
+            [2]
+        );
+        for (sql, error) in [
+            (
+                "SELECT double() FROM apples",
+                "wrong number of arguments to function double()",
+            ),
+            (
+                "SELECT double(name) FROM apples",
+                "invalid column type: expected integer, found text",
+            ),
+            ("SELECT id FROM apples WHERE fail()", "no luck"),
+        ] {
+            assert_eq!(query(sql).unwrap_err().to_string(), error, "{sql}");
+        }
+    }
+
+    #[test]
+    fn user_collations() {
+        let database_file = TempDatabase::sample("user_collations");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        conn.create_collation("de_DE", |a, b| a.to_lowercase().cmp(&b.to_lowercase()));
+        for sql in [
+            "CREATE TABLE words (word TEXT COLLATE de_DE)",
+            "INSERT INTO words VALUES ('Äpfel'), ('Birne')",
+        ] {
+            conn.execute(sql, &mut RowCollector::default()).unwrap();
+        }
+        let query = |conn: &mut Connection, sql: &str| -> Result<Vec<String>> {
+            conn.prepare(sql)?
+                .query()?
+                .map(|row| Ok(row.get(0)?))
+                .collect()
+        };
+        assert_eq!(
+            query(&mut conn, "SELECT word FROM words WHERE word = 'äPFEL'").unwrap(),
+            ["Äpfel"]
+        );
+
+        // Indexes are ordered and searched with the collation too
+        conn.execute(
+            "CREATE UNIQUE INDEX words_word ON words (word)",
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            query(&mut conn, "SELECT word FROM words WHERE word = 'birne'").unwrap(),
+            ["Birne"]
+        );
+        assert_eq!(
+            conn.execute(
+                "INSERT INTO words VALUES ('ÄPFEL')",
+                &mut RowCollector::default()
+            )
+            .unwrap_err()
+            .to_string(),
+            "UNIQUE constraint failed: words.word"
+        );
+
+        // Without the collation, the column can't be compared
+        let mut conn = Connection::open(path).unwrap();
+        assert_eq!(
+            conn.execute(
+                "SELECT word FROM words WHERE word = 'Birne'",
+                &mut RowCollector::default()
+            )
+            .unwrap_err()
+            .to_string(),
+            "no such collation sequence: de_DE"
+        );
+    }
+
+    #[test]
+    fn correlated_subqueries() {
+        let database_file = TempDatabase::sample("correlated_subqueries");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        conn.execute_script(
+            "CREATE TABLE orders (id INTEGER PRIMARY KEY, apple_id INTEGER, quantity INTEGER);
+             INSERT INTO orders (apple_id, quantity) VALUES (1, 3), (1, 5), (3, 2);",
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        let query = |conn: &mut Connection, sql: &str| -> Result<Vec<Vec<Value>>> {
+            conn.prepare(sql)?
+                .query()?
+                .map(|row| Ok(row.values().to_vec()))
+                .collect()
+        };
+        let names = |conn: &mut Connection, sql: &str| -> Vec<Value> {
+            query(conn, sql).unwrap().into_iter().flatten().collect()
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+
+        assert_eq!(
+            names(
+                &mut conn,
+                "SELECT name FROM apples a WHERE EXISTS (SELECT 1 FROM orders o WHERE o.apple_id = a.id)"
+            ),
+            [text("Granny Smith"), text("Honeycrisp")]
+        );
+        assert_eq!(
+            names(
+                &mut conn,
+                "SELECT name FROM apples WHERE NOT EXISTS (SELECT * FROM orders WHERE apple_id = apples.id)"
+            ),
+            [text("Fuji"), text("Golden Delicious")]
+        );
+        // Columns the subquery's table doesn't have are the ones of the outer query
+        assert_eq!(
+            names(
+                &mut conn,
+                "SELECT id FROM apples WHERE EXISTS (SELECT 1 FROM orders WHERE quantity = 3 AND name = 'Granny Smith')"
+            ),
+            [Value::Integer(1)]
+        );
+        assert_eq!(
+            query(
+                &mut conn,
+                "SELECT name, (SELECT COUNT(*) FROM orders WHERE apple_id = a.id) FROM apples AS a WHERE id < 4"
+            )
+            .unwrap(),
+            [
+                vec![text("Granny Smith"), Value::Integer(2)],
+                vec![text("Fuji"), Value::Integer(0)],
+                vec![text("Honeycrisp"), Value::Integer(1)],
+            ]
+        );
+        assert_eq!(
+            names(
+                &mut conn,
+                "SELECT COUNT(*) FROM apples WHERE id IN (SELECT apple_id FROM orders WHERE quantity > 1 AND apple_id = apples.id)"
+            ),
+            [Value::Integer(2)]
+        );
+        // The subqueries of a subquery can read the columns of the queries it's in
+        assert_eq!(
+            names(
+                &mut conn,
+                "SELECT name FROM (SELECT id AS apple, name FROM apples) AS a WHERE EXISTS \
+                 (SELECT 1 FROM orders o WHERE o.apple_id = a.apple AND EXISTS \
+                 (SELECT 1 FROM orders WHERE quantity > o.quantity AND apple_id = a.apple))"
+            ),
+            [text("Granny Smith")]
+        );
+        let plan = query(
+            &mut conn,
+            "EXPLAIN SELECT name FROM apples a WHERE EXISTS (SELECT 1 FROM orders o WHERE o.apple_id = a.id)",
+        )
+        .unwrap();
+        assert_eq!(
+            plan[0][1..],
+            [text("Correlated"), text("subqueries run for each row of a")]
+        );
+        assert_eq!(
+            query(&mut conn, "SELECT name FROM apples a WHERE EXISTS (SELECT 1 FROM orders o WHERE o.apple_id = b.id)")
+                .unwrap_err()
+                .to_string(),
+            "no such column: b.id"
+        );
+    }
+
+    #[test]
+    fn common_table_expressions() {
+        let mut conn = Connection::open("sample.db").unwrap();
+        let query = |conn: &mut Connection, sql: &str| -> Result<Vec<Value>> {
+            let rows = conn
+                .prepare(sql)?
+                .query()?
+                .map(|row| Ok(row.values().to_vec()))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(rows.into_iter().flatten().collect())
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+
+        assert_eq!(
+            query(
+                &mut conn,
+                "WITH red AS (SELECT name, id FROM apples WHERE id IN (2, 3)) \
+                 SELECT name FROM red WHERE id > 2"
+            )
+            .unwrap(),
+            [text("Honeycrisp")]
+        );
+        // Later ones can read earlier ones, and the columns can be renamed
+        assert_eq!(
+            query(
+                &mut conn,
+                "WITH red(apple, n) AS (SELECT name, id FROM apples WHERE id IN (2, 3)), \
+                 first AS (SELECT apple FROM red WHERE n = (SELECT COUNT(*) FROM red)) \
+                 SELECT * FROM first"
+            )
+            .unwrap(),
+            [text("Fuji")]
+        );
+        // They take precedence over tables with the same name
+        assert_eq!(
+            query(
+                &mut conn,
+                "WITH apples AS (SELECT name FROM apples WHERE id = 4) SELECT COUNT(*) FROM apples"
+            )
+            .unwrap(),
+            [Value::Integer(1)]
+        );
+        assert_eq!(
+            query(
+                &mut conn,
+                "WITH t AS (SELECT id FROM apples) SELECT COUNT(*) FROM t WHERE id IN (1, 2)"
+            )
+            .unwrap(),
+            [Value::Integer(2)]
+        );
+        // They're only visible in the query that defines them
+        assert_eq!(
+            query(&mut conn, "WITH t AS (SELECT id FROM apples) SELECT * FROM apples WHERE id IN (SELECT id FROM t) AND id = 3")
+                .unwrap()[1],
+            text("Honeycrisp")
+        );
+        assert_eq!(
+            query(&mut conn, "SELECT * FROM t").unwrap_err().to_string(),
+            "no such table: t"
+        );
+        assert_eq!(
+            query(
+                &mut conn,
+                "WITH t(a, b) AS (SELECT id FROM apples) SELECT * FROM t"
+            )
+            .unwrap_err()
+            .to_string(),
+            "expected 2 columns for 't' but got 1"
+        );
+
+        let plan = query(
+            &mut conn,
+            "EXPLAIN WITH t AS (SELECT id FROM apples) SELECT id FROM t WHERE id > 1",
+        )
+        .unwrap();
+        assert_eq!(plan[1..3], [text("OpenEphemeral"), text("rowset 1")]);
+        assert_eq!(plan[13..15], [text("Scan"), text("rowset 1")]);
+    }
+
+    #[test]
+    fn recursive_common_table_expressions() {
+        let database_file = TempDatabase::sample("recursive_common_table_expressions");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        conn.execute_script(
+            "CREATE TABLE employees (id INTEGER PRIMARY KEY, name TEXT, manager_id INTEGER);
+             INSERT INTO employees (name, manager_id) VALUES
+                 ('Ada', NULL), ('Brian', 1), ('Cleo', 1), ('Dana', 2), ('Eli', 4), ('Fay', NULL);",
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        let query = |conn: &mut Connection, sql: &str| -> Result<Vec<Value>> {
+            let rows = conn
+                .prepare(sql)?
+                .query()?
+                .map(|row| Ok(row.values().to_vec()))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(rows.into_iter().flatten().collect())
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+
+        // Everyone who reports to Brian, directly or not, level by level
+        assert_eq!(
+            query(
+                &mut conn,
+                "WITH RECURSIVE reports(id, name) AS ( \
+                     SELECT id, name FROM employees WHERE name = 'Brian' \
+                     UNION ALL \
+                     SELECT id, name FROM employees WHERE manager_id IN (SELECT id FROM reports) \
+                 ) SELECT name FROM reports"
+            )
+            .unwrap(),
+            [text("Brian"), text("Dana"), text("Eli")]
+        );
+        // The chain of managers above Eli, with the step reading the table of the previous
+        // step's rows
+        assert_eq!(
+            query(
+                &mut conn,
+                "WITH RECURSIVE chain AS ( \
+                     SELECT id, manager_id FROM employees WHERE name = 'Eli' \
+                     UNION ALL \
+                     SELECT e.id, e.manager_id FROM employees e \
+                     WHERE EXISTS (SELECT 1 FROM chain WHERE chain.manager_id = e.id) \
+                 ) SELECT COUNT(*) FROM chain"
+            )
+            .unwrap(),
+            [Value::Integer(4)]
+        );
+        // With UNION, steps that only find rows it has stop the recursion
+        let cycle = "WITH RECURSIVE cycle(id) AS ( \
+                         SELECT id FROM employees WHERE id = 1 \
+                         UNION {} SELECT id FROM employees WHERE id IN (SELECT id FROM cycle) \
+                     ) SELECT * FROM cycle";
+        assert_eq!(
+            query(&mut conn, &cycle.replace("{}", "")).unwrap(),
+            [Value::Integer(1)]
+        );
+        conn.set_recursion_limit(10);
+        assert_eq!(
+            query(&mut conn, &cycle.replace("{}", "ALL"))
+                .unwrap_err()
+                .to_string(),
+            "recursive query cycle didn't finish in 10 steps"
+        );
+        assert_eq!(
+            query(
+                &mut conn,
+                "WITH RECURSIVE t AS (SELECT * FROM t) SELECT * FROM t"
+            )
+            .unwrap_err()
+            .to_string(),
+            "circular reference: t"
+        );
+
+        let plan = query(&mut conn, &format!("EXPLAIN {}", cycle.replace("{}", ""))).unwrap();
+        assert!(
+            plan.contains(&text("until rowset 2 is empty, reading it as cycle")),
+            "{plan:?}"
+        );
+    }
+
+    #[test]
+    fn window_functions() {
+        let database_file = TempDatabase::sample("window_functions");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        conn.execute_script(
+            "CREATE TABLE sales (id INTEGER PRIMARY KEY, region TEXT, amount INTEGER);
+             INSERT INTO sales (region, amount) VALUES
+                 ('north', 10), ('south', 5), ('north', 30), ('north', 10), ('south', NULL);",
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        let query = |conn: &mut Connection, sql: &str| -> Result<Vec<Vec<Value>>> {
+            conn.prepare(sql)?
+                .query()?
+                .map(|row| Ok(row.values().to_vec()))
+                .collect()
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+        let int = Value::Integer;
+
+        // Rows keep the order of the query, whatever the order of their windows
+        assert_eq!(
+            query(
+                &mut conn,
+                "SELECT id, ROW_NUMBER() OVER (PARTITION BY region ORDER BY amount DESC), \
+                 RANK() OVER (ORDER BY amount) FROM sales WHERE id < 5"
+            )
+            .unwrap(),
+            [
+                vec![int(1), int(2), int(2)],
+                vec![int(2), int(1), int(1)],
+                vec![int(3), int(1), int(4)],
+                vec![int(4), int(3), int(2)],
+            ]
+        );
+        assert_eq!(
+            query(
+                &mut conn,
+                "SELECT s.region, COUNT(*) OVER (PARTITION BY s.region), \
+                 COUNT(amount) OVER (PARTITION BY region), SUM(amount) OVER (PARTITION BY region), \
+                 MAX(amount) OVER () FROM sales AS s WHERE region = 'south'"
+            )
+            .unwrap(),
+            [
+                vec![text("south"), int(2), int(1), int(5), int(5)],
+                vec![text("south"), int(2), int(1), int(5), int(5)],
+            ]
+        );
+        // With ORDER BY, the window ends with the rows that sort like the row
+        assert_eq!(
+            query(
+                &mut conn,
+                "SELECT *, SUM(amount) OVER (ORDER BY amount) AS running FROM sales WHERE region = 'north'"
+            )
+            .unwrap(),
+            [
+                vec![int(1), text("north"), int(10), int(20)],
+                vec![int(3), text("north"), int(30), int(50)],
+                vec![int(4), text("north"), int(10), int(20)],
+            ]
+        );
+        let mut rows = conn
+            .prepare("SELECT region, AVG(amount) OVER (PARTITION BY region) AS average FROM sales")
+            .unwrap()
+            .query()
+            .unwrap();
+        assert_eq!(rows.column_names(), ["region", "average"]);
+        assert_eq!(rows.next().unwrap().values()[1], Value::Real(50.0 / 3.0));
+
+        assert_eq!(
+            query(&mut conn, "SELECT id FROM sales WHERE rank() OVER () = 1")
+                .unwrap_err()
+                .to_string(),
+            "misuse of window function rank()"
+        );
+        assert_eq!(
+            query(&mut conn, "SELECT upper(region) OVER () FROM sales")
+                .unwrap_err()
+                .to_string(),
+            "upper() may not be used as a window function"
+        );
+        let plan = query(
+            &mut conn,
+            "EXPLAIN SELECT id, rank() OVER (ORDER BY amount) FROM sales",
+        )
+        .unwrap();
+        assert!(plan.iter().any(|step| step[1..]
+            == [
+                text("Window"),
+                text("rank() OVER (ORDER BY amount) of rowset 1")
+            ]));
+    }
+
+    #[test]
+    fn joins() {
+        let database_file = TempDatabase::sample("joins");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        conn.execute_script(
+            "CREATE TABLE orders (id INTEGER PRIMARY KEY, apple_id INTEGER, quantity INTEGER);
+             INSERT INTO orders (apple_id, quantity) VALUES (1, 3), (3, 2), (1, 5), (NULL, 1), (9, 4);",
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        let query = |conn: &mut Connection, sql: &str| -> Result<Vec<Vec<Value>>> {
+            conn.prepare(sql)?
+                .query()?
+                .map(|row| Ok(row.values().to_vec()))
+                .collect()
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+        let int = Value::Integer;
+
+        // Each order with its apple, in the order of the orders
+        let joined =
+            "SELECT o.id, name, quantity FROM orders o JOIN apples ON apples.id = o.apple_id";
+        let rows = vec![
+            vec![int(1), text("Granny Smith"), int(3)],
+            vec![int(2), text("Honeycrisp"), int(2)],
+            vec![int(3), text("Granny Smith"), int(5)],
+        ];
+        assert_eq!(query(&mut conn, joined).unwrap(), rows);
+        // Partitioned into temporary files, the rows are the same, in another order
+        conn.set_join_memory_limit(1);
+        let mut spilled = query(&mut conn, joined).unwrap();
+        spilled.sort_by_key(|row| match row[0] {
+            Value::Integer(id) => id,
+            _ => unreachable!(),
+        });
+        assert_eq!(spilled, rows);
+        conn.set_join_memory_limit(DEFAULT_JOIN_MEMORY_LIMIT);
+
+        // The rest of the ON condition filters the rows like WHERE
+        assert_eq!(
+            query(
+                &mut conn,
+                "SELECT a.name, b.name FROM apples a INNER JOIN apples b \
+                 ON a.color = b.color OR a.id < b.id WHERE b.id = 2"
+            )
+            .unwrap(),
+            [
+                vec![text("Granny Smith"), text("Fuji")],
+                vec![text("Fuji"), text("Fuji")],
+            ]
+        );
+        let mut rows = conn
+            .prepare("SELECT * FROM apples JOIN orders ON apple_id = apples.id AND quantity > 4")
+            .unwrap()
+            .query()
+            .unwrap();
+        assert_eq!(
+            rows.column_names(),
+            ["id", "name", "color", "id", "apple_id", "quantity"]
+        );
+        assert_eq!(
+            rows.next().unwrap().values(),
+            [
+                int(1),
+                text("Granny Smith"),
+                text("Light Green"),
+                int(3),
+                int(1),
+                int(5)
+            ]
+        );
+        assert!(rows.next().is_none());
+        assert_eq!(
+            query(
+                &mut conn,
+                "SELECT 1 FROM apples CROSS JOIN orders JOIN (SELECT id FROM apples)"
+            )
+            .unwrap()
+            .len(),
+            80
+        );
+
+        assert_eq!(
+            query(
+                &mut conn,
+                "SELECT id FROM apples JOIN orders ON apple_id = apples.id"
+            )
+            .unwrap_err()
+            .to_string(),
+            "ambiguous column name: id"
+        );
+        assert_eq!(
+            query(&mut conn, "SELECT o.name FROM apples JOIN orders o")
+                .unwrap_err()
+                .to_string(),
+            "no such column: o.name"
+        );
+
+        let plan = query(&mut conn, &format!("EXPLAIN {joined}")).unwrap();
+        assert!(plan.iter().any(|step| step[1..]
+            == [
+                text("HashJoin"),
+                text("rowset 1 with rowset 2 on apples.id = o.apple_id into rowset 3")
+            ]));
+    }
+
+    #[test]
+    fn open_from_bytes() {
+        let bytes = std::fs::read("sample.db").unwrap();
+        let mut conn = Connection::from_bytes(bytes.clone()).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT name FROM apples WHERE id = 2")
+            .unwrap();
+        let names = stmt
+            .query()
+            .unwrap()
+            .map(|row| row.get(0).unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(names, ["Fuji"]);
+
+        // Writes only change the copy in memory
+        conn.execute(
+            "INSERT INTO apples (name) VALUES ('Jazz')",
+            &mut RowCollector::default(),
+        )
+        .unwrap();
+        let mut stmt = conn.prepare("SELECT * FROM apples").unwrap();
+        assert_eq!(stmt.query().unwrap().count(), 5);
+        assert_eq!(std::fs::read("sample.db").unwrap(), bytes);
+    }
+
+    #[test]
+    fn import_csv_files() {
+        let database_file = TempDatabase::sample("import_csv_files");
+        let path = database_file.path();
+        let csv_path = format!("{path}.csv");
+        std::fs::write(
+            &csv_path,
+            "id,full name,score\n1,\"Smith, Jo\",2.5\n2,\"Ann \"\"Annie\"\"\",\n",
+        )
+        .unwrap();
+        let mut conn = Connection::open(path).unwrap();
+        assert_eq!(conn.import_csv(&csv_path, "people").unwrap(), 2);
+        let columns = &conn.database().schema.table("people").unwrap().columns;
+        assert_eq!(
+            columns
+                .iter()
+                .map(|column| (column.name.as_str(), column.affinity()))
+                .collect::<Vec<_>>(),
+            [
+                ("id", Affinity::Integer),
+                ("full name", Affinity::Text),
+                ("score", Affinity::Real),
+            ]
+        );
+
+        // Into an existing table, the first record is a row too
+        std::fs::write(&csv_path, "3,Bo,1\n").unwrap();
+        assert_eq!(conn.import_csv(&csv_path, "people").unwrap(), 1);
+        let mut stmt = conn.prepare("SELECT * FROM people").unwrap();
+        let rows = stmt
+            .query()
+            .unwrap()
+            .map(|row| {
+                (
+                    row.get(0).unwrap(),
+                    row.get(1).unwrap(),
+                    row.get(2).unwrap(),
+                )
+            })
+            .collect::<Vec<(i64, String, Option<f64>)>>();
+        assert_eq!(
+            rows,
+            [
+                (1, "Smith, Jo".to_string(), Some(2.5)),
+                (2, "Ann \"Annie\"".to_string(), None),
+                (3, "Bo".to_string(), Some(1.0)),
+            ]
+        );
+
+        std::fs::write(&csv_path, "4,Cy\n").unwrap();
+        assert_eq!(
+            conn.import_csv(&csv_path, "people")
+                .unwrap_err()
+                .to_string(),
+            format!("{csv_path}: record 1 has 2 values but the table has 3 columns")
+        );
+        std::fs::remove_file(&csv_path).unwrap();
+    }
+
+    #[test]
+    fn query_with_parameters() {
+        let mut conn = Connection::open("sample.db").unwrap();
+        let mut stmt = conn
+            .prepare("SELECT name FROM apples WHERE color = :color OR id = ?3")
+            .unwrap();
+        assert_eq!(stmt.parameter_count(), 3);
+        assert_eq!(stmt.parameter_index(":color"), Some(1));
+
+        let names = |stmt: &mut Statement| {
+            stmt.query()
+                .unwrap()
+                .map(|row| row.get::<String>(0).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        stmt.bind_name(":color", "Yellow").unwrap();
+        assert_eq!(names(&mut stmt), vec!["Golden Delicious"]);
+
+        // The statement is reused with other values
+        stmt.bind(3, 1).unwrap();
+        assert_eq!(names(&mut stmt), vec!["Granny Smith", "Golden Delicious"]);
+
+        stmt.clear_bindings();
+        assert!(names(&mut stmt).is_empty());
+
+        assert_eq!(stmt.bind(4, 1), Err(Error::InvalidParameterIndex(4)));
+        assert_eq!(stmt.bind(0, 1), Err(Error::InvalidParameterIndex(0)));
+        assert_eq!(
+            stmt.bind_name(":other", 1),
+            Err(Error::NoSuchParameter(":other".to_string()))
+        );
+    }
+
+    #[test]
+    fn attach_databases() {
+        let database_file = TempDatabase::sample("attach_main");
+        let path = database_file.path();
+        let aux_file = TempDatabase::new("attach_aux");
+        let aux = aux_file.path();
+
+        let mut conn = Connection::open(path).unwrap();
+        let mut run = |statement: &str| {
+            conn.prepare(statement)
+                .and_then(|mut stmt| stmt.query())
+                .map(|rows| rows.map(|row| row.values().to_vec()).collect::<Vec<_>>())
+        };
+        run(&format!("ATTACH DATABASE '{aux}' AS aux")).unwrap();
+        run("CREATE TABLE aux.apples (id integer primary key, name text)").unwrap();
+        run("INSERT INTO aux.apples (name) VALUES ('Envy')").unwrap();
+        run("CREATE TABLE aux.pears (name text)").unwrap();
+        run("INSERT INTO pears VALUES ('Conference')").unwrap();
+
+        // Unqualified names are looked up in the main database first
+        let names = |rows: Vec<Vec<Value>>| {
+            rows.into_iter()
+                .map(|row| row[0].clone())
+                .collect::<Vec<_>>()
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_eq!(
+            names(run("SELECT name FROM aux.apples").unwrap()),
+            vec![text("Envy")]
+        );
+        assert_eq!(
+            names(run("SELECT name FROM MAIN.apples WHERE id = 1").unwrap()),
+            vec![text("Granny Smith")]
+        );
+        assert_eq!(
+            names(run("SELECT name FROM apples WHERE id = 1").unwrap()),
+            vec![text("Granny Smith")]
+        );
+        assert_eq!(
+            names(run("SELECT name FROM pears").unwrap()),
+            vec![text("Conference")]
+        );
+
+        let error = |result: Result<Vec<Vec<Value>>>| result.unwrap_err().to_string();
+        assert_eq!(
+            error(run(&format!("ATTACH '{aux}' AS AUX"))),
+            "database AUX is already in use"
+        );
+        assert_eq!(
+            error(run("CREATE TABLE other.t (a)")),
+            "unknown database other"
+        );
+        assert_eq!(error(run("DETACH main")), "cannot detach database main");
+        run("DETACH DATABASE aux").unwrap();
+        assert_eq!(error(run("DETACH aux")), "no such database: aux");
+        assert_eq!(
+            error(run("SELECT name FROM aux.apples")),
+            "no such table: aux.apples"
+        );
+        assert_eq!(error(run("SELECT name FROM pears")), "no such table: pears");
+    }
+
+    #[test]
+    fn temporary_tables() {
+        let mut conn = Connection::open("sample.db").unwrap();
+        let mut run = |statement: &str| {
+            conn.prepare(statement)
+                .and_then(|mut stmt| stmt.query())
+                .map(|rows| rows.map(|row| row.values().to_vec()).collect::<Vec<_>>())
+        };
+        run("CREATE TEMP TABLE apples (id integer primary key, name text)").unwrap();
+        run("INSERT INTO apples (name) VALUES ('Envy'), ('Gala')").unwrap();
+        run("CREATE INDEX apples_name ON apples (name)").unwrap();
+
+        // Temporary tables hide the tables of the main database with the same name
+        let text = |text: &str| vec![Value::Text(text.to_string())];
+        assert_eq!(
+            run("SELECT name FROM apples WHERE name = 'Gala'").unwrap(),
+            vec![text("Gala")]
+        );
+        assert_eq!(
+            run("SELECT name FROM main.apples WHERE id = 1").unwrap(),
+            vec![text("Granny Smith")]
+        );
+        assert_eq!(
+            run("SELECT type, name FROM temp.sqlite_schema")
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // A failed statement is rolled back from the journal in memory
+        assert_eq!(
+            run("INSERT INTO apples VALUES (3, 'Jazz'), (1, 'Fuji')")
+                .unwrap_err()
+                .to_string(),
+            "UNIQUE constraint failed: apples.id"
+        );
+        assert_eq!(run("SELECT id FROM temp.apples").unwrap().len(), 2);
+
+        assert_eq!(
+            run("CREATE TEMP TABLE main.pears (name)")
+                .unwrap_err()
+                .to_string(),
+            "temporary table name must be unqualified"
+        );
+        assert_eq!(
+            run("DETACH temp").unwrap_err().to_string(),
+            "cannot detach database temp"
+        );
+    }
+
+    #[test]
+    fn subqueries_in_from() {
+        let mut conn = Connection::open("sample.db").unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT color, x FROM (SELECT name AS x, (color) FROM apples WHERE id > 1) AS s \
+                 WHERE x = 'Fuji'",
+            )
+            .unwrap();
+        let rows = stmt.query().unwrap();
+        assert_eq!(rows.column_names(), ["color", "x"]);
+        let rows = rows
+            .map(|row| (row.get(0).unwrap(), row.get("x").unwrap()))
+            .collect::<Vec<(String, String)>>();
+        assert_eq!(rows, vec![("Red".to_string(), "Fuji".to_string())]);
+
+        let mut stmt = conn
+            .prepare("SELECT n, id = 3 FROM (SELECT * FROM (SELECT id, name AS n FROM apples))")
+            .unwrap();
+        let rows = stmt.query().unwrap();
+        assert_eq!(rows.column_names(), ["n", "id = 3"]);
+        let rows = rows
+            .map(|row| (row.get(0).unwrap(), row.get(1).unwrap()))
+            .collect::<Vec<(String, i64)>>();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[2], ("Honeycrisp".to_string(), 1));
+    }
+
+    #[test]
+    fn views() {
+        let database_file = TempDatabase::sample("views");
+        let path = database_file.path();
+        let mut conn = Connection::open(path).unwrap();
+        let mut out = RowCollector::default();
+        for statement in [
+            "CREATE VIEW reds AS SELECT id, name AS apple FROM apples WHERE color = 'Red' OR color = 'Blush Red'",
+            "CREATE VIEW names (n) AS SELECT apple FROM reds",
+            "CREATE VIEW wrong (a, b) AS SELECT id FROM apples",
+        ] {
+            conn.execute(statement, &mut out).unwrap();
+        }
+
+        // Views are stored in the schema table, and read back when the file is reopened
+        let mut conn = Connection::open(path).unwrap();
+        let mut query = |statement: &str| {
+            let mut stmt = conn.prepare(statement)?;
+            let rows = stmt.query()?;
+            let columns = rows.column_names().to_vec();
+            let values = rows.map(|row| row.get(0).unwrap()).collect::<Vec<Value>>();
+            Ok::<_, anyhow::Error>((columns, values))
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_eq!(
+            query("SELECT apple FROM reds WHERE id > 2").unwrap(),
+            (vec!["apple".to_string()], vec![text("Honeycrisp")])
+        );
+        assert_eq!(
+            query("SELECT * FROM names").unwrap(),
+            (
+                vec!["n".to_string()],
+                vec![text("Fuji"), text("Honeycrisp")]
+            )
+        );
+        assert_eq!(
+            query("SELECT count(*) FROM names").unwrap().1,
+            [Value::Integer(2)]
+        );
+        assert_eq!(
+            query("SELECT * FROM wrong").unwrap_err().to_string(),
+            "expected 2 columns for 'wrong' but got 1"
+        );
+        assert_eq!(
+            query("SELECT name FROM reds").unwrap_err().to_string(),
+            "no such column: name"
+        );
+
+        for (statement, error) in [
+            (
+                "CREATE VIEW reds AS SELECT 1 FROM apples",
+                "view reds already exists",
+            ),
+            ("CREATE TABLE reds (id)", "view reds already exists"),
+            (
+                "CREATE VIEW apples AS SELECT 1 FROM apples",
+                "table apples already exists",
+            ),
+            (
+                "INSERT INTO reds VALUES (1, 'x')",
+                "cannot modify reds because it is a view",
+            ),
+            (
+                "CREATE INDEX by_apple ON reds (apple)",
+                "views may not be indexed",
+            ),
+        ] {
+            assert_eq!(
+                conn.execute(statement, &mut out).unwrap_err().to_string(),
+                error
+            );
+        }
+        conn.execute(
+            "CREATE VIEW IF NOT EXISTS reds AS SELECT 1 FROM apples",
+            &mut out,
+        )
+        .unwrap();
+
+        // Views may refer to views that are created later, but not to themselves
+        conn.execute("CREATE VIEW a AS SELECT * FROM b", &mut out)
+            .unwrap();
+        conn.execute("CREATE VIEW b AS SELECT * FROM a", &mut out)
+            .unwrap();
+        assert_eq!(
+            conn.execute("SELECT * FROM a", &mut out)
+                .unwrap_err()
+                .to_string(),
+            "view a is circularly defined"
+        );
+    }
+
+    #[test]
+    fn unions() {
+        let mut conn = Connection::open("sample.db").unwrap();
+        let mut run = |statement: &str| {
+            let mut stmt = conn.prepare(statement)?;
+            let rows = stmt.query()?;
+            let columns = rows.column_names().to_vec();
+            let values = rows.map(|row| row.get(0).unwrap()).collect::<Vec<String>>();
+            Ok::<_, anyhow::Error>((columns, values))
+        };
+        let (columns, values) = run("SELECT color AS c FROM apples WHERE id > 2 \
+             UNION ALL SELECT color FROM apples WHERE id < 4")
+        .unwrap();
+        assert_eq!(columns, ["c"]);
+        assert_eq!(
+            values,
+            ["Blush Red", "Yellow", "Light Green", "Red", "Blush Red"]
+        );
+        // UNION removes duplicates and sorts the rows
+        let (_, values) = run("SELECT color FROM apples WHERE id > 2 \
+             UNION SELECT color FROM apples WHERE id < 4")
+        .unwrap();
+        assert_eq!(values, ["Blush Red", "Light Green", "Red", "Yellow"]);
+
+        assert_eq!(
+            run("SELECT id, name FROM apples UNION ALL SELECT name FROM apples")
+                .unwrap_err()
+                .to_string(),
+            "SELECTs to the left and right of UNION ALL do not have the same number of result \
+             columns"
+        );
+    }
+
+    #[test]
+    fn subqueries_in_expressions() {
+        let mut conn = Connection::open("sample.db").unwrap();
+        let mut names = |statement: &str| {
+            let mut stmt = conn.prepare(statement)?;
+            if statement.contains('?') {
+                stmt.bind(1, "Red")?;
+            }
+            let rows = stmt.query()?;
+            Ok::<_, anyhow::Error>(rows.map(|row| row.get(0).unwrap()).collect::<Vec<String>>())
+        };
+        assert_eq!(
+            names(
+                "SELECT name FROM apples WHERE id IN (SELECT id FROM apples WHERE color = ?) \
+                 OR id = (SELECT id FROM apples WHERE name = 'Honeycrisp')"
+            )
+            .unwrap(),
+            ["Fuji", "Honeycrisp"]
+        );
+        assert_eq!(
+            names("SELECT name FROM apples WHERE id NOT IN (SELECT id FROM apples WHERE id > 1)")
+                .unwrap(),
+            ["Granny Smith"]
+        );
+        assert_eq!(
+            names("SELECT name FROM apples WHERE id = (SELECT id FROM apples WHERE id > 9)")
+                .unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            names("SELECT name FROM apples WHERE id IN (SELECT id, name FROM apples)")
+                .unwrap_err()
+                .to_string(),
+            "sub-select returns 2 columns - expected 1"
+        );
+    }
+
+    #[test]
+    fn get_errors() {
+        let row = row();
+
+        assert_eq!(row.get::<i64>(4), Err(Error::InvalidColumnIndex(4)));
+        assert_eq!(
+            row.get::<i64>("missing"),
+            Err(Error::NoSuchColumn("missing".to_string()))
+        );
+        assert_eq!(
+            row.get::<i64>("name"),
+            Err(Error::InvalidType {
+                expected: "integer",
+                found: "text"
+            })
+        );
+        assert_eq!(
+            row.get::<&str>("note"),
+            Err(Error::InvalidType {
+                expected: "text",
+                found: "null"
+            })
+        );
+    }
+}
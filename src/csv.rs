@@ -0,0 +1,116 @@
+//! Reading CSV files for `.import`, following RFC 4180 like sqlite3 does: fields are separated
+//! by commas and records by newlines, and fields in double quotes can contain both, with
+//! doubled quotes standing for a quote.
+
+use anyhow::{bail, Result};
+
+/// The records of a CSV file. A line ending with `\r\n` ends a record like `\n`, and a final
+/// newline doesn't start an empty record.
+pub fn parse(input: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    // Where the current quoted field started, for the error if it's never closed
+    let mut quote_line = 0;
+    let mut line = 1;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            // Quotes only start a quoted field at its beginning, like in sqlite3
+            '"' if field.is_empty() => {
+                quoted = true;
+                quote_line = line;
+            }
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                line += 1;
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        bail!(
+            "unterminated quoted field that starts on line {}",
+            quote_line
+        );
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The declared type of a column with the given values: INTEGER or REAL if all of them are
+/// numbers of that kind, TEXT otherwise. Empty values don't count, so that a column with a
+/// few missing numbers is still numeric.
+pub fn column_type<'a>(values: impl IntoIterator<Item = &'a str>) -> &'static str {
+    let mut column_type = None;
+    for value in values.into_iter().filter(|value| !value.is_empty()) {
+        let value_type = if value.parse::<i64>().is_ok() {
+            "INTEGER"
+        } else if value.parse::<f64>().map_or(false, f64::is_finite) {
+            "REAL"
+        } else {
+            return "TEXT";
+        };
+        column_type = match (column_type, value_type) {
+            (None | Some("INTEGER"), value_type) => Some(value_type),
+            (column_type, _) => column_type,
+        };
+    }
+    column_type.unwrap_or("TEXT")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_records() {
+        let records = parse(
+            "name,note\r\nplain,\"with, comma\"\n\"multi\nline\",\"say \"\"hi\"\"\"\nlast,\n",
+        )
+        .unwrap();
+        assert_eq!(
+            records,
+            [
+                vec!["name", "note"],
+                vec!["plain", "with, comma"],
+                vec!["multi\nline", "say \"hi\""],
+                vec!["last", ""],
+            ]
+        );
+        assert_eq!(parse("a,b").unwrap(), [vec!["a", "b"]]);
+        assert_eq!(parse("").unwrap(), Vec::<Vec<String>>::new());
+        assert_eq!(
+            parse("a\n\"b\nc").unwrap_err().to_string(),
+            "unterminated quoted field that starts on line 2"
+        );
+    }
+
+    #[test]
+    fn infer_column_types() {
+        assert_eq!(column_type(["1", "", "-20"]), "INTEGER");
+        assert_eq!(column_type(["1", "2.5", "1e3"]), "REAL");
+        assert_eq!(column_type(["1", "x", "2.5"]), "TEXT");
+        assert_eq!(column_type(["inf"]), "TEXT");
+        assert_eq!(column_type([""]), "TEXT");
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::{bail, Result};
+
+use crate::database::{Database, MAX_DEPTH};
+use crate::error::Error;
+use crate::page::{Cell, Page, PageKind};
+use crate::record::{OwnedRecord, Record};
+
+/// Walks the leaves of a table b-tree in rowid order, reading one page at a time. The rows are
+/// copied out of their pages, so they can be kept after the cursor moves on.
+pub struct TableCursor<'db> {
+    database: &'db Database,
+    /// The pages from the root to the current leaf, each with the position of the next cell
+    stack: Vec<(Page, usize)>,
+}
+
+impl<'db> TableCursor<'db> {
+    pub fn new(database: &'db Database, rootpage: u32) -> Result<Self> {
+        let root = database.get_page(rootpage - 1)?;
+        Ok(Self {
+            database,
+            stack: vec![(root, 0)],
+        })
+    }
+
+    /// A cursor whose first row is the first one with a rowid larger than `after`. It's found by
+    /// descending the b-tree, without reading the rows before it.
+    pub fn seek(database: &'db Database, rootpage: u32, after: i64) -> Result<Self> {
+        let mut stack = vec![];
+        let mut page = database.get_page(rootpage - 1)?;
+        loop {
+            let mut position = 0;
+            let child = match page.header.kind {
+                PageKind::LeafTable => {
+                    while let Some(cell) = page.cell(position) {
+                        match cell {
+                            Cell::LeafTable { rowid, .. } if rowid <= after => position += 1,
+                            Cell::LeafTable { .. } => break,
+                            _ => bail!("Unsupported cell type"),
+                        }
+                    }
+                    None
+                }
+                // The left child of a cell has the rows up to and including its key, and the
+                // right child the ones after the key of the last cell
+                PageKind::InteriorTable => loop {
+                    match page.cell(position) {
+                        Some(Cell::InteriorTable {
+                            left_child_page,
+                            key,
+                        }) => {
+                            position += 1;
+                            if key > after {
+                                break Some(left_child_page);
+                            }
+                        }
+                        Some(_) => bail!("Unsupported cell type"),
+                        None => {
+                            position += 1;
+                            break page.header.right_child_page_number;
+                        }
+                    }
+                },
+                PageKind::InteriorIndex | PageKind::LeafIndex => {
+                    bail!("Malformed table: table contains index pages")
+                }
+            };
+            stack.push((page, position));
+            match child {
+                Some(_) if stack.len() >= MAX_DEPTH => {
+                    bail!(Error::Corrupt("b-tree is too deep".to_string()))
+                }
+                Some(number) => page = database.get_page(number - 1)?,
+                None => return Ok(Self { database, stack }),
+            }
+        }
+    }
+
+    fn advance(&mut self) -> Result<Option<OwnedRecord>> {
+        loop {
+            let Some((page, position)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+
+            let child = match page.header.kind {
+                PageKind::LeafTable => match page.cell(*position) {
+                    Some(Cell::LeafTable {
+                        size,
+                        rowid,
+                        payload,
+                        overflow_page,
+                    }) => {
+                        *position += 1;
+                        let payload = self.database.payload(payload, size, overflow_page)?;
+                        return Ok(Some(Record::read(rowid, &payload)?.to_owned_record()));
+                    }
+                    Some(_) => bail!("Unsupported cell type"),
+                    None => None,
+                },
+                PageKind::InteriorTable => match page.cell(*position) {
+                    Some(Cell::InteriorTable {
+                        left_child_page, ..
+                    }) => Some(left_child_page),
+                    Some(_) => bail!("Unsupported cell type"),
+                    // The right child comes after all cells
+                    None if *position == page.cell_pointers.len() => {
+                        page.header.right_child_page_number
+                    }
+                    None => None,
+                },
+                PageKind::InteriorIndex | PageKind::LeafIndex => {
+                    bail!("Malformed table: table contains index pages")
+                }
+            };
+            *position += 1;
+
+            match child {
+                Some(_) if self.stack.len() >= MAX_DEPTH => {
+                    bail!(Error::Corrupt("b-tree is too deep".to_string()))
+                }
+                Some(number) => {
+                    let page = self.database.get_page(number - 1)?;
+                    self.stack.push((page, 0));
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for TableCursor<'_> {
+    type Item = Result<OwnedRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.advance();
+        if next.is_err() {
+            self.stack.clear();
+        }
+        next.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Value;
+    use crate::sql;
+    use crate::test_support::TempDatabase;
+
+    #[test]
+    fn scan_sample_table() {
+        let database = Database::open("sample.db").unwrap();
+        let rows = database
+            .scan("apples")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].rowid, 1);
+        assert_eq!(
+            rows[3].values[1],
+            Value::Text("Golden Delicious".to_string())
+        );
+    }
+
+    #[test]
+    fn seek_past_rowids() {
+        let database_file = TempDatabase::sample("seek");
+        let mut database = Database::open(database_file.path()).unwrap();
+        // Enough rows for interior pages, with gaps between the rowids
+        let rows = (3..=600)
+            .map(|n| format!("({}, '{}', 'Red')", n * 2, "x".repeat(300)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let sql::SQLCommand::Insert(insert) =
+            sql::parse(format!("INSERT INTO apples VALUES {rows}").as_bytes())
+                .unwrap()
+                .1
+        else {
+            panic!("not an insert");
+        };
+        database.insert(&insert).unwrap();
+
+        let rootpage = database.schema.table("apples").unwrap().rootpage;
+        let root = database.get_page(rootpage - 1).unwrap();
+        assert!(root.header.kind.is_interior());
+        let all = database
+            .scan("apples")
+            .unwrap()
+            .map(|row| row.unwrap().rowid)
+            .collect::<Vec<_>>();
+        for after in [-1, 0, 3, 4, 5, 601, 1199, 1200, 5000] {
+            let rowids = TableCursor::seek(&database, rootpage, after)
+                .unwrap()
+                .map(|row| row.unwrap().rowid)
+                .collect::<Vec<_>>();
+            let expected = all
+                .iter()
+                .copied()
+                .filter(|rowid| *rowid > after)
+                .collect::<Vec<_>>();
+            assert_eq!(rowids, expected, "after {after}");
+        }
+    }
+
+    #[test]
+    fn cyclic_btree_is_an_error() {
+        // Turn the apples leaf on page 2 into an interior page that points to itself
+        let mut bytes = std::fs::read("sample.db").unwrap();
+        bytes[4096] = 0x05;
+        bytes[4096 + 3..4096 + 5].copy_from_slice(&[0, 0]);
+        bytes[4096 + 8..4096 + 12].copy_from_slice(&2u32.to_be_bytes());
+        let database_file = TempDatabase::with_bytes("cycle", &bytes);
+
+        let database = Database::open(database_file.path()).unwrap();
+        let error = database
+            .scan("apples")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "database disk image is malformed: b-tree is too deep"
+        );
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        let bytes = std::fs::read("sample.db").unwrap();
+        let database_file = TempDatabase::with_bytes("truncated", &bytes[..4096 + 2048]);
+
+        let database = Database::open(database_file.path()).unwrap();
+        let error = database.scan("apples").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "database disk image is malformed: page 2 is past the end of the file"
+        );
+    }
+}
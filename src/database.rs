@@ -1,54 +1,448 @@
-use std::fs::File;
-use std::io::{prelude::*, SeekFrom};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
 
 use anyhow::{bail, Result};
-use itertools::Itertools;
 
-use crate::page::{Cell, Page};
-use crate::record::{ColumnValue, Record};
-use crate::sql::{self, SelectFields};
-use crate::sqlite_schema::{Index, SchemaStore, Table};
+use crate::affinity::Affinity;
+use crate::collation::{Collation, Collations};
+use crate::cursor::TableCursor;
+use crate::error::Error;
+use crate::explain::Program;
+use crate::expr::{self, Expression};
+use crate::freelist::Freelist;
+use crate::functions::Functions;
+use crate::integrity;
+use crate::log;
+use crate::output::OutputFormatter;
+use crate::page::{self, Cell, Page, PageKind};
+use crate::pager::{Pager, RemoteFile};
+use crate::record::{compare_keys, encode_record, ColumnValue, Record, Value};
+use crate::sql::{self, Expr, Literal, ResultColumn, SelectFields};
+use crate::sqlite_schema::{
+    automatic_index_name, Index, SQLiteSchema, SchemaStore, Table, SEQUENCE_TABLE_NAME,
+};
+use crate::stats::{self, Statistics, STAT1_TABLE_NAME, STAT1_TABLE_SQL};
+use crate::varint;
+
+/// The cells of one of the pages an overflowing page is split into. `divider` is the content
+/// of the cell that points to the page from the parent, without the page number: the largest
+/// rowid for tables, or the entry that separates the page from the next one for indexes.
+struct Sibling {
+    cells: Vec<Vec<u8>>,
+    right_child: Option<u32>,
+    divider: Vec<u8>,
+}
+
+/// Where the value of a column of an index comes from in the rows of its table.
+enum IndexColumn {
+    /// The position of a column of the table, and whether it aliases the rowid
+    Column(usize, bool),
+    /// An expression of the columns, for indexes on expressions
+    Expression(Expression),
+}
+
+/// The columns of an index, resolved against the columns of its table.
+fn index_columns(
+    table: &Table,
+    columns: &[String],
+    expressions: &[Option<Expr>],
+) -> Result<Vec<IndexColumn>> {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(position, name)| match expressions.get(position) {
+            Some(Some(expr)) => match Expression::new(expr, table) {
+                Ok(expression) => Ok(IndexColumn::Expression(expression)),
+                Err(Error::UnresolvedSubquery) => {
+                    bail!("subqueries prohibited in index expressions")
+                }
+                Err(error) => Err(error.into()),
+            },
+            _ => {
+                let (index, column) = table.column(name)?;
+                Ok(IndexColumn::Column(index, column.is_rowid_alias))
+            }
+        })
+        .collect()
+}
+
+/// The index entry of a row: the values of the indexed columns followed by the rowid.
+fn index_entry(columns: &[IndexColumn], rowid: i64, values: &[Value]) -> Result<Vec<Value>> {
+    // Expressions are evaluated on the record of the row
+    let payload = columns
+        .iter()
+        .any(|column| matches!(column, IndexColumn::Expression(_)))
+        .then(|| encode_record(values));
+    let record = payload
+        .as_deref()
+        .map(|payload| Record::read(rowid, payload))
+        .transpose()?;
+    let mut entry = columns
+        .iter()
+        .map(|column| match column {
+            IndexColumn::Column(_, true) => Ok(Value::Integer(rowid)),
+            IndexColumn::Column(index, false) => {
+                Ok(values.get(*index).cloned().unwrap_or(Value::Null))
+            }
+            IndexColumn::Expression(expression) => match &record {
+                Some(record) => Ok(Value::from(&expression.evaluate(record)?)),
+                None => Ok(Value::Null),
+            },
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    entry.push(Value::Integer(rowid));
+    Ok(entry)
+}
+
+/// The error for a row that clashes with another one in a unique index. Like in SQLite, it
+/// names the columns of the index, or the index itself if it has expressions.
+fn unique_constraint(
+    table: &Table,
+    name: &str,
+    columns: &[String],
+    expressions: &[Option<Expr>],
+) -> Error {
+    if expressions.iter().any(Option::is_some) {
+        return Error::UniqueConstraint(format!("index '{name}'"));
+    }
+    let columns = columns
+        .iter()
+        .map(|column| format!("{}.{}", table.name, column))
+        .collect::<Vec<_>>();
+    Error::UniqueConstraint(columns.join(", "))
+}
+
+/// The condition of a partial index, resolved against the columns of its table.
+fn partial_index_condition(table: &Table, condition: Option<&Expr>) -> Result<Option<Expression>> {
+    let Some(condition) = condition else {
+        return Ok(None);
+    };
+    match Expression::new(condition, table) {
+        Ok(condition) => Ok(Some(condition)),
+        Err(Error::UnresolvedSubquery) => {
+            bail!("subqueries prohibited in partial index WHERE clauses")
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Whether a row has an entry in an index with the given partial index condition.
+pub(crate) fn satisfies(
+    condition: Option<&Expression>,
+    rowid: i64,
+    values: &[Value],
+) -> Result<bool> {
+    let Some(condition) = condition else {
+        return Ok(true);
+    };
+    let payload = encode_record(values);
+    Ok(condition.matches(&Record::read(rowid, &payload)?)?)
+}
+
+/// Adds the step that opens `table` for reading to `program`.
+fn open_table(table: &Table, program: &mut Program) {
+    program.push(
+        "OpenRead",
+        format!("table {} (root page {})", table.name, table.rootpage),
+    );
+}
+
+/// Adds the steps that read the columns of `query` from the rows of `table`, and filter them
+/// with `where_clause`, to `program`.
+fn explain_columns(
+    table: &Table,
+    query: &Query,
+    where_clause: Option<&Expr>,
+    program: &mut Program,
+) {
+    let columns = query.read_columns(where_clause.is_some());
+    if !columns.is_empty() {
+        let names = columns
+            .iter()
+            .map(|&column| table.columns[column].name.as_str())
+            .collect::<Vec<_>>();
+        program.push("Column", format!("{columns:?} ({})", names.join(", ")));
+    }
+    if let Some(where_clause) = where_clause {
+        program.push("Filter", where_clause.to_string());
+    }
+}
 
 #[derive(Debug)]
 pub struct DatabaseHeader {
-    pub page_size: u16,
+    pub page_size: u32,
+    pub reserved_space: u8,
+    /// Incremented on every write, so that other connections notice the change
+    pub change_counter: u32,
+    /// Size of the database file in pages
+    pub database_size: u32,
+    /// The first trunk page of the freelist, or 0 if no page is free
+    pub first_freelist_page: u32,
+    /// The number of pages in the freelist, trunk pages included
+    pub freelist_count: u32,
+    /// Incremented whenever the schema changes, so that other connections reload it
+    pub schema_cookie: u32,
+    /// The schema format number. Formats before 4 ignore the sort order of index columns.
+    pub schema_format: u32,
 }
 
 const MAGIC_HEADER: [u8; 16] = *b"SQLite format 3\0";
+pub const HEADER_SIZE: usize = 100;
+pub const DEFAULT_PAGE_SIZE: u32 = 4096;
+/// The SQLite version whose file format is written, stored in the header of new files.
+const SQLITE_VERSION_NUMBER: u32 = 3_045_000;
+const SEQUENCE_TABLE_SQL: &str = "CREATE TABLE sqlite_sequence(name,seq)";
+/// SQLite never builds b-trees with more levels than this, a deeper path through the pages
+/// means that they form a cycle.
+pub const MAX_DEPTH: usize = 20;
+/// The byte range starting here is used for file locks on some platforms, so the page that
+/// contains it never holds data, in databases large enough to reach it.
+const PENDING_BYTE: u64 = 0x4000_0000;
 impl DatabaseHeader {
-    pub fn read(file: &mut File) -> Result<Self> {
-        let mut header = [0; 100];
-        file.read_exact(&mut header)?;
-
-        if &header[0..16] != MAGIC_HEADER {
+    pub fn parse(header: &[u8]) -> Result<Self> {
+        if header[0..16] != MAGIC_HEADER {
             return Err(anyhow::anyhow!("Invalid database file"));
         }
 
+        // A page size of 65536 doesn't fit into two bytes and is stored as 1 instead.
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            n => n as u32,
+        };
+        if !page_size.is_power_of_two() || page_size < 512 {
+            bail!(Error::Corrupt(format!("invalid page size {}", page_size)));
+        }
+        // Pages need at least 480 usable bytes, so that four cells fit on them
+        if page_size - (header[20] as u32) < 480 {
+            bail!(Error::Corrupt(format!(
+                "{} reserved bytes leave too little space on the pages",
+                header[20]
+            )));
+        }
+
         Ok(Self {
-            page_size: u16::from_be_bytes([header[16], header[17]]),
+            page_size,
+            reserved_space: header[20],
+            change_counter: u32::from_be_bytes([header[24], header[25], header[26], header[27]]),
+            database_size: u32::from_be_bytes([header[28], header[29], header[30], header[31]]),
+            first_freelist_page: u32::from_be_bytes([
+                header[32], header[33], header[34], header[35],
+            ]),
+            freelist_count: u32::from_be_bytes([header[36], header[37], header[38], header[39]]),
+            schema_cookie: u32::from_be_bytes([header[40], header[41], header[42], header[43]]),
+            schema_format: u32::from_be_bytes([header[44], header[45], header[46], header[47]]),
         })
     }
+
+    /// The header of a new database file that only contains the empty schema table.
+    pub fn initial(page_size: u32) -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        header[0..16].copy_from_slice(&MAGIC_HEADER);
+        let stored_page_size = if page_size == 65536 {
+            1
+        } else {
+            page_size as u16
+        };
+        header[16..18].copy_from_slice(&stored_page_size.to_be_bytes());
+        // File format versions 1 for rollback journals
+        header[18] = 1;
+        header[19] = 1;
+        // Maximum, minimum and leaf payload fractions, which must be 64, 32 and 32
+        header[21] = 64;
+        header[22] = 32;
+        header[23] = 32;
+        header[24..28].copy_from_slice(&1u32.to_be_bytes());
+        header[28..32].copy_from_slice(&1u32.to_be_bytes());
+        // Schema format 4 and UTF-8 text encoding
+        header[44..48].copy_from_slice(&4u32.to_be_bytes());
+        header[56..60].copy_from_slice(&1u32.to_be_bytes());
+        header[92..96].copy_from_slice(&1u32.to_be_bytes());
+        header[96..100].copy_from_slice(&SQLITE_VERSION_NUMBER.to_be_bytes());
+        header
+    }
+
+    /// Whether `DESC` index columns are stored in descending order, which they are since
+    /// schema format 4.
+    pub fn has_descending_indexes(&self) -> bool {
+        self.schema_format >= 4
+    }
+
+    /// The page size minus the bytes reserved at the end of each page for extensions.
+    pub fn usable_size(&self) -> usize {
+        self.page_size as usize - self.reserved_space as usize
+    }
+
+    /// The page, counting from 1, that contains the lock bytes. It's neither part of a b-tree
+    /// nor of the freelist.
+    pub fn lock_byte_page(&self) -> u32 {
+        (PENDING_BYTE / self.page_size as u64) as u32 + 1
+    }
+}
+
+/// An equality filter on a record value. The compared value has the column's affinity applied,
+/// and text compares with the column's collation.
+#[derive(Debug)]
+pub struct Filter {
+    pub column: usize,
+    pub is_rowid_alias: bool,
+    pub value: Literal,
+    pub collation: Collation,
+}
+
+impl Filter {
+    pub fn new(
+        column: usize,
+        is_rowid_alias: bool,
+        affinity: Affinity,
+        value: &Literal,
+        collation: Collation,
+    ) -> Self {
+        Self {
+            column,
+            is_rowid_alias,
+            value: affinity.apply(value),
+            collation,
+        }
+    }
+
+    /// Whether the value equals the filter value. Like `=`, nothing matches NULL.
+    pub fn matches(&self, record: &Record) -> bool {
+        if matches!(self.value(record), ColumnValue::Null) || self.value == Literal::Null {
+            return false;
+        }
+        self.compare(record) == Ordering::Equal
+    }
+
+    /// How the value of a record sorts relative to the filter value.
+    pub fn compare(&self, record: &Record) -> Ordering {
+        self.collation
+            .compare(&self.value(record), &ColumnValue::from(&self.value))
+    }
+
+    fn value<'a>(&self, record: &Record<'a>) -> ColumnValue<'a> {
+        if self.is_rowid_alias {
+            ColumnValue::I64(record.rowid)
+        } else {
+            record.value(self.column)
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Query<'query> {
     pub table: &'query Table,
-    pub select_fields: Vec<(usize, bool)>,
-    pub filter: Option<&'query sql::WhereClause>,
+    /// The names of the result columns: their alias, the name of the column they select or
+    /// their text
+    pub columns: Vec<String>,
+    pub results: Vec<Expression>,
+    /// The affinity of the results that are columns, which converts the values read from an
+    /// index like from the table
+    pub affinities: Vec<Affinity>,
+    pub filter: Option<Expression>,
 }
 
 impl<'query> Query<'query> {
-    pub fn new(table: &'query Table, sql_statement: &'query SelectFields) -> Self {
-        let select_fields = sql_statement
-            .fields
+    /// Resolves the results and the filter of a query against `table`, with calls resolved to
+    /// `functions` and collation names to `collations`.
+    pub fn new(
+        table: &'query Table,
+        sql_statement: &'query SelectFields,
+        functions: &Functions,
+        collations: &Collations,
+    ) -> Result<Self, Error> {
+        let mut columns = vec![];
+        let mut results = vec![];
+        for field in &sql_statement.fields {
+            match field {
+                ResultColumn::All => {
+                    for column in &table.columns {
+                        columns.push(column.name.clone());
+                        let column = Expr::Column(column.name.clone());
+                        results.push(Expression::resolve(&column, table, functions, collations)?);
+                    }
+                }
+                ResultColumn::Expr { expr, alias, text } => {
+                    columns.push(match (alias, expr) {
+                        (Some(alias), _) => alias.clone(),
+                        (None, Expr::Column(name)) => table.column(name)?.1.name.clone(),
+                        (None, _) => text.clone(),
+                    });
+                    results.push(Expression::resolve(expr, table, functions, collations)?);
+                }
+            }
+        }
+        let affinities = results
             .iter()
-            .map(|sql_field| table.find_column(sql_field).expect("Fields not found"))
-            .map(|(pos, field)| (pos, field.is_primary_key))
-            .collect::<Vec<_>>();
-        Self {
+            .map(|result| match result {
+                Expression::Column { affinity, .. } => *affinity,
+                _ => Affinity::Blob,
+            })
+            .collect();
+        let filter = sql_statement
+            .where_clause
+            .as_ref()
+            .map(|where_clause| Expression::resolve(where_clause, table, functions, collations))
+            .transpose()?;
+
+        Ok(Self {
             table,
-            select_fields,
-            filter: sql_statement.where_clause.as_ref(),
+            columns,
+            results,
+            affinities,
+            filter,
+        })
+    }
+
+    /// The position of each selected value in the entries of `index`, if the results are
+    /// columns that the index has. Entries hold the values of the indexed columns followed by
+    /// the rowid, which is the value of an INTEGER PRIMARY KEY column.
+    pub fn index_positions(&self, index: &Index) -> Option<Vec<usize>> {
+        self.results
+            .iter()
+            .map(|result| match result {
+                Expression::Column {
+                    is_rowid_alias: true,
+                    ..
+                } => Some(index.columns.len()),
+                Expression::Column { index: column, .. } => index
+                    .find_column(&self.table.columns[*column].name)
+                    .map(|(position, _)| position),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The positions of the record values that the results read, and the filter if `filtered`,
+    /// in order.
+    pub fn read_columns(&self, filtered: bool) -> Vec<usize> {
+        let mut columns = BTreeSet::new();
+        let filter = self.filter.iter().filter(|_| filtered);
+        for expression in self.results.iter().chain(filter) {
+            expression.read_columns(&mut columns);
+        }
+        columns.into_iter().collect()
+    }
+
+    /// Replaces `row` with the results for a record. Reusing the row saves an allocation per
+    /// result row.
+    pub fn project<'a>(
+        &'a self,
+        record: &Record<'a>,
+        row: &mut Vec<ColumnValue<'a>>,
+    ) -> Result<(), Error> {
+        row.clear();
+        for result in &self.results {
+            row.push(result.evaluate(record)?);
+        }
+        Ok(())
+    }
+
+    /// Whether a record satisfies the filter, if there is one.
+    pub fn matches(&self, record: &Record) -> Result<bool, Error> {
+        match &self.filter {
+            Some(filter) => filter.matches(record),
+            None => Ok(true),
         }
     }
 }
@@ -56,318 +450,2165 @@ impl<'query> Query<'query> {
 #[derive(Debug)]
 pub struct IndexQuery<'query> {
     pub table: &'query Table,
-    pub select_fields: Vec<(usize, bool)>,
-    pub filter: &'query sql::WhereClause,
+    pub filter: Filter,
     pub index: &'query Index,
-    pub index_field: usize,
 }
 
 impl<'query> IndexQuery<'query> {
+    /// A lookup of the rows where `field`, a column or an indexed expression, equals `value`.
+    /// The index orders text with the collation it names in `collations`.
     pub fn new(
         table: &'query Table,
-        sql_statement: &'query SelectFields,
+        field: &Expr,
+        value: &Literal,
         index: &'query Index,
-    ) -> Self {
-        let select_fields = sql_statement
-            .fields
-            .iter()
-            .map(|sql_field| table.find_column(sql_field).expect("Fields not found"))
-            .map(|(pos, field)| (pos, field.is_primary_key))
-            .collect::<Vec<_>>();
-
+        collations: &Collations,
+    ) -> Result<Self, Error> {
         let index_field = index
-            .find_column(&sql_statement.where_clause.as_ref().unwrap().field)
-            .unwrap()
-            .0;
-        Self {
+            .position(field)
+            .ok_or_else(|| Error::NoSuchColumn(field.to_string()))?;
+        // Like in comparisons, the value takes the affinity of a column or CAST
+        let affinity = expr::column_affinity(field, table).unwrap_or(Affinity::Blob);
+        let collation = collations.find(index.collation(index_field))?;
+
+        Ok(Self {
             table,
-            select_fields,
-            filter: &sql_statement.where_clause.as_ref().unwrap(),
+            filter: Filter::new(index_field, false, affinity, value, collation),
             index,
-            index_field,
+        })
+    }
+
+    /// How an index entry sorts relative to the looked up value, in the order of the index.
+    pub fn compare(&self, record: &Record) -> Ordering {
+        match self.index.is_descending(self.filter.column) {
+            true => self.filter.compare(record).reverse(),
+            false => self.filter.compare(record),
         }
     }
 }
 
+/// A database file with its schema. Queries only need a shared reference, so one database can
+/// be queried from several threads at once, e.g. in an `Arc`. Statements that change the
+/// database need it to themselves.
 #[derive(Debug)]
 pub struct Database {
     pub header: DatabaseHeader,
-    pub file: File,
-    pub schema: SchemaStore,
+    pub pager: Pager,
+    pub(crate) schema: SchemaStore,
+    /// Statistics about the indexes from `sqlite_stat1`, which tell whether using an index is
+    /// worth it
+    pub statistics: Statistics,
+    /// The number of threads that decode and filter the leaves of full table scans. With more
+    /// than one, the pages are still read in order by the calling thread.
+    pub threads: usize,
+    /// The functions queries can call besides the built-in ones
+    pub functions: Functions,
+    /// The collations columns and indexes can name besides the built-in ones
+    pub collations: Collations,
 }
 
-impl Database {
-    pub fn open(path: &str) -> Result<Self> {
-        let mut file = File::open(path)?;
-        let header = DatabaseHeader::read(&mut file)?;
+/// The number of leaf pages each thread decodes in a batch of a parallel scan. Batches bound
+/// the memory the decoded rows take until they're written out in page order.
+const LEAVES_PER_THREAD: usize = 16;
 
-        let page = Page::read_with_offset(&mut file, header.page_size - 100, 100)?;
-        let schema = SchemaStore::read(page)?;
+/// Gathers the result rows of one leaf page on a worker thread of a parallel scan.
+#[derive(Default)]
+struct LeafRows(Vec<Vec<Value>>);
 
-        Ok(Self {
-            header,
-            file,
-            schema,
-        })
+impl OutputFormatter for LeafRows {
+    fn begin(&mut self, _columns: &[String]) -> Result<()> {
+        Ok(())
     }
 
-    pub fn get_page(&mut self, number: u32) -> Result<Page> {
-        self.file.seek(SeekFrom::Start(
-            number as u64 * self.header.page_size as u64,
-        ))?;
-
-        Page::read(&mut self.file, self.header.page_size)
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        self.0.push(values.iter().map(Value::from).collect());
+        Ok(())
     }
 
-    pub fn select_fields(
-        &mut self,
-        sql_statement: &sql::SelectFields,
-        out: &mut impl std::io::Write,
-    ) -> Result<()> {
-        let schema_definition = self
-            .schema
-            .find_table(&sql_statement.table)
-            .ok_or(anyhow::anyhow!("Table not found: {}", &sql_statement.table))?
-            .clone();
-
-        if let Some(index) = schema_definition.find_applicable_index(&sql_statement.where_clause) {
-            let query = IndexQuery::new(&schema_definition, sql_statement, index);
-            let page = self.get_page(index.rootpage - 1)?;
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
 
-            let mut results = Vec::with_capacity(self.header.page_size as usize);
-            self.read_index(&page, &query, &mut results)?;
-            results.sort_unstable();
+/// Counts the rows written by a query instead of keeping them, for `SELECT count(*)`.
+#[derive(Default)]
+pub struct RowCount(pub usize);
 
-            let query = Query::new(&schema_definition, sql_statement);
-            let page = self.get_page(schema_definition.rootpage - 1)?;
-            self.read_ids_from_table(&page, &query, &results, out)?;
+impl OutputFormatter for RowCount {
+    fn begin(&mut self, _columns: &[String]) -> Result<()> {
+        Ok(())
+    }
 
-            return Ok(());
-        }
+    fn row(&mut self, _values: &[ColumnValue]) -> Result<()> {
+        self.0 += 1;
+        Ok(())
+    }
 
-        let query = Query::new(&schema_definition, sql_statement);
-        let page = self.get_page(schema_definition.rootpage - 1)?;
-        self.read_table(&page, &query, out)
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
     }
+}
 
-    fn read_index(
-        &mut self,
-        page: &Page,
-        query: &IndexQuery,
-        results: &mut Vec<i64>,
-    ) -> Result<()> {
-        match page.header.kind {
-            crate::page::PageKind::InteriorIndex => {
-                self.read_interior_index(&page, &query, results)
-            }
-            crate::page::PageKind::LeafIndex => self.read_leaf_index(&page, &query, results),
-            crate::page::PageKind::InteriorTable | crate::page::PageKind::LeafTable => {
-                bail!("Malformed index: index contains table pages")
-            }
-        }
+impl Database {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::load(Pager::open(path)?)
     }
 
-    fn read_interior_index(
-        &mut self,
-        page: &Page,
-        query: &IndexQuery,
-        results: &mut Vec<i64>,
-    ) -> Result<()> {
-        for cell in page.cells() {
-            let Cell::InteriorIndex { left_child_page, payload, .. } = cell else {
-                bail!("Unsupported cell type");
-            };
-            let record = Record::read(0, payload);
+    /// Opens the content of a database file that was read into memory, see
+    /// [`Pager::from_bytes`].
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        Self::load(Pager::from_bytes(bytes))
+    }
 
-            let ColumnValue::Text(value) = record.values[query.index_field]  else {
-                let page = self.get_page(left_child_page - 1)?;
-                self.read_index(&page, query, results)?;
-                continue;
-            };
+    /// Opens a database file that isn't on the local file system for reading, see
+    /// [`Pager::remote`].
+    pub fn remote(path: &str, file: Box<dyn RemoteFile>) -> Result<Self> {
+        Self::load(Pager::remote(path, file))
+    }
 
-            if query.filter.value.as_bytes() == value {
-                let id = record.values.last().expect("index must have id value");
-                if id.is_number() {
-                    let id: i64 = id.clone().into();
-                    results.push(id);
-                } else {
-                    return Err(anyhow::anyhow!("Id was not a number"));
-                }
-            }
+    /// Reads the header and the schema of the database of a pager.
+    fn load(mut pager: Pager) -> Result<Self> {
+        let header = Self::read_header(&mut pager)?;
+        let mut database = Self {
+            header,
+            pager,
+            schema: SchemaStore::default(),
+            statistics: Statistics::default(),
+            threads: 1,
+            functions: Functions::default(),
+            collations: Collations::default(),
+        };
+        database.read_schema()?;
 
-            // if query.filter.value.as_bytes() > value {
-            //     continue;
-            // }
+        Ok(database)
+    }
 
-            let page = self.get_page(left_child_page - 1)?;
-            self.read_index(&page, query, results)?;
+    fn read_header(pager: &mut Pager) -> Result<DatabaseHeader> {
+        let bytes = pager.read_header(HEADER_SIZE)?;
+        let mut header = DatabaseHeader::parse(&bytes)?;
+        pager.set_page_size(header.page_size);
+        // The size in the header is only valid if it was written together with the change
+        // counter, older versions of SQLite didn't update it. It can't be larger than the file.
+        if header.database_size == 0
+            || bytes[24..28] != bytes[92..96]
+            || header.database_size > pager.page_count()?
+        {
+            header.database_size = pager.page_count()?;
         }
+        Ok(header)
+    }
 
-        if let Some(number) = page.header.right_child_page_number {
-            let page = self.get_page(number - 1)?;
-            self.read_index(&page, query, results)?;
+    /// The tables of the database with their columns, indexes and root pages, e.g. for tools
+    /// that list them.
+    pub fn schema(&self) -> &SchemaStore {
+        &self.schema
+    }
+
+    fn read_schema(&mut self) -> Result<()> {
+        let schema_table = SQLiteSchema::read(TableCursor::new(self, 1)?)?;
+        self.schema = SchemaStore::read(schema_table)?;
+        if !self.header.has_descending_indexes() {
+            self.schema.ignore_sort_orders();
         }
+        self.statistics = Statistics::read(self)?;
         Ok(())
     }
 
-    fn read_leaf_index(
-        &mut self,
-        page: &Page,
-        query: &IndexQuery,
-        results: &mut Vec<i64>,
-    ) -> Result<()> {
-        let ids = page
-            .cells()
-            .map(|cell| match cell {
-                Cell::LeafIndex { payload, .. } => Ok(Record::read(0, payload)),
-                _ => bail!("Unsupported cell type"),
-            })
-            .filter(|record| {
-                let Ok(record) = record else { return true; };
-                format!("{}", record.values[query.index_field]) == query.filter.value
-            })
-            .map_ok(|record| {
-                let id = record.values.last().expect("index must have id value");
-                if id.is_number() {
-                    let id: i64 = id.clone().into();
-                    Ok(id)
-                } else {
-                    Err(anyhow::anyhow!("Id was not a number"))
-                }
-            });
+    /// Creates a database file that only contains the empty schema table.
+    pub fn create(path: &str) -> Result<Self> {
+        Self::create_with_page_size(path, DEFAULT_PAGE_SIZE)
+    }
+
+    fn create_with_page_size(path: &str, page_size: u32) -> Result<Self> {
+        Self::initialize(Pager::create(path)?, page_size)?;
+        Self::open(path)
+    }
 
-        for id in ids {
-            let id = id??;
-            results.push(id);
+    /// Creates a database that only lives in memory, like the one that holds temporary tables.
+    pub fn memory() -> Result<Self> {
+        Self::load(Self::initialize(Pager::memory(), DEFAULT_PAGE_SIZE)?)
+    }
+
+    /// Writes the header and the empty schema table to a new database.
+    fn initialize(mut pager: Pager, page_size: u32) -> Result<Pager> {
+        let schema_page = Page::empty(
+            PageKind::LeafTable,
+            page_size,
+            page_size as usize,
+            HEADER_SIZE as u16,
+        );
+        pager.write(0, &DatabaseHeader::initial(page_size))?;
+        pager.write(HEADER_SIZE as u64, &schema_page.data)?;
+        Ok(pager)
+    }
+
+    /// Iterates over the pages in the freelist.
+    pub fn free_pages(&self) -> Freelist<'_> {
+        Freelist::new(self)
+    }
+
+    pub fn get_page(&self, number: u32) -> Result<Page> {
+        // The first page starts with the database header
+        let offset = if number == 0 { HEADER_SIZE as u16 } else { 0 };
+        if number + 1 == self.header.lock_byte_page() {
+            bail!(Error::Corrupt(format!(
+                "page {} is the lock-byte page",
+                number + 1
+            )));
         }
+        log::trace!("reading page {}", number + 1);
+        let data = self.pager.read_page(number, offset)?;
+        Page::parse(data, self.header.usable_size(), offset)
+    }
 
-        Ok(())
+    /// Reads the child page `number`, counting from 1, of a page at `depth` in a b-tree.
+    fn child_page(&self, number: u32, depth: usize) -> Result<Page> {
+        if depth + 1 >= MAX_DEPTH {
+            bail!(Error::Corrupt("b-tree is too deep".to_string()));
+        }
+        self.get_page(number - 1)
     }
 
-    fn read_ids_from_table(
-        &mut self,
-        page: &Page,
-        query: &Query,
-        ids: &[i64],
-        out: &mut impl std::io::Write,
-    ) -> Result<()> {
-        match page.header.kind {
-            crate::page::PageKind::InteriorTable => {
-                self.read_ids_from_interior_table(&page, &query, ids, out)
-            }
-            crate::page::PageKind::LeafTable => {
-                self.read_ids_from_leaf_table(&page, &query, ids, out)
+    /// The whole payload of a cell of `size` bytes: the part stored on the page, followed by
+    /// the rest from its chain of overflow pages, if it has one.
+    pub fn payload<'page>(
+        &self,
+        local: &'page [u8],
+        size: u64,
+        overflow_page: u32,
+    ) -> Result<Cow<'page, [u8]>> {
+        if overflow_page == 0 {
+            return Ok(Cow::Borrowed(local));
+        }
+        let size = size as usize;
+        let capacity = self.header.usable_size() - 4;
+        let mut payload = Vec::with_capacity(size);
+        payload.extend_from_slice(local);
+        let mut next = overflow_page;
+        while payload.len() < size {
+            if next == 0 {
+                bail!(Error::Corrupt("overflow list is too short".to_string()));
             }
-            crate::page::PageKind::InteriorIndex | crate::page::PageKind::LeafIndex => {
-                bail!("Malformed table: table contains index pages")
+            if next == self.header.lock_byte_page() {
+                bail!(Error::Corrupt(format!(
+                    "overflow page {next} is the lock-byte page"
+                )));
             }
+            let data = self.pager.read_page(next - 1, 0)?;
+            next = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            let length = (size - payload.len()).min(capacity);
+            payload.extend_from_slice(&data[4..4 + length]);
         }
+        Ok(Cow::Owned(payload))
     }
-    fn read_ids_from_interior_table(
-        &mut self,
-        page: &Page,
-        query: &Query,
-        ids: &[i64],
-        out: &mut impl std::io::Write,
-    ) -> Result<()> {
-        let mut ids = ids;
-        for cell in page.cells() {
-            let Cell::InteriorTable { left_child_page, key } = cell else {
-                bail!("Unsupported cell type");
-            };
 
-            let split_at = ids.split_at(ids.partition_point(|id| *id < key as i64));
-            let left_ids = split_at.0; // Ids to the left
-            ids = split_at.1; // Ids to the right
+    /// Describes a page of a b-tree for debugging, see [`Page::inspect`]. `number` counts from
+    /// 1, like in `.btree-inspect`.
+    pub fn inspect_page(&self, number: u32) -> Result<Vec<String>> {
+        self.check_page_number(number)?;
+        Ok(self.get_page(number - 1)?.inspect(number))
+    }
 
-            if !left_ids.is_empty() {
-                let page = self.get_page(left_child_page - 1)?;
-                self.read_ids_from_table(&page, query, left_ids, out)?;
+    /// A hexdump of a page for `.pagehex`, see [`Page::hexdump`]. Pages that aren't part of a
+    /// b-tree, like overflow and freelist pages, are dumped without annotations.
+    pub fn hexdump_page(&self, number: u32) -> Result<Vec<String>> {
+        self.check_page_number(number)?;
+        match self.get_page(number - 1) {
+            Ok(page) => {
+                let database_header = match number {
+                    1 => self.pager.read_header(HEADER_SIZE)?,
+                    _ => vec![],
+                };
+                Ok(page.hexdump(number, &database_header))
+            }
+            Err(error) => {
+                let data = self.pager.read_page(number - 1, 0)?;
+                let mut lines = vec![format!("page {number}: not a b-tree page, {error}")];
+                page::hexdump(0, &data, "", &mut lines);
+                Ok(lines)
             }
         }
+    }
 
-        // No more ids to the right. We're done.
-        if ids.len() == 0 {
-            return Ok(());
+    fn check_page_number(&self, number: u32) -> Result<()> {
+        if number == 0 || number > self.header.database_size {
+            bail!(
+                "page {} is out of range, the database has {} pages",
+                number,
+                self.header.database_size
+            );
         }
+        Ok(())
+    }
 
-        if let Some(number) = page.header.right_child_page_number {
-            let page = self.get_page(number - 1)?;
-            self.read_ids_from_table(&page, query, ids, out)?;
+    /// Writes a modified page back to the file.
+    pub fn write_page(&mut self, number: u32, page: &Page) -> Result<()> {
+        self.pager.write_page(number, page.offset, &page.data)
+    }
+
+    /// Appends a page to the file and returns its page number, counting from 1 like child page
+    /// pointers do. The lock-byte page is skipped and stays empty.
+    fn allocate_page(&mut self) -> Result<u32> {
+        self.header.database_size += 1;
+        if self.header.database_size == self.header.lock_byte_page() {
+            self.header.database_size += 1;
         }
-        Ok(())
+        self.pager
+            .write(28, &self.header.database_size.to_be_bytes())?;
+        Ok(self.header.database_size)
     }
 
-    fn read_ids_from_leaf_table(
-        &self,
-        page: &Page,
-        query: &Query,
-        ids: &[i64],
-        out: &mut impl std::io::Write,
-    ) -> Result<()> {
-        let records = page
-            .cells()
-            .map(|cell| match cell {
-                Cell::LeafTable { payload, rowid, .. } => Ok(Record::read(rowid, payload)),
-                _ => bail!("Unsupported cell type"),
-            })
-            .filter(|record| {
-                let Ok(record) = record else { return true; };
-                ids.binary_search(&record.rowid).is_ok()
-            })
-            .collect::<Result<Vec<Record>>>()?;
+    /// Marks the file as changed by incrementing the change counter. The "version valid for"
+    /// number has to match it, or SQLite ignores parts of the header.
+    fn bump_change_counter(&mut self) -> Result<()> {
+        self.header.change_counter = self.header.change_counter.wrapping_add(1);
+        let counter = self.header.change_counter.to_be_bytes();
+        self.pager.write(24, &counter)?;
+        self.pager.write(92, &counter)
+    }
 
-        for record in records {
-            let values = query
-                .select_fields
-                .iter()
-                .map(|(i, is_primary_key)| {
-                    if *is_primary_key {
-                        format!("{}", record.rowid)
-                    } else {
-                        format!("{}", record.values[*i])
-                    }
-                })
-                .collect::<Vec<_>>()
-                .join("|");
-            write!(out, "{}\n", values)?;
+    /// Increments the schema cookie after a change to the schema.
+    fn bump_schema_cookie(&mut self) -> Result<()> {
+        self.header.schema_cookie = self.header.schema_cookie.wrapping_add(1);
+        self.pager
+            .write(40, &self.header.schema_cookie.to_be_bytes())
+    }
+
+    /// Copies the pages in the write-ahead log into the database file and removes the log.
+    /// Returns the number of pages that were copied.
+    pub fn checkpoint(&mut self) -> Result<usize> {
+        let pages = self.pager.checkpoint()?;
+        self.header = Self::read_header(&mut self.pager)?;
+        Ok(pages)
+    }
+
+    /// Rebuilds the database in a new file and replaces the database file with it. The new
+    /// file has no free pages, and the b-trees are packed with their cells in order.
+    pub fn vacuum(&mut self) -> Result<()> {
+        if self.pager.is_memory() {
+            bail!("VACUUM of in-memory databases is not supported");
         }
+        let header = self.pager.read_header(HEADER_SIZE)?;
+        if header[52..56] != [0; 4] {
+            bail!("VACUUM of auto-vacuum databases is not supported");
+        }
+        if self.header.reserved_space != 0 {
+            bail!("VACUUM of databases with reserved space on pages is not supported");
+        }
+        self.checkpoint()?;
+
+        let path = self.pager.path().to_string();
+        let temp_path = format!("{path}-vacuum");
+        let _ = std::fs::remove_file(&temp_path);
+        let mut target = Self::create_with_page_size(&temp_path, self.header.page_size)?;
+        let result = self.copy_to(&mut target, &header);
+        drop(target);
+        if let Err(error) = result {
+            std::fs::remove_file(&temp_path)?;
+            return Err(error);
+        }
+
+        std::fs::rename(&temp_path, &path)?;
+        let threads = self.threads;
+        *self = Self::open(&path)?;
+        self.threads = threads;
         Ok(())
     }
 
-    fn read_table(
-        &mut self,
-        page: &Page,
-        query: &Query,
-        out: &mut impl std::io::Write,
-    ) -> Result<()> {
-        match page.header.kind {
-            crate::page::PageKind::InteriorTable => self.read_interior_table(&page, &query, out),
-            crate::page::PageKind::LeafTable => self.read_leaf_table(&page, &query, out),
-            crate::page::PageKind::InteriorIndex | crate::page::PageKind::LeafIndex => {
-                bail!("Malformed table: table contains index pages")
+    /// Copies the b-trees and the schema to an empty database, for [`Database::vacuum`].
+    fn copy_to(&mut self, target: &mut Database, header: &[u8]) -> Result<()> {
+        // Header fields that describe the content rather than the file layout
+        for range in [18..20, 44..52, 56..64, 68..72] {
+            target.pager.write(range.start as u64, &header[range])?;
+        }
+
+        let schema_rows = TableCursor::new(self, 1)?.collect::<Result<Vec<_>>>()?;
+        for mut row in schema_rows {
+            if let Some(&Value::Integer(rootpage @ 1..)) = row.values.get(3) {
+                let kind = match self.get_page(rootpage as u32 - 1)?.header.kind {
+                    PageKind::InteriorTable | PageKind::LeafTable => PageKind::LeafTable,
+                    PageKind::InteriorIndex | PageKind::LeafIndex => PageKind::LeafIndex,
+                };
+                let cells = self.btree_cells(rootpage as u32, 0, target)?;
+                let new_rootpage = target.allocate_page()?;
+                target.store_cells(&mut vec![], new_rootpage - 1, kind, cells, None, true)?;
+                row.values[3] = Value::Integer(new_rootpage as i64);
+            }
+            target.insert_record(&Table::schema_table(), row.rowid, &row.values)?;
+        }
+
+        target.header.schema_cookie = self.header.schema_cookie;
+        target.bump_schema_cookie()?;
+        target.header.change_counter = self.header.change_counter;
+        target.bump_change_counter()?;
+        target.pager.sync()
+    }
+
+    /// The cells of a b-tree in order, as leaf cells. The cells of interior index pages hold
+    /// entries too, they come between the entries of the pages to their left and right. The
+    /// overflow pages of the cells are copied to new pages of `target`, and the cells point to
+    /// the copies.
+    fn btree_cells(
+        &self,
+        number: u32,
+        depth: usize,
+        target: &mut Database,
+    ) -> Result<Vec<Vec<u8>>> {
+        if depth >= MAX_DEPTH {
+            bail!(Error::Corrupt("b-tree is too deep".to_string()));
+        }
+        let page = self.get_page(number - 1)?;
+        let mut raw_cells = page.raw_cells();
+        for (cell, raw_cell) in page.cells().zip(&mut raw_cells) {
+            let (Cell::InteriorIndex {
+                size,
+                payload,
+                overflow_page,
+                ..
+            }
+            | Cell::LeafIndex {
+                size,
+                payload,
+                overflow_page,
+            }
+            | Cell::LeafTable {
+                size,
+                payload,
+                overflow_page,
+                ..
+            }) = cell
+            else {
+                continue;
+            };
+            if overflow_page != 0 {
+                let content = self.payload(payload, size, overflow_page)?;
+                let copy = target.write_overflow_pages(&content[payload.len()..])?;
+                // The number of the first overflow page is at the end of the cell
+                let end = raw_cell.len();
+                raw_cell[end - 4..].copy_from_slice(&copy.to_be_bytes());
+            }
+        }
+        if page.header.kind.is_leaf() {
+            return Ok(raw_cells);
+        }
+
+        let mut cells = vec![];
+        for cell in raw_cells {
+            let child = u32::from_be_bytes(cell[0..4].try_into()?);
+            cells.extend(self.btree_cells(child, depth + 1, target)?);
+            if page.header.kind == PageKind::InteriorIndex {
+                cells.push(cell[4..].to_vec());
+            }
+        }
+        let right_child = page
+            .header
+            .right_child_page_number
+            .ok_or_else(|| Error::Corrupt("interior page without children".to_string()))?;
+        cells.extend(self.btree_cells(right_child, depth + 1, target)?);
+        Ok(cells)
+    }
+
+    /// Runs `f` in a transaction. If it fails, the pages it changed are restored from the
+    /// rollback journal and the header and schema are read again, so a statement either
+    /// completes or leaves the database unchanged.
+    fn transaction(&mut self, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        self.pager.begin()?;
+        match f(self) {
+            Ok(()) => self.pager.commit(),
+            Err(error) => {
+                self.pager.rollback()?;
+                self.header = Self::read_header(&mut self.pager)?;
+                self.read_schema()?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Computes the statistics of the indexes of all tables, or of one table or index, and
+    /// writes them to `out` as `sqlite_stat1` rows. Tables without indexes get a row with
+    /// their row count. The statistics are stored in a new `sqlite_stat1` table, but an
+    /// existing one isn't updated yet, since rows can't be deleted. The new statistics are
+    /// used by this connection either way.
+    pub fn analyze(&mut self, name: Option<&str>, out: &mut dyn OutputFormatter) -> Result<()> {
+        let tables = self
+            .schema
+            .tables()
+            .filter(|table| {
+                name.map_or(true, |name| {
+                    table.name == name || table.indexes.iter().any(|index| index.name == name)
+                })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if let (Some(name), true) = (name, tables.is_empty()) {
+            bail!("no such table or index: {}", name);
+        }
+
+        let mut rows = vec![];
+        for table in &tables {
+            if table.indexes.is_empty() {
+                let count = self.scan(&table.name)?.count();
+                if count > 0 {
+                    rows.push([
+                        Value::Text(table.name.clone()),
+                        Value::Null,
+                        Value::Text(count.to_string()),
+                    ]);
+                }
+            }
+            for index in &table.indexes {
+                if name.map_or(false, |name| name != table.name && name != index.name) {
+                    continue;
+                }
+                let stats = stats::analyze_index(self, index)?;
+                if stats.rows > 0 {
+                    rows.push([
+                        Value::Text(table.name.clone()),
+                        Value::Text(index.name.clone()),
+                        Value::Text(stats.to_stat()),
+                    ]);
+                }
+                self.statistics.insert(index.name.clone(), stats);
+            }
+        }
+
+        if self.schema.find_table(STAT1_TABLE_NAME).is_none() && !rows.is_empty() {
+            self.transaction(|database| database.add_statistics(&rows))?;
+        }
+        out.begin(&["tbl", "idx", "stat"].map(String::from))?;
+        for row in &rows {
+            out.row(&row.iter().map(ColumnValue::from).collect::<Vec<_>>())?;
+        }
+        out.finish()
+    }
+
+    /// Creates `sqlite_stat1` with the given rows.
+    fn add_statistics(&mut self, rows: &[[Value; 3]]) -> Result<()> {
+        let (_, statement) = sql::parse_creation(STAT1_TABLE_SQL.as_bytes())
+            .map_err(|_e| anyhow::anyhow!("Failed to parse table definition"))?;
+        let rootpage = self.add_schema_row(
+            "table",
+            STAT1_TABLE_NAME,
+            STAT1_TABLE_NAME,
+            Some(STAT1_TABLE_SQL),
+        )?;
+        self.schema.add_table(&statement, rootpage);
+        let table = self.schema.table(STAT1_TABLE_NAME)?.clone();
+        for (rowid, row) in (1..).zip(rows) {
+            self.insert_record(&table, rowid, row)?;
+        }
+        self.bump_schema_cookie()?;
+        self.bump_change_counter()
+    }
+
+    /// Creates an empty table. `sql` is the source of the statement, which is stored in the
+    /// schema table.
+    pub fn create_table(&mut self, statement: &sql::CreateTableStatement, sql: &str) -> Result<()> {
+        self.transaction(|database| database.add_table(statement, sql))
+    }
+
+    fn add_table(&mut self, statement: &sql::CreateTableStatement, sql: &str) -> Result<()> {
+        let name = &statement.table;
+        if self.schema.find_table(name).is_some() || self.schema.find_virtual_table(name).is_some()
+        {
+            if statement.if_not_exists {
+                return Ok(());
+            }
+            bail!("table {} already exists", name);
+        }
+        if self.schema.find_view(name).is_some() {
+            bail!("view {} already exists", name);
+        }
+        if self
+            .schema
+            .indexes(None)
+            .iter()
+            .any(|index| index.name == *name)
+        {
+            bail!("there is already an index named {}", name);
+        }
+        if name.to_ascii_lowercase().starts_with("sqlite_") {
+            bail!("object name reserved for internal use: {}", name);
+        }
+        let sql = sql::schema_sql(sql)
+            .ok_or_else(|| anyhow::anyhow!("not a CREATE TABLE statement: {}", sql))?;
+
+        // Tables with AUTOINCREMENT keep their largest rowid in sqlite_sequence
+        let autoincrement = statement.fields.iter().any(|field| field.autoincrement);
+        if autoincrement && self.schema.sequence_table().is_none() {
+            let (_, sequence) = sql::parse_creation(SEQUENCE_TABLE_SQL.as_bytes())
+                .map_err(|_e| anyhow::anyhow!("Failed to parse table definition"))?;
+            let rootpage = self.add_schema_row(
+                "table",
+                SEQUENCE_TABLE_NAME,
+                SEQUENCE_TABLE_NAME,
+                Some(SEQUENCE_TABLE_SQL),
+            )?;
+            self.schema.add_table(&sequence, rootpage);
+        }
+
+        let rootpage = self.add_schema_row("table", name, name, Some(&sql))?;
+        self.schema.add_table(statement, rootpage);
+        // The PRIMARY KEY and UNIQUE constraints are enforced with automatic indexes
+        for (number, columns) in (1..).zip(statement.automatic_indexes()) {
+            let index_name = automatic_index_name(name, number);
+            let rootpage = self.add_schema_row("index", &index_name, name, None)?;
+            self.schema
+                .add_automatic_index(&index_name, name, &columns, rootpage)?;
+        }
+        self.bump_schema_cookie()?;
+        self.bump_change_counter()
+    }
+
+    /// Creates a view. `sql` is the source of the statement, which is stored in the schema
+    /// table. The SELECT of the view isn't checked until the view is queried, like in SQLite.
+    pub fn create_view(&mut self, statement: &sql::CreateViewStatement, sql: &str) -> Result<()> {
+        self.transaction(|database| database.add_view(statement, sql))
+    }
+
+    fn add_view(&mut self, statement: &sql::CreateViewStatement, sql: &str) -> Result<()> {
+        let name = &statement.name;
+        if self.schema.find_view(name).is_some() {
+            if statement.if_not_exists {
+                return Ok(());
+            }
+            bail!("view {} already exists", name);
+        }
+        if self.schema.find_table(name).is_some() || self.schema.find_virtual_table(name).is_some()
+        {
+            bail!("table {} already exists", name);
+        }
+        if self
+            .schema
+            .indexes(None)
+            .iter()
+            .any(|index| index.name == *name)
+        {
+            bail!("there is already an index named {}", name);
+        }
+        if name.to_ascii_lowercase().starts_with("sqlite_") {
+            bail!("object name reserved for internal use: {}", name);
+        }
+        let sql = sql::schema_sql(sql)
+            .ok_or_else(|| anyhow::anyhow!("not a CREATE VIEW statement: {}", sql))?;
+
+        // Views have no b-tree, so their root page is 0
+        self.insert_schema_row("view", name, name, 0, Some(&sql))?;
+        self.schema.add_view(statement);
+        self.bump_schema_cookie()?;
+        self.bump_change_counter()
+    }
+
+    /// Would create a virtual table, but no modules are built in. Only the shadow tables of
+    /// the virtual tables of existing databases can be read.
+    pub fn create_virtual_table(
+        &mut self,
+        statement: &sql::CreateVirtualTableStatement,
+    ) -> Result<()> {
+        let name = &statement.name;
+        if self.schema.find_table(name).is_some() || self.schema.find_virtual_table(name).is_some()
+        {
+            if statement.if_not_exists {
+                return Ok(());
+            }
+            bail!("table {} already exists", name);
+        }
+        Err(Error::NoSuchModule(statement.module.clone()).into())
+    }
+
+    /// Creates an index and fills it with an entry for each row of the table. `sql` is the
+    /// source of the statement, which is stored in the schema table.
+    pub fn create_index(&mut self, statement: &sql::CreateIndexStatement, sql: &str) -> Result<()> {
+        self.transaction(|database| database.add_index(statement, sql))
+    }
+
+    fn add_index(&mut self, statement: &sql::CreateIndexStatement, sql: &str) -> Result<()> {
+        let name = &statement.name;
+        if self
+            .schema
+            .indexes(None)
+            .iter()
+            .any(|index| index.name == *name)
+        {
+            if statement.if_not_exists {
+                return Ok(());
+            }
+            bail!("index {} already exists", name);
+        }
+        if self.schema.find_table(name).is_some() || self.schema.find_virtual_table(name).is_some()
+        {
+            bail!("there is already a table named {}", name);
+        }
+        if name.to_ascii_lowercase().starts_with("sqlite_") {
+            bail!("object name reserved for internal use: {}", name);
+        }
+        if self.schema.find_view(&statement.table).is_some() {
+            bail!("views may not be indexed");
+        }
+        if self.schema.find_virtual_table(&statement.table).is_some() {
+            bail!("virtual tables may not be indexed");
+        }
+        let table = self.schema.table(&statement.table)?.clone();
+        if !table.is_user_table() {
+            bail!("table {} may not be indexed", table.name);
+        }
+        let columns = index_columns(&table, &statement.fields, &statement.expressions)?;
+        let condition = partial_index_condition(&table, statement.where_clause.as_ref())?;
+        let mut statement = statement.clone();
+        if !self.header.has_descending_indexes() {
+            statement.descending.fill(false);
+        }
+        let collations = self.index_collations(&table.index_collations(&statement))?;
+        let sql = sql::schema_sql(sql)
+            .ok_or_else(|| anyhow::anyhow!("not a CREATE INDEX statement: {}", sql))?;
+
+        let mut entries = vec![];
+        for row in self.scan(&table.name)? {
+            let mut row = row?;
+            let missing = table.columns.iter().skip(row.values.len());
+            row.values
+                .extend(missing.map(|column| Value::from(column.default_value())));
+            if satisfies(condition.as_ref(), row.rowid, &row.values)? {
+                entries.push(index_entry(&columns, row.rowid, &row.values)?);
+            }
+        }
+        entries.sort_by(|a, b| {
+            let a = a.iter().map(ColumnValue::from).collect::<Vec<_>>();
+            let b = b.iter().map(ColumnValue::from).collect::<Vec<_>>();
+            compare_keys(&a, &b, &statement.descending, &collations)
+        });
+        // Rows with the same values are next to each other once sorted
+        if statement.unique {
+            let key_size = statement.fields.len();
+            let clash = entries.windows(2).any(|pair| {
+                let a = pair[0][..key_size].iter().map(ColumnValue::from);
+                let b = pair[1][..key_size].iter().map(ColumnValue::from);
+                let (a, b) = (a.collect::<Vec<_>>(), b.collect::<Vec<_>>());
+                !a.iter().any(|value| matches!(value, ColumnValue::Null))
+                    && compare_keys(&a, &b, &[], &collations) == Ordering::Equal
+            });
+            if clash {
+                bail!(unique_constraint(
+                    &table,
+                    name,
+                    &statement.fields,
+                    &statement.expressions
+                ));
+            }
+        }
+        let cells = entries
+            .iter()
+            .map(|entry| self.index_cell(entry))
+            .collect::<Result<Vec<_>>>()?;
+
+        // The sorted entries are packed into full pages, building the b-tree bottom up
+        let rootpage = self.add_schema_row("index", name, &table.name, Some(&sql))?;
+        let mut path = vec![];
+        self.store_cells(
+            &mut path,
+            rootpage - 1,
+            PageKind::LeafIndex,
+            cells,
+            None,
+            true,
+        )?;
+
+        self.schema.add_index(&statement, rootpage)?;
+        self.bump_schema_cookie()?;
+        self.bump_change_counter()
+    }
+
+    /// Allocates an empty root page for a new table or index and adds its row to the schema
+    /// table. Automatic indexes have no `sql`.
+    fn add_schema_row(
+        &mut self,
+        kind: &str,
+        name: &str,
+        table_name: &str,
+        sql: Option<&str>,
+    ) -> Result<u32> {
+        let rootpage = self.allocate_page()?;
+        let page_kind = match kind {
+            "index" => PageKind::LeafIndex,
+            _ => PageKind::LeafTable,
+        };
+        let page = self.empty_page(rootpage - 1, page_kind);
+        self.write_page(rootpage - 1, &page)?;
+        self.insert_schema_row(kind, name, table_name, rootpage, sql)?;
+        Ok(rootpage)
+    }
+
+    /// Adds a row to the schema table, after its other rows.
+    fn insert_schema_row(
+        &mut self,
+        kind: &str,
+        name: &str,
+        table_name: &str,
+        rootpage: u32,
+        sql: Option<&str>,
+    ) -> Result<()> {
+        let values = [
+            Value::Text(kind.to_string()),
+            Value::Text(name.to_string()),
+            Value::Text(table_name.to_string()),
+            Value::Integer(rootpage as i64),
+            sql.map_or(Value::Null, |sql| Value::Text(sql.to_string())),
+        ];
+        let rowid = self.max_rowid(1)?.map_or(1, |max| max + 1);
+        self.insert_record(&Table::schema_table(), rowid, &values)
+    }
+
+    /// Inserts the rows of an INSERT statement. Each row is added to the leaf page that covers
+    /// its rowid, splitting pages that run out of space.
+    pub fn insert(&mut self, statement: &sql::InsertStatement) -> Result<()> {
+        self.transaction(|database| database.insert_rows(statement))
+    }
+
+    fn insert_rows(&mut self, statement: &sql::InsertStatement) -> Result<()> {
+        if self.schema.find_view(&statement.table).is_some() {
+            bail!("cannot modify {} because it is a view", statement.table);
+        }
+        if let Some(trigger) = self.schema.triggers(Some(&statement.table)).first() {
+            bail!(
+                "cannot modify {} because triggers are not supported yet: {}",
+                statement.table,
+                trigger.name
+            );
+        }
+        let table = self.schema.table(&statement.table)?.clone();
+        let indexes = table
+            .indexes
+            .iter()
+            .map(|index| {
+                let columns = index_columns(&table, &index.columns, &index.expressions)?;
+                let condition = partial_index_condition(&table, index.where_clause.as_ref())?;
+                Ok((index, columns, condition))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let columns = if statement.columns.is_empty() {
+            (0..table.columns.len()).collect()
+        } else {
+            statement
+                .columns
+                .iter()
+                .map(|name| Ok(table.column(name)?.0))
+                .collect::<Result<Vec<_>, Error>>()?
+        };
+
+        // The largest rowid an AUTOINCREMENT table has used, which new rowids have to exceed
+        // even if the row was deleted since
+        let sequence_row = match table.is_autoincrement() {
+            true => self.sequence_row(&table.name)?,
+            false => None,
+        };
+        let mut sequence = sequence_row.map(|(_, sequence)| sequence);
+        if table.is_autoincrement() && !statement.rows.is_empty() {
+            sequence.get_or_insert(0);
+        }
+
+        for row in &statement.rows {
+            if row.len() != columns.len() {
+                if statement.columns.is_empty() {
+                    bail!(
+                        "table {} has {} columns but {} values were supplied",
+                        table.name,
+                        columns.len(),
+                        row.len()
+                    );
+                }
+                bail!("{} values for {} columns", row.len(), columns.len());
+            }
+            let (rowid, values) = self.row_values(&table, &columns, row, sequence)?;
+            let mut entries = vec![];
+            for (index, columns, condition) in &indexes {
+                if !satisfies(condition.as_ref(), rowid, &values)? {
+                    continue;
+                }
+                let entry = index_entry(columns, rowid, &values)?;
+                if index.unique && self.has_entry(index, &entry[..index.columns.len()])? {
+                    bail!(unique_constraint(
+                        &table,
+                        &index.name,
+                        &index.columns,
+                        &index.expressions
+                    ));
+                }
+                entries.push((index, entry));
+            }
+            self.insert_record(&table, rowid, &values)?;
+            for (index, entry) in entries {
+                self.insert_index_entry(index, &entry)?;
+            }
+            sequence = sequence.map(|sequence| sequence.max(rowid));
+        }
+
+        if let Some(sequence) = sequence {
+            let sequence_table = self.schema.table(SEQUENCE_TABLE_NAME)?.clone();
+            let values = [Value::Text(table.name.clone()), Value::Integer(sequence)];
+            match sequence_row {
+                Some((rowid, _)) => self.update_record(&sequence_table, rowid, &values)?,
+                None => {
+                    let rowid = self.max_rowid(sequence_table.rootpage)?.unwrap_or(0) + 1;
+                    self.insert_record(&sequence_table, rowid, &values)?
+                }
+            }
+        }
+        self.bump_change_counter()
+    }
+
+    /// The largest rowid an AUTOINCREMENT table has used, as kept in `sqlite_sequence`, or
+    /// `None` if it has no rows yet.
+    pub fn sequence(&self, table_name: &str) -> Result<Option<i64>> {
+        Ok(self.sequence_row(table_name)?.map(|(_, sequence)| sequence))
+    }
+
+    /// The rowid of the `sqlite_sequence` row of a table, and its sequence.
+    fn sequence_row(&self, table_name: &str) -> Result<Option<(i64, i64)>> {
+        if self.schema.sequence_table().is_none() {
+            return Ok(None);
+        }
+        for row in self.scan(SEQUENCE_TABLE_NAME)? {
+            let row = row?;
+            match row.values.as_slice() {
+                [Value::Text(name), sequence, ..] if name == table_name => {
+                    let sequence = match sequence {
+                        Value::Integer(sequence) => *sequence,
+                        _ => 0,
+                    };
+                    return Ok(Some((row.rowid, sequence)));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// The collations of the columns of an index, by name.
+    pub(crate) fn index_collations(&self, names: &[Option<String>]) -> Result<Vec<Collation>> {
+        Ok(names
+            .iter()
+            .map(|name| self.collations.find(name.as_deref()))
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Encodes an index entry as a leaf cell. The part of a large entry that doesn't fit in
+    /// the cell goes to overflow pages.
+    fn index_cell(&mut self, entry: &[Value]) -> Result<Vec<u8>> {
+        let payload = encode_record(entry);
+        let mut cell = varint::write(payload.len() as i64);
+        cell.extend(self.local_payload(PageKind::LeafIndex, payload)?);
+        Ok(cell)
+    }
+
+    /// The part of `payload` that's stored in a cell on a page of `kind`. When the payload is
+    /// too large for that, the rest is written to a new chain of overflow pages, and the part
+    /// ends with the number of its first page.
+    fn local_payload(&mut self, kind: PageKind, mut payload: Vec<u8>) -> Result<Vec<u8>> {
+        let local = kind.local_payload_size(payload.len(), self.header.usable_size());
+        if local == payload.len() {
+            return Ok(payload);
+        }
+        let overflow_page = self.write_overflow_pages(&payload[local..])?;
+        payload.truncate(local);
+        payload.extend(overflow_page.to_be_bytes());
+        Ok(payload)
+    }
+
+    /// Writes `content` to new overflow pages and returns the number of the first one. Each
+    /// page starts with the number of the next one, or 0 on the last page.
+    fn write_overflow_pages(&mut self, content: &[u8]) -> Result<u32> {
+        let capacity = self.header.usable_size() - 4;
+        let pages = content
+            .chunks(capacity)
+            .map(|_| self.allocate_page())
+            .collect::<Result<Vec<_>>>()?;
+        for (index, chunk) in content.chunks(capacity).enumerate() {
+            let next = pages.get(index + 1).copied().unwrap_or(0);
+            let mut data = vec![0; self.header.page_size as usize];
+            data[..4].copy_from_slice(&next.to_be_bytes());
+            data[4..4 + chunk.len()].copy_from_slice(chunk);
+            self.pager.write_page(pages[index] - 1, 0, &data)?;
+        }
+        Ok(pages[0])
+    }
+
+    /// Whether a unique index has an entry with the values of `key` in its columns. Entries
+    /// with a NULL in them never clash, so a key with a NULL is never found.
+    fn has_entry(&self, index: &Index, key: &[Value]) -> Result<bool> {
+        if key.iter().any(|value| matches!(value, Value::Null)) {
+            return Ok(false);
+        }
+        let key = key.iter().map(ColumnValue::from).collect::<Vec<_>>();
+        let collations = self.index_collations(&index.collations)?;
+        let compare = |payload: &[u8]| -> Result<Ordering> {
+            let values = Record::read(0, payload)?.values();
+            let prefix = &values[..key.len().min(values.len())];
+            Ok(compare_keys(prefix, &key, &index.descending, &collations))
+        };
+
+        let mut page = self.get_page(index.rootpage - 1)?;
+        let mut depth = 0;
+        loop {
+            // Equal entries are either in the cell that sorts equal, or left of the first cell
+            // that sorts after the key
+            let mut child = page.header.right_child_page_number;
+            for cell in page.cells() {
+                let (size, payload, overflow_page, left_child_page) = match cell {
+                    Cell::InteriorIndex {
+                        left_child_page,
+                        size,
+                        payload,
+                        overflow_page,
+                    } => (size, payload, overflow_page, Some(left_child_page)),
+                    Cell::LeafIndex {
+                        size,
+                        payload,
+                        overflow_page,
+                    } => (size, payload, overflow_page, None),
+                    _ => bail!(Error::Corrupt("index contains table pages".to_string())),
+                };
+                match compare(&self.payload(payload, size, overflow_page)?)? {
+                    Ordering::Less => {}
+                    Ordering::Equal => return Ok(true),
+                    Ordering::Greater => {
+                        child = left_child_page;
+                        break;
+                    }
+                }
+            }
+            match child {
+                Some(number) => {
+                    page = self.child_page(number, depth)?;
+                    depth += 1;
+                }
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Adds an entry to an index b-tree, after the entries that sort before it.
+    fn insert_index_entry(&mut self, index: &Index, entry: &[Value]) -> Result<()> {
+        let new_cell = self.index_cell(entry)?;
+        let key = entry.iter().map(ColumnValue::from).collect::<Vec<_>>();
+        let collations = self.index_collations(&index.collations)?;
+        // The position of the first entry on the page that sorts after the new one
+        let position = |page: &Page| -> Result<usize> {
+            for (position, cell) in page.cells().enumerate() {
+                let (Cell::InteriorIndex {
+                    size,
+                    payload,
+                    overflow_page,
+                    ..
+                }
+                | Cell::LeafIndex {
+                    size,
+                    payload,
+                    overflow_page,
+                }) = cell
+                else {
+                    bail!(Error::Corrupt("index contains table pages".to_string()));
+                };
+                let payload = self.payload(payload, size, overflow_page)?;
+                let record = Record::read(0, &payload)?;
+                if compare_keys(&record.values(), &key, &index.descending, &collations)
+                    == Ordering::Greater
+                {
+                    return Ok(position);
+                }
+            }
+            Ok(page.cell_pointers.len())
+        };
+
+        let mut path = vec![];
+        let mut number = index.rootpage - 1;
+        let mut page = self.get_page(number)?;
+        while page.header.kind == PageKind::InteriorIndex {
+            let index = position(&page)?;
+            let child = match page.cell(index) {
+                Some(Cell::InteriorIndex {
+                    left_child_page, ..
+                }) => left_child_page,
+                _ => page
+                    .header
+                    .right_child_page_number
+                    .ok_or_else(|| Error::Corrupt("interior page without children".to_string()))?,
+            };
+            page = self.child_page(child, path.len())?;
+            path.push((number, index));
+            number = child - 1;
+        }
+        if page.header.kind != PageKind::LeafIndex {
+            bail!(Error::Corrupt("index contains table pages".to_string()));
+        }
+
+        let index = position(&page)?;
+        if new_cell.len() + 2 <= page.free_space() {
+            page.insert_cell(index, &new_cell)?;
+            return self.write_page(number, &page);
+        }
+        let mut cells = page.raw_cells();
+        cells.insert(index, new_cell);
+        self.store_cells(&mut path, number, PageKind::LeafIndex, cells, None, false)
+    }
+
+    /// The rowid and the record values of a row to insert. Columns that aren't given get their
+    /// default value, and values are converted to the affinity of their column. A new rowid is
+    /// larger than the ones in the table, and than `sequence` for AUTOINCREMENT tables.
+    fn row_values(
+        &mut self,
+        table: &Table,
+        columns: &[usize],
+        row: &[sql::Expr],
+        sequence: Option<i64>,
+    ) -> Result<(i64, Vec<Value>)> {
+        let mut given = vec![None; table.columns.len()];
+        for (&index, expr) in columns.iter().zip(row) {
+            let sql::Expr::Literal(value) = expr else {
+                bail!("only literal values can be inserted");
+            };
+            given[index] = Some(value.clone());
+        }
+
+        let mut rowid = None;
+        let mut values = Vec::with_capacity(table.columns.len());
+        for (column, value) in table.columns.iter().zip(given) {
+            let value = match (value, &column.default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => sql::parse_literal(default)
+                    .ok_or_else(|| anyhow::anyhow!("unsupported default value: {}", default))?,
+                (None, None) => Literal::Null,
+            };
+            let value = column.affinity().apply(&value);
+
+            if column.is_rowid_alias {
+                // The rowid is stored in the cell, the column itself is always NULL
+                match value {
+                    Literal::Null => {}
+                    Literal::Integer(n) => rowid = Some(n),
+                    _ => bail!("datatype mismatch"),
+                }
+                values.push(Value::Null);
+                continue;
+            }
+            if column.not_null && matches!(value, Literal::Null) {
+                bail!("NOT NULL constraint failed: {}.{}", table.name, column.name);
+            }
+            values.push(Value::from(value));
+        }
+
+        let rowid = match rowid {
+            Some(rowid) => rowid,
+            None => match self.max_rowid(table.rootpage)?.max(sequence) {
+                Some(max) => max
+                    .checked_add(1)
+                    .ok_or_else(|| anyhow::anyhow!("database or disk is full"))?,
+                None => 1,
+            },
+        };
+        Ok((rowid, values))
+    }
+
+    /// The largest rowid of a table, found in the last cell of its rightmost leaf.
+    fn max_rowid(&self, rootpage: u32) -> Result<Option<i64>> {
+        let mut page = self.get_page(rootpage - 1)?;
+        let mut depth = 0;
+        while let Some(right_child) = page.header.right_child_page_number {
+            page = self.child_page(right_child, depth)?;
+            depth += 1;
+        }
+        match page.cell(page.cell_pointers.len().wrapping_sub(1)) {
+            Some(Cell::LeafTable { rowid, .. }) => Ok(Some(rowid)),
+            Some(_) => bail!(Error::Corrupt("table contains index pages".to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn insert_record(&mut self, table: &Table, rowid: i64, values: &[Value]) -> Result<()> {
+        self.write_record(table, rowid, values, false)
+    }
+
+    /// Replaces the record of an existing row.
+    fn update_record(&mut self, table: &Table, rowid: i64, values: &[Value]) -> Result<()> {
+        self.write_record(table, rowid, values, true)
+    }
+
+    /// Adds a row, or replaces the one with the same rowid if `replace`.
+    fn write_record(
+        &mut self,
+        table: &Table,
+        rowid: i64,
+        values: &[Value],
+        replace: bool,
+    ) -> Result<()> {
+        let payload = encode_record(values);
+
+        // Descend to the leaf whose range covers the rowid. Interior cells point to the pages
+        // with rowids up to and including their key. The path keeps the interior pages and the
+        // index of the child taken, which is the cell count for the right child.
+        let mut path = vec![];
+        let mut rightmost = true;
+        let mut number = table.rootpage - 1;
+        let mut page = self.get_page(number)?;
+        while page.header.kind == PageKind::InteriorTable {
+            let cell_count = page.cell_pointers.len();
+            let (index, child) = page
+                .cells()
+                .enumerate()
+                .find_map(|(index, cell)| match cell {
+                    Cell::InteriorTable {
+                        left_child_page,
+                        key,
+                    } if rowid <= key => Some((index, left_child_page)),
+                    _ => None,
+                })
+                .or(page
+                    .header
+                    .right_child_page_number
+                    .map(|child| (cell_count, child)))
+                .ok_or_else(|| Error::Corrupt("interior page without children".to_string()))?;
+            rightmost &= index == cell_count;
+            page = self.child_page(child, path.len())?;
+            path.push((number, index));
+            number = child - 1;
+        }
+        if page.header.kind != PageKind::LeafTable {
+            bail!(Error::Corrupt("table contains index pages".to_string()));
+        }
+
+        let mut position = page.cell_pointers.len();
+        let mut replaced = None;
+        for (index, cell_on_page) in page.cells().enumerate() {
+            let Cell::LeafTable {
+                rowid: existing,
+                overflow_page,
+                ..
+            } = cell_on_page
+            else {
+                bail!(Error::Corrupt("unexpected cell in table leaf".to_string()));
+            };
+            if existing == rowid && replace {
+                // Pages are never freed yet, so the chain of the old record would be lost
+                if overflow_page != 0 {
+                    bail!("replacing records on overflow pages is not supported yet");
+                }
+                replaced = Some(index);
+                break;
+            }
+            if existing == rowid {
+                let column = table
+                    .columns
+                    .iter()
+                    .find(|column| column.is_rowid_alias)
+                    .map_or("rowid", |column| &column.name);
+                bail!(Error::UniqueConstraint(format!(
+                    "{}.{}",
+                    table.name, column
+                )));
+            }
+            if existing > rowid {
+                position = index;
+                break;
+            }
+        }
+
+        // The overflow pages are only written once the record is known to be valid
+        let mut cell = varint::write(payload.len() as i64);
+        cell.extend(varint::write(rowid));
+        cell.extend(self.local_payload(PageKind::LeafTable, payload)?);
+
+        match replaced {
+            Some(index) => {
+                let mut cells = page.raw_cells();
+                cells[index] = cell;
+                return self.store_cells(
+                    &mut path,
+                    number,
+                    PageKind::LeafTable,
+                    cells,
+                    None,
+                    false,
+                );
+            }
+            None if replace => bail!("no row {} in {} to replace", rowid, table.name),
+            None => {}
+        }
+
+        if cell.len() + 2 <= page.free_space() {
+            page.insert_cell(position, &cell)?;
+            return self.write_page(number, &page);
+        }
+
+        // Appending to the end of the table leaves the full page as it is, so that tables
+        // filled in rowid order don't end up with half empty pages.
+        let append = rightmost && position == page.cell_pointers.len();
+        let mut cells = page.raw_cells();
+        cells.insert(position, cell);
+        self.store_cells(&mut path, number, PageKind::LeafTable, cells, None, append)
+    }
+
+    /// Writes `cells` to a page. If they don't fit, they are spread over the page and new
+    /// pages to its left, and the parent at the end of `path` gets a divider cell for each new
+    /// page. That can overflow the parent in turn. A root page keeps its page number, so when
+    /// it overflows, its cells move to new children and it becomes an interior page.
+    fn store_cells(
+        &mut self,
+        path: &mut Vec<(u32, usize)>,
+        number: u32,
+        kind: PageKind,
+        cells: Vec<Vec<u8>>,
+        right_child: Option<u32>,
+        append: bool,
+    ) -> Result<()> {
+        let mut page = self.empty_page(number, kind);
+        if page.fits(&cells) {
+            page.set_cells(&cells, right_child)?;
+            return self.write_page(number, &page);
+        }
+
+        let kind = page.header.kind;
+        let mut siblings = self.split_cells(kind, cells, right_child, append);
+        let last = siblings
+            .pop()
+            .expect("cells that don't fit on a page are split");
+        let mut dividers = Vec::with_capacity(siblings.len());
+        for sibling in siblings {
+            let child = self.allocate_page()?;
+            self.write_sibling(child - 1, kind, &sibling)?;
+            let mut divider = child.to_be_bytes().to_vec();
+            divider.extend(sibling.divider);
+            dividers.push(divider);
+        }
+
+        let Some((parent, index)) = path.pop() else {
+            let child = self.allocate_page()?;
+            self.write_sibling(child - 1, kind, &last)?;
+            return self.store_cells(path, number, kind.interior(), dividers, Some(child), append);
+        };
+        self.write_sibling(number, kind, &last)?;
+
+        let parent_page = self.get_page(parent)?;
+        let mut parent_cells = parent_page.raw_cells();
+        parent_cells.splice(index..index, dividers);
+        let right_child = parent_page.header.right_child_page_number;
+        self.store_cells(
+            path,
+            parent,
+            kind.interior(),
+            parent_cells,
+            right_child,
+            false,
+        )
+    }
+
+    /// Divides the cells of an overflowing page into pages of about the same size. When
+    /// appending, the pages are filled as much as possible instead.
+    ///
+    /// Except for table leaves, the last cell of each sibling but the last moves up to the
+    /// parent. On interior pages its child becomes the sibling's right child. Table leaves keep
+    /// all their rows, and the parent only gets their largest rowid.
+    fn split_cells(
+        &self,
+        kind: PageKind,
+        cells: Vec<Vec<u8>>,
+        right_child: Option<u32>,
+        append: bool,
+    ) -> Vec<Sibling> {
+        let header_size = if kind.is_interior() { 12 } else { 8 };
+        let capacity = self.header.usable_size() - header_size;
+        let total: usize = cells.iter().map(|cell| cell.len() + 2).sum();
+        let target = if append {
+            capacity
+        } else {
+            let parts = (total + capacity - 1) / capacity;
+            (total + parts - 1) / parts
+        };
+
+        let mut groups: Vec<Vec<Vec<u8>>> = vec![vec![]];
+        let mut size = 0;
+        for cell in cells {
+            let len = cell.len() + 2;
+            if size + len > capacity || size >= target {
+                groups.push(vec![]);
+                size = 0;
+            }
+            size += len;
+            groups.last_mut().unwrap().push(cell);
+        }
+
+        let last = groups.len() - 1;
+        groups
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut cells)| {
+                if i == last {
+                    return Sibling {
+                        cells,
+                        right_child,
+                        divider: vec![],
+                    };
+                }
+                match kind {
+                    PageKind::InteriorTable | PageKind::InteriorIndex => {
+                        let mut promoted = cells.pop().unwrap_or_default();
+                        let divider = promoted.split_off(4);
+                        Sibling {
+                            cells,
+                            right_child: Some(u32::from_be_bytes([
+                                promoted[0],
+                                promoted[1],
+                                promoted[2],
+                                promoted[3],
+                            ])),
+                            divider,
+                        }
+                    }
+                    PageKind::LeafIndex => {
+                        let divider = cells.pop().unwrap_or_default();
+                        Sibling {
+                            cells,
+                            right_child: None,
+                            divider,
+                        }
+                    }
+                    PageKind::LeafTable => {
+                        let rowid = cells.last().map_or(0, |cell| {
+                            let (_, size_len) = varint::read(cell);
+                            varint::read(&cell[size_len..]).0
+                        });
+                        Sibling {
+                            cells,
+                            right_child: None,
+                            divider: varint::write(rowid),
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn write_sibling(&mut self, number: u32, kind: PageKind, sibling: &Sibling) -> Result<()> {
+        let mut page = self.empty_page(number, kind);
+        page.set_cells(&sibling.cells, sibling.right_child)?;
+        self.write_page(number, &page)
+    }
+
+    fn empty_page(&self, number: u32, kind: PageKind) -> Page {
+        let offset = if number == 0 { HEADER_SIZE as u16 } else { 0 };
+        Page::empty(
+            kind,
+            self.header.page_size,
+            self.header.usable_size(),
+            offset,
+        )
+    }
+
+    /// Iterates over the rows of a table in rowid order.
+    pub fn scan(&self, table_name: &str) -> Result<TableCursor<'_>> {
+        let rootpage = self.schema.table(table_name)?.rootpage;
+        TableCursor::new(self, rootpage)
+    }
+
+    pub fn pragma(
+        &mut self,
+        pragma: &sql::PragmaStatement,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<()> {
+        match (pragma.name.to_ascii_lowercase().as_str(), &pragma.argument) {
+            ("table_info", Some(table_name)) => {
+                let table = self.schema.table(table_name)?;
+                out.begin(
+                    &["cid", "name", "type", "notnull", "dflt_value", "pk"].map(String::from),
+                )?;
+
+                for (cid, column) in table.columns.iter().enumerate() {
+                    let pk = table
+                        .primary_key
+                        .iter()
+                        .position(|name| *name == column.name)
+                        .map_or(0, |position| position as i64 + 1);
+                    out.row(&[
+                        ColumnValue::I64(cid as i64),
+                        ColumnValue::Text(column.name.as_bytes().into()),
+                        ColumnValue::Text(
+                            column.ty.as_deref().unwrap_or_default().as_bytes().into(),
+                        ),
+                        ColumnValue::I64(column.not_null as i64),
+                        column.default.as_ref().map_or(ColumnValue::Null, |value| {
+                            ColumnValue::Text(value.as_bytes().into())
+                        }),
+                        ColumnValue::I64(pk),
+                    ])?;
+                }
+                out.finish()
+            }
+            ("integrity_check", None) => {
+                let problems = integrity::check(self)?;
+                out.begin(&["integrity_check".to_string()])?;
+                if problems.is_empty() {
+                    out.row(&[ColumnValue::Text(b"ok"[..].into())])?;
+                }
+                for problem in &problems {
+                    out.row(&[ColumnValue::Text(problem.as_bytes().into())])?;
+                }
+                out.finish()
+            }
+            ("page_size", None) => {
+                out.begin(&["page_size".to_string()])?;
+                out.row(&[ColumnValue::I64(self.header.page_size as i64)])?;
+                out.finish()
+            }
+            _ => bail!("Unsupported pragma: {}", pragma.name),
+        }
+    }
+
+    pub fn select_fields(
+        &self,
+        sql_statement: &sql::SelectFields,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<()> {
+        let table = sql_statement
+            .table()
+            .ok_or_else(|| anyhow::anyhow!("subqueries are run by the connection"))?;
+        let schema_definition = self.schema.table(table)?;
+
+        if let Some((field, value, index)) = self.choose_index(schema_definition, sql_statement) {
+            let index_query =
+                IndexQuery::new(schema_definition, field, value, index, &self.collations)?;
+            let query = Query::new(
+                schema_definition,
+                sql_statement,
+                &self.functions,
+                &self.collations,
+            )?;
+            let page = self.get_page(index.rootpage - 1)?;
+            out.begin(&query.columns)?;
+
+            // The index has all selected values, so the table doesn't have to be read. The rows
+            // come in the order of the index.
+            if let Some(positions) = query.index_positions(index) {
+                log::debug!("looking up {} in covering index {}", field, index.name);
+                self.read_index(
+                    &page,
+                    &index_query,
+                    &mut |entry| {
+                        let row = positions
+                            .iter()
+                            .zip(&query.affinities)
+                            .map(|(&position, affinity)| affinity.read(entry.value(position)))
+                            .collect::<Vec<_>>();
+                        out.row(&row)
+                    },
+                    0,
+                )?;
+                return out.finish();
+            }
+
+            log::debug!("looking up {} in index {}", field, index.name);
+            let mut results = Vec::with_capacity(self.header.page_size as usize);
+            self.read_index(
+                &page,
+                &index_query,
+                &mut |entry| {
+                    let id = entry
+                        .last()
+                        .ok_or_else(|| Error::Corrupt("index entry without rowid".to_string()))?;
+                    results.push(i64::try_from(id)?);
+                    Ok(())
+                },
+                0,
+            )?;
+            // The ids are sorted so the table only has to be read once, every page of it at
+            // most once
+            results.sort_unstable();
+            results.dedup();
+            log::debug!(
+                "reading {} rows of {}",
+                results.len(),
+                schema_definition.name
+            );
+
+            let page = self.get_page(schema_definition.rootpage - 1)?;
+            self.read_ids_from_table(&page, &query, &results, out, 0)?;
+
+            return out.finish();
+        }
+
+        let query = Query::new(
+            schema_definition,
+            sql_statement,
+            &self.functions,
+            &self.collations,
+        )?;
+        out.begin(&query.columns)?;
+        let page = self.get_page(schema_definition.rootpage - 1)?;
+        log::debug!("scanning {}", schema_definition.name);
+        if self.threads > 1 && page.header.kind.is_interior() {
+            log::debug!("decoding leaves on {} threads", self.threads);
+            let mut batch = Vec::with_capacity(self.threads * LEAVES_PER_THREAD);
+            self.scan_in_parallel(page, &query, out, &mut batch, 0)?;
+            self.read_leaves_in_parallel(&mut batch, &query, out, self.threads)?;
+        } else {
+            self.read_table(&page, &query, out, 0)?;
+        }
+        out.finish()
+    }
+
+    /// Runs a query for at most `page_size` rows, scanning the table in rowid order from the
+    /// first row after the one with rowid `after`. Indexes aren't used, so that the next page
+    /// can start where this one stops. Returns the rowid of the last row if there are more.
+    pub fn select_page(
+        &self,
+        sql_statement: &SelectFields,
+        after: Option<i64>,
+        page_size: usize,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<Option<i64>> {
+        let table = sql_statement
+            .table()
+            .ok_or_else(|| anyhow::anyhow!("subqueries are run by the connection"))?;
+        let schema_definition = self.schema.table(table)?;
+        let query = Query::new(
+            schema_definition,
+            sql_statement,
+            &self.functions,
+            &self.collations,
+        )?;
+        out.begin(&query.columns)?;
+        let rows = match after {
+            Some(after) => TableCursor::seek(self, schema_definition.rootpage, after)?,
+            None => TableCursor::new(self, schema_definition.rootpage)?,
+        };
+        log::debug!(
+            "scanning {} after rowid {:?}",
+            schema_definition.name,
+            after
+        );
+        let mut count = 0;
+        let mut last = None;
+        for row in rows {
+            let row = row?;
+            let record = row.as_record();
+            if !query.matches(&record)? {
+                continue;
+            }
+            // A row that matches after a full page means there's another page
+            if count == page_size {
+                out.finish()?;
+                return Ok(last);
+            }
+            let mut values = Vec::with_capacity(query.results.len());
+            query.project(&record, &mut values)?;
+            out.row(&values)?;
+            count += 1;
+            last = Some(row.rowid);
+        }
+        out.finish()?;
+        Ok(None)
+    }
+
+    /// Counts the rows of a table by the number of cells of its leaf pages, without decoding
+    /// any of them.
+    pub fn count_rows(&self, table_name: &str) -> Result<usize> {
+        let rootpage = self.schema.table(table_name)?.rootpage;
+        self.count_leaf_cells(&self.get_page(rootpage - 1)?, 0)
+    }
+
+    fn count_leaf_cells(&self, page: &Page, depth: usize) -> Result<usize> {
+        match page.header.kind {
+            PageKind::LeafTable => Ok(page.header.number_of_cells as usize),
+            PageKind::InteriorTable => {
+                let mut count = 0;
+                for cell in page.cells() {
+                    let Cell::InteriorTable {
+                        left_child_page, ..
+                    } = cell
+                    else {
+                        bail!("Unsupported cell type");
+                    };
+                    count += self
+                        .count_leaf_cells(&self.child_page(left_child_page, depth)?, depth + 1)?;
+                }
+                if let Some(number) = page.header.right_child_page_number {
+                    count += self.count_leaf_cells(&self.child_page(number, depth)?, depth + 1)?;
+                }
+                Ok(count)
+            }
+            PageKind::InteriorIndex | PageKind::LeafIndex => {
+                bail!("Malformed table: table contains index pages")
+            }
+        }
+    }
+
+    /// Counts the rows of a table that match the filter of a query. The rows found in an index
+    /// are counted by their index entries, without reading the table.
+    pub fn count(&self, sql_statement: &SelectFields) -> Result<usize> {
+        let table = sql_statement
+            .table()
+            .ok_or_else(|| anyhow::anyhow!("subqueries are run by the connection"))?;
+        let schema_definition = self.schema.table(table)?;
+
+        if let Some((field, value, index)) = self.choose_index(schema_definition, sql_statement) {
+            log::debug!("counting {} in index {}", field, index.name);
+            let index_query =
+                IndexQuery::new(schema_definition, field, value, index, &self.collations)?;
+            let page = self.get_page(index.rootpage - 1)?;
+            let mut count = 0;
+            self.read_index(
+                &page,
+                &index_query,
+                &mut |_| {
+                    count += 1;
+                    Ok(())
+                },
+                0,
+            )?;
+            return Ok(count);
+        }
+
+        let mut rows = RowCount::default();
+        self.select_fields(sql_statement, &mut rows)?;
+        Ok(rows.0)
+    }
+
+    /// The index to look up the rows of a query in, together with the column and value it looks
+    /// up. Only `column = literal` comparisons can use an index.
+    fn choose_index<'a>(
+        &self,
+        table: &'a Table,
+        sql_statement: &'a SelectFields,
+    ) -> Option<(&'a Expr, &'a Literal, &'a Index)> {
+        let where_clause = sql_statement.where_clause.as_ref()?;
+        let (field, value) = where_clause.equality()?;
+        let index = table.find_applicable_index(field, where_clause)?;
+        // An index that matches a large part of the table is slower than a full scan
+        let selective = self
+            .statistics
+            .index(&index.name)
+            .map_or(true, |stats| stats.is_selective());
+        if !selective {
+            log::debug!("not using index {}, it matches too many rows", index.name);
+        }
+        selective.then_some((field, value, index))
+    }
+
+    /// Adds the steps [`Database::select_fields`] takes to run a query to `program`. The result
+    /// rows are returned, or added to the rowset `into`. `where_clause` is the filter as written,
+    /// which can still have the subqueries that the query has placeholders for.
+    pub fn explain_select(
+        &self,
+        sql_statement: &SelectFields,
+        where_clause: Option<&Expr>,
+        program: &mut Program,
+        into: Option<&str>,
+    ) -> Result<()> {
+        let table = sql_statement
+            .table()
+            .ok_or_else(|| anyhow::anyhow!("subqueries are explained by the connection"))?;
+        let schema_definition = self.schema.table(table)?;
+        let query = Query::new(
+            schema_definition,
+            sql_statement,
+            &self.functions,
+            &self.collations,
+        )?;
+
+        // The rows found in an index all match the filter, which only compares the indexed column
+        if let Some((field, value, index)) = self.choose_index(schema_definition, sql_statement) {
+            program.push(
+                "OpenRead",
+                format!("index {} (root page {})", index.name, index.rootpage),
+            );
+            program.push("SeekIndex", format!("{field} = {value}"));
+            if let Some(positions) = query.index_positions(index) {
+                let names = positions
+                    .iter()
+                    .map(|&position| match index.columns.get(position) {
+                        Some(column) => column.as_str(),
+                        None => "rowid",
+                    })
+                    .collect::<Vec<_>>();
+                program.push("IdxColumn", format!("{positions:?} ({})", names.join(", ")));
+                program.result_row(&query.columns, into);
+                return Ok(());
+            }
+            program.push(
+                "IdxRowid",
+                "collect the rowids, sorted and without duplicates",
+            );
+            open_table(schema_definition, program);
+            program.push(
+                "SeekRowid",
+                format!("each collected rowid in {}", schema_definition.name),
+            );
+            explain_columns(schema_definition, &query, None, program);
+        } else {
+            self.explain_scan(schema_definition, &query, where_clause, program);
+        }
+        program.result_row(&query.columns, into);
+        Ok(())
+    }
+
+    /// Adds the steps [`Database::count`] takes to `program`, see [`Database::explain_select`].
+    pub fn explain_count(
+        &self,
+        sql_statement: &SelectFields,
+        where_clause: Option<&Expr>,
+        program: &mut Program,
+        into: Option<&str>,
+    ) -> Result<()> {
+        let table = sql_statement
+            .table()
+            .ok_or_else(|| anyhow::anyhow!("subqueries are explained by the connection"))?;
+        let schema_definition = self.schema.table(table)?;
+
+        if let Some((field, value, index)) = self.choose_index(schema_definition, sql_statement) {
+            program.push(
+                "OpenRead",
+                format!("index {} (root page {})", index.name, index.rootpage),
+            );
+            program.push("SeekIndex", format!("{field} = {value}"));
+            program.push("Count", format!("entries of {} that match", index.name));
+            program.result_row(&["count(*)".to_string()], into);
+            return Ok(());
+        }
+
+        let query = Query::new(
+            schema_definition,
+            sql_statement,
+            &self.functions,
+            &self.collations,
+        )?;
+        self.explain_scan(schema_definition, &query, where_clause, program);
+        program.push("Count", "rows that match");
+        program.result_row(&["count(*)".to_string()], into);
+        Ok(())
+    }
+
+    /// Adds the steps of a full scan of `table` that reads the columns of `query` and filters
+    /// the rows with `where_clause` to `program`.
+    fn explain_scan(
+        &self,
+        table: &Table,
+        query: &Query,
+        where_clause: Option<&Expr>,
+        program: &mut Program,
+    ) {
+        open_table(table, program);
+        let threads = match self.threads {
+            threads if threads > 1 => format!(" on {threads} threads"),
+            _ => String::new(),
+        };
+        program.push("Scan", format!("{}{threads}", table.name));
+        explain_columns(table, query, where_clause, program);
+    }
+
+    /// Walks a table b-tree and collects its leaves into batches, which are decoded and
+    /// filtered by [`Database::read_leaves_in_parallel`] whenever they're full.
+    fn scan_in_parallel(
+        &self,
+        page: Page,
+        query: &Query,
+        out: &mut dyn OutputFormatter,
+        batch: &mut Vec<Page>,
+        depth: usize,
+    ) -> Result<()> {
+        match page.header.kind {
+            PageKind::InteriorTable => {
+                let children = page
+                    .cells()
+                    .map(|cell| match cell {
+                        Cell::InteriorTable {
+                            left_child_page, ..
+                        } => left_child_page,
+                        _ => unreachable!("interior table pages only have interior table cells"),
+                    })
+                    .chain(page.header.right_child_page_number);
+                for number in children.collect::<Vec<_>>() {
+                    let child = self.child_page(number, depth)?;
+                    self.scan_in_parallel(child, query, out, batch, depth + 1)?;
+                }
+            }
+            PageKind::LeafTable => {
+                batch.push(page);
+                if batch.len() == self.threads * LEAVES_PER_THREAD {
+                    self.read_leaves_in_parallel(batch, query, out, self.threads)?;
+                }
+            }
+            PageKind::InteriorIndex | PageKind::LeafIndex => {
+                bail!("Malformed table: table contains index pages")
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes and filters the leaves of a batch on `threads` threads, and writes the rows to
+    /// `out` in page order, like a scan on a single thread would.
+    fn read_leaves_in_parallel(
+        &self,
+        batch: &mut Vec<Page>,
+        query: &Query,
+        out: &mut dyn OutputFormatter,
+        threads: usize,
+    ) -> Result<()> {
+        let chunk_size = ((batch.len() + threads - 1) / threads).max(1);
+        let results = std::thread::scope(|scope| {
+            let workers = batch
+                .chunks(chunk_size)
+                .map(|pages| {
+                    scope.spawn(move || {
+                        let mut rows = LeafRows::default();
+                        for page in pages {
+                            self.read_leaf_table(page, query, &mut rows)?;
+                        }
+                        Ok::<_, anyhow::Error>(rows)
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("scan thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        batch.clear();
+
+        for rows in results {
+            let rows = rows?;
+            let mut row = Vec::with_capacity(query.results.len());
+            for values in &rows.0 {
+                row.clear();
+                row.extend(values.iter().map(ColumnValue::from));
+                out.row(&row)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `found` with every index entry that matches the query, in index order. Only the
+    /// subtrees that can have entries equal to the value are read.
+    fn read_index(
+        &self,
+        page: &Page,
+        query: &IndexQuery,
+        found: &mut dyn FnMut(&Record) -> Result<()>,
+        depth: usize,
+    ) -> Result<()> {
+        match page.header.kind {
+            crate::page::PageKind::InteriorIndex => {
+                self.read_interior_index(page, query, found, depth)
+            }
+            crate::page::PageKind::LeafIndex => self.read_leaf_index(page, query, found),
+            crate::page::PageKind::InteriorTable | crate::page::PageKind::LeafTable => {
+                bail!("Malformed index: index contains table pages")
+            }
+        }
+    }
+
+    fn read_interior_index(
+        &self,
+        page: &Page,
+        query: &IndexQuery,
+        found: &mut dyn FnMut(&Record) -> Result<()>,
+        depth: usize,
+    ) -> Result<()> {
+        for cell in page.cells() {
+            let Cell::InteriorIndex {
+                left_child_page,
+                size,
+                payload,
+                overflow_page,
+            } = cell
+            else {
+                bail!("Unsupported cell type");
+            };
+
+            // The left child has the entries up to the entry of the cell, and matching entries
+            // can be on both sides of an equal entry. Once an entry sorts after the value, the
+            // rest of the page does too.
+            let payload = self.payload(payload, size, overflow_page)?;
+            let record = Record::read(0, &payload)?;
+            let ordering = query.compare(&record);
+            if ordering.is_ge() {
+                let page = self.child_page(left_child_page, depth)?;
+                self.read_index(&page, query, found, depth + 1)?;
+            }
+            match ordering {
+                Ordering::Less => {}
+                Ordering::Equal => {
+                    if query.filter.matches(&record) {
+                        found(&record)?;
+                    }
+                }
+                Ordering::Greater => return Ok(()),
+            }
+        }
+
+        if let Some(number) = page.header.right_child_page_number {
+            let page = self.child_page(number, depth)?;
+            self.read_index(&page, query, found, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn read_leaf_index(
+        &self,
+        page: &Page,
+        query: &IndexQuery,
+        found: &mut dyn FnMut(&Record) -> Result<()>,
+    ) -> Result<()> {
+        for cell in page.cells() {
+            let Cell::LeafIndex {
+                size,
+                payload,
+                overflow_page,
+            } = cell
+            else {
+                bail!("Unsupported cell type");
+            };
+            let payload = self.payload(payload, size, overflow_page)?;
+            let record = Record::read(0, &payload)?;
+            match query.compare(&record) {
+                Ordering::Less => {}
+                Ordering::Equal => {
+                    if query.filter.matches(&record) {
+                        found(&record)?;
+                    }
+                }
+                Ordering::Greater => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn read_ids_from_table(
+        &self,
+        page: &Page,
+        query: &Query,
+        ids: &[i64],
+        out: &mut dyn OutputFormatter,
+        depth: usize,
+    ) -> Result<()> {
+        match page.header.kind {
+            crate::page::PageKind::InteriorTable => {
+                self.read_ids_from_interior_table(page, query, ids, out, depth)
+            }
+            crate::page::PageKind::LeafTable => {
+                self.read_ids_from_leaf_table(page, query, ids, out)
+            }
+            crate::page::PageKind::InteriorIndex | crate::page::PageKind::LeafIndex => {
+                bail!("Malformed table: table contains index pages")
+            }
+        }
+    }
+    fn read_ids_from_interior_table(
+        &self,
+        page: &Page,
+        query: &Query,
+        ids: &[i64],
+        out: &mut dyn OutputFormatter,
+        depth: usize,
+    ) -> Result<()> {
+        let mut ids = ids;
+        for cell in page.cells() {
+            let Cell::InteriorTable {
+                left_child_page,
+                key,
+            } = cell
+            else {
+                bail!("Unsupported cell type");
+            };
+
+            // The left child has the rows up to and including the key
+            let split_at = ids.split_at(ids.partition_point(|id| *id <= key));
+            let left_ids = split_at.0; // Ids to the left
+            ids = split_at.1; // Ids to the right
+
+            if !left_ids.is_empty() {
+                let page = self.child_page(left_child_page, depth)?;
+                self.read_ids_from_table(&page, query, left_ids, out, depth + 1)?;
+            }
+        }
+
+        // No more ids to the right. We're done.
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(number) = page.header.right_child_page_number {
+            let page = self.child_page(number, depth)?;
+            self.read_ids_from_table(&page, query, ids, out, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn read_ids_from_leaf_table(
+        &self,
+        page: &Page,
+        query: &Query,
+        ids: &[i64],
+        out: &mut dyn OutputFormatter,
+    ) -> Result<()> {
+        for cell in page.cells() {
+            let Cell::LeafTable {
+                size,
+                rowid,
+                payload,
+                overflow_page,
+            } = cell
+            else {
+                bail!("Unsupported cell type");
+            };
+            if ids.binary_search(&rowid).is_err() {
+                continue;
+            }
+            let payload = self.payload(payload, size, overflow_page)?;
+            let mut row = Vec::with_capacity(query.results.len());
+            query.project(&Record::read(rowid, &payload)?, &mut row)?;
+            out.row(&row)?;
+        }
+        Ok(())
+    }
+
+    fn read_table(
+        &self,
+        page: &Page,
+        query: &Query,
+        out: &mut dyn OutputFormatter,
+        depth: usize,
+    ) -> Result<()> {
+        match page.header.kind {
+            crate::page::PageKind::InteriorTable => {
+                self.read_interior_table(page, query, out, depth)
+            }
+            crate::page::PageKind::LeafTable => self.read_leaf_table(page, query, out),
+            crate::page::PageKind::InteriorIndex | crate::page::PageKind::LeafIndex => {
+                bail!("Malformed table: table contains index pages")
             }
         }
     }
 
     fn read_interior_table(
-        &mut self,
+        &self,
         page: &Page,
         query: &Query,
-        out: &mut impl std::io::Write,
+        out: &mut dyn OutputFormatter,
+        depth: usize,
     ) -> Result<()> {
         for cell in page.cells() {
-            let Cell::InteriorTable { left_child_page, .. } = cell else {
+            let Cell::InteriorTable {
+                left_child_page, ..
+            } = cell
+            else {
                 bail!("Unsupported cell type");
             };
 
-            let page = self.get_page(left_child_page - 1)?;
-            self.read_table(&page, query, out)?;
+            let page = self.child_page(left_child_page, depth)?;
+            self.read_table(&page, query, out, depth + 1)?;
         }
 
         if let Some(number) = page.header.right_child_page_number {
-            let page = self.get_page(number - 1)?;
-            self.read_table(&page, query, out)?;
+            let page = self.child_page(number, depth)?;
+            self.read_table(&page, query, out, depth + 1)?;
         }
         Ok(())
     }
@@ -376,44 +2617,1479 @@ impl Database {
         &self,
         page: &Page,
         query: &Query,
-        out: &mut impl std::io::Write,
+        out: &mut dyn OutputFormatter,
     ) -> Result<()> {
-        let records = page
-            .cells()
-            .map(|cell| match cell {
-                Cell::LeafTable { payload, rowid, .. } => Ok(Record::read(rowid, payload)),
-                _ => bail!("Unsupported cell type"),
+        for cell in page.cells() {
+            let Cell::LeafTable {
+                size,
+                rowid,
+                payload,
+                overflow_page,
+            } = cell
+            else {
+                bail!("Unsupported cell type");
+            };
+            let payload = self.payload(payload, size, overflow_page)?;
+            let record = Record::read(rowid, &payload)?;
+            if !query.matches(&record)? {
+                continue;
+            }
+            let mut row = Vec::with_capacity(query.results.len());
+            query.project(&record, &mut row)?;
+            out.row(&row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{run_sqlite3, TempDatabase};
+    use std::process::Command;
+
+    fn insert(database: &mut Database, statement: &str) -> Result<()> {
+        match sql::parse(statement.as_bytes()).unwrap().1 {
+            sql::SQLCommand::Insert(insert) => database.insert(&insert),
+            _ => panic!("not an insert"),
+        }
+    }
+
+    /// Runs `PRAGMA integrity_check` with the sqlite3 shell, or with Python's sqlite3 module
+    /// when the shell isn't installed. Returns `None` if neither is available.
+    fn integrity_check(path: &str) -> Option<String> {
+        let script = "import sqlite3, sys
+print(sqlite3.connect(sys.argv[1]).execute('PRAGMA integrity_check').fetchone()[0])";
+        let output = Command::new("sqlite3")
+            .args([path, "PRAGMA integrity_check"])
+            .output()
+            .or_else(|_| Command::new("python3").args(["-c", script, path]).output())
+            .ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    #[test]
+    fn inserts_split_pages() {
+        let database_file = TempDatabase::sample("split");
+        let path = database_file.path();
+
+        // Large rows in shuffled order fill enough leaves to split interior pages too
+        let mut database = Database::open(path).unwrap();
+        for i in 0..1500 {
+            let id = (i * 7919) % 1500 + 5;
+            let name = "x".repeat(1000 + id % 2000);
+            let statement = format!("INSERT INTO apples VALUES ({}, '{}', 'Red')", id, name);
+            insert(&mut database, &statement).unwrap();
+        }
+        assert!(database.header.database_size > 700);
+
+        let database = Database::open(path).unwrap();
+        let root = database.get_page(1).unwrap();
+        assert_eq!(root.header.kind, PageKind::InteriorTable);
+        let children = root.cells().map(|cell| match cell {
+            Cell::InteriorTable {
+                left_child_page, ..
+            } => left_child_page,
+            _ => panic!("not an interior table cell"),
+        });
+        for child in children.collect::<Vec<_>>() {
+            let page = database.get_page(child - 1).unwrap();
+            assert_eq!(page.header.kind, PageKind::InteriorTable);
+        }
+
+        let rows = database
+            .scan("apples")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(rows.len(), 1504);
+        assert!(rows.iter().map(|row| row.rowid).eq(1..=1504));
+        assert_eq!(rows[1503].values[1], Value::Text("x".repeat(2504)));
+
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn create_database_from_scratch() {
+        let database_file = TempDatabase::new("create");
+        let path = database_file.path();
+
+        let mut database = Database::create(path).unwrap();
+        assert!(Database::create(path).is_err());
+        let statements = [
+            "create table if not exists main.people (id integer primary key autoincrement, name text not null);",
+            "CREATE TABLE notes (body)",
+        ];
+        for statement in statements {
+            let sql::SQLCommand::CreateTable(create) = sql::parse(statement.as_bytes()).unwrap().1
+            else {
+                panic!("not a create table statement");
+            };
+            database.create_table(&create, statement).unwrap();
+            // Creating it again is an error, unless the statement says IF NOT EXISTS
+            assert_eq!(
+                database.create_table(&create, statement).is_ok(),
+                create.if_not_exists
+            );
+        }
+        insert(
+            &mut database,
+            "INSERT INTO people (name) VALUES ('ann'), ('bob')",
+        )
+        .unwrap();
+
+        let database = Database::open(path).unwrap();
+        assert_eq!(
+            database
+                .schema()
+                .tables()
+                .map(|table| table.name.as_str())
+                .collect::<Vec<_>>(),
+            ["people", "notes"]
+        );
+        assert!(database.schema.find_table("sqlite_sequence").is_some());
+        let schema = database
+            .scan("sqlite_schema")
+            .unwrap()
+            .map(|row| row.unwrap().values[4].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            schema[1],
+            Value::Text(
+                "CREATE TABLE people (id integer primary key autoincrement, name text not null)"
+                    .to_string()
+            )
+        );
+        let names = database
+            .scan("people")
+            .unwrap()
+            .map(|row| row.unwrap().values[1].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                Value::Text("ann".to_string()),
+                Value::Text("bob".to_string())
+            ]
+        );
+
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn autoincrement_rowids_only_grow() {
+        let mut database = Database::memory().unwrap();
+        let statement = "CREATE TABLE people (id INTEGER PRIMARY KEY AUTOINCREMENT, name)";
+        let (_, create) = sql::parse_creation(statement.as_bytes()).unwrap();
+        database.create_table(&create, statement).unwrap();
+        assert!(database.schema().sequence_table().is_some());
+        assert!(database
+            .schema()
+            .table("people")
+            .unwrap()
+            .is_autoincrement());
+        assert_eq!(database.sequence("people").unwrap(), None);
+
+        insert(&mut database, "INSERT INTO people VALUES (-5, 'ann')").unwrap();
+        assert_eq!(database.sequence("people").unwrap(), Some(0));
+        insert(&mut database, "INSERT INTO people (name) VALUES ('bob')").unwrap();
+        insert(&mut database, "INSERT INTO people VALUES (10, 'cat')").unwrap();
+        assert_eq!(database.sequence("people").unwrap(), Some(10));
+
+        // Rowids continue after the sequence even when it's past the largest rowid, like after
+        // the last rows were deleted
+        let sequence = database
+            .schema()
+            .table(SEQUENCE_TABLE_NAME)
+            .unwrap()
+            .clone();
+        let values = [Value::Text("people".to_string()), Value::Integer(100)];
+        database
+            .transaction(|database| database.update_record(&sequence, 1, &values))
+            .unwrap();
+        insert(&mut database, "INSERT INTO people (name) VALUES ('dan')").unwrap();
+        let rowids = database
+            .scan("people")
+            .unwrap()
+            .map(|row| row.unwrap().rowid)
+            .collect::<Vec<_>>();
+        assert_eq!(rowids, [-5, 1, 10, 101]);
+        assert_eq!(database.sequence("people").unwrap(), Some(101));
+        assert_eq!(
+            database.scan(SEQUENCE_TABLE_NAME).unwrap().count(),
+            1,
+            "the row of the table is updated in place"
+        );
+    }
+
+    fn create_index(database: &mut Database, statement: &str) -> Result<()> {
+        match sql::parse(statement.as_bytes()).unwrap().1 {
+            sql::SQLCommand::CreateIndex(create) => database.create_index(&create, statement),
+            _ => panic!("not a create index statement"),
+        }
+    }
+
+    #[test]
+    fn list_tables_of_schema() {
+        let mut database = Database::from_bytes(std::fs::read("sample.db").unwrap()).unwrap();
+        create_index(
+            &mut database,
+            "CREATE INDEX by_color ON apples (color, name)",
+        )
+        .unwrap();
+
+        let tables = database.schema().tables().collect::<Vec<_>>();
+        assert_eq!(
+            tables
+                .iter()
+                .map(|table| (table.name.as_str(), table.rootpage))
+                .collect::<Vec<_>>(),
+            [("apples", 2), ("oranges", 4)]
+        );
+        let columns = tables[0]
+            .columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.ty.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            columns,
+            [
+                ("id", Some("integer")),
+                ("name", Some("text")),
+                ("color", Some("text"))
+            ]
+        );
+        assert!(tables[0].columns[0].is_rowid_alias);
+        assert_eq!(tables[0].indexes[0].name, "by_color");
+        assert_eq!(tables[0].indexes[0].columns, ["color", "name"]);
+        assert!(tables[1].indexes.is_empty());
+    }
+
+    #[test]
+    fn tolerate_triggers() {
+        let mut database = Database::from_bytes(std::fs::read("sample.db").unwrap()).unwrap();
+        let sql = "CREATE TRIGGER log AFTER INSERT ON apples BEGIN SELECT new.id; END";
+        database
+            .transaction(|database| {
+                database.insert_schema_row("trigger", "log", "apples", 0, Some(sql))
             })
-            .filter(|record| {
-                let Ok(record) = record else { return true; };
-                let Some(ref filter) = query.filter else {
-                                return true;
-                            };
-
-                let (pos, _field) = query
-                    .table
-                    .find_column(&filter.field)
-                    .expect("Field not found");
-
-                format!("{}", record.values[pos]) == filter.value
+            .unwrap();
+        database.read_schema().unwrap();
+
+        let triggers = database.schema().triggers(Some("apples"));
+        assert_eq!(
+            triggers
+                .iter()
+                .map(|trigger| (trigger.name.as_str(), trigger.sql.as_str()))
+                .collect::<Vec<_>>(),
+            [("log", sql)]
+        );
+        assert!(database.schema().triggers(Some("oranges")).is_empty());
+        assert_eq!(
+            insert(&mut database, "INSERT INTO apples (name) VALUES ('Jazz')")
+                .unwrap_err()
+                .to_string(),
+            "cannot modify apples because triggers are not supported yet: log"
+        );
+        insert(&mut database, "INSERT INTO oranges (name) VALUES ('Navel')").unwrap();
+    }
+
+    #[test]
+    fn tolerate_virtual_tables() {
+        let mut database = Database::from_bytes(std::fs::read("sample.db").unwrap()).unwrap();
+        let (_, shadow) =
+            sql::parse_creation(b"CREATE TABLE 'docs_content'(id INTEGER PRIMARY KEY, c0)")
+                .unwrap();
+        database
+            .create_table(
+                &shadow,
+                "CREATE TABLE 'docs_content'(id INTEGER PRIMARY KEY, c0)",
+            )
+            .unwrap();
+        let sql = "CREATE VIRTUAL TABLE docs USING fts5(body)";
+        database
+            .transaction(|database| {
+                database.insert_schema_row("table", "docs", "docs", 0, Some(sql))
             })
-            .collect::<Result<Vec<Record>>>()?;
+            .unwrap();
+        database.read_schema().unwrap();
 
-        for record in records {
-            let values = query
-                .select_fields
+        let tables = database.schema().virtual_tables().collect::<Vec<_>>();
+        assert_eq!(
+            tables
                 .iter()
-                .map(|(i, is_primary_key)| {
-                    if *is_primary_key {
-                        format!("{}", record.rowid)
-                    } else {
-                        format!("{}", record.values[*i])
-                    }
+                .map(|table| (table.name.as_str(), table.module.as_str()))
+                .collect::<Vec<_>>(),
+            [("docs", "fts5")]
+        );
+        assert_eq!(
+            insert(&mut database, "INSERT INTO docs (body) VALUES ('hello')")
+                .unwrap_err()
+                .to_string(),
+            "no such module: fts5"
+        );
+        insert(
+            &mut database,
+            "INSERT INTO docs_content (c0) VALUES ('hello')",
+        )
+        .unwrap();
+        assert_eq!(
+            create_index(&mut database, "CREATE INDEX body ON docs (body)")
+                .unwrap_err()
+                .to_string(),
+            "virtual tables may not be indexed"
+        );
+    }
+
+    #[test]
+    fn search_fts_tables() {
+        let database_file = TempDatabase::sample("fts");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        let sql = "CREATE TABLE 'docs_content'(id INTEGER PRIMARY KEY, c0, c1)";
+        let (_, shadow) = sql::parse_creation(sql.as_bytes()).unwrap();
+        database.create_table(&shadow, sql).unwrap();
+        insert(
+            &mut database,
+            "INSERT INTO docs_content (c0, c1) VALUES ('Apples', 'Crisp and sweet'), \
+             ('Oranges', 'Sweet citrus fruit'), ('Lemons', NULL)",
+        )
+        .unwrap();
+        let sql = "CREATE VIRTUAL TABLE docs USING fts5(title, body)";
+        database
+            .transaction(|database| {
+                database.insert_schema_row("table", "docs", "docs", 0, Some(sql))
+            })
+            .unwrap();
+        drop(database);
+
+        let mut connection = crate::connection::Connection::open(path).unwrap();
+        let mut query = |statement: &str| {
+            let mut statement = connection.prepare(statement)?;
+            let rows = statement.query()?;
+            let columns = rows.column_names().to_vec();
+            let values = rows
+                .map(|row| row.get::<Value>(0).unwrap())
+                .collect::<Vec<_>>();
+            Ok::<_, anyhow::Error>((columns, values))
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_eq!(
+            query("SELECT * FROM docs WHERE docs MATCH 'sweet'").unwrap(),
+            (
+                vec!["title".to_string(), "body".to_string()],
+                vec![text("Apples"), text("Oranges")]
+            )
+        );
+        assert_eq!(
+            query("SELECT title FROM docs WHERE body MATCH 'citr* OR lemons'")
+                .unwrap()
+                .1,
+            [text("Oranges")]
+        );
+        assert_eq!(
+            query("SELECT title FROM docs WHERE docs MATCH '\"and sweet\"'")
+                .unwrap()
+                .1,
+            [text("Apples")]
+        );
+        assert_eq!(
+            query("SELECT count(*) FROM docs").unwrap().1,
+            [Value::Integer(3)]
+        );
+    }
+
+    #[test]
+    fn create_and_maintain_index() {
+        let database_file = TempDatabase::sample("index");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        for i in 0..1000 {
+            let color = ["Red", "Green", "Yellow"][i % 3];
+            let statement = format!(
+                "INSERT INTO apples (name, color) VALUES ('apple {}', '{}')",
+                i, color
+            );
+            insert(&mut database, &statement).unwrap();
+        }
+        create_index(
+            &mut database,
+            "CREATE INDEX by_color ON apples (color, name)",
+        )
+        .unwrap();
+        assert!(create_index(&mut database, "CREATE INDEX by_color ON apples (name)").is_err());
+        create_index(
+            &mut database,
+            "CREATE INDEX IF NOT EXISTS by_color ON apples (name)",
+        )
+        .unwrap();
+        assert!(create_index(&mut database, "CREATE INDEX by_size ON apples (size)").is_err());
+
+        // Long entries in shuffled order split the index pages
+        for i in 0..300 {
+            let name = format!("{} {}", "x".repeat(300), (i * 7) % 300);
+            let statement = format!(
+                "INSERT INTO apples (name, color) VALUES ('{}', 'Blue')",
+                name
+            );
+            insert(&mut database, &statement).unwrap();
+        }
+
+        let database = Database::open(path).unwrap();
+        let index = database.schema.table("apples").unwrap().indexes[0].clone();
+        assert_eq!(index.columns, ["color", "name"]);
+        let root = database.get_page(index.rootpage - 1).unwrap();
+        assert_eq!(root.header.kind, PageKind::InteriorIndex);
+
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn appends_fill_pages() {
+        let database_file = TempDatabase::sample("append");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        let pages = database.header.database_size;
+        for _ in 0..100 {
+            insert(
+                &mut database,
+                &format!("INSERT INTO oranges (name) VALUES ('{}')", "o".repeat(400)),
+            )
+            .unwrap();
+        }
+        // About 10 of these rows fit on a leaf, and the root becomes an interior page
+        assert!(database.header.database_size - pages <= 12);
+
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn parallel_scan_keeps_row_order() {
+        let database_file = TempDatabase::sample("parallel");
+        let path = database_file.path();
+
+        // Enough leaves for several batches, with the last one only partly full
+        let mut database = Database::open(path).unwrap();
+        let values = (0..3000)
+            .map(|i| {
+                format!(
+                    "('{} {}', '{}')",
+                    "x".repeat(200),
+                    i,
+                    ["Red", "Green"][i % 2]
+                )
+            })
+            .collect::<Vec<_>>();
+        insert(
+            &mut database,
+            &format!(
+                "INSERT INTO apples (name, color) VALUES {}",
+                values.join(", ")
+            ),
+        )
+        .unwrap();
+        assert!(database.header.database_size > 4 * LEAVES_PER_THREAD as u32);
+
+        let statement = match sql::parse(b"SELECT id, name FROM apples WHERE color = 'Green'")
+            .unwrap()
+            .1
+        {
+            sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+            _ => unreachable!(),
+        };
+        let mut select = |threads| {
+            database.threads = threads;
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            String::from_utf8(out).unwrap()
+        };
+        let serial = select(1);
+        assert_eq!(serial.lines().count(), 1500);
+        assert_eq!(select(3), serial);
+    }
+
+    #[test]
+    fn covering_index_answers_query() {
+        let database_file = TempDatabase::sample("covering");
+        let path = database_file.path();
+
+        // Inserted in the reverse order of the names
+        let mut database = Database::open(path).unwrap();
+        let values = (0..1000)
+            .map(|i| format!("('apple {:03}', '{}')", 999 - i, ["Red", "Green"][i % 2]))
+            .collect::<Vec<_>>();
+        insert(
+            &mut database,
+            &format!(
+                "INSERT INTO apples (name, color) VALUES {}",
+                values.join(", ")
+            ),
+        )
+        .unwrap();
+
+        let statement = match sql::parse(b"SELECT name, id FROM apples WHERE color = 'Red'")
+            .unwrap()
+            .1
+        {
+            sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+            _ => unreachable!(),
+        };
+        let select = |database: &mut Database| {
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            String::from_utf8(out).unwrap()
+        };
+        let mut scanned = select(&mut database)
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        scanned.sort();
+
+        create_index(
+            &mut database,
+            "CREATE INDEX by_color ON apples (color, name)",
+        )
+        .unwrap();
+        let table = database.schema.table("apples").unwrap();
+        let query = Query::new(
+            table,
+            &statement,
+            &Functions::default(),
+            &Collations::default(),
+        )
+        .unwrap();
+        assert_eq!(query.index_positions(&table.indexes[0]), Some(vec![1, 2]));
+
+        // The rows come from the index, in the order of the names
+        let covered = select(&mut database);
+        assert_eq!(covered.lines().collect::<Vec<_>>(), scanned);
+        assert_eq!(scanned.len(), 501);
+    }
+
+    #[test]
+    fn index_lookup_finds_every_row() {
+        let database_file = TempDatabase::sample("lookup");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        create_index(&mut database, "CREATE INDEX by_color ON apples (color)").unwrap();
+        // Many rows per color, so the rowids of a color are spread over all leaves of the
+        // table and include the keys of its interior cells
+        let values = (0..2000)
+            .map(|i| format!("('{} {i}', '{}')", "x".repeat(100), ["Red", "Green"][i % 2]))
+            .collect::<Vec<_>>();
+        insert(
+            &mut database,
+            &format!(
+                "INSERT INTO apples (name, color) VALUES {}",
+                values.join(", ")
+            ),
+        )
+        .unwrap();
+
+        let statement = match sql::parse(b"SELECT id, name FROM apples WHERE color = 'Green'")
+            .unwrap()
+            .1
+        {
+            sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+            _ => unreachable!(),
+        };
+        let mut out = Vec::new();
+        let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+        database
+            .select_fields(&statement, formatter.as_mut())
+            .unwrap();
+        drop(formatter);
+        let ids = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('|').next().unwrap().parse::<i64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (6..=2004).step_by(2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn read_payloads_from_overflow_pages() {
+        let database_file = TempDatabase::new("overflow");
+        let path = database_file.path();
+        // Both the rows and their index entries are too large for a page
+        let script = "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT);
+            CREATE INDEX by_body ON notes (body);
+            INSERT INTO notes (body) VALUES
+                (replace(hex(zeroblob(3000)), '00', 'ab')),
+                ('short'),
+                (replace(hex(zeroblob(10000)), '00', 'cd'));";
+        run_sqlite3(path, script);
+
+        let mut database = Database::open(path).unwrap();
+        let rows = database
+            .scan("notes")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let bodies = rows
+            .iter()
+            .map(|row| row.values[1].clone())
+            .collect::<Vec<_>>();
+        let text = |text: String| Value::Text(text);
+        assert_eq!(
+            bodies,
+            [
+                text("ab".repeat(3000)),
+                text("short".to_string()),
+                text("cd".repeat(10000))
+            ]
+        );
+
+        // New entries are sorted between entries on overflow pages
+        insert(&mut database, "INSERT INTO notes (body) VALUES ('b')").unwrap();
+        let query = |sql: &str| {
+            let statement = match sql::parse(sql.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+                _ => unreachable!(),
+            };
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            String::from_utf8(out).unwrap()
+        };
+        let long = "cd".repeat(10000);
+        assert_eq!(
+            query(&format!("SELECT id FROM notes WHERE body = '{long}'")),
+            "3\n"
+        );
+        assert_eq!(query("SELECT id FROM notes WHERE body = 'b'"), "4\n");
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn write_payloads_to_overflow_pages() {
+        let database_file = TempDatabase::sample("write_overflow");
+        let path = database_file.path();
+
+        // Rows larger than a page, with index entries that spill over too
+        let mut database = Database::open(path).unwrap();
+        create_index(&mut database, "CREATE INDEX by_name ON apples (name)").unwrap();
+        for (id, length) in [(5, 10_000), (6, 4_000), (7, 1_000)] {
+            let name = "x".repeat(length);
+            let statement = format!("INSERT INTO apples VALUES ({id}, '{name}', 'Red')");
+            insert(&mut database, &statement).unwrap();
+        }
+        create_index(
+            &mut database,
+            "CREATE INDEX by_name_and_color ON apples (name, color)",
+        )
+        .unwrap();
+
+        let database = Database::open(path).unwrap();
+        let rows = database
+            .scan("apples")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let names = rows[4..]
+            .iter()
+            .map(|row| row.values[1].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [10_000, 4_000, 1_000].map(|length| Value::Text("x".repeat(length)))
+        );
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn descending_index_lookups() {
+        let database_file = TempDatabase::sample("descending");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        create_index(
+            &mut database,
+            "CREATE INDEX by_color ON apples (color DESC, name)",
+        )
+        .unwrap();
+        let index = &database.schema().table("apples").unwrap().indexes[0];
+        assert_eq!(index.descending, [true, false]);
+        // Enough entries for interior pages, added after the index was built
+        let values = (0..500)
+            .map(|i| {
+                format!(
+                    "('{} {i}', '{}')",
+                    "x".repeat(50),
+                    ["Red", "Aqua", "Zinc"][i % 3]
+                )
+            })
+            .collect::<Vec<_>>();
+        insert(
+            &mut database,
+            &format!(
+                "INSERT INTO apples (name, color) VALUES {}",
+                values.join(", ")
+            ),
+        )
+        .unwrap();
+
+        let database = Database::open(path).unwrap();
+        for (color, count) in [("Red", 168), ("Aqua", 167), ("Yellow", 1), ("Blue", 0)] {
+            let sql = format!("SELECT name FROM apples WHERE color = '{color}'");
+            let statement = match sql::parse(sql.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+                _ => unreachable!(),
+            };
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            assert_eq!(String::from_utf8(out).unwrap().lines().count(), count);
+        }
+        assert_eq!(integrity::check(&database).unwrap(), Vec::<String>::new());
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn partial_indexes() {
+        let database_file = TempDatabase::sample("partial");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        create_index(
+            &mut database,
+            "CREATE INDEX late ON apples (color) WHERE id > 2",
+        )
+        .unwrap();
+        create_index(
+            &mut database,
+            "CREATE INDEX colored ON apples (color) WHERE color IS NOT NULL",
+        )
+        .unwrap();
+        insert(
+            &mut database,
+            "INSERT INTO apples (name, color) VALUES ('Jazz', 'Red'), ('Gala', NULL)",
+        )
+        .unwrap();
+
+        let query = |database: &Database, sql: &str| {
+            let statement = match sql::parse(sql.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+                _ => unreachable!(),
+            };
+            let table = database.schema().table("apples").unwrap();
+            let index = database
+                .choose_index(table, &statement)
+                .map(|(_, _, index)| index.name.clone());
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            (index, String::from_utf8(out).unwrap())
+        };
+        // Fuji has id 2, so it isn't in the first index, but a color that's looked up isn't NULL
+        let reds = (Some("colored".to_string()), "Fuji\nJazz\n".to_string());
+        assert_eq!(
+            query(&database, "SELECT name FROM apples WHERE color = 'Red'"),
+            reds
+        );
+        let database = Database::open(path).unwrap();
+        assert_eq!(
+            query(&database, "SELECT name FROM apples WHERE color = 'Red'"),
+            reds
+        );
+        let index = &database.schema().table("apples").unwrap().indexes[0];
+        assert_eq!(
+            (
+                index.name.as_str(),
+                index.where_clause.as_ref().unwrap().to_string()
+            ),
+            ("late", "id > 2".to_string())
+        );
+        assert_eq!(integrity::check(&database).unwrap(), Vec::<String>::new());
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn expression_indexes() {
+        let database_file = TempDatabase::sample("expression");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        create_index(
+            &mut database,
+            "CREATE INDEX by_text ON apples (CAST(id AS TEXT))",
+        )
+        .unwrap();
+        insert(
+            &mut database,
+            "INSERT INTO apples (name, color) VALUES ('Jazz', 'Red')",
+        )
+        .unwrap();
+        assert_eq!(
+            create_index(
+                &mut database,
+                "CREATE INDEX bad ON apples ((SELECT id FROM apples))"
+            )
+            .unwrap_err()
+            .to_string(),
+            "subqueries prohibited in index expressions"
+        );
+
+        let query = |database: &Database, sql: &str| {
+            let statement = match sql::parse(sql.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+                _ => unreachable!(),
+            };
+            let table = database.schema().table("apples").unwrap();
+            let index = database
+                .choose_index(table, &statement)
+                .map(|(_, _, index)| index.name.clone());
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            (index, String::from_utf8(out).unwrap())
+        };
+        let database = Database::open(path).unwrap();
+        assert_eq!(
+            query(
+                &database,
+                "SELECT name FROM apples WHERE CAST(id AS TEXT) = '5'"
+            ),
+            (Some("by_text".to_string()), "Jazz\n".to_string())
+        );
+        // The value is compared as text, like the results of the CAST
+        assert_eq!(
+            query(
+                &database,
+                "SELECT name FROM apples WHERE CAST(id AS TEXT) = 3"
+            ),
+            (Some("by_text".to_string()), "Honeycrisp\n".to_string())
+        );
+        // Only the expression itself is indexed, not the column
+        assert_eq!(
+            query(&database, "SELECT name FROM apples WHERE id = 3"),
+            (None, "Honeycrisp\n".to_string())
+        );
+        let index = &database.schema().table("apples").unwrap().indexes[0];
+        assert_eq!(index.columns, ["CAST(id AS TEXT)"]);
+        assert!(index.find_column("id").is_none());
+
+        assert_eq!(integrity::check(&database).unwrap(), Vec::<String>::new());
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn unique_indexes() {
+        let database_file = TempDatabase::sample("unique");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        create_index(
+            &mut database,
+            "CREATE UNIQUE INDEX by_name ON apples (name, color)",
+        )
+        .unwrap();
+        assert!(database.schema().table("apples").unwrap().indexes[0].unique);
+
+        // Rows only clash if all columns are equal and none of them is NULL
+        insert(
+            &mut database,
+            "INSERT INTO apples (name, color) VALUES ('Fuji', 'Green'), ('Gala', NULL), ('Gala', NULL)",
+        )
+        .unwrap();
+        let error = insert(
+            &mut database,
+            "INSERT INTO apples (name, color) VALUES ('Jazz', 'Red'), ('Fuji', 'Red')",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Error>(),
+            Some(&Error::UniqueConstraint(
+                "apples.name, apples.color".to_string()
+            ))
+        );
+        // The whole statement is rolled back
+        assert_eq!(database.scan("apples").unwrap().count(), 7);
+
+        // Existing rows have to be unique for the index to be created
+        for (statement, error) in [
+            (
+                "CREATE UNIQUE INDEX by_fruit ON apples (name)",
+                "UNIQUE constraint failed: apples.name",
+            ),
+            (
+                "CREATE UNIQUE INDEX by_text ON apples (CAST(name AS TEXT))",
+                "UNIQUE constraint failed: index 'by_text'",
+            ),
+        ] {
+            assert_eq!(
+                create_index(&mut database, statement)
+                    .unwrap_err()
+                    .to_string(),
+                error
+            );
+        }
+
+        assert_eq!(integrity::check(&database).unwrap(), Vec::<String>::new());
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn automatic_indexes() {
+        let database_file = TempDatabase::new("autoindex");
+        let path = database_file.path();
+
+        let mut database = Database::create(path).unwrap();
+        let statement = "CREATE TABLE users (id integer primary key, email text unique, \
+                         first, last, UNIQUE (first, last), UNIQUE (email))";
+        let sql::SQLCommand::CreateTable(create) = sql::parse(statement.as_bytes()).unwrap().1
+        else {
+            panic!("not a create table statement");
+        };
+        database.create_table(&create, statement).unwrap();
+        insert(
+            &mut database,
+            "INSERT INTO users (email, first, last) VALUES ('a@x', 'Ann', 'Lee'), ('b@x', 'Bo', 'Lee')",
+        )
+        .unwrap();
+        assert_eq!(
+            insert(
+                &mut database,
+                "INSERT INTO users (email, first) VALUES ('a@x', 'Cy')"
+            )
+            .unwrap_err()
+            .to_string(),
+            "UNIQUE constraint failed: users.email"
+        );
+
+        // The columns of the indexes are taken from the constraints when the file is read
+        let database = Database::open(path).unwrap();
+        let table = database.schema().table("users").unwrap();
+        let indexes = table
+            .indexes
+            .iter()
+            .map(|index| (index.name.as_str(), index.columns.clone(), index.unique))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            indexes,
+            [
+                ("sqlite_autoindex_users_1", vec!["email".to_string()], true),
+                (
+                    "sqlite_autoindex_users_2",
+                    vec!["first".to_string(), "last".to_string()],
+                    true
+                ),
+            ]
+        );
+        let statement = match sql::parse(b"SELECT id FROM users WHERE email = 'b@x'")
+            .unwrap()
+            .1
+        {
+            sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            database
+                .choose_index(table, &statement)
+                .map(|(_, _, index)| index.name.as_str()),
+            Some("sqlite_autoindex_users_1")
+        );
+
+        assert_eq!(integrity::check(&database).unwrap(), Vec::<String>::new());
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn index_lookup_reads_duplicate_keys() {
+        let database_file = TempDatabase::sample("duplicates");
+        let path = database_file.path();
+
+        // Long keys, so that every color takes up many pages of an index with two levels of
+        // interior pages, and interior cells have keys equal to the value looked up
+        let mut database = Database::open(path).unwrap();
+        create_index(&mut database, "CREATE INDEX by_color ON apples (color)").unwrap();
+        let colors = ["Blue", "Green", "Red", "Yellow"].map(|color| color.repeat(80));
+        let values = (0..1200)
+            .map(|i| format!("('apple {i}', '{}')", colors[i % 4]))
+            .collect::<Vec<_>>();
+        insert(
+            &mut database,
+            &format!(
+                "INSERT INTO apples (name, color) VALUES {}",
+                values.join(", ")
+            ),
+        )
+        .unwrap();
+        let index = database.schema.table("apples").unwrap().indexes[0].clone();
+        let root = database.get_page(index.rootpage - 1).unwrap();
+        let Some(Cell::InteriorIndex {
+            left_child_page, ..
+        }) = root.cell(0)
+        else {
+            panic!("index root isn't an interior page");
+        };
+        let child = database.get_page(left_child_page - 1).unwrap();
+        assert_eq!(child.header.kind, PageKind::InteriorIndex);
+
+        let count = |color: &str| {
+            let statement = format!("SELECT id FROM apples WHERE color = '{color}'");
+            let statement = match sql::parse(statement.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+                _ => unreachable!(),
+            };
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            String::from_utf8(out).unwrap().lines().count()
+        };
+        for color in &colors {
+            assert_eq!(count(color), 300, "{color}");
+        }
+        for color in ["Aqua", "Gray", "Purple", "Zinc"] {
+            assert_eq!(count(color), 0, "{color}");
+        }
+    }
+
+    #[test]
+    fn short_records_have_column_defaults() {
+        let database_file = TempDatabase::sample("defaults");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        insert(&mut database, "INSERT INTO oranges (name) VALUES ('Navel')").unwrap();
+        // Like ALTER TABLE ADD COLUMN, only change the schema. Records written before have
+        // no values for the new columns.
+        let statement = "CREATE TABLE oranges (id integer primary key autoincrement, name text, \
+            description text, size integer DEFAULT '5', peel)";
+        let sql::SQLCommand::CreateTable(create) = sql::parse(statement.as_bytes()).unwrap().1
+        else {
+            panic!("not a create table statement");
+        };
+        let rootpage = database.schema.table("oranges").unwrap().rootpage;
+        database.schema.add_table(&create, rootpage);
+        insert(
+            &mut database,
+            "INSERT INTO oranges (name, size, peel) VALUES ('Blood', 7, 'thick')",
+        )
+        .unwrap();
+
+        let select = |database: &mut Database, statement: &str| {
+            let statement = match sql::parse(statement.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+                _ => unreachable!(),
+            };
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            select(
+                &mut database,
+                "SELECT name, size, peel FROM oranges WHERE id > 6"
+            ),
+            "Navel|5|\nBlood|7|thick\n"
+        );
+        let statement = "SELECT name FROM oranges WHERE size = 5";
+        assert_eq!(select(&mut database, statement).lines().count(), 7);
+
+        create_index(&mut database, "CREATE INDEX by_size ON oranges (size)").unwrap();
+        assert_eq!(select(&mut database, statement).lines().count(), 7);
+    }
+
+    #[test]
+    fn query_from_several_threads() {
+        let database = Database::open("sample.db").unwrap();
+        let select = |statement: &str| {
+            let statement = match sql::parse(statement.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+                _ => unreachable!(),
+            };
+            let mut out = Vec::new();
+            let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+            database
+                .select_fields(&statement, formatter.as_mut())
+                .unwrap();
+            drop(formatter);
+            String::from_utf8(out).unwrap()
+        };
+        let statements = [
+            "SELECT name FROM apples",
+            "SELECT name FROM apples WHERE id = 2",
+            "SELECT name, description FROM oranges WHERE name = 'Clementine'",
+        ];
+        let expected = statements.map(select);
+        std::thread::scope(|scope| {
+            let threads = (0..8)
+                .map(|i| {
+                    let select = &select;
+                    let statement = statements[i % statements.len()];
+                    scope.spawn(move || (0..50).map(|_| select(statement)).collect::<Vec<_>>())
                 })
-                .collect::<Vec<_>>()
-                .join("|");
-            write!(out, "{}\n", values)?;
+                .collect::<Vec<_>>();
+            for (i, thread) in threads.into_iter().enumerate() {
+                for result in thread.join().unwrap() {
+                    assert_eq!(result, expected[i % statements.len()]);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn negative_rowids() {
+        let database_file = TempDatabase::sample("negative");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        create_index(&mut database, "CREATE INDEX by_color ON apples (color)").unwrap();
+        let ids = (-600..0).chain([i64::MIN, i64::MAX]).collect::<Vec<_>>();
+        let values = ids
+            .iter()
+            .map(|id| format!("({id}, '{}', '{}')", "x".repeat(100), id.rem_euclid(3)))
+            .collect::<Vec<_>>();
+        insert(
+            &mut database,
+            &format!(
+                "INSERT INTO apples (id, name, color) VALUES {}",
+                values.join(", ")
+            ),
+        )
+        .unwrap();
+        let root = database.get_page(1).unwrap();
+        let Some(Cell::InteriorTable { key, .. }) = root.cell(0) else {
+            panic!("table root isn't an interior page");
+        };
+        assert!(key < 0);
+
+        let rowids = database
+            .scan("apples")
+            .unwrap()
+            .map(|row| row.unwrap().rowid)
+            .collect::<Vec<_>>();
+        let mut expected = ids.clone();
+        expected.extend(1..=4);
+        expected.sort();
+        assert_eq!(rowids, expected);
+
+        let statement = match sql::parse(b"SELECT id FROM apples WHERE color = '1'")
+            .unwrap()
+            .1
+        {
+            sql::SQLCommand::Select(sql::SelectStatement::Fields(select)) => select,
+            _ => unreachable!(),
+        };
+        let mut out = Vec::new();
+        let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+        database
+            .select_fields(&statement, formatter.as_mut())
+            .unwrap();
+        drop(formatter);
+        let found = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| line.parse::<i64>().unwrap())
+            .collect::<Vec<_>>();
+        // In the order of the index, which is by rowid for equal colors
+        let mut expected = ids
+            .into_iter()
+            .filter(|id| id.rem_euclid(3) == 1)
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
         }
-        Ok(())
+    }
+
+    #[test]
+    fn analyze_stores_statistics() {
+        let database_file = TempDatabase::sample("analyze");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        create_index(&mut database, "CREATE INDEX by_color ON apples (color)").unwrap();
+        create_index(
+            &mut database,
+            "CREATE INDEX by_name ON apples (name, color)",
+        )
+        .unwrap();
+        let values = (0..996)
+            .map(|i| format!("('apple {i}', '{}')", ["Red", "Green"][i % 2]))
+            .collect::<Vec<_>>();
+        insert(
+            &mut database,
+            &format!(
+                "INSERT INTO apples (name, color) VALUES {}",
+                values.join(", ")
+            ),
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        let mut formatter = crate::output::OutputMode::List.formatter(&mut out, false);
+        database.analyze(None, formatter.as_mut()).unwrap();
+        drop(formatter);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "apples|by_color|1000 200\napples|by_name|1000 1 1\noranges||6\n"
+        );
+
+        // A connection that opens the database later reads the statistics back
+        let database = Database::open(path).unwrap();
+        assert!(!database
+            .statistics
+            .index("by_color")
+            .unwrap()
+            .is_selective());
+        assert!(database.statistics.index("by_name").unwrap().is_selective());
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn reads_large_root_pages() {
+        let database_file = TempDatabase::new("rootpages");
+        let path = database_file.path();
+
+        // The schema stores root pages as 2, 3 and 4 byte integers. Skipped pages are left out
+        // of a sparse file.
+        let mut database = Database::create_with_page_size(path, 512).unwrap();
+        let sizes = [200, 40_000, 9_000_000];
+        for (name, size) in ["a", "b", "c"].iter().zip(sizes) {
+            database.header.database_size = size;
+            let statement = format!("CREATE TABLE {name} (x)");
+            let sql::SQLCommand::CreateTable(create) = sql::parse(statement.as_bytes()).unwrap().1
+            else {
+                panic!("not a create table statement");
+            };
+            database.create_table(&create, &statement).unwrap();
+            insert(
+                &mut database,
+                &format!("INSERT INTO {name} VALUES ('{name}')"),
+            )
+            .unwrap();
+        }
+
+        let database = Database::open(path).unwrap();
+        for (name, size) in ["a", "b", "c"].iter().zip(sizes) {
+            assert_eq!(database.schema.find_table(name).unwrap().rootpage, size + 1);
+            let rows = database
+                .scan(name)
+                .unwrap()
+                .map(|row| row.unwrap().values)
+                .collect::<Vec<_>>();
+            assert_eq!(rows, [[Value::Text(name.to_string())]]);
+        }
+    }
+
+    #[test]
+    fn allocation_skips_lock_byte_page() {
+        let database_file = TempDatabase::new("lock-byte");
+        let path = database_file.path();
+
+        // Pretend the file already ends right before the lock-byte page, leaving a sparse file
+        let mut database = Database::create_with_page_size(path, 65536).unwrap();
+        let lock_byte_page = database.header.lock_byte_page();
+        assert_eq!(lock_byte_page, 16385);
+        database.header.database_size = lock_byte_page - 2;
+        for statement in ["CREATE TABLE a (x)", "CREATE TABLE b (x)"] {
+            let sql::SQLCommand::CreateTable(create) = sql::parse(statement.as_bytes()).unwrap().1
+            else {
+                panic!("not a create table statement");
+            };
+            database.create_table(&create, statement).unwrap();
+        }
+        insert(&mut database, "INSERT INTO b VALUES (1)").unwrap();
+
+        let database = Database::open(path).unwrap();
+        let rootpages = ["a", "b"].map(|name| database.schema.find_table(name).unwrap().rootpage);
+        assert_eq!(rootpages, [lock_byte_page - 1, lock_byte_page + 1]);
+        assert_eq!(database.scan("b").unwrap().count(), 1);
+        assert_eq!(
+            database
+                .get_page(lock_byte_page - 1)
+                .unwrap_err()
+                .to_string(),
+            "database disk image is malformed: page 16385 is the lock-byte page"
+        );
+    }
+
+    #[test]
+    fn vacuum_packs_pages() {
+        let database_file = TempDatabase::sample("vacuum");
+        let path = database_file.path();
+
+        // Rows in shuffled order leave the split pages half empty
+        let mut database = Database::open(path).unwrap();
+        create_index(&mut database, "CREATE INDEX by_name ON apples (name)").unwrap();
+        for i in 0..600 {
+            let id = (i * 7919) % 600 + 5;
+            let statement = format!(
+                "INSERT INTO apples VALUES ({}, '{} {}', 'Red')",
+                id,
+                "x".repeat(400),
+                id
+            );
+            insert(&mut database, &statement).unwrap();
+        }
+        let pages = database.header.database_size;
+        let rows = database
+            .scan("apples")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        database.vacuum().unwrap();
+        assert!(database.header.database_size < pages * 3 / 4);
+        let vacuumed = database
+            .scan("apples")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(vacuumed, rows);
+        let index = database.schema.table("apples").unwrap().indexes[0].clone();
+        assert_eq!(index.name, "by_name");
+        assert_eq!(stats::analyze_index(&database, &index).unwrap().rows, 604);
+        assert!(!std::path::Path::new(&format!("{path}-vacuum")).exists());
+
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn vacuum_copies_overflow_pages() {
+        let database_file = TempDatabase::sample("vacuum_overflow");
+        let path = database_file.path();
+
+        // A record over several pages, with an index entry that overflows too
+        let mut database = Database::open(path).unwrap();
+        create_index(&mut database, "CREATE INDEX by_name ON apples (name)").unwrap();
+        let name = (0..3000).map(|i| i.to_string()).collect::<String>();
+        let statement = format!("INSERT INTO apples VALUES (5, '{name}', 'Red')");
+        insert(&mut database, &statement).unwrap();
+        let rows = database
+            .scan("apples")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        database.vacuum().unwrap();
+        let vacuumed = database
+            .scan("apples")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(vacuumed, rows);
+        assert_eq!(vacuumed[4].values[1], Value::Text(name));
+        if let Some(result) = integrity_check(path) {
+            assert_eq!(result, "ok");
+        }
+    }
+
+    #[test]
+    fn failed_statement_is_rolled_back() {
+        let database_file = TempDatabase::sample("rollback");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        let statement = "INSERT INTO apples VALUES (10, 'Honeycrisp', 'Red'), (1, 'Gala', 'Red')";
+        let error = insert(&mut database, statement).unwrap_err();
+        assert_eq!(error.to_string(), "UNIQUE constraint failed: apples.id");
+        assert_eq!(database.scan("apples").unwrap().count(), 4);
+
+        let database = Database::open(path).unwrap();
+        assert_eq!(database.scan("apples").unwrap().count(), 4);
+        assert_eq!(
+            std::fs::metadata(format!("{path}-journal")).unwrap().len(),
+            0
+        );
+    }
+
+    #[test]
+    fn interrupted_transaction_is_recovered() {
+        let database_file = TempDatabase::sample("interrupted");
+        let path = database_file.path();
+        let original = std::fs::read(path).unwrap();
+
+        // Leave the transaction open, like a process that dies halfway through a write
+        let mut database = Database::open(path).unwrap();
+        database.pager.begin().unwrap();
+        for i in 0..50 {
+            let statement = format!(
+                "INSERT INTO apples (name) VALUES ('{}')",
+                "x".repeat(i * 40)
+            );
+            match sql::parse(statement.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Insert(insert) => database.insert_rows(&insert).unwrap(),
+                _ => unreachable!(),
+            }
+        }
+        drop(database);
+        assert_ne!(std::fs::read(path).unwrap(), original);
+
+        // SQLite rolls back the hot journal when it opens the database
+        let copy_file = TempDatabase::new("interrupted-copy");
+        let copy = copy_file.path();
+        std::fs::copy(path, copy).unwrap();
+        std::fs::copy(format!("{path}-journal"), format!("{copy}-journal")).unwrap();
+        if let Some(result) = integrity_check(copy) {
+            assert_eq!(result, "ok");
+            assert_eq!(std::fs::read(copy).unwrap(), original);
+        }
+
+        let database = Database::open(path).unwrap();
+        assert_eq!(database.scan("apples").unwrap().count(), 4);
+        assert_eq!(std::fs::read(path).unwrap(), original);
+        assert!(!std::path::Path::new(&format!("{path}-journal")).exists());
     }
 }
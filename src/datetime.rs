@@ -0,0 +1,696 @@
+//! The date and time functions `date()`, `time()`, `datetime()`, `julianday()` and
+//! `strftime()`, which follow SQLite's `date.c`.
+//!
+//! A time is a julian day number in milliseconds. It can be given as ISO-8601 text like
+//! `2024-02-29 13:45:00.123+01:00`, as `now`, or as a number: a julian day, or seconds since
+//! 1970 with the `unixepoch` modifier. Invalid times and modifiers make the result NULL.
+//!
+//! There is no time zone database, so times are always in UTC and the `localtime` and `utc`
+//! modifiers leave them unchanged.
+
+use crate::affinity::parse_real;
+use crate::clock;
+use crate::error::Error;
+use crate::record::{format_real_digits, ColumnValue};
+
+/// A day in milliseconds.
+const DAY: i64 = 86_400_000;
+/// 1970-01-01 00:00:00 as a julian day number in milliseconds.
+const UNIX_EPOCH_JD: i64 = 210_866_760_000_000;
+/// The last millisecond of 9999-12-31, the latest time the functions support. The earliest is
+/// julian day 0, in 4714 BC.
+const MAX_JD: i64 = 464_269_060_799_999;
+
+/// The units of `+N unit` modifiers: their length in seconds, and the limit of N that keeps
+/// the result in range.
+const UNITS: &[(&str, f64, f64)] = &[
+    ("second", 1.0, 4.6427e14),
+    ("minute", 60.0, 7.7379e12),
+    ("hour", 3600.0, 1.2897e11),
+    ("day", 86400.0, 5373485.0),
+    ("month", 2592000.0, 176546.0),
+    ("year", 31536000.0, 14713.0),
+];
+
+/// `date(time, modifiers...)`, the date as YYYY-MM-DD.
+pub fn date<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    Ok(text(
+        evaluate(&args).map(|time| format_date(&time.fields())),
+    ))
+}
+
+/// `time(time, modifiers...)`, the time as HH:MM:SS.
+pub fn time<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    Ok(text(
+        evaluate(&args).map(|time| format_time(&time.fields())),
+    ))
+}
+
+/// `datetime(time, modifiers...)`, the date and time as YYYY-MM-DD HH:MM:SS.
+pub fn datetime<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    Ok(text(evaluate(&args).map(|time| {
+        let fields = time.fields();
+        format!("{} {}", format_date(&fields), format_time(&fields))
+    })))
+}
+
+/// `julianday(time, modifiers...)`, the fractional number of days since noon on November 24,
+/// 4714 BC.
+pub fn julianday<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    Ok(match evaluate(&args).and_then(|time| time.jd) {
+        Some(jd) => ColumnValue::F64(jd as f64 / DAY as f64),
+        None => ColumnValue::Null,
+    })
+}
+
+/// `strftime(format, time, modifiers...)`, the time formatted with the substitutions `%d`,
+/// `%f`, `%H`, `%j`, `%J`, `%m`, `%M`, `%s`, `%S`, `%w`, `%W`, `%Y` and `%%`.
+pub fn strftime<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    let Some(format) = args.first().and_then(modifier_text) else {
+        return Ok(ColumnValue::Null);
+    };
+    Ok(text(
+        evaluate(&args[1..]).and_then(|time| format_time_with(&format, &time)),
+    ))
+}
+
+fn text<'a>(text: Option<String>) -> ColumnValue<'a> {
+    match text {
+        Some(text) => ColumnValue::Text(text.into_bytes().into()),
+        None => ColumnValue::Null,
+    }
+}
+
+/// The time given by the arguments of a date and time function: a time value and modifiers
+/// that are applied to it in order. Without arguments, it's the current time.
+fn evaluate(args: &[ColumnValue]) -> Option<Time> {
+    let mut time = match args.first() {
+        None => Time::now(),
+        Some(ColumnValue::Null) => return None,
+        Some(value) if value.is_number() => Time::number(value.as_f64()?),
+        Some(value) => Time::parse(&modifier_text(value)?)?,
+    };
+    for (position, modifier) in args.iter().skip(1).enumerate() {
+        let modifier = modifier_text(modifier)?;
+        time.modify(&modifier.to_ascii_lowercase(), position == 0)?;
+    }
+    (0..=MAX_JD).contains(&time.jd?).then_some(time)
+}
+
+/// The text of an argument. Numbers are converted like SQLite converts them to text.
+fn modifier_text(value: &ColumnValue) -> Option<String> {
+    match value {
+        ColumnValue::Null => None,
+        ColumnValue::Text(text) | ColumnValue::Blob(text) => {
+            Some(String::from_utf8_lossy(text).into_owned())
+        }
+        ColumnValue::F64(n) => Some(crate::record::format_real(*n)),
+        value => value.as_i64().map(|n| n.to_string()),
+    }
+}
+
+/// A year, month and day.
+type Date = (i64, i64, i64);
+/// An hour, minute and second.
+type TimeOfDay = (i64, i64, f64);
+
+/// A time that modifiers are applied to.
+#[derive(Debug, Clone, Copy)]
+struct Time {
+    /// The julian day number in milliseconds, or `None` for a number that is only a valid time
+    /// with the `unixepoch` modifier
+    jd: Option<i64>,
+    /// The number the time was given as, until a modifier changes it
+    raw: Option<f64>,
+    /// The year, month and day as they were given, until a modifier changes the time. Like in
+    /// SQLite, they're returned as they are, even past the end of the month like 2024-02-31.
+    date: Option<Date>,
+    /// The hour, minute and second as they were given, which can be 24:00
+    time: Option<TimeOfDay>,
+    /// Whether the time was given with a time zone, so it's in UTC already
+    utc: bool,
+    /// Whether the time was given without a date. It's on 2000-01-01 for the `start of` and
+    /// `weekday` modifiers even at 24:00, which is on the next day otherwise.
+    time_only: bool,
+}
+
+impl Time {
+    fn at(jd: i64) -> Self {
+        Self {
+            jd: Some(jd),
+            raw: None,
+            date: None,
+            time: None,
+            utc: false,
+            time_only: false,
+        }
+    }
+
+    fn now() -> Self {
+        Self::at(UNIX_EPOCH_JD + clock::now().as_millis() as i64)
+    }
+
+    /// A time given as a number, which is a julian day number unless `unixepoch` follows.
+    fn number(number: f64) -> Self {
+        Self {
+            jd: (0.0..5373484.5)
+                .contains(&number)
+                .then_some((number * DAY as f64 + 0.5) as i64),
+            raw: Some(number),
+            ..Self::at(0)
+        }
+    }
+
+    /// A time given as text: a date with an optional time, a time on 2000-01-01, `now` or a
+    /// number.
+    fn parse(text: &str) -> Option<Self> {
+        if let Some(time) = parse_date_time(text.as_bytes()) {
+            return Some(time);
+        }
+        if text.eq_ignore_ascii_case("now") {
+            return Some(Self::now());
+        }
+        parse_real(text.trim()).map(Self::number)
+    }
+
+    /// A time with the given date and time of day, which are kept as they are.
+    fn from_fields(fields: Fields) -> Option<Self> {
+        Some(Self {
+            date: Some((fields.year, fields.month, fields.day)),
+            time: Some((fields.hour, fields.minute, fields.second)),
+            ..Self::at(fields.to_jd()?)
+        })
+    }
+
+    /// The date and time of day, as they were given or computed from the julian day number.
+    fn fields(&self) -> Fields {
+        let mut fields = Fields::from_jd(self.jd.unwrap_or_default());
+        if let Some((year, month, day)) = self.date {
+            (fields.year, fields.month, fields.day) = (year, month, day);
+        }
+        if let Some((hour, minute, second)) = self.time {
+            (fields.hour, fields.minute, fields.second) = (hour, minute, second);
+        }
+        fields
+    }
+
+    /// The fields the `start of` and `weekday` modifiers change.
+    fn modifier_fields(&self) -> Fields {
+        let mut fields = self.fields();
+        if self.time_only {
+            (fields.year, fields.month, fields.day) = (2000, 1, 1);
+        }
+        fields
+    }
+
+    /// Applies a lowercase modifier. `unixepoch` has to be the first one.
+    fn modify(&mut self, modifier: &str, first: bool) -> Option<()> {
+        if modifier == "unixepoch" {
+            let raw = self.raw.filter(|_| first)?;
+            let ms = raw * 1000.0 + UNIX_EPOCH_JD as f64;
+            if !(0.0..(MAX_JD + 1) as f64).contains(&ms) {
+                return None;
+            }
+            *self = Self::at((ms + 0.5) as i64);
+            return Some(());
+        }
+        let jd = valid(self.jd?)?;
+        match modifier {
+            "localtime" => *self = Self::at(jd),
+            "utc" if self.utc => {}
+            "utc" => {
+                *self = Self {
+                    utc: true,
+                    ..Self::at(jd)
+                }
+            }
+            _ => {
+                if let Some(weekday) = modifier.strip_prefix("weekday ") {
+                    let weekday = parse_real(weekday.trim())?;
+                    if !(0.0..7.0).contains(&weekday) || weekday.fract() != 0.0 {
+                        return None;
+                    }
+                    let jd = self.modifier_fields().to_jd()?;
+                    let mut current = ((jd + 129_600_000) / DAY) % 7;
+                    if current > weekday as i64 {
+                        current -= 7;
+                    }
+                    *self = Self::at(jd + (weekday as i64 - current) * DAY);
+                } else if let Some(unit) = modifier.strip_prefix("start of ") {
+                    let mut fields = self.modifier_fields();
+                    (fields.hour, fields.minute, fields.second) = (0, 0, 0.0);
+                    match unit {
+                        "month" => fields.day = 1,
+                        "year" => (fields.month, fields.day) = (1, 1),
+                        "day" => {}
+                        _ => return None,
+                    }
+                    *self = Self::from_fields(fields)?;
+                } else if modifier.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-')
+                {
+                    *self = Self::at(self.add(modifier)?);
+                } else {
+                    return None;
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Applies a `±HH:MM[:SS.SSS]` or `±N unit` modifier, and returns the new julian day
+    /// number.
+    fn add(&self, modifier: &str) -> Option<i64> {
+        let jd = self.jd?;
+        let end = modifier
+            .find(|c: char| c == ':' || c.is_ascii_whitespace())
+            .unwrap_or(modifier.len());
+        let amount = parse_real(&modifier[..end])?;
+        if modifier[end..].starts_with(':') {
+            let time = modifier.strip_prefix(['+', '-']).unwrap_or(modifier);
+            let ((hour, minute, second), zone) = parse_time(time.as_bytes())?;
+            let ms = time_ms(hour, minute, second) - zone.unwrap_or(0) * 60_000;
+            let ms = ms - ms / DAY * DAY;
+            return Some(if modifier.starts_with('-') {
+                jd - ms
+            } else {
+                jd + ms
+            });
+        }
+
+        let unit = modifier[end..].trim_start();
+        if !(3..=10).contains(&unit.len()) {
+            return None;
+        }
+        let unit = if unit.len() > 3 {
+            unit.strip_suffix('s').unwrap_or(unit)
+        } else {
+            unit
+        };
+        let &(name, seconds, limit) = UNITS.iter().find(|(name, ..)| *name == unit)?;
+        if !(-limit < amount && amount < limit) {
+            return None;
+        }
+        let mut jd = jd;
+        let mut amount = amount;
+        if name == "month" || name == "year" {
+            let mut fields = self.fields();
+            let whole = amount.trunc() as i64;
+            if name == "month" {
+                fields.month += whole;
+                let years = if fields.month > 0 {
+                    (fields.month - 1) / 12
+                } else {
+                    (fields.month - 12) / 12
+                };
+                fields.year += years;
+                fields.month -= years * 12;
+            } else {
+                fields.year += whole;
+            }
+            jd = fields.to_jd()?;
+            amount = amount.fract();
+        }
+        let rounder = if amount < 0.0 { -0.5 } else { 0.5 };
+        Some(jd + (amount * 1000.0 * seconds + rounder) as i64)
+    }
+}
+
+fn valid(jd: i64) -> Option<i64> {
+    (0..=MAX_JD).contains(&jd).then_some(jd)
+}
+
+/// The fields of a time in the proleptic Gregorian calendar.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Fields {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: f64,
+}
+
+impl Fields {
+    fn from_jd(jd: i64) -> Self {
+        let z = (jd + DAY / 2) / DAY;
+        let a = ((z as f64 - 1867216.25) / 36524.25) as i64;
+        let a = z + 1 + a - a / 4;
+        let b = a + 1524;
+        let c = ((b as f64 - 122.1) / 365.25) as i64;
+        let d = (36525 * (c & 32767)) / 100;
+        let e = ((b - d) as f64 / 30.6001) as i64;
+        let month = if e < 14 { e - 1 } else { e - 13 };
+
+        let day_ms = (jd + DAY / 2) % DAY;
+        let day_minutes = day_ms / 60_000;
+        Self {
+            year: if month > 2 { c - 4716 } else { c - 4715 },
+            month,
+            day: b - d - (30.6001 * e as f64) as i64,
+            hour: day_minutes / 60,
+            minute: day_minutes % 60,
+            second: day_ms as f64 / 1000.0 - (day_minutes * 60) as f64,
+        }
+    }
+
+    /// The julian day number in milliseconds, or `None` for years out of range. Days and
+    /// times past the end of their month or day carry into the next one.
+    fn to_jd(self) -> Option<i64> {
+        if !(-4713..=9999).contains(&self.year) {
+            return None;
+        }
+        let (mut year, mut month) = (self.year, self.month);
+        if month <= 2 {
+            year -= 1;
+            month += 12;
+        }
+        let a = year / 100;
+        let b = 2 - a + a / 4;
+        let x1 = 36525 * (year + 4716) / 100;
+        let x2 = 306001 * (month + 1) / 10000;
+        let days = ((x1 + x2 + self.day + b) as f64 - 1524.5) * DAY as f64;
+        Some(days as i64 + time_ms(self.hour, self.minute, self.second))
+    }
+}
+
+fn time_ms(hour: i64, minute: i64, second: f64) -> i64 {
+    hour * 3_600_000 + minute * 60_000 + (second * 1000.0 + 0.5) as i64
+}
+
+/// Parses `[-]YYYY-MM-DD`, optionally followed by a time after spaces or a `T`, or a time
+/// alone, which is on 2000-01-01.
+fn parse_date_time(text: &[u8]) -> Option<Time> {
+    let (date, text) = match parse_date(text) {
+        Some((date, rest)) => (
+            Some(date),
+            skip(rest, |c| c.is_ascii_whitespace() || c == b'T'),
+        ),
+        None => (None, text),
+    };
+    let (time, zone) = match date {
+        Some(_) if text.is_empty() => (None, None),
+        _ => {
+            let (time, zone) = parse_time(text)?;
+            (Some(time), zone)
+        }
+    };
+    let (year, month, day) = date.unwrap_or((2000, 1, 1));
+    let (hour, minute, second) = time.unwrap_or((0, 0, 0.0));
+    let fields = Fields {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    };
+    let offset = zone.unwrap_or(0);
+    let jd = fields.to_jd()? - offset * 60_000;
+    // Converting to UTC changes the date and time that were given
+    let given = offset == 0;
+    Some(Time {
+        date: date.filter(|_| given),
+        time: time.filter(|_| given),
+        utc: zone.is_some(),
+        time_only: date.is_none(),
+        ..Time::at(jd)
+    })
+}
+
+/// Parses `[-]YYYY-MM-DD` into the year, month and day, and returns the text after it.
+fn parse_date(text: &[u8]) -> Option<(Date, &[u8])> {
+    let (negative, text) = match text.strip_prefix(b"-") {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let (year, text) = digits(text, 4, 0, 9999)?;
+    let (month, text) = digits(text.strip_prefix(b"-")?, 2, 1, 12)?;
+    let (day, text) = digits(text.strip_prefix(b"-")?, 2, 1, 31)?;
+    Some(((if negative { -year } else { year }, month, day), text))
+}
+
+/// Parses `HH:MM[:SS[.SSS]]` with an optional time zone: `Z` or `±HH:MM`. Returns the hour,
+/// minute and second, and the time zone's offset from UTC in minutes.
+fn parse_time(text: &[u8]) -> Option<(TimeOfDay, Option<i64>)> {
+    let (hour, text) = digits(text, 2, 0, 24)?;
+    let (minute, mut text) = digits(text.strip_prefix(b":")?, 2, 0, 59)?;
+    let mut second = 0.0;
+    if let Some(rest) = text.strip_prefix(b":") {
+        let (whole, rest) = digits(rest, 2, 0, 59)?;
+        second = whole as f64;
+        text = rest;
+        if let Some(fraction) = text
+            .strip_prefix(b".")
+            .filter(|fraction| fraction.first().map_or(false, u8::is_ascii_digit))
+        {
+            let length = fraction.iter().take_while(|c| c.is_ascii_digit()).count();
+            let (mut value, mut scale) = (0.0, 1.0);
+            for digit in &fraction[..length] {
+                value = value * 10.0 + f64::from(digit - b'0');
+                scale *= 10.0;
+            }
+            second += value / scale;
+            text = &fraction[length..];
+        }
+    }
+    Some(((hour, minute, second), parse_time_zone(text)?))
+}
+
+/// Parses the time zone after a time into its offset in minutes, or `Some(None)` without one.
+fn parse_time_zone(text: &[u8]) -> Option<Option<i64>> {
+    let text = skip(text, |c| c.is_ascii_whitespace());
+    let (offset, rest) = match text.first() {
+        None => return Some(None),
+        Some(b'Z' | b'z') => (0, &text[1..]),
+        Some(&sign @ (b'+' | b'-')) => {
+            let (hours, rest) = digits(&text[1..], 2, 0, 14)?;
+            let (minutes, rest) = digits(rest.strip_prefix(b":")?, 2, 0, 59)?;
+            let offset = hours * 60 + minutes;
+            (if sign == b'-' { -offset } else { offset }, rest)
+        }
+        Some(_) => return None,
+    };
+    skip(rest, |c| c.is_ascii_whitespace())
+        .is_empty()
+        .then_some(Some(offset))
+}
+
+/// Parses exactly `count` digits into a number between `min` and `max`.
+fn digits(text: &[u8], count: usize, min: i64, max: i64) -> Option<(i64, &[u8])> {
+    let digits = text.get(..count)?;
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let number = digits
+        .iter()
+        .fold(0, |number, digit| number * 10 + i64::from(digit - b'0'));
+    (min..=max)
+        .contains(&number)
+        .then_some((number, &text[count..]))
+}
+
+fn skip(text: &[u8], predicate: impl Fn(u8) -> bool) -> &[u8] {
+    let skipped = text.iter().take_while(|&&c| predicate(c)).count();
+    &text[skipped..]
+}
+
+/// The date as YYYY-MM-DD. Years before 1 BC are negative.
+fn format_date(fields: &Fields) -> String {
+    let sign = if fields.year < 0 { "-" } else { "" };
+    format!(
+        "{sign}{:04}-{:02}-{:02}",
+        fields.year.abs(),
+        fields.month,
+        fields.day
+    )
+}
+
+fn format_time(fields: &Fields) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        fields.hour, fields.minute, fields.second as i64
+    )
+}
+
+/// Formats a time for `strftime()`, or returns `None` for an unknown substitution.
+fn format_time_with(format: &str, time: &Time) -> Option<String> {
+    let (jd, fields) = (time.jd?, time.fields());
+    let mut result = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'd' => result += &format!("{:02}", fields.day),
+            'f' => result += &format!("{:06.3}", fields.second.min(59.999)),
+            'H' => result += &format!("{:02}", fields.hour),
+            substitution @ ('j' | 'W') => {
+                let start_of_year = Fields {
+                    month: 1,
+                    day: 1,
+                    ..fields
+                };
+                let day_of_year = (jd - start_of_year.to_jd()? + DAY / 2) / DAY;
+                if substitution == 'j' {
+                    result += &format!("{:03}", day_of_year + 1);
+                } else {
+                    // 0 is Monday
+                    let weekday = ((jd + DAY / 2) / DAY) % 7;
+                    result += &format!("{:02}", (day_of_year + 7 - weekday) / 7);
+                }
+            }
+            'J' => {
+                let julian_day = format_real_digits(jd as f64 / DAY as f64, 16);
+                result += julian_day.strip_suffix(".0").unwrap_or(&julian_day);
+            }
+            'm' => result += &format!("{:02}", fields.month),
+            'M' => result += &format!("{:02}", fields.minute),
+            's' => result += &(jd / 1000 - UNIX_EPOCH_JD / 1000).to_string(),
+            'S' => result += &format!("{:02}", fields.second as i64),
+            // 0 is Sunday
+            'w' => result += &(((jd + 129_600_000) / DAY) % 7).to_string(),
+            'Y' => result += &format!("{:04}", fields.year),
+            '%' => result.push('%'),
+            _ => return None,
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(function: crate::functions::ScalarFunction, args: &[&str]) -> Option<String> {
+        let args = args
+            .iter()
+            .map(|arg| ColumnValue::Text(arg.as_bytes().into()))
+            .collect();
+        function(args).unwrap().as_str().map(str::to_string)
+    }
+
+    #[test]
+    fn parse_times() {
+        let date = |args: &[&str]| call(datetime, args);
+        assert_eq!(
+            date(&["2024-02-29"]).as_deref(),
+            Some("2024-02-29 00:00:00")
+        );
+        assert_eq!(
+            date(&["2024-02-29T13:45:59.999"]).as_deref(),
+            Some("2024-02-29 13:45:59")
+        );
+        assert_eq!(
+            date(&["2024-02-29 23:30 -02:00"]).as_deref(),
+            Some("2024-03-01 01:30:00")
+        );
+        assert_eq!(
+            date(&["2024-02-29 12:00Z"]).as_deref(),
+            Some("2024-02-29 12:00:00")
+        );
+        assert_eq!(date(&["13:45"]).as_deref(), Some("2000-01-01 13:45:00"));
+        // Like in SQLite, the date and time are returned as they were given
+        assert_eq!(
+            date(&["2024-02-31"]).as_deref(),
+            Some("2024-02-31 00:00:00")
+        );
+        assert_eq!(
+            date(&["2024-01-01 24:00"]).as_deref(),
+            Some("2024-01-01 24:00:00")
+        );
+        assert_eq!(
+            date(&["2024-02-31", "+0 days"]).as_deref(),
+            Some("2024-03-02 00:00:00")
+        );
+        assert_eq!(date(&["2460369.5"]).as_deref(), Some("2024-02-29 00:00:00"));
+        assert_eq!(
+            date(&["-0044-03-15"]).as_deref(),
+            Some("-0044-03-15 00:00:00")
+        );
+        assert_eq!(date(&["2024-13-01"]), None);
+        assert_eq!(date(&["2024-2-29"]), None);
+        assert_eq!(date(&["2024-02-29 noon"]), None);
+        assert_eq!(date(&["yesterday"]), None);
+        assert!(date(&["now"]).is_some());
+        assert!(matches!(
+            datetime(vec![ColumnValue::Null]),
+            Ok(ColumnValue::Null)
+        ));
+        assert_eq!(
+            datetime(vec![
+                ColumnValue::I64(1709164800),
+                ColumnValue::Text(b"unixepoch"[..].into())
+            ])
+            .unwrap()
+            .as_str(),
+            Some("2024-02-29 00:00:00")
+        );
+    }
+
+    #[test]
+    fn modifiers() {
+        let date = |args: &[&str]| call(datetime, args);
+        let cases = [
+            (&["2024-02-29", "-7 days"][..], Some("2024-02-22 00:00:00")),
+            (&["2024-02-29", "+1 year"], Some("2025-03-01 00:00:00")),
+            (&["2024-01-31", "+1 month"], Some("2024-03-02 00:00:00")),
+            (&["2024-01-31", "-13 months"], Some("2022-12-31 00:00:00")),
+            (&["2024-01-01", "+1.5 day"], Some("2024-01-02 12:00:00")),
+            (&["2024-01-01", "+90 minutes"], Some("2024-01-01 01:30:00")),
+            (&["2024-01-01 12:00", "-12:30"], Some("2023-12-31 23:30:00")),
+            (
+                &["2024-02-29 13:45", "start of month"],
+                Some("2024-02-01 00:00:00"),
+            ),
+            (
+                &["2024-02-29 13:45", "start of year"],
+                Some("2024-01-01 00:00:00"),
+            ),
+            (
+                &["2024-02-29 13:45", "START OF DAY"],
+                Some("2024-02-29 00:00:00"),
+            ),
+            (&["2024-02-29", "weekday 0"], Some("2024-03-03 00:00:00")),
+            (&["2024-02-29", "weekday 4"], Some("2024-02-29 00:00:00")),
+            (&["2024-02-29", "localtime"], Some("2024-02-29 00:00:00")),
+            (&["1000000", "unixepoch"], Some("1970-01-12 13:46:40")),
+            (&["2024-02-29", "unixepoch"], None),
+            (&["1000000", "+1 day", "unixepoch"], None),
+            (&["2024-02-29", "+1 fortnight"], None),
+            (&["2024-02-29", "weekday 7"], None),
+            (&["9999-12-31", "+1 day"], None),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(date(args).as_deref(), expected, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn formats() {
+        let format = |format: &str| call(strftime, &[format, "2024-03-04 05:06:07.891"]);
+        assert_eq!(
+            format("%Y-%m-%d %H:%M:%S %f %%").as_deref(),
+            Some("2024-03-04 05:06:07 07.891 %")
+        );
+        assert_eq!(format("%j %w %W").as_deref(), Some("064 1 10"));
+        assert_eq!(format("%s").as_deref(), Some("1709528767"));
+        assert_eq!(format("%J").as_deref(), Some("2460373.712591331"));
+        assert_eq!(format("%Q"), None);
+        assert_eq!(
+            call(date, &["2024-03-04 05:06:07"]).as_deref(),
+            Some("2024-03-04")
+        );
+        assert_eq!(
+            call(time, &["2024-03-04 05:06:07"]).as_deref(),
+            Some("05:06:07")
+        );
+        assert_eq!(
+            julianday(vec![ColumnValue::Text(b"2000-01-01 12:00"[..].into())])
+                .unwrap()
+                .as_f64(),
+            Some(2451545.0)
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! How much space the b-trees of a database take up, like SQLite's `dbstat` virtual table and
+//! `sqlite3_analyzer` report it.
+
+use anyhow::{bail, Result};
+
+use crate::cursor::TableCursor;
+use crate::database::{Database, MAX_DEPTH};
+use crate::error::Error;
+use crate::page::Cell;
+use crate::sqlite_schema::{SQLiteSchema, SCHEMA_TABLE_NAME};
+
+/// The space used by the b-tree of a table or index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpaceUsage {
+    pub name: String,
+    pub is_index: bool,
+    /// The number of levels of pages, 1 if the root page is a leaf
+    pub depth: usize,
+    pub interior_pages: u64,
+    pub leaf_pages: u64,
+    pub overflow_pages: u64,
+    /// The cells on the leaves, which are the rows of a table or the entries of an index
+    pub entries: u64,
+    /// The size of the records in the cells, including the parts on overflow pages
+    pub payload: u64,
+    /// The bytes of the pages that hold nothing: unallocated space, freeblocks and fragments
+    /// of b-tree pages, and the end of the last overflow page of a record
+    pub unused: u64,
+}
+
+impl SpaceUsage {
+    pub fn pages(&self) -> u64 {
+        self.interior_pages + self.leaf_pages + self.overflow_pages
+    }
+}
+
+/// The space used by each b-tree of the database: the schema table first, then the tables
+/// and indexes in the order they were created.
+pub fn space_usage(database: &Database) -> Result<Vec<SpaceUsage>> {
+    let schema = SQLiteSchema::read(TableCursor::new(database, 1)?)?;
+    let trees = std::iter::once((SCHEMA_TABLE_NAME.to_string(), false, 1)).chain(
+        schema
+            .rows
+            .into_iter()
+            .filter(|row| matches!(row.kind.as_str(), "table" | "index") && row.rootpage > 0)
+            .map(|row| (row.name, row.kind == "index", row.rootpage)),
+    );
+    trees
+        .map(|(name, is_index, rootpage)| {
+            let mut usage = SpaceUsage {
+                name,
+                is_index,
+                ..SpaceUsage::default()
+            };
+            add_tree(database, rootpage, &mut usage)?;
+            Ok(usage)
+        })
+        .collect()
+}
+
+/// Adds the pages of the b-tree rooted at `rootpage` to `usage`.
+fn add_tree(database: &Database, rootpage: u32, usage: &mut SpaceUsage) -> Result<()> {
+    let mut pages = vec![(rootpage, 1)];
+    while let Some((number, depth)) = pages.pop() {
+        if depth > MAX_DEPTH {
+            bail!(Error::Corrupt("b-tree is too deep".to_string()));
+        }
+        let page = database.get_page(number - 1)?;
+        usage.depth = usage.depth.max(depth);
+        usage.unused += page.unused_bytes() as u64;
+        if page.header.kind.is_leaf() {
+            usage.leaf_pages += 1;
+            usage.entries += page.cell_pointers.len() as u64;
+        } else {
+            usage.interior_pages += 1;
+        }
+        pages.extend(
+            page.header
+                .right_child_page_number
+                .map(|child| (child, depth + 1)),
+        );
+
+        for cell in page.cells() {
+            let (size, local, overflow_page) = match cell {
+                Cell::InteriorTable {
+                    left_child_page, ..
+                } => {
+                    pages.push((left_child_page, depth + 1));
+                    continue;
+                }
+                Cell::InteriorIndex {
+                    left_child_page,
+                    size,
+                    payload,
+                    overflow_page,
+                } => {
+                    pages.push((left_child_page, depth + 1));
+                    (size, payload.len(), overflow_page)
+                }
+                Cell::LeafIndex {
+                    size,
+                    payload,
+                    overflow_page,
+                }
+                | Cell::LeafTable {
+                    size,
+                    payload,
+                    overflow_page,
+                    ..
+                } => (size, payload.len(), overflow_page),
+            };
+            usage.payload += size;
+            if overflow_page != 0 {
+                add_overflow(database, overflow_page, size as usize - local, usage)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds the overflow pages that hold the last `size` bytes of a record, starting at `first`.
+fn add_overflow(
+    database: &Database,
+    first: u32,
+    size: usize,
+    usage: &mut SpaceUsage,
+) -> Result<()> {
+    let capacity = database.header.usable_size() - 4;
+    let mut next = first;
+    let mut remaining = size;
+    while remaining > 0 {
+        if next == 0 {
+            bail!(Error::Corrupt("overflow list is too short".to_string()));
+        }
+        let data = database.pager.read_page(next - 1, 0)?;
+        next = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        usage.overflow_pages += 1;
+        usage.unused += capacity.saturating_sub(remaining) as u64;
+        remaining = remaining.saturating_sub(capacity);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_space_usage() {
+        let database = Database::open("sample.db").unwrap();
+        let usage = space_usage(&database).unwrap();
+        assert_eq!(
+            usage
+                .iter()
+                .map(|usage| usage.name.as_str())
+                .collect::<Vec<_>>(),
+            ["sqlite_schema", "apples", "sqlite_sequence", "oranges"]
+        );
+        // Four rows of 27, 11, 23 and 26 bytes, which take two more bytes each for the size
+        // and the rowid
+        assert_eq!(
+            usage[1],
+            SpaceUsage {
+                name: "apples".to_string(),
+                is_index: false,
+                depth: 1,
+                interior_pages: 0,
+                leaf_pages: 1,
+                overflow_pages: 0,
+                entries: 4,
+                payload: 87,
+                unused: 4096 - 8 - 4 * 2 - 87 - 4 * 2,
+            }
+        );
+        assert_eq!(usage.iter().map(SpaceUsage::pages).sum::<u64>(), 4);
+    }
+}
@@ -0,0 +1,71 @@
+use thiserror::Error;
+
+use crate::sql::SyntaxError;
+
+/// Errors raised while looking up schema objects and decoding records. The messages follow
+/// the ones of the sqlite3 shell.
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("no such table: {0}")]
+    NoSuchTable(String),
+    #[error("no such column: {0}")]
+    NoSuchColumn(String),
+    #[error("ambiguous column name: {0}")]
+    AmbiguousColumn(String),
+    #[error("no such database: {0}")]
+    NoSuchDatabase(String),
+    #[error("unknown database {0}")]
+    UnknownDatabase(String),
+    #[error("database {0} is already in use")]
+    DatabaseInUse(String),
+    #[error("cannot detach database {0}")]
+    CannotDetach(String),
+    #[error("syntax error {0}")]
+    Syntax(SyntaxError),
+    #[error("syntax error in statement {statement} {error}")]
+    ScriptSyntax {
+        statement: usize,
+        error: SyntaxError,
+    },
+    #[error("no such module: {0}")]
+    NoSuchModule(String),
+    #[error("no such function: {0}")]
+    NoSuchFunction(String),
+    #[error("wrong number of arguments to function {0}()")]
+    WrongNumberOfArguments(String),
+    #[error("{0}")]
+    UserFunction(String),
+    #[error("no such collation sequence: {0}")]
+    NoSuchCollation(String),
+    #[error("circular reference: {0}")]
+    CircularReference(String),
+    #[error("recursive query {name} didn't finish in {limit} steps")]
+    RecursionLimit { name: String, limit: usize },
+    #[error("misuse of window function {0}()")]
+    WindowMisuse(String),
+    #[error("{0}() may not be used as a window function")]
+    NotWindowFunction(String),
+    #[error("row value misused")]
+    RowValueMisused,
+    #[error("integer overflow")]
+    IntegerOverflow,
+    #[error("subqueries can only be run by a connection")]
+    UnresolvedSubquery,
+    #[error("UNIQUE constraint failed: {0}")]
+    UniqueConstraint(String),
+    #[error("database disk image is malformed: {0}")]
+    Corrupt(String),
+    #[error("column index out of range: {0}")]
+    InvalidColumnIndex(usize),
+    #[error("parameter index out of range: {0}")]
+    InvalidParameterIndex(usize),
+    #[error("no such parameter: {0}")]
+    NoSuchParameter(String),
+    #[error("invalid continuation token")]
+    InvalidContinuationToken,
+    #[error("invalid column type: expected {expected}, found {found}")]
+    InvalidType {
+        expected: &'static str,
+        found: &'static str,
+    },
+}
@@ -0,0 +1,67 @@
+//! The steps of a query as `EXPLAIN` lists them. There is no bytecode to show, so each step
+//! describes what the executor does when it runs the query: which b-trees it opens, how it
+//! finds the rows, which values it decodes and what it does with the result rows.
+
+use anyhow::Result;
+
+use crate::output::OutputFormatter;
+use crate::record::ColumnValue;
+
+#[derive(Debug)]
+struct Step {
+    opcode: &'static str,
+    detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Program {
+    steps: Vec<Step>,
+    /// The number of rowsets opened for subqueries so far
+    rowsets: usize,
+}
+
+impl Program {
+    pub fn push(&mut self, opcode: &'static str, detail: impl Into<String>) {
+        self.steps.push(Step {
+            opcode,
+            detail: detail.into(),
+        });
+    }
+
+    /// Adds a step that opens a rowset for the rows of a subquery, and returns its name.
+    pub fn open_rowset(&mut self) -> String {
+        self.rowsets += 1;
+        let name = format!("rowset {}", self.rowsets);
+        self.push("OpenEphemeral", name.clone());
+        name
+    }
+
+    /// Adds the step that returns the result rows, or adds them to a rowset.
+    pub fn result_row(&mut self, columns: &[String], into: Option<&str>) {
+        let columns = columns.join(", ");
+        self.push(
+            "ResultRow",
+            match into {
+                Some(rowset) => format!("{columns} into {rowset}"),
+                None => columns,
+            },
+        );
+    }
+
+    /// Writes a row with the address, opcode and description of each step.
+    pub fn write(&self, out: &mut dyn OutputFormatter) -> Result<()> {
+        out.begin(&[
+            "addr".to_string(),
+            "opcode".to_string(),
+            "detail".to_string(),
+        ])?;
+        for (address, step) in self.steps.iter().enumerate() {
+            out.row(&[
+                ColumnValue::I64(address as i64),
+                ColumnValue::Text(step.opcode.as_bytes().into()),
+                ColumnValue::Text(step.detail.as_bytes().into()),
+            ])?;
+        }
+        out.finish()
+    }
+}
@@ -0,0 +1,672 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashSet};
+
+use crate::affinity::Affinity;
+use crate::collation::{Collation, Collations};
+use crate::error::Error;
+use crate::fts;
+use crate::functions::{Function, Functions};
+use crate::record::{ColumnValue, Record};
+use crate::sql::{BinaryOperator, Expr, Literal};
+use crate::sqlite_schema::Table;
+
+/// An expression whose column references are resolved against a table, ready to be evaluated
+/// for each record.
+///
+/// Evaluation follows SQLite's three-valued logic: comparisons with NULL are NULL, and NULL
+/// propagates through AND, OR and NOT unless the result is decided by the other operand.
+#[derive(Debug)]
+pub enum Expression {
+    Literal(Literal),
+    Column {
+        index: usize,
+        is_rowid_alias: bool,
+        /// The value in records that are too short to store the column
+        default: Literal,
+        affinity: Affinity,
+    },
+    Binary {
+        op: BinaryOperator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+        /// The collation that orders text in comparisons
+        collation: Collation,
+    },
+    Not(Box<Expression>),
+    IsNull {
+        expr: Box<Expression>,
+        negated: bool,
+    },
+    Cast {
+        expr: Box<Expression>,
+        affinity: Affinity,
+    },
+    Function {
+        function: Function,
+        args: Vec<Expression>,
+    },
+    /// The values of an IN subquery as a set, and whether one of them is NULL
+    In {
+        expr: Box<Expression>,
+        values: HashSet<Key>,
+        has_null: bool,
+        negated: bool,
+    },
+}
+
+/// A value as a set member. Numbers that compare equal have the same key, so reals without a
+/// fraction are stored as integers.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Integer(i64),
+    Real(u64),
+    Text(Vec<u8>),
+    Blob(Vec<u8>),
+}
+
+impl Key {
+    /// The key of a value, or `None` for NULL, which isn't equal to anything.
+    pub fn new(value: &ColumnValue) -> Option<Self> {
+        Some(match value {
+            ColumnValue::Null => return None,
+            ColumnValue::F64(n)
+                if n.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(n) =>
+            {
+                Key::Integer(*n as i64)
+            }
+            ColumnValue::F64(n) => Key::Real(n.to_bits()),
+            ColumnValue::Text(text) => Key::Text(text.to_vec()),
+            ColumnValue::Blob(blob) => Key::Blob(blob.to_vec()),
+            value => Key::Integer(value.as_i64().unwrap_or_default()),
+        })
+    }
+}
+
+impl Expression {
+    /// Resolves the columns of `expr`. Literals compared to a column get the column's affinity,
+    /// and the comparison its collation. Only built-in functions and collations can be used.
+    pub fn new(expr: &Expr, table: &Table) -> Result<Self, Error> {
+        Self::resolve(expr, table, &Functions::default(), &Collations::default())
+    }
+
+    /// Like [`Expression::new`], with calls resolved to `functions` and collation names to
+    /// `collations`.
+    pub fn resolve(
+        expr: &Expr,
+        table: &Table,
+        functions: &Functions,
+        collations: &Collations,
+    ) -> Result<Self, Error> {
+        Ok(match expr {
+            Expr::Literal(value) => Self::Literal(value.clone()),
+            // Like in SQLite, parameters that haven't been bound are NULL
+            Expr::Parameter(_) => Self::Literal(Literal::Null),
+            Expr::Column(name) => {
+                let (index, column) = table.column(name)?;
+                Self::Column {
+                    index,
+                    is_rowid_alias: column.is_rowid_alias,
+                    default: column.default_value(),
+                    affinity: column.affinity(),
+                }
+            }
+            Expr::Binary { op, left, right }
+                if matches!(left.as_ref(), Expr::Row(_))
+                    || matches!(right.as_ref(), Expr::Row(_)) =>
+            {
+                Self::resolve(
+                    &compare_rows(*op, left, right)?,
+                    table,
+                    functions,
+                    collations,
+                )?
+            }
+            Expr::Binary { op, left, right } => {
+                let mut bound_left = Self::resolve(left, table, functions, collations)?;
+                let mut bound_right = Self::resolve(right, table, functions, collations)?;
+                if is_comparison(*op) {
+                    if let Some(affinity) = column_affinity(left, table) {
+                        bound_right = bound_right.with_affinity(affinity);
+                    }
+                    if let Some(affinity) = column_affinity(right, table) {
+                        bound_left = bound_left.with_affinity(affinity);
+                    }
+                }
+                // Like in SQLite, the collation of a column on the left takes precedence
+                let collation = match table.collation_of(left) {
+                    Some(name) => Some(name),
+                    None => table.collation_of(right),
+                };
+                Self::Binary {
+                    op: *op,
+                    left: Box::new(bound_left),
+                    right: Box::new(bound_right),
+                    collation: collations.find(collation)?,
+                }
+            }
+            Expr::Not(expr) => {
+                Self::Not(Box::new(Self::resolve(expr, table, functions, collations)?))
+            }
+            Expr::Cast { expr, ty } => Self::Cast {
+                expr: Box::new(Self::resolve(expr, table, functions, collations)?),
+                affinity: Affinity::from_declared_type(Some(ty)),
+            },
+            Expr::IsNull { expr, negated } => Self::IsNull {
+                expr: Box::new(Self::resolve(expr, table, functions, collations)?),
+                negated: *negated,
+            },
+            Expr::Function { name, args } => Self::Function {
+                function: functions.find(name, args.len())?,
+                args: args
+                    .iter()
+                    .map(|arg| Self::resolve(arg, table, functions, collations))
+                    .collect::<Result<_, _>>()?,
+            },
+            Expr::InValues {
+                expr: left,
+                values,
+                negated,
+            } => {
+                let affinity = column_affinity(left, table);
+                let values = values
+                    .iter()
+                    .map(|value| match affinity {
+                        Some(affinity) => affinity.apply(value),
+                        None => value.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                Self::In {
+                    expr: Box::new(Self::resolve(left, table, functions, collations)?),
+                    values: values
+                        .iter()
+                        .filter_map(|value| Key::new(&ColumnValue::from(value)))
+                        .collect(),
+                    has_null: values.contains(&Literal::Null),
+                    negated: *negated,
+                }
+            }
+            // Each item is compared like with `=`, with the affinity and collation of the column
+            Expr::InList {
+                expr: left,
+                list,
+                negated,
+            } => {
+                let found = any_of(list.iter().map(|item| Expr::Binary {
+                    op: BinaryOperator::Eq,
+                    left: left.clone(),
+                    right: Box::new(item.clone()),
+                }));
+                let found = match negated {
+                    true => Expr::Not(Box::new(found)),
+                    false => found,
+                };
+                Self::resolve(&found, table, functions, collations)?
+            }
+            // The columns of the table are unqualified before a query runs
+            Expr::QualifiedColumn { table, column } => {
+                return Err(Error::NoSuchColumn(format!("{table}.{column}")))
+            }
+            Expr::Row(_) => return Err(Error::RowValueMisused),
+            // The connection computes window functions of results before the query reads them
+            Expr::Window { name, .. } => return Err(Error::WindowMisuse(name.clone())),
+            Expr::InSubquery { .. } | Expr::Subquery(_) | Expr::Exists(_) => {
+                return Err(Error::UnresolvedSubquery)
+            }
+        })
+    }
+
+    /// Adds the positions of the record values the expression reads to `columns`. The value of
+    /// a rowid alias column is the rowid, which isn't read from the record.
+    pub fn read_columns(&self, columns: &mut BTreeSet<usize>) {
+        match self {
+            Self::Literal(_) => {}
+            Self::Column {
+                index,
+                is_rowid_alias,
+                ..
+            } => {
+                if !is_rowid_alias {
+                    columns.insert(*index);
+                }
+            }
+            Self::Binary { left, right, .. } => {
+                left.read_columns(columns);
+                right.read_columns(columns);
+            }
+            Self::Not(expr)
+            | Self::IsNull { expr, .. }
+            | Self::Cast { expr, .. }
+            | Self::In { expr, .. } => expr.read_columns(columns),
+            Self::Function { args, .. } => {
+                for arg in args {
+                    arg.read_columns(columns);
+                }
+            }
+        }
+    }
+
+    fn with_affinity(self, affinity: Affinity) -> Self {
+        match self {
+            Self::Literal(value) => Self::Literal(affinity.apply(&value)),
+            expr => expr,
+        }
+    }
+
+    /// The value of the expression for a record. Only functions added to the connection can
+    /// fail.
+    pub fn evaluate<'a>(&'a self, record: &Record<'a>) -> Result<ColumnValue<'a>, Error> {
+        Ok(match self {
+            Self::Literal(value) => ColumnValue::from(value),
+            Self::Column {
+                index,
+                is_rowid_alias,
+                default,
+                affinity,
+            } => {
+                if *is_rowid_alias {
+                    ColumnValue::I64(record.rowid)
+                } else if *index >= record.len() {
+                    ColumnValue::from(default)
+                } else {
+                    affinity.read(record.value(*index))
+                }
+            }
+            Self::Binary {
+                op,
+                left,
+                right,
+                collation,
+            } => {
+                let left = left.evaluate(record)?;
+                let right = right.evaluate(record)?;
+                match op {
+                    BinaryOperator::And => from_truth(match (truth(&left), truth(&right)) {
+                        (Some(false), _) | (_, Some(false)) => Some(false),
+                        (Some(true), Some(true)) => Some(true),
+                        _ => None,
+                    }),
+                    BinaryOperator::Or => from_truth(match (truth(&left), truth(&right)) {
+                        (Some(true), _) | (_, Some(true)) => Some(true),
+                        (Some(false), Some(false)) => Some(false),
+                        _ => None,
+                    }),
+                    op => {
+                        if matches!(left, ColumnValue::Null) || matches!(right, ColumnValue::Null) {
+                            return Ok(ColumnValue::Null);
+                        }
+                        if *op == BinaryOperator::Match {
+                            let text = |value: &ColumnValue| match value.as_str() {
+                                Some(text) => text.to_string(),
+                                None => value.to_string(),
+                            };
+                            return Ok(from_truth(Some(fts::matches(&text(&left), &text(&right)))));
+                        }
+                        let ordering = collation.compare(&left, &right);
+                        from_truth(Some(match op {
+                            BinaryOperator::Eq => ordering == Ordering::Equal,
+                            BinaryOperator::NotEq => ordering != Ordering::Equal,
+                            BinaryOperator::Lt => ordering == Ordering::Less,
+                            BinaryOperator::LtEq => ordering != Ordering::Greater,
+                            BinaryOperator::Gt => ordering == Ordering::Greater,
+                            BinaryOperator::GtEq => ordering != Ordering::Less,
+                            BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Match => {
+                                unreachable!()
+                            }
+                        }))
+                    }
+                }
+            }
+            Self::Not(expr) => from_truth(truth(&expr.evaluate(record)?).map(|value| !value)),
+            Self::Cast { expr, affinity } => affinity.cast(expr.evaluate(record)?),
+            Self::IsNull { expr, negated } => {
+                let is_null = matches!(expr.evaluate(record)?, ColumnValue::Null);
+                from_truth(Some(is_null != *negated))
+            }
+            Self::Function { function, args } => function.call(
+                args.iter()
+                    .map(|arg| arg.evaluate(record))
+                    .collect::<Result<_, _>>()?,
+            )?,
+            Self::In {
+                expr,
+                values,
+                has_null,
+                negated,
+            } => {
+                // Nothing is in an empty set, not even NULL
+                let found = match Key::new(&expr.evaluate(record)?) {
+                    _ if values.is_empty() && !has_null => Some(false),
+                    Some(key) if values.contains(&key) => Some(true),
+                    Some(_) if !has_null => Some(false),
+                    _ => None,
+                };
+                from_truth(found.map(|found| found != *negated))
+            }
+        })
+    }
+
+    /// Whether the record satisfies the expression. NULL results don't match, like in WHERE.
+    pub fn matches(&self, record: &Record) -> Result<bool, Error> {
+        Ok(truth(&self.evaluate(record)?) == Some(true))
+    }
+}
+
+/// Rewrites a comparison of row values as comparisons of their values: rows are equal if all
+/// values are, and otherwise ordered by the first values that aren't equal. Like in SQLite, the
+/// result is NULL if that depends on a NULL.
+fn compare_rows(op: BinaryOperator, left: &Expr, right: &Expr) -> Result<Expr, Error> {
+    let (Expr::Row(left), Expr::Row(right)) = (left, right) else {
+        return Err(Error::RowValueMisused);
+    };
+    if left.len() != right.len() || !is_comparison(op) || op == BinaryOperator::Match {
+        return Err(Error::RowValueMisused);
+    }
+    let compare = |op, left: &Expr, right: &Expr| Expr::Binary {
+        op,
+        left: Box::new(left.clone()),
+        right: Box::new(right.clone()),
+    };
+    let pairs = left.iter().zip(right);
+    Ok(match op {
+        BinaryOperator::Eq => {
+            let all_equal = pairs.map(|(l, r)| compare(BinaryOperator::Eq, l, r));
+            all_equal
+                .reduce(|all, equal| Expr::Binary {
+                    op: BinaryOperator::And,
+                    left: Box::new(all),
+                    right: Box::new(equal),
+                })
+                .ok_or(Error::RowValueMisused)?
+        }
+        BinaryOperator::NotEq => any_of(pairs.map(|(l, r)| compare(BinaryOperator::NotEq, l, r))),
+        op => {
+            // `(a, b) < (x, y)` is `a < x OR (a = x AND b < y)`, from the last value backwards
+            let strict = match op {
+                BinaryOperator::Lt | BinaryOperator::LtEq => BinaryOperator::Lt,
+                _ => BinaryOperator::Gt,
+            };
+            let mut pairs = pairs.rev();
+            let (l, r) = pairs.next().ok_or(Error::RowValueMisused)?;
+            pairs.fold(compare(op, l, r), |rest, (l, r)| Expr::Binary {
+                op: BinaryOperator::Or,
+                left: Box::new(compare(strict, l, r)),
+                right: Box::new(Expr::Binary {
+                    op: BinaryOperator::And,
+                    left: Box::new(compare(BinaryOperator::Eq, l, r)),
+                    right: Box::new(rest),
+                }),
+            })
+        }
+    })
+}
+
+/// The conditions joined with OR, or false without any.
+fn any_of(conditions: impl Iterator<Item = Expr>) -> Expr {
+    conditions
+        .reduce(|any, condition| Expr::Binary {
+            op: BinaryOperator::Or,
+            left: Box::new(any),
+            right: Box::new(condition),
+        })
+        .unwrap_or(Expr::Literal(Literal::Integer(0)))
+}
+
+fn is_comparison(op: BinaryOperator) -> bool {
+    !matches!(op, BinaryOperator::And | BinaryOperator::Or)
+}
+
+/// The affinity a comparison with `expr` gives the other side, if any.
+pub(crate) fn column_affinity(expr: &Expr, table: &Table) -> Option<Affinity> {
+    match expr {
+        Expr::Column(name) => table.find_column(name).map(|(_, column)| column.affinity()),
+        // Like in SQLite, a CAST compares like a column of its type
+        Expr::Cast { ty, .. } => Some(Affinity::from_declared_type(Some(ty))),
+        _ => None,
+    }
+}
+
+/// The boolean value of a result, or `None` for NULL. Text is true if it holds a non-zero number.
+fn truth(value: &ColumnValue) -> Option<bool> {
+    match value {
+        ColumnValue::Null => None,
+        ColumnValue::Text(text) | ColumnValue::Blob(text) => Some(
+            String::from_utf8_lossy(text)
+                .trim()
+                .parse::<f64>()
+                .map_or(false, |n| n != 0.0),
+        ),
+        ColumnValue::F64(n) => Some(*n != 0.0),
+        value => Some(value.as_i64() != Some(0)),
+    }
+}
+
+fn from_truth(value: Option<bool>) -> ColumnValue<'static> {
+    match value {
+        Some(value) => ColumnValue::I64(value as i64),
+        None => ColumnValue::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{encode_record, Value};
+    use crate::sqlite_schema::Column;
+
+    fn table() -> Table {
+        Table {
+            name: "t".to_string(),
+            columns: vec![Column::new("a"), Column::new("b")],
+            primary_key: vec![],
+            indexes: vec![],
+            rootpage: 2,
+        }
+    }
+
+    fn matches(where_clause: &str, values: Vec<ColumnValue>) -> bool {
+        let statement = format!("SELECT a FROM t WHERE {}", where_clause);
+        let where_clause = match crate::sql::parse(statement.as_bytes()).unwrap().1 {
+            crate::sql::SQLCommand::Select(crate::sql::SelectStatement::Fields(select)) => {
+                select.where_clause.unwrap()
+            }
+            _ => panic!("not a select"),
+        };
+        let payload = encode_record(&values.iter().map(Value::from).collect::<Vec<_>>());
+        let record = Record::read(1, &payload).unwrap();
+        Expression::new(&where_clause, &table())
+            .unwrap()
+            .matches(&record)
+            .unwrap()
+    }
+
+    #[test]
+    fn function_calls() {
+        let row = || {
+            vec![
+                ColumnValue::Text(b"2024-02-25 10:00"[..].into()),
+                ColumnValue::Null,
+            ]
+        };
+        assert!(matches("a > date('2024-02-29', '-7 days')", row()));
+        assert!(!matches("a > DATE('2024-02-29', '-1 days')", row()));
+        assert!(matches("strftime('%w', a) = '0'", row()));
+        assert!(matches("date(b) IS NULL", row()));
+
+        let table = table();
+        let function = |name: &str| Expr::Function {
+            name: name.to_string(),
+            args: vec![],
+        };
+        assert_eq!(
+            Expression::new(&function("nope"), &table).err(),
+            Some(Error::NoSuchFunction("nope".to_string()))
+        );
+        assert_eq!(
+            Expression::new(&function("strftime"), &table).err(),
+            Some(Error::WrongNumberOfArguments("strftime".to_string()))
+        );
+    }
+
+    #[test]
+    fn comparisons_with_null_never_match() {
+        let row = || vec![ColumnValue::Null, ColumnValue::I64(1)];
+        assert!(!matches("a = NULL", row()));
+        assert!(!matches("a != NULL", row()));
+        assert!(!matches("a <> 1", row()));
+        assert!(!matches("NOT a = 1", row()));
+        assert!(matches("a IS NULL", row()));
+        assert!(!matches("a IS NOT NULL", row()));
+        assert!(matches("b IS NOT NULL", row()));
+        assert!(!matches("b = NULL", row()));
+    }
+
+    #[test]
+    fn three_valued_logic() {
+        let row = || vec![ColumnValue::Null, ColumnValue::I64(1)];
+        // NULL AND false is false, NULL OR true is true, anything else involving NULL is NULL
+        assert!(matches("NOT (a = 1 AND b = 2)", row()));
+        assert!(matches("a = 1 OR b = 1", row()));
+        assert!(!matches("a = 1 AND b = 1", row()));
+        assert!(!matches("NOT (a = 1 OR b = 2)", row()));
+    }
+
+    #[test]
+    fn comparison_operators() {
+        let row = || vec![ColumnValue::I64(5), ColumnValue::Text(b"x"[..].into())];
+        assert!(matches("a > 4 AND a >= 5 AND a < 6 AND a <= 5", row()));
+        assert!(matches("a != 4 AND b <> 'y'", row()));
+        assert!(!matches("a < 5", row()));
+        assert!(matches("4 < a", row()));
+    }
+
+    #[test]
+    fn casts_compare_with_their_affinity() {
+        let row = || vec![ColumnValue::Text(b"12abc"[..].into()), ColumnValue::I64(1)];
+        assert!(matches("CAST(a AS INTEGER) = 12", row()));
+        assert!(matches("CAST(b AS TEXT) = 1", row()));
+        assert!(matches("CAST(b AS REAL) = '1'", row()));
+        assert!(!matches("b = '1'", row()));
+        assert!(matches("CAST(NULL AS TEXT) IS NULL", row()));
+    }
+
+    #[test]
+    fn blob_literals() {
+        let row = || {
+            vec![
+                ColumnValue::Blob(b"\x00\xff"[..].into()),
+                ColumnValue::Text(b"\x00\xff"[..].into()),
+            ]
+        };
+        assert!(matches("a = X'00ff'", row()));
+        assert!(matches("a > X'00' AND a < x'01'", row()));
+        // Blobs sort after text, so they are never equal to text with the same bytes
+        assert!(!matches("b = X'00ff'", row()));
+        assert!(matches("b < X'00'", row()));
+    }
+
+    #[test]
+    fn in_values() {
+        let evaluate = |values: Vec<Literal>, negated, value: ColumnValue| {
+            let expr = Expr::InValues {
+                expr: Box::new(Expr::Column("a".to_string())),
+                values,
+                negated,
+            };
+            let payload = encode_record(&[Value::from(&value)]);
+            let record = Record::read(1, &payload).unwrap();
+            let expression = Expression::new(&expr, &table()).unwrap();
+            truth(&expression.evaluate(&record).unwrap())
+        };
+        let values = || vec![Literal::Integer(1), Literal::Real(2.5), "x".into()];
+        assert_eq!(evaluate(values(), false, ColumnValue::F64(1.0)), Some(true));
+        assert_eq!(evaluate(values(), false, ColumnValue::F64(2.5)), Some(true));
+        assert_eq!(
+            evaluate(values(), true, ColumnValue::Text(b"x"[..].into())),
+            Some(false)
+        );
+        // Text is never equal to a number
+        assert_eq!(
+            evaluate(values(), false, ColumnValue::Text(b"1"[..].into())),
+            Some(false)
+        );
+
+        // A value that isn't found may be the NULL, and NULL may be any value
+        let with_null = || vec![Literal::Integer(1), Literal::Null];
+        assert_eq!(
+            evaluate(with_null(), false, ColumnValue::I64(1)),
+            Some(true)
+        );
+        assert_eq!(evaluate(with_null(), false, ColumnValue::I64(2)), None);
+        assert_eq!(evaluate(with_null(), true, ColumnValue::I64(2)), None);
+        assert_eq!(evaluate(values(), false, ColumnValue::Null), None);
+        assert_eq!(evaluate(vec![], false, ColumnValue::Null), Some(false));
+        assert_eq!(evaluate(vec![], true, ColumnValue::Null), Some(true));
+    }
+
+    #[test]
+    fn row_values() {
+        let row = || vec![ColumnValue::I64(5), ColumnValue::Text(b"x"[..].into())];
+        assert!(matches("(a, b) = (5, 'x')", row()));
+        assert!(matches("(b, a) != ('x', 6)", row()));
+        assert!(matches("(a, b) < (5, 'y') AND (a, b) > (4, 'z')", row()));
+        assert!(matches("(a, b) <= (5, 'x') AND (a, b) >= (5, 'x')", row()));
+        assert!(!matches("(a, b) < (5, 'x')", row()));
+        // Each pair of values is compared with the affinity of its own side
+        assert!(matches("(CAST(a AS TEXT), b) = ('5', 'x')", row()));
+        assert!(!matches("(a, b) = ('5', 'x')", row()));
+
+        // NULL only decides the result if the values before it are equal
+        assert!(!matches("(a, b) = (4, NULL)", row()));
+        assert!(!matches(
+            "(a, b) = (5, NULL) OR NOT (a, b) = (5, NULL)",
+            row()
+        ));
+        assert!(matches("(a, b) < (6, NULL)", row()));
+        assert!(!matches(
+            "(a, b) < (5, NULL) OR NOT (a, b) < (5, NULL)",
+            row()
+        ));
+
+        assert!(matches("(a, b) IN ((1, 'x'), (5, 'x'))", row()));
+        assert!(matches("(a, b) NOT IN ((1, 'x'), (5, 'y'))", row()));
+        assert!(!matches("(a, b) NOT IN ((1, 'x'), (5, NULL))", row()));
+        assert!(matches("a IN (4, 5) AND b NOT IN ()", row()));
+
+        let table = table();
+        let resolve = |where_clause: &str| {
+            let statement = format!("SELECT a FROM t WHERE {where_clause}");
+            let Ok((
+                _,
+                crate::sql::SQLCommand::Select(crate::sql::SelectStatement::Fields(select)),
+            )) = crate::sql::parse(statement.as_bytes())
+            else {
+                panic!("not a select: {statement}");
+            };
+            Expression::new(&select.where_clause.unwrap(), &table).err()
+        };
+        for where_clause in [
+            "(a, b) = (1, 2, 3)",
+            "(a, b) = 1",
+            "a IN ((1, 2))",
+            "(a, b)",
+        ] {
+            assert_eq!(
+                resolve(where_clause),
+                Some(Error::RowValueMisused),
+                "{where_clause}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_column_is_an_error() {
+        let where_clause = Expr::IsNull {
+            expr: Box::new(Expr::Column("c".to_string())),
+            negated: false,
+        };
+        assert_eq!(
+            Expression::new(&where_clause, &table()).unwrap_err(),
+            Error::NoSuchColumn("c".to_string())
+        );
+    }
+}
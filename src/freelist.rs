@@ -0,0 +1,141 @@
+use anyhow::{bail, Result};
+
+use crate::database::Database;
+use crate::error::Error;
+
+/// Walks the freelist, the pages that are part of the file but not in use. The freelist is a
+/// chain of trunk pages, each listing leaf pages that are free too. Every trunk page is
+/// returned before its leaves, with page numbers counting from 1.
+pub struct Freelist<'db> {
+    database: &'db Database,
+    next_trunk: u32,
+    leaves: std::vec::IntoIter<u32>,
+    /// The number of free pages according to the header that haven't been returned yet
+    remaining: u32,
+}
+
+impl<'db> Freelist<'db> {
+    pub fn new(database: &'db Database) -> Self {
+        Self {
+            next_trunk: database.header.first_freelist_page,
+            remaining: database.header.freelist_count,
+            leaves: Vec::new().into_iter(),
+            database,
+        }
+    }
+
+    fn advance(&mut self) -> Result<Option<u32>> {
+        let page = match self.leaves.next() {
+            Some(leaf) => leaf,
+            None if self.next_trunk == 0 => {
+                if self.remaining > 0 {
+                    bail!(Error::Corrupt(format!(
+                        "freelist is {} pages shorter than the header says",
+                        self.remaining
+                    )));
+                }
+                return Ok(None);
+            }
+            None => {
+                let trunk = self.next_trunk;
+                self.check(trunk)?;
+                let data = self.database.pager.read_page(trunk - 1, 0)?;
+                let count = u32::from_be_bytes(data[4..8].try_into()?) as usize;
+                if 8 + count * 4 > self.database.header.usable_size() {
+                    bail!(Error::Corrupt(format!(
+                        "freelist trunk page {} lists too many leaves",
+                        trunk
+                    )));
+                }
+                self.next_trunk = u32::from_be_bytes(data[0..4].try_into()?);
+                self.leaves = data[8..8 + count * 4]
+                    .chunks_exact(4)
+                    .map(|leaf| u32::from_be_bytes(leaf.try_into().expect("4 bytes")))
+                    .collect::<Vec<_>>()
+                    .into_iter();
+                trunk
+            }
+        };
+
+        self.check(page)?;
+        if self.remaining == 0 {
+            bail!(Error::Corrupt(
+                "freelist is longer than the header says".to_string()
+            ));
+        }
+        self.remaining -= 1;
+        Ok(Some(page))
+    }
+
+    /// Free pages have to be part of the file, and neither the first page nor the lock-byte
+    /// page is ever free.
+    fn check(&self, page: u32) -> Result<()> {
+        let header = &self.database.header;
+        if page < 2 || page > header.database_size || page == header.lock_byte_page() {
+            bail!(Error::Corrupt(format!("invalid page {} in freelist", page)));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Freelist<'_> {
+    type Item = Result<u32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.advance();
+        if next.is_err() {
+            self.next_trunk = 0;
+            self.remaining = 0;
+            self.leaves = Vec::new().into_iter();
+        }
+        next.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDatabase;
+
+    /// Copies sample.db and appends four free pages: trunk pages 5 and 6, with leaf page 8 on
+    /// the first trunk and leaf page 7 on the second. `count` is stored as the freelist count.
+    fn sample_with_freelist(name: &str, count: u32) -> TempDatabase {
+        let mut bytes = std::fs::read("sample.db").unwrap();
+        let page_size = 4096;
+        assert_eq!(bytes.len(), 4 * page_size);
+
+        let mut trunks = vec![0; 2 * page_size];
+        trunks[0..12].copy_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 0, 0, 8]);
+        trunks[page_size..page_size + 12].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 7]);
+        bytes.extend_from_slice(&trunks);
+        bytes.resize(8 * page_size, 0);
+        bytes[28..32].copy_from_slice(&8u32.to_be_bytes());
+        bytes[32..36].copy_from_slice(&5u32.to_be_bytes());
+        bytes[36..40].copy_from_slice(&count.to_be_bytes());
+        TempDatabase::with_bytes(name, &bytes)
+    }
+
+    #[test]
+    fn iterate_free_pages() {
+        let database_file = sample_with_freelist("freelist", 4);
+        let database = Database::open(database_file.path()).unwrap();
+        assert_eq!(database.header.freelist_count, 4);
+        let pages = database.free_pages().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(pages, vec![5, 8, 6, 7]);
+        assert_eq!(database.scan("apples").unwrap().count(), 4);
+    }
+
+    #[test]
+    fn freelist_count_mismatch_is_an_error() {
+        let database_file = sample_with_freelist("freelist-count", 3);
+        let database = Database::open(database_file.path()).unwrap();
+        let error = database
+            .free_pages()
+            .collect::<Result<Vec<_>>>()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "database disk image is malformed: freelist is longer than the header says"
+        );
+    }
+}
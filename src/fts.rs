@@ -0,0 +1,311 @@
+//! Full-text search of FTS5 tables without the fts5 module. An FTS5 table keeps its text in an
+//! ordinary content table, which is scanned for the rows that match a query instead of looking
+//! them up in the full-text index. That's slow for large tables, but it finds the same rows for
+//! simple queries.
+//!
+//! Queries are words that all have to be in the text, in any order. A word ending in `*`
+//! matches the words it's a prefix of, words in double quotes have to follow each other, and
+//! `OR` separates alternatives. Words are compared ignoring case.
+
+use std::borrow::Cow;
+
+use anyhow::{bail, Result};
+
+use crate::error::Error;
+use crate::output::OutputFormatter;
+use crate::record::ColumnValue;
+use crate::sql::{self, Expr, ResultColumn, SelectFields, SelectStatement, TableSource};
+use crate::sqlite_schema::VirtualTable;
+
+/// The module whose tables can be searched.
+const MODULE: &str = "fts5";
+
+/// An FTS5 table, as declared by its CREATE VIRTUAL TABLE statement.
+#[derive(Debug, Clone)]
+pub struct FtsTable {
+    pub name: String,
+    /// The indexed columns, which are all the columns of the table that `*` selects
+    pub columns: Vec<String>,
+    content: Content,
+}
+
+/// Where an FTS5 table keeps its text.
+#[derive(Debug, Clone)]
+enum Content {
+    /// The `name_content` table, whose columns are called `c0`, `c1` and so on
+    Shadow,
+    /// An ordinary table named with the `content` option, with columns of the same names
+    External(String),
+    /// Only the full-text index is kept, with `content=''`
+    None,
+}
+
+impl FtsTable {
+    /// Reads the declaration of a virtual table. Only FTS5 tables can be searched, others fail
+    /// like in SQLite when their module is missing.
+    pub fn new(table: &VirtualTable) -> Result<Self> {
+        let statement = sql::parse_virtual_table_creation(table.sql.as_bytes())
+            .map_err(|_e| anyhow::anyhow!("Failed to parse table definition"))?
+            .1;
+        if !statement.module.eq_ignore_ascii_case(MODULE) {
+            bail!(Error::NoSuchModule(statement.module));
+        }
+
+        let mut columns = vec![];
+        let mut content = Content::Shadow;
+        for argument in &statement.arguments {
+            match argument.split_once('=') {
+                Some((option, value)) if option.trim().eq_ignore_ascii_case("content") => {
+                    content = match unquote(value.trim()) {
+                        "" => Content::None,
+                        table => Content::External(table.to_string()),
+                    };
+                }
+                Some(_) => {}
+                // A column may be followed by options like UNINDEXED
+                None => {
+                    let name = argument.split_whitespace().next().unwrap_or_default();
+                    columns.push(unquote(name).to_string());
+                }
+            }
+        }
+        Ok(Self {
+            name: statement.name,
+            columns,
+            content,
+        })
+    }
+
+    /// The query that reads the text of the table from its content table in `schema`.
+    pub fn content_query(&self, schema: Option<&str>) -> Result<SelectStatement> {
+        let (table, columns) = match &self.content {
+            Content::Shadow => (
+                format!("{}_content", self.name),
+                (0..self.columns.len()).map(|i| format!("c{i}")).collect(),
+            ),
+            Content::External(table) => (table.clone(), self.columns.clone()),
+            Content::None => bail!("fts5 table {} has no content to search", self.name),
+        };
+        Ok(SelectStatement::Fields(SelectFields {
+            fields: columns
+                .into_iter()
+                .map(|column| ResultColumn::Expr {
+                    expr: Expr::Column(column.clone()),
+                    alias: None,
+                    text: column,
+                })
+                .collect(),
+            from: TableSource::Table {
+                schema: schema.map(str::to_string),
+                name: table,
+            },
+            alias: None,
+            where_clause: None,
+        }))
+    }
+
+    /// Names the rows of the content query with the columns of the table, followed by the
+    /// hidden column named after the table, which holds the text of the whole row so that
+    /// `table MATCH 'query'` searches all columns.
+    pub fn documents<'a>(&'a self, out: &'a mut dyn OutputFormatter) -> Documents<'a> {
+        Documents { table: self, out }
+    }
+
+    /// Copies a query of the table, replacing `*` with the columns of the table, as the hidden
+    /// column isn't one of them.
+    pub fn expand(&self, command: &SelectFields) -> SelectFields {
+        let fields = command
+            .fields
+            .iter()
+            .flat_map(|field| match field {
+                ResultColumn::All => self
+                    .columns
+                    .iter()
+                    .map(|column| ResultColumn::Expr {
+                        expr: Expr::Column(column.clone()),
+                        alias: None,
+                        text: column.clone(),
+                    })
+                    .collect(),
+                field => vec![field.clone()],
+            })
+            .collect();
+        SelectFields {
+            fields,
+            ..command.clone()
+        }
+    }
+}
+
+/// The rows of an FTS5 table, see [`FtsTable::documents`].
+pub struct Documents<'a> {
+    table: &'a FtsTable,
+    out: &'a mut dyn OutputFormatter,
+}
+
+impl OutputFormatter for Documents<'_> {
+    fn begin(&mut self, _: &[String]) -> Result<()> {
+        let mut columns = self.table.columns.clone();
+        columns.push(self.table.name.clone());
+        self.out.begin(&columns)
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        let text = values
+            .iter()
+            .filter_map(|value| match value {
+                ColumnValue::Null => None,
+                value => Some(value.to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut row = values.to_vec();
+        row.push(ColumnValue::Text(Cow::Owned(text.into_bytes())));
+        self.out.row(&row)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.finish()
+    }
+}
+
+/// Whether `text` matches the full-text `query`.
+pub fn matches(text: &str, query: &str) -> bool {
+    let words = tokens(text);
+    parse_query(query)
+        .iter()
+        .any(|phrases| phrases.iter().all(|phrase| contains_phrase(&words, phrase)))
+}
+
+/// A word of a query, and whether it's a prefix.
+type Term = (String, bool);
+
+/// The alternatives of a query, each a list of phrases that all have to match.
+fn parse_query(query: &str) -> Vec<Vec<Vec<Term>>> {
+    let mut alternatives = vec![vec![]];
+    for (i, part) in query.split('"').enumerate() {
+        // Every other part is inside double quotes
+        if i % 2 == 1 {
+            let phrase = terms(part);
+            if !phrase.is_empty() {
+                alternatives.last_mut().unwrap().push(phrase);
+            }
+            continue;
+        }
+        for word in part.split_whitespace() {
+            match word {
+                "OR" => alternatives.push(vec![]),
+                "AND" => {}
+                word => alternatives
+                    .last_mut()
+                    .unwrap()
+                    .extend(terms(word).into_iter().map(|term| vec![term])),
+            }
+        }
+    }
+    alternatives.retain(|phrases| !phrases.is_empty());
+    alternatives
+}
+
+/// The terms of some query text. A `*` right after a word makes it a prefix.
+fn terms(text: &str) -> Vec<Term> {
+    let mut terms: Vec<Term> = vec![];
+    let mut word = String::new();
+    for chr in text.chars().chain([' ']) {
+        if chr.is_alphanumeric() {
+            word.extend(chr.to_lowercase());
+            continue;
+        }
+        if !word.is_empty() {
+            terms.push((std::mem::take(&mut word), false));
+        }
+        if let (Some(last), '*') = (terms.last_mut(), chr) {
+            last.1 = true;
+        }
+    }
+    terms
+}
+
+/// The words of a text, in lower case.
+fn tokens(text: &str) -> Vec<String> {
+    text.split(|chr: char| !chr.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn contains_phrase(words: &[String], phrase: &[Term]) -> bool {
+    words.windows(phrase.len()).any(|window| {
+        window
+            .iter()
+            .zip(phrase)
+            .all(|(word, (term, prefix))| match prefix {
+                true => word.starts_with(term.as_str()),
+                false => word == term,
+            })
+    })
+}
+
+/// A name without the quotes it may be written in.
+fn unquote(name: &str) -> &str {
+    match name.as_bytes() {
+        [b'\'', .., b'\''] | [b'"', .., b'"'] | [b'`', .., b'`'] | [b'[', .., b']'] => {
+            &name[1..name.len() - 1]
+        }
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_queries() {
+        let text = "The quick brown fox jumps over the lazy dog";
+        assert!(matches(text, "fox"));
+        assert!(matches(text, "DOG quick"));
+        assert!(matches(text, "dog AND quick"));
+        assert!(!matches(text, "fox cat"));
+        assert!(matches(text, "cat OR fox"));
+        assert!(matches(text, "jump*"));
+        assert!(!matches(text, "jump"));
+        assert!(matches(text, "\"brown fox\""));
+        assert!(!matches(text, "\"fox brown\""));
+        assert!(matches(text, "\"lazy d*\""));
+        assert!(!matches(text, ""));
+    }
+
+    #[test]
+    fn read_declarations() {
+        let table = |sql: &str| VirtualTable {
+            name: "docs".to_string(),
+            module: "fts5".to_string(),
+            sql: sql.to_string(),
+        };
+        let fts = FtsTable::new(&table(
+            "CREATE VIRTUAL TABLE docs USING fts5(title, body UNINDEXED, prefix='2 3')",
+        ))
+        .unwrap();
+        assert_eq!(fts.columns, ["title", "body"]);
+        assert!(matches!(fts.content, Content::Shadow));
+
+        let fts = FtsTable::new(&table(
+            "CREATE VIRTUAL TABLE docs USING fts5(body, content='posts')",
+        ))
+        .unwrap();
+        assert!(matches!(fts.content, Content::External(ref table) if table == "posts"));
+        let fts = FtsTable::new(&table(
+            "CREATE VIRTUAL TABLE docs USING fts5(body, content='')",
+        ))
+        .unwrap();
+        assert!(fts.content_query(None).is_err());
+
+        assert_eq!(
+            FtsTable::new(&table("CREATE VIRTUAL TABLE docs USING rtree(id, x, y)"))
+                .unwrap_err()
+                .to_string(),
+            "no such module: rtree"
+        );
+    }
+}
@@ -0,0 +1,461 @@
+//! The scalar functions that expressions can call, like `date()`.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::affinity::Affinity;
+use crate::datetime;
+use crate::error::Error;
+use crate::record::{format_real, ColumnValue, Value};
+
+/// A function that computes a value from the values of its arguments.
+pub type ScalarFunction = for<'a> fn(Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error>;
+
+/// A function added with [`Connection::create_scalar_function`]. It's called with the values of
+/// its arguments, and an error fails the statement that calls it.
+///
+/// [`Connection::create_scalar_function`]: crate::connection::Connection::create_scalar_function
+pub type UserFunction = Arc<dyn Fn(&[Value]) -> Result<Value, Error> + Send + Sync>;
+
+/// A function that an expression calls.
+#[derive(Clone)]
+pub enum Function {
+    Builtin(ScalarFunction),
+    User(UserFunction),
+}
+
+impl Function {
+    pub fn call<'a>(&self, args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+        match self {
+            Function::Builtin(function) => function(args),
+            Function::User(function) => {
+                let args = args.iter().map(Value::from).collect::<Vec<_>>();
+                Ok(ColumnValue::from(function(&args)?))
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Function::Builtin(function) => f
+                .debug_tuple("Builtin")
+                .field(&(*function as *const ()))
+                .finish(),
+            Function::User(_) => f.write_str("User"),
+        }
+    }
+}
+
+/// The functions that expressions can call: the built-in ones and the ones added to a
+/// connection. Like in SQLite, added functions take precedence over built-in ones with the same
+/// name.
+#[derive(Debug, Clone, Default)]
+pub struct Functions {
+    /// By lower case name
+    user: HashMap<String, (RangeInclusive<usize>, Function)>,
+}
+
+impl Functions {
+    /// Adds a function that can be called with `arguments` arguments, replacing an added function
+    /// with the same name.
+    pub fn add(&mut self, name: &str, arguments: RangeInclusive<usize>, function: UserFunction) {
+        self.user.insert(
+            name.to_ascii_lowercase(),
+            (arguments, Function::User(function)),
+        );
+    }
+
+    /// The function called `name` for a call with `arguments` arguments, see [`find`].
+    pub fn find(&self, name: &str, arguments: usize) -> Result<Function, Error> {
+        match self.user.get(&name.to_ascii_lowercase()) {
+            Some((range, _)) if !range.contains(&arguments) => {
+                Err(Error::WrongNumberOfArguments(name.to_string()))
+            }
+            Some((_, function)) => Ok(function.clone()),
+            None => find(name, arguments).map(Function::Builtin),
+        }
+    }
+}
+
+struct Builtin {
+    name: &'static str,
+    /// The numbers of arguments the function can be called with
+    arguments: RangeInclusive<usize>,
+    function: ScalarFunction,
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "date",
+        arguments: 0..=usize::MAX,
+        function: datetime::date,
+    },
+    Builtin {
+        name: "time",
+        arguments: 0..=usize::MAX,
+        function: datetime::time,
+    },
+    Builtin {
+        name: "datetime",
+        arguments: 0..=usize::MAX,
+        function: datetime::datetime,
+    },
+    Builtin {
+        name: "julianday",
+        arguments: 0..=usize::MAX,
+        function: datetime::julianday,
+    },
+    Builtin {
+        name: "strftime",
+        arguments: 1..=usize::MAX,
+        function: datetime::strftime,
+    },
+    Builtin {
+        name: "abs",
+        arguments: 1..=1,
+        function: abs,
+    },
+    Builtin {
+        name: "round",
+        arguments: 1..=2,
+        function: round,
+    },
+    Builtin {
+        name: "coalesce",
+        arguments: 2..=usize::MAX,
+        function: coalesce,
+    },
+    Builtin {
+        name: "ifnull",
+        arguments: 2..=2,
+        function: coalesce,
+    },
+    Builtin {
+        name: "nullif",
+        arguments: 2..=2,
+        function: nullif,
+    },
+    Builtin {
+        name: "typeof",
+        arguments: 1..=1,
+        function: type_of,
+    },
+    Builtin {
+        name: "hex",
+        arguments: 1..=1,
+        function: hex,
+    },
+    Builtin {
+        name: "quote",
+        arguments: 1..=1,
+        function: quote,
+    },
+    Builtin {
+        name: "random",
+        arguments: 0..=0,
+        function: random,
+    },
+    Builtin {
+        name: "randomblob",
+        arguments: 1..=1,
+        function: randomblob,
+    },
+];
+
+/// The state of the generator behind `random()` and `randomblob()`. It's seeded with the random
+/// keys of the standard library's hash maps, unless [`seed_random`] sets it.
+static RANDOM: Mutex<Option<u64>> = Mutex::new(None);
+
+/// The function called `name` for a call with `arguments` arguments. Function names ignore case.
+pub fn find(name: &str, arguments: usize) -> Result<ScalarFunction, Error> {
+    let builtin = BUILTINS
+        .iter()
+        .find(|builtin| builtin.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| Error::NoSuchFunction(name.to_string()))?;
+    if !builtin.arguments.contains(&arguments) {
+        return Err(Error::WrongNumberOfArguments(name.to_string()));
+    }
+    Ok(builtin.function)
+}
+
+/// `abs(x)`. Integers stay integers, anything else is converted to a real like CAST does.
+/// The absolute value of the smallest integer doesn't fit in an integer, which is an error.
+fn abs<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    let value = &args[0];
+    if let ColumnValue::Null = value {
+        return Ok(ColumnValue::Null);
+    }
+    match value.as_i64() {
+        Some(n) => n
+            .checked_abs()
+            .map(ColumnValue::I64)
+            .ok_or(Error::IntegerOverflow),
+        None => Ok(ColumnValue::F64(real(value).abs())),
+    }
+}
+
+/// `round(x, digits)`, the real `x` rounded to `digits` decimal places, 0 to 30. Halves round
+/// away from zero.
+fn round<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    if args.iter().any(|arg| matches!(arg, ColumnValue::Null)) {
+        return Ok(ColumnValue::Null);
+    }
+    let digits = match args.get(1) {
+        Some(digits) => Affinity::Integer
+            .cast(digits.clone())
+            .as_i64()
+            .unwrap_or_default()
+            .clamp(0, 30) as usize,
+        None => 0,
+    };
+    let x = real(&args[0]);
+    let scale = 10f64.powi(digits as i32);
+    // Reals this large have no fractional part to round. Decimals like 2.675 are a little less
+    // in binary, so like SQLite the digits are rounded as written with 15 significant digits.
+    let scaled = x * scale;
+    if !scaled.is_finite() || scaled.abs() >= 4503599627370496.0 {
+        return Ok(ColumnValue::F64(x));
+    }
+    let scaled: f64 = format!("{scaled:.14e}").parse().unwrap_or(scaled);
+    Ok(ColumnValue::F64(scaled.round() / scale))
+}
+
+/// `coalesce(x, y, ...)` and `ifnull(x, y)`, the first argument that isn't NULL.
+fn coalesce<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    Ok(args
+        .into_iter()
+        .find(|arg| !matches!(arg, ColumnValue::Null))
+        .unwrap_or(ColumnValue::Null))
+}
+
+/// `nullif(x, y)`, NULL if the arguments are equal and `x` otherwise.
+fn nullif<'a>(mut args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    let y = args.pop().unwrap_or(ColumnValue::Null);
+    let x = args.pop().unwrap_or(ColumnValue::Null);
+    Ok(match x.compare(&y) {
+        Ordering::Equal => ColumnValue::Null,
+        _ => x,
+    })
+}
+
+/// `typeof(x)`, the storage class of `x`: null, integer, real, text or blob.
+fn type_of<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    Ok(ColumnValue::Text(args[0].type_name().as_bytes().into()))
+}
+
+/// `hex(x)`, the bytes of a blob, or of the text of any other value, as upper case hex digits.
+/// NULL has no bytes.
+fn hex<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    let bytes = match Affinity::Blob.cast(args[0].clone()) {
+        ColumnValue::Blob(bytes) => bytes,
+        _ => Default::default(),
+    };
+    Ok(ColumnValue::Text(upper_hex(&bytes).into_bytes().into()))
+}
+
+/// `quote(x)`, the SQL literal of `x`. Reals have as many digits as it takes to read them back
+/// as the same number.
+fn quote<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    let literal = match &args[0] {
+        ColumnValue::Null => "NULL".to_string(),
+        ColumnValue::Text(text) => {
+            format!("'{}'", String::from_utf8_lossy(text).replace('\'', "''"))
+        }
+        ColumnValue::Blob(blob) => format!("X'{}'", upper_hex(blob)),
+        ColumnValue::F64(real) => {
+            let text = format_real(*real);
+            if text.parse() == Ok(*real) {
+                text
+            } else {
+                let scientific = format!("{real:.20e}");
+                let (mantissa, exponent) = scientific.split_once('e').expect("exponent");
+                let exponent = exponent.parse::<i32>().expect("exponent");
+                let sign = if exponent < 0 { '-' } else { '+' };
+                format!("{mantissa}e{sign}{:02}", exponent.abs())
+            }
+        }
+        integer => integer.to_string(),
+    };
+    Ok(ColumnValue::Text(literal.into_bytes().into()))
+}
+
+fn upper_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// `random()`, a random integer.
+fn random<'a>(_args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    Ok(ColumnValue::I64(next_random() as i64))
+}
+
+/// `randomblob(n)`, a blob of `n` random bytes, at least 1.
+fn randomblob<'a>(args: Vec<ColumnValue<'a>>) -> Result<ColumnValue<'a>, Error> {
+    let length = Affinity::Integer
+        .cast(args[0].clone())
+        .as_i64()
+        .unwrap_or_default()
+        .max(1) as usize;
+    let mut blob = Vec::with_capacity(length + 7);
+    while blob.len() < length {
+        blob.extend(next_random().to_le_bytes());
+    }
+    blob.truncate(length);
+    Ok(ColumnValue::Blob(blob.into()))
+}
+
+/// Makes `random()` and `randomblob()` return the same values every time, so that results can
+/// be reproduced. The generator is shared by all connections, and parallel scans call it in no
+/// particular order.
+pub fn seed_random(seed: u64) {
+    *RANDOM.lock().unwrap_or_else(PoisonError::into_inner) = Some(seed);
+}
+
+/// The next 64 random bits, from a SplitMix64 generator.
+fn next_random() -> u64 {
+    let mut random = RANDOM.lock().unwrap_or_else(PoisonError::into_inner);
+    let state = random.get_or_insert_with(|| RandomState::new().build_hasher().finish());
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// A number argument as a real. Text and blobs become the number they start with, or 0.
+fn real(value: &ColumnValue) -> f64 {
+    Affinity::Real
+        .cast(value.clone())
+        .as_f64()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Value;
+
+    #[test]
+    fn find_functions() {
+        let function = find("DATE", 1).unwrap();
+        assert_eq!(
+            function(vec![ColumnValue::Text(b"2024-02-29 12:00"[..].into())])
+                .unwrap()
+                .as_str(),
+            Some("2024-02-29")
+        );
+        assert_eq!(
+            find("nope", 0).err(),
+            Some(Error::NoSuchFunction("nope".to_string()))
+        );
+        assert_eq!(
+            find("strftime", 0).err(),
+            Some(Error::WrongNumberOfArguments("strftime".to_string()))
+        );
+    }
+
+    fn call(name: &str, args: &[Value]) -> Value {
+        try_call(name, args).unwrap()
+    }
+
+    fn try_call(name: &str, args: &[Value]) -> Result<Value, Error> {
+        let function = find(name, args.len()).unwrap();
+        Ok(Value::from(&function(
+            args.iter().map(ColumnValue::from).collect(),
+        )?))
+    }
+
+    #[test]
+    fn numbers_and_nulls() {
+        use Value::{Integer, Null, Real};
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_eq!(call("abs", &[Integer(-3)]), Integer(3));
+        assert_eq!(call("abs", &[Real(-2.5)]), Real(2.5));
+        assert_eq!(call("abs", &[text("-3")]), Real(3.0));
+        assert_eq!(call("abs", &[Null]), Null);
+        assert_eq!(call("abs", &[Integer(i64::MIN + 1)]), Integer(i64::MAX));
+        assert_eq!(
+            try_call("abs", &[Integer(i64::MIN)]),
+            Err(Error::IntegerOverflow)
+        );
+
+        assert_eq!(call("round", &[Real(-2.5)]), Real(-3.0));
+        assert_eq!(call("round", &[Integer(3)]), Real(3.0));
+        for (x, digits, rounded) in [(2.675, 2, 2.68), (1.005, 2, 1.01), (0.15, 1, 0.2)] {
+            assert_eq!(call("round", &[Real(x), Integer(digits)]), Real(rounded));
+        }
+        assert_eq!(call("round", &[text("3.456x"), Integer(1)]), Real(3.5));
+        assert_eq!(call("round", &[Real(2.5), Integer(-1)]), Real(3.0));
+        assert_eq!(call("round", &[Real(2.5), Null]), Null);
+
+        assert_eq!(
+            call("coalesce", &[Null, Integer(1), Integer(2)]),
+            Integer(1)
+        );
+        assert_eq!(call("ifnull", &[Null, Null]), Null);
+        assert_eq!(
+            find("coalesce", 1).err(),
+            Some(Error::WrongNumberOfArguments("coalesce".to_string()))
+        );
+
+        assert_eq!(call("nullif", &[Integer(1), Real(1.0)]), Null);
+        assert_eq!(call("nullif", &[text("1"), Integer(1)]), text("1"));
+        assert_eq!(call("nullif", &[Integer(1), Null]), Integer(1));
+    }
+
+    #[test]
+    fn describe_values() {
+        use Value::{Blob, Integer, Null, Real};
+        let text = |text: &str| Value::Text(text.to_string());
+        for (value, name) in [
+            (Null, "null"),
+            (Integer(1), "integer"),
+            (Real(1.5), "real"),
+            (text("1"), "text"),
+            (Blob(vec![1]), "blob"),
+        ] {
+            assert_eq!(call("typeof", &[value]), text(name));
+        }
+
+        assert_eq!(call("hex", &[Null]), text(""));
+        assert_eq!(call("hex", &[Integer(12)]), text("3132"));
+        assert_eq!(call("hex", &[Real(1.5)]), text("312E35"));
+        assert_eq!(call("hex", &[text("é")]), text("C3A9"));
+        assert_eq!(call("hex", &[Blob(vec![0x0a, 0xff])]), text("0AFF"));
+
+        assert_eq!(call("quote", &[Null]), text("NULL"));
+        assert_eq!(call("quote", &[Integer(-7)]), text("-7"));
+        assert_eq!(call("quote", &[Real(0.1)]), text("0.1"));
+        assert_eq!(call("quote", &[Real(1e300)]), text("1.0e+300"));
+        assert_eq!(
+            call("quote", &[Real(0.30000000000000004)]),
+            text("3.00000000000000044409e-01")
+        );
+        assert_eq!(call("quote", &[text("it's")]), text("'it''s'"));
+        assert_eq!(call("quote", &[Blob(vec![0x0a, 0xff])]), text("X'0AFF'"));
+    }
+
+    #[test]
+    fn seeded_random_values() {
+        let values = || {
+            seed_random(42);
+            [
+                call("random", &[]),
+                call("randomblob", &[Value::Integer(10)]),
+                call("randomblob", &[Value::Null]),
+            ]
+        };
+        let first = values();
+        assert_eq!(values(), first);
+        assert_ne!(first[0], call("random", &[]));
+        match &first[1..] {
+            [Value::Blob(ten), Value::Blob(one)] => assert_eq!((ten.len(), one.len()), (10, 1)),
+            blobs => panic!("not blobs: {blobs:?}"),
+        }
+    }
+}
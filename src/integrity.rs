@@ -0,0 +1,621 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::collation::Collation;
+use crate::cursor::TableCursor;
+use crate::database::{self, Database, HEADER_SIZE, MAX_DEPTH};
+use crate::expr::Expression;
+use crate::page::{Cell, Page, PageKind};
+use crate::record::{compare_keys, ColumnValue, Record, Value};
+use crate::sql::{self, Expr};
+
+/// Checks the structure of the whole file like `PRAGMA integrity_check`: every b-tree in the
+/// schema, the overflow pages of their cells, and the freelist. Returns the problems found,
+/// which is empty for a healthy database.
+pub fn check(database: &Database) -> Result<Vec<String>> {
+    let size = database.header.database_size;
+    let mut checker = Checker {
+        database,
+        problems: vec![],
+        referenced: vec![false; size as usize + 1],
+    };
+    // The lock-byte page is unused on purpose, and no b-tree may refer to it
+    let lock_byte_page = checker.database.header.lock_byte_page();
+    if lock_byte_page <= size {
+        checker.referenced[lock_byte_page as usize] = true;
+    }
+
+    checker.walk_tree(1, false, true, Order::default())?;
+    if !checker.problems.is_empty() {
+        return Ok(checker.problems);
+    }
+
+    // Tables come first, so their rowids are known when their indexes are checked
+    let schema = TableCursor::new(checker.database, 1)?.collect::<Result<Vec<_>>>()?;
+    let text = |value: &Value| match value {
+        Value::Text(text) => text.clone(),
+        _ => String::new(),
+    };
+    // WITHOUT ROWID tables are stored in index b-trees, and their indexes refer to rows by
+    // primary key instead of rowid
+    let without_rowid = |sql: &Value| {
+        let words = text(sql).to_ascii_uppercase();
+        let words = words.split_whitespace().collect::<Vec<_>>();
+        words.windows(2).any(|pair| pair == ["WITHOUT", "ROWID"])
+    };
+    let mut trees = vec![];
+    for row in &schema {
+        let Some(&Value::Integer(rootpage @ 1..)) = row.values.get(3) else {
+            continue;
+        };
+        let rootpage = rootpage as u32;
+        match text(&row.values[0]).as_str() {
+            "table" if without_rowid(&row.values[4]) => {
+                trees.insert(0, (rootpage, true, Order::default(), None))
+            }
+            "table" => trees.insert(0, (rootpage, false, Order::default(), None)),
+            "index" => {
+                let sql = text(&row.values[4]);
+                let index = sql::parse_index_creation(sql.as_bytes())
+                    .ok()
+                    .map(|(_, index)| index);
+                // Entries of DESC columns are stored in reverse order
+                let descending = match &index {
+                    Some(index) if checker.database.header.has_descending_indexes() => {
+                        index.descending.clone()
+                    }
+                    _ => vec![],
+                };
+                let table = schema.iter().find(|table| {
+                    text(&table.values[0]) == "table" && table.values[1] == row.values[2]
+                });
+                // Text is ordered by the collations of the index, or else of the table columns
+                let names = match (
+                    &index,
+                    checker.database.schema().find_table(&text(&row.values[2])),
+                ) {
+                    (Some(index), Some(table)) => table.index_collations(index),
+                    (Some(index), None) => index.collations.clone(),
+                    _ => vec![],
+                };
+                let collations = match checker.database.index_collations(&names) {
+                    Ok(collations) => collations,
+                    Err(error) => {
+                        checker
+                            .problems
+                            .push(format!("index {}: {error}", text(&row.values[1])));
+                        vec![]
+                    }
+                };
+                let order = Order {
+                    descending,
+                    collations,
+                };
+                let condition = index.and_then(|index| index.where_clause);
+                let table_rootpage = table.and_then(|table| match table.values[3] {
+                    Value::Integer(rootpage) if !without_rowid(&table.values[4]) => {
+                        Some(rootpage as u32)
+                    }
+                    _ => None,
+                });
+                let name = text(&row.values[1]);
+                match table {
+                    Some(_) => trees.push((
+                        rootpage,
+                        true,
+                        order,
+                        table_rootpage.map(|root| (name, root, condition)),
+                    )),
+                    None => {
+                        checker
+                            .problems
+                            .push(format!("index {name} belongs to a missing table"));
+                        trees.push((rootpage, true, order, None));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut rowids = HashMap::new();
+    for (rootpage, is_index, order, table) in trees {
+        let has_rowids = !is_index || table.is_some();
+        let tree = checker.walk_tree(rootpage, is_index, has_rowids, order)?;
+        if !is_index {
+            rowids.insert(rootpage, tree.into_iter().collect::<HashSet<_>>());
+            continue;
+        }
+        let Some((name, table_rootpage, condition)) = table else {
+            continue;
+        };
+        let table_rowids = &rowids[&table_rootpage];
+        for rowid in &tree {
+            if !table_rowids.contains(rowid) {
+                checker
+                    .problems
+                    .push(format!("index {name} references missing row {rowid}"));
+            }
+        }
+        // A partial index only has the rows that satisfy its condition
+        let partial_rowids;
+        let table_rowids = match condition {
+            Some(condition) => {
+                partial_rowids = satisfying_rowids(checker.database, table_rootpage, &condition)?;
+                &partial_rowids
+            }
+            None => table_rowids,
+        };
+        let indexed = tree.iter().collect::<HashSet<_>>();
+        let mut missing = table_rowids
+            .iter()
+            .filter(|rowid| !indexed.contains(rowid))
+            .collect::<Vec<_>>();
+        missing.sort();
+        for rowid in missing {
+            checker
+                .problems
+                .push(format!("row {rowid} missing from index {name}"));
+        }
+        if tree.len() != table_rowids.len() {
+            checker.problems.push(format!(
+                "wrong # of entries in index {name}: {} instead of {}",
+                tree.len(),
+                table_rowids.len()
+            ));
+        }
+    }
+
+    checker.check_freelist()?;
+    for number in 1..=size {
+        if !checker.referenced[number as usize] {
+            checker
+                .problems
+                .push(format!("Page {number} is never used"));
+        }
+    }
+    Ok(checker.problems)
+}
+
+/// The rowids of the rows of the table rooted at `rootpage` that satisfy `condition`.
+fn satisfying_rowids(database: &Database, rootpage: u32, condition: &Expr) -> Result<HashSet<i64>> {
+    let table = database
+        .schema()
+        .tables()
+        .find(|table| table.rootpage == rootpage)
+        .ok_or_else(|| anyhow::anyhow!("no table with root page {rootpage}"))?;
+    let condition = Expression::new(condition, table)?;
+    let mut rowids = HashSet::new();
+    for row in TableCursor::new(database, rootpage)? {
+        let row = row?;
+        if database::satisfies(Some(&condition), row.rowid, &row.values)? {
+            rowids.insert(row.rowid);
+        }
+    }
+    Ok(rowids)
+}
+
+struct Checker<'db> {
+    database: &'db Database,
+    problems: Vec<String>,
+    /// Whether each page, by number counting from 1, belongs to a b-tree, an overflow chain or
+    /// the freelist
+    referenced: Vec<bool>,
+}
+
+/// The state of the walk through one b-tree, in key order.
+struct Tree {
+    rootpage: u32,
+    is_index: bool,
+    /// Whether index entries end with the rowid of a row in the table
+    has_rowids: bool,
+    /// The depth of the first leaf, all others have to be as deep
+    leaf_depth: Option<usize>,
+    /// The last rowid or interior key of a table
+    last_rowid: Option<i64>,
+    /// The previous entry of an index
+    last_entry: Option<Vec<Value>>,
+    /// How the entries of an index sort
+    order: Order,
+    /// The rowids of a table, or the rowids the entries of an index point to
+    rowids: Vec<i64>,
+}
+
+/// The order of the entries of an index b-tree.
+#[derive(Default)]
+struct Order {
+    /// Whether each column sorts in descending order
+    descending: Vec<bool>,
+    /// The collation of each column
+    collations: Vec<Collation>,
+}
+
+impl Checker<'_> {
+    /// Checks a b-tree and returns the rowids of the table, or the rowids the index entries
+    /// point to.
+    fn walk_tree(
+        &mut self,
+        rootpage: u32,
+        is_index: bool,
+        has_rowids: bool,
+        order: Order,
+    ) -> Result<Vec<i64>> {
+        let mut tree = Tree {
+            rootpage,
+            is_index,
+            has_rowids,
+            leaf_depth: None,
+            last_rowid: None,
+            last_entry: None,
+            order,
+            rowids: vec![],
+        };
+        self.walk(&mut tree, rootpage, 0, &format!("Tree {rootpage}"))?;
+        Ok(tree.rowids)
+    }
+
+    /// Marks a page as used. Returns false if it's out of range or already used elsewhere.
+    fn reference(&mut self, number: u32, context: &str) -> bool {
+        if number == 0 || number as usize >= self.referenced.len() {
+            self.problems
+                .push(format!("{context}: invalid page number {number}"));
+            return false;
+        }
+        if self.referenced[number as usize] {
+            self.problems
+                .push(format!("{context}: 2nd reference to page {number}"));
+            return false;
+        }
+        self.referenced[number as usize] = true;
+        true
+    }
+
+    fn walk(&mut self, tree: &mut Tree, number: u32, depth: usize, context: &str) -> Result<()> {
+        if depth >= MAX_DEPTH {
+            self.problems.push(format!("{context}: b-tree is too deep"));
+            return Ok(());
+        }
+        if !self.reference(number, context) {
+            return Ok(());
+        }
+        let context = format!("Tree {} page {}", tree.rootpage, number);
+        let Some(page) = self.read_page(number, &context)? else {
+            return Ok(());
+        };
+
+        let is_index = matches!(
+            page.header.kind,
+            PageKind::InteriorIndex | PageKind::LeafIndex
+        );
+        if is_index != tree.is_index {
+            let kind = if is_index { "index" } else { "table" };
+            self.problems.push(format!(
+                "{context}: {kind} page in the wrong kind of b-tree"
+            ));
+            return Ok(());
+        }
+        if page.header.kind.is_leaf() {
+            match tree.leaf_depth {
+                None => tree.leaf_depth = Some(depth),
+                Some(leaf_depth) if leaf_depth != depth => {
+                    self.problems.push(format!(
+                        "{context}: leaf at depth {depth} instead of {leaf_depth}"
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (i, cell) in page.cells().enumerate() {
+            let context = format!("{context} cell {i}");
+            match cell {
+                Cell::InteriorTable {
+                    left_child_page,
+                    key,
+                } => {
+                    self.walk(tree, left_child_page, depth + 1, &context)?;
+                    self.check_rowid(tree, key, false, &context);
+                }
+                Cell::LeafTable {
+                    size,
+                    rowid,
+                    payload,
+                    overflow_page,
+                } => {
+                    self.check_rowid(tree, rowid, true, &context);
+                    tree.rowids.push(rowid);
+                    let Some(payload) =
+                        self.payload(&page, size, payload, overflow_page, &context)?
+                    else {
+                        continue;
+                    };
+                    if let Err(error) = Record::read(rowid, &payload) {
+                        self.problems.push(format!("{context}: {error}"));
+                    }
+                }
+                Cell::InteriorIndex {
+                    left_child_page,
+                    size,
+                    payload,
+                    overflow_page,
+                } => {
+                    self.walk(tree, left_child_page, depth + 1, &context)?;
+                    self.check_entry(tree, &page, size, payload, overflow_page, &context)?;
+                }
+                Cell::LeafIndex {
+                    size,
+                    payload,
+                    overflow_page,
+                } => {
+                    self.check_entry(tree, &page, size, payload, overflow_page, &context)?;
+                }
+            }
+        }
+        if let Some(right_child) = page.header.right_child_page_number {
+            self.walk(
+                tree,
+                right_child,
+                depth + 1,
+                &format!("{context} right child"),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads a b-tree page, after checking that its header, cell pointers and cells are inside
+    /// the page.
+    fn read_page(&mut self, number: u32, context: &str) -> Result<Option<Page>> {
+        let offset = if number == 1 { HEADER_SIZE } else { 0 };
+        let data = self.database.pager.read_page(number - 1, offset as u16)?;
+        let usable_size = self.database.header.usable_size();
+
+        if PageKind::try_from(data[0]).is_err() {
+            self.problems
+                .push(format!("{context}: invalid page type {}", data[0]));
+            return Ok(None);
+        }
+        let header_size = if matches!(data[0], 2 | 5) { 12 } else { 8 };
+        let cell_count = u16::from_be_bytes([data[3], data[4]]) as usize;
+        let content_start = offset + header_size + cell_count * 2;
+        if content_start > usable_size {
+            self.problems
+                .push(format!("{context}: too many cells ({cell_count})"));
+            return Ok(None);
+        }
+        for i in 0..cell_count {
+            let pointer =
+                u16::from_be_bytes([data[header_size + 2 * i], data[header_size + 2 * i + 1]])
+                    as usize;
+            if pointer < content_start || pointer > usable_size - 4 {
+                self.problems.push(format!(
+                    "{context} cell {i}: offset {pointer} out of range {content_start}..{}",
+                    usable_size - 4
+                ));
+                return Ok(None);
+            }
+        }
+
+        let page = Page::parse(data, usable_size, offset as u16)?;
+        for (i, &pointer) in page.cell_pointers.iter().enumerate() {
+            let end = pointer as usize + page.cell_len(pointer as usize) + offset;
+            if end > usable_size {
+                self.problems
+                    .push(format!("{context} cell {i}: extends off end of page"));
+                return Ok(None);
+            }
+        }
+        Ok(Some(page))
+    }
+
+    /// Rowids have to increase across the whole table. Interior keys have to be at least the
+    /// rowids to their left, and the rowids to their right have to be larger.
+    fn check_rowid(&mut self, tree: &mut Tree, rowid: i64, is_row: bool, context: &str) {
+        if let Some(last) = tree.last_rowid {
+            if rowid < last || is_row && rowid == last {
+                self.problems
+                    .push(format!("{context}: rowid {rowid} out of order"));
+            }
+        }
+        tree.last_rowid = Some(rowid);
+    }
+
+    fn check_entry(
+        &mut self,
+        tree: &mut Tree,
+        page: &Page,
+        size: u64,
+        payload: &[u8],
+        overflow_page: u32,
+        context: &str,
+    ) -> Result<()> {
+        let Some(payload) = self.payload(page, size, payload, overflow_page, context)? else {
+            // Without the whole entry, it can't be compared with the next one
+            tree.last_entry = None;
+            return Ok(());
+        };
+
+        let entry = match Record::read(0, &payload) {
+            Ok(record) => record.values(),
+            Err(error) => {
+                self.problems.push(format!("{context}: {error}"));
+                return Ok(());
+            }
+        };
+        if tree.has_rowids {
+            match entry.last().and_then(ColumnValue::as_i64) {
+                Some(rowid) => tree.rowids.push(rowid),
+                None => self
+                    .problems
+                    .push(format!("{context}: index entry without rowid")),
+            }
+        }
+        if let Some(last) = &tree.last_entry {
+            let last = last.iter().map(ColumnValue::from).collect::<Vec<_>>();
+            if compare_keys(
+                &last,
+                &entry,
+                &tree.order.descending,
+                &tree.order.collations,
+            ) != Ordering::Less
+            {
+                self.problems
+                    .push(format!("{context}: index entry out of order"));
+            }
+        }
+        tree.last_entry = Some(entry.iter().map(Value::from).collect());
+        Ok(())
+    }
+
+    /// The whole payload of a cell, after checking its overflow pages. `None` if they have a
+    /// problem, which has been reported.
+    fn payload<'page>(
+        &mut self,
+        page: &Page,
+        size: u64,
+        local: &'page [u8],
+        overflow_page: u32,
+        context: &str,
+    ) -> Result<Option<Cow<'page, [u8]>>> {
+        if overflow_page != 0
+            && !self.check_overflow(page, size, local.len(), overflow_page, context)?
+        {
+            return Ok(None);
+        }
+        Ok(Some(self.database.payload(local, size, overflow_page)?))
+    }
+
+    /// Follows the chain of overflow pages of a cell and checks that it has the length the
+    /// payload needs. Returns whether it does.
+    fn check_overflow(
+        &mut self,
+        page: &Page,
+        size: u64,
+        local: usize,
+        first: u32,
+        context: &str,
+    ) -> Result<bool> {
+        let capacity = page.usable_size - 4;
+        let expected = (size as usize - local + capacity - 1) / capacity;
+        let mut next = first;
+        let mut count = 0;
+        while next != 0 && count < expected {
+            if !self.reference(next, context) {
+                return Ok(false);
+            }
+            let data = self.database.pager.read_page(next - 1, 0)?;
+            next = u32::from_be_bytes(data[0..4].try_into()?);
+            count += 1;
+        }
+        if count != expected || next != 0 {
+            self.problems.push(format!(
+                "{context}: overflow list length is {} but should be {expected}",
+                if next != 0 { count + 1 } else { count }
+            ));
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn check_freelist(&mut self) -> Result<()> {
+        let mut pages = vec![];
+        let mut problem = None;
+        for page in self.database.free_pages() {
+            match page {
+                Ok(page) => pages.push(page),
+                Err(error) => problem = Some(error.to_string()),
+            }
+        }
+        for page in pages {
+            self.reference(page, "Freelist");
+        }
+        if let Some(problem) = problem {
+            self.problems.push(format!("Freelist: {problem}"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{run_sqlite3, TempDatabase};
+
+    /// Copies sample.db, changes its bytes with `corrupt` and checks it.
+    fn check_corrupted(name: &str, corrupt: impl FnOnce(&mut Vec<u8>)) -> Vec<String> {
+        let mut bytes = std::fs::read("sample.db").unwrap();
+        corrupt(&mut bytes);
+        let database_file = TempDatabase::with_bytes(name, &bytes);
+
+        let database = Database::open(database_file.path()).unwrap();
+        check(&database).unwrap()
+    }
+
+    #[test]
+    fn sample_database_is_ok() {
+        let database = Database::open("sample.db").unwrap();
+        assert_eq!(check(&database).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn database_with_overflow_pages_is_ok() {
+        let database_file = TempDatabase::new("integrity-overflow");
+        // The rows and the entries of both indexes need overflow pages
+        let script = "CREATE TABLE notes (id INTEGER PRIMARY KEY, title TEXT, body TEXT);
+            CREATE INDEX by_body ON notes (body);
+            CREATE INDEX by_title_body ON notes (title, body);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 40)
+            INSERT INTO notes (title, body)
+                SELECT 'note ' || (i % 3), printf('%.*c', 1000 + i * 150, char(96 + i % 26))
+                FROM n;";
+        run_sqlite3(database_file.path(), script);
+
+        let database = Database::open(database_file.path()).unwrap();
+        assert_eq!(check(&database).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rowids_out_of_order() {
+        // Swap the first two cell pointers of the apples leaf on page 2
+        let problems = check_corrupted("integrity-order", |bytes| {
+            bytes.swap(4096 + 8, 4096 + 10);
+            bytes.swap(4096 + 9, 4096 + 11);
+        });
+        assert_eq!(problems, ["Tree 2 page 2 cell 1: rowid 1 out of order"]);
+    }
+
+    #[test]
+    fn cell_pointer_out_of_range() {
+        let problems = check_corrupted("integrity-pointer", |bytes| {
+            bytes[4096 + 8..4096 + 10].copy_from_slice(&4094u16.to_be_bytes());
+        });
+        assert_eq!(
+            problems,
+            ["Tree 2 page 2 cell 0: offset 4094 out of range 16..4092"]
+        );
+    }
+
+    #[test]
+    fn unused_page() {
+        let problems = check_corrupted("integrity-unused", |bytes| {
+            bytes.resize(5 * 4096, 0);
+            bytes[28..32].copy_from_slice(&5u32.to_be_bytes());
+        });
+        assert_eq!(problems, ["Page 5 is never used"]);
+    }
+
+    #[test]
+    fn page_in_table_and_freelist() {
+        // Page 5 is a freelist trunk that lists page 2, the root of apples
+        let problems = check_corrupted("integrity-shared", |bytes| {
+            bytes.resize(5 * 4096, 0);
+            bytes[4 * 4096..4 * 4096 + 12].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2]);
+            bytes[28..32].copy_from_slice(&5u32.to_be_bytes());
+            bytes[32..36].copy_from_slice(&5u32.to_be_bytes());
+            bytes[36..40].copy_from_slice(&2u32.to_be_bytes());
+        });
+        assert_eq!(problems, ["Freelist: 2nd reference to page 2"]);
+    }
+}
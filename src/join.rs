@@ -0,0 +1,569 @@
+//! Joins of table sources, `left [INNER | CROSS] JOIN right [ON condition]`. The rows of both
+//! sides are collected first. The equalities of the ON condition between an expression of each
+//! side are the keys of a hash join: the rows of the right side are put in a hash table by
+//! their key values, and each row of the left side looks up the right rows with the same
+//! values. When the right side has more bytes of rows than the memory limit, the rows of both
+//! sides are first partitioned by the hash of their key values into temporary files, and each
+//! pair of partitions is joined on its own. Without keys, every pair of rows is joined.
+//!
+//! The rest of the ON condition is checked by the query that reads the rows of the join, like
+//! its WHERE clause.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicUsize};
+
+use anyhow::Result;
+
+use crate::collation::Collations;
+use crate::error::Error;
+use crate::expr::{Expression, Key};
+use crate::functions::Functions;
+use crate::output::OutputFormatter;
+use crate::record::{encode_record, Record, Value};
+use crate::rowset::Rowset;
+use crate::sql::{BinaryOperator, Expr, ResultColumn};
+
+/// How many bytes of rows the right side of a hash join can have before the join partitions
+/// its rows into temporary files, by default.
+pub const DEFAULT_JOIN_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// The columns of the rows of a join, with the name or alias of the table each comes from.
+#[derive(Debug, Clone, Default)]
+pub struct JoinColumns(Vec<(Option<String>, String)>);
+
+impl JoinColumns {
+    /// The columns called `names` of a table or subquery called `qualifier`.
+    pub fn new(qualifier: Option<&str>, names: &[String]) -> Self {
+        Self(
+            names
+                .iter()
+                .map(|name| (qualifier.map(str::to_string), name.clone()))
+                .collect(),
+        )
+    }
+
+    /// The columns of the rows of the join of rows with these columns and rows with `right`.
+    pub fn join(&self, right: &JoinColumns) -> Self {
+        Self(self.0.iter().chain(&right.0).cloned().collect())
+    }
+
+    /// The names of the columns in the rows of the join: `table.column`, or `column` for
+    /// subqueries without an alias.
+    pub fn names(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|(table, column)| qualified_name(table.as_deref(), column))
+            .collect()
+    }
+
+    /// The name in the rows of the join of the column `table.column`, or of `column` in any
+    /// table, which is an error if more than one table has it.
+    fn find(&self, table: Option<&str>, column: &str) -> Result<String, Error> {
+        let mut found = self.0.iter().filter(|(qualifier, name)| {
+            name.eq_ignore_ascii_case(column)
+                && table.map_or(true, |table| {
+                    qualifier
+                        .as_deref()
+                        .map_or(false, |qualifier| qualifier.eq_ignore_ascii_case(table))
+                })
+        });
+        match (found.next(), found.next()) {
+            (Some((qualifier, name)), None) => Ok(qualified_name(qualifier.as_deref(), name)),
+            (None, _) => Err(Error::NoSuchColumn(qualified_name(table, column))),
+            (Some(_), Some(_)) => Err(Error::AmbiguousColumn(qualified_name(table, column))),
+        }
+    }
+
+    /// Copies an expression with the columns it reads renamed to their names in the rows of
+    /// the join. Subqueries are copied as they are.
+    pub fn resolve(&self, expr: &Expr) -> Result<Expr, Error> {
+        match expr {
+            Expr::Column(column) => Ok(Expr::Column(self.find(None, column)?)),
+            Expr::QualifiedColumn { table, column } => {
+                Ok(Expr::Column(self.find(Some(table), column)?))
+            }
+            expr => expr.map_children(&mut |child| self.resolve(child)),
+        }
+    }
+
+    /// Copies the results of a query of the join like [`JoinColumns::resolve`]. `*` is every
+    /// column, and columns are named like in their tables.
+    pub fn resolve_results(&self, fields: &[ResultColumn]) -> Result<Vec<ResultColumn>, Error> {
+        let mut results = vec![];
+        for field in fields {
+            match field {
+                ResultColumn::All => {
+                    for (table, column) in &self.0 {
+                        results.push(ResultColumn::Expr {
+                            expr: Expr::Column(qualified_name(table.as_deref(), column)),
+                            alias: Some(column.clone()),
+                            text: column.clone(),
+                        });
+                    }
+                }
+                ResultColumn::Expr { expr, alias, text } => {
+                    let alias = match (alias, expr) {
+                        (Some(alias), _) => Some(alias.clone()),
+                        (None, Expr::Column(column) | Expr::QualifiedColumn { column, .. }) => {
+                            Some(column.clone())
+                        }
+                        (None, _) => None,
+                    };
+                    results.push(ResultColumn::Expr {
+                        expr: self.resolve(expr)?,
+                        alias,
+                        text: text.clone(),
+                    });
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+/// Copies an expression with the columns `table.column` read as the columns of that name in
+/// the rows of a join, without checking that the tables have them. Subqueries are copied as
+/// they are.
+pub fn qualified_names(expr: &Expr) -> Expr {
+    match expr {
+        Expr::QualifiedColumn { table, column } => {
+            Expr::Column(qualified_name(Some(table), column))
+        }
+        expr => expr
+            .map_children(&mut |child| Ok::<_, Infallible>(qualified_names(child)))
+            .unwrap_or_else(|never| match never {}),
+    }
+}
+
+fn qualified_name(table: Option<&str>, column: &str) -> String {
+    match table {
+        Some(table) => format!("{table}.{column}"),
+        None => column.to_string(),
+    }
+}
+
+/// Splits a resolved ON condition into the equalities between an expression of the rows with
+/// the columns `left` and one of the rows with the columns `right`, and the rest.
+pub fn split_condition(
+    condition: &Expr,
+    left: &[String],
+    right: &[String],
+) -> (Vec<(Expr, Expr)>, Option<Expr>) {
+    let mut terms = vec![];
+    conjuncts(condition, &mut terms);
+    let reads_only = |expr: &Expr, columns: &[String]| {
+        read_columns(expr).map_or(false, |read| {
+            !read.is_empty()
+                && read
+                    .iter()
+                    .all(|name| columns.iter().any(|column| column == name))
+        })
+    };
+    let mut keys = vec![];
+    let mut rest = vec![];
+    for term in terms {
+        match term {
+            Expr::Binary {
+                op: BinaryOperator::Eq,
+                left: a,
+                right: b,
+            } if reads_only(a, left) && reads_only(b, right) => {
+                keys.push((a.as_ref().clone(), b.as_ref().clone()))
+            }
+            Expr::Binary {
+                op: BinaryOperator::Eq,
+                left: a,
+                right: b,
+            } if reads_only(a, right) && reads_only(b, left) => {
+                keys.push((b.as_ref().clone(), a.as_ref().clone()))
+            }
+            term => rest.push(term.clone()),
+        }
+    }
+    let rest = rest.into_iter().reduce(|a, b| Expr::Binary {
+        op: BinaryOperator::And,
+        left: Box::new(a),
+        right: Box::new(b),
+    });
+    (keys, rest)
+}
+
+/// Whether an ON condition has equalities that a hash join could use as keys, as far as it's
+/// known without the columns of the tables.
+pub fn has_equality(condition: &Expr) -> bool {
+    let mut terms = vec![];
+    conjuncts(condition, &mut terms);
+    terms.iter().any(|term| {
+        matches!(
+            term,
+            Expr::Binary {
+                op: BinaryOperator::Eq,
+                ..
+            }
+        )
+    })
+}
+
+/// The terms of a chain of ANDs.
+fn conjuncts<'a>(expr: &'a Expr, terms: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary {
+            op: BinaryOperator::And,
+            left,
+            right,
+        } => {
+            conjuncts(left, terms);
+            conjuncts(right, terms);
+        }
+        expr => terms.push(expr),
+    }
+}
+
+/// The columns an expression reads, or `None` if it has subqueries, whose value can't be
+/// computed by the join.
+fn read_columns(expr: &Expr) -> Option<Vec<String>> {
+    fn visit(expr: &Expr, columns: &mut Vec<String>) -> Result<Expr, ()> {
+        match expr {
+            Expr::Column(name) => columns.push(name.clone()),
+            Expr::Subquery(_) | Expr::Exists(_) | Expr::InSubquery { .. } => return Err(()),
+            _ => {}
+        }
+        expr.map_children(&mut |child| visit(child, columns))
+    }
+    let mut columns = vec![];
+    visit(expr, &mut columns).ok()?;
+    Some(columns)
+}
+
+/// Joins the rows of two rowsets into `out`: each row of `left` followed by each row of
+/// `right` whose values of the right expressions of `keys` equal the values of the left
+/// expressions for the left row. Like with `=`, NULL doesn't equal anything. Without keys,
+/// each row of `left` is joined with every row of `right`.
+pub fn hash_join(
+    left: &Rowset,
+    right: &Rowset,
+    keys: &[(Expr, Expr)],
+    functions: &Functions,
+    collations: &Collations,
+    memory_limit: usize,
+    out: &mut dyn OutputFormatter,
+) -> Result<()> {
+    let mut columns = left.column_names();
+    columns.extend(right.column_names());
+    out.begin(&columns)?;
+    let resolve =
+        |rows: &Rowset, expr: &Expr| Expression::resolve(expr, rows.table(), functions, collations);
+    let left_keys = keys
+        .iter()
+        .map(|(expr, _)| resolve(left, expr))
+        .collect::<Result<Vec<_>, _>>()?;
+    let right_keys = keys
+        .iter()
+        .map(|(_, expr)| resolve(right, expr))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut join = Join {
+        left_keys: &left_keys,
+        right_keys: &right_keys,
+        out,
+    };
+    if right.size() <= memory_limit {
+        let left_rows = left.rows().collect::<Result<Vec<_>>>()?;
+        let right_rows = right.rows().collect::<Result<Vec<_>>>()?;
+        join.join(&left_rows, &right_rows)?;
+    } else {
+        // Each partition of the right side should fit in memory
+        let memory_limit = memory_limit.max(1);
+        let partitions = (right.size() + memory_limit - 1) / memory_limit + 1;
+        let left_files = join.partition(left, &left_keys, partitions)?;
+        let right_files = join.partition(right, &right_keys, partitions)?;
+        for (left_file, right_file) in left_files.paths.iter().zip(&right_files.paths) {
+            let left_payloads = read_partition(left_file)?;
+            let right_payloads = read_partition(right_file)?;
+            join.join(&records(&left_payloads)?, &records(&right_payloads)?)?;
+        }
+    }
+    join.out.finish()
+}
+
+/// The state of a running join.
+struct Join<'a> {
+    left_keys: &'a [Expression],
+    right_keys: &'a [Expression],
+    out: &'a mut dyn OutputFormatter,
+}
+
+impl Join<'_> {
+    /// Joins rows that all fit in memory.
+    fn join(&mut self, left: &[Record], right: &[Record]) -> Result<()> {
+        if self.left_keys.is_empty() {
+            for left_row in left {
+                for right_row in right {
+                    self.write(left_row, right_row)?;
+                }
+            }
+            return Ok(());
+        }
+        let mut table = HashMap::<_, Vec<&Record>>::new();
+        for right_row in right {
+            if let Some(key) = key(self.right_keys, right_row)? {
+                table.entry(key).or_default().push(right_row);
+            }
+        }
+        for left_row in left {
+            let Some(key) = key(self.left_keys, left_row)? else {
+                continue;
+            };
+            for right_row in table.get(&key).into_iter().flatten() {
+                self.write(left_row, right_row)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, left: &Record, right: &Record) -> Result<()> {
+        let mut row = left.values();
+        row.extend(right.values());
+        self.out.row(&row)
+    }
+
+    /// Writes the rows of a side of the join to `partitions` temporary files by the hash of
+    /// their key values. Rows with a NULL key value can't be joined and are left out.
+    fn partition(
+        &self,
+        rows: &Rowset,
+        keys: &[Expression],
+        partitions: usize,
+    ) -> Result<SpillFiles> {
+        let files = SpillFiles::new(partitions);
+        let mut writers = files
+            .paths
+            .iter()
+            .map(|path| Ok(BufWriter::new(File::create(path)?)))
+            .collect::<Result<Vec<_>>>()?;
+        for row in rows.rows() {
+            let row = row?;
+            let Some(key) = key(keys, &row)? else {
+                continue;
+            };
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            let writer = &mut writers[hasher.finish() as usize % partitions];
+            let values = row.values().iter().map(Value::from).collect::<Vec<_>>();
+            let payload = encode_record(&values);
+            writer.write_all(&row.rowid.to_le_bytes())?;
+            writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+            writer.write_all(&payload)?;
+        }
+        for mut writer in writers {
+            writer.flush()?;
+        }
+        Ok(files)
+    }
+}
+
+/// The values of the key expressions for a row, or `None` if one of them is NULL.
+fn key(keys: &[Expression], row: &Record) -> Result<Option<Vec<Key>>> {
+    let mut values = Vec::with_capacity(keys.len());
+    for key in keys {
+        match Key::new(&key.evaluate(row)?) {
+            Some(value) => values.push(value),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(values))
+}
+
+/// Reads the rows [`Join::partition`] wrote to a file.
+fn read_partition(path: &PathBuf) -> Result<Vec<(i64, Vec<u8>)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut rows = vec![];
+    let mut rowid = [0; 8];
+    loop {
+        match reader.read_exact(&mut rowid) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(rows),
+            Err(error) => return Err(error.into()),
+        }
+        let mut length = [0; 8];
+        reader.read_exact(&mut length)?;
+        let mut payload = vec![0; u64::from_le_bytes(length) as usize];
+        reader.read_exact(&mut payload)?;
+        rows.push((i64::from_le_bytes(rowid), payload));
+    }
+}
+
+fn records(payloads: &[(i64, Vec<u8>)]) -> Result<Vec<Record<'_>>, Error> {
+    payloads
+        .iter()
+        .map(|(rowid, payload)| Record::read(*rowid, payload))
+        .collect()
+}
+
+/// Temporary files that are deleted when they're dropped.
+struct SpillFiles {
+    paths: Vec<PathBuf>,
+}
+
+/// Makes the names of the temporary files of a process unique.
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+impl SpillFiles {
+    fn new(count: usize) -> Self {
+        let spill = SPILLS.fetch_add(1, atomic::Ordering::Relaxed);
+        let paths = (0..count)
+            .map(|partition| {
+                std::env::temp_dir()
+                    .join(format!("join-{}-{spill}-{partition}", std::process::id()))
+            })
+            .collect();
+        Self { paths }
+    }
+}
+
+impl Drop for SpillFiles {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::ColumnValue;
+
+    /// A rowset with the rows of integers, with columns called `names`.
+    fn rowset(names: &[&str], rows: &[&[Option<i64>]]) -> Rowset {
+        let mut rowset = Rowset::default();
+        let names = names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        rowset.begin(&names).unwrap();
+        for row in rows {
+            let values = row
+                .iter()
+                .map(|value| value.map_or(ColumnValue::Null, ColumnValue::I64))
+                .collect::<Vec<_>>();
+            rowset.row(&values).unwrap();
+        }
+        rowset
+    }
+
+    #[test]
+    fn hash_join_in_memory_and_spilled() {
+        let left = rowset(
+            &["a.id", "a.x"],
+            &[&[Some(1), Some(10)], &[Some(2), None], &[Some(3), Some(30)]],
+        );
+        let right = rowset(
+            &["b.x", "b.y"],
+            &[
+                &[Some(30), Some(1)],
+                &[Some(10), Some(2)],
+                &[None, Some(3)],
+                &[Some(30), Some(4)],
+            ],
+        );
+        let columns = JoinColumns::new(Some("a"), &["id".to_string(), "x".to_string()]).join(
+            &JoinColumns::new(Some("b"), &["x".to_string(), "y".to_string()]),
+        );
+        let condition = columns
+            .resolve(&Expr::Binary {
+                op: BinaryOperator::And,
+                left: Box::new(Expr::Binary {
+                    op: BinaryOperator::Eq,
+                    left: Box::new(Expr::QualifiedColumn {
+                        table: "b".to_string(),
+                        column: "x".to_string(),
+                    }),
+                    right: Box::new(Expr::QualifiedColumn {
+                        table: "a".to_string(),
+                        column: "x".to_string(),
+                    }),
+                }),
+                right: Box::new(Expr::Binary {
+                    op: BinaryOperator::Gt,
+                    left: Box::new(Expr::Column("y".to_string())),
+                    right: Box::new(Expr::Column("id".to_string())),
+                }),
+            })
+            .unwrap();
+        let (keys, rest) = split_condition(&condition, &left.column_names(), &right.column_names());
+        assert_eq!(
+            keys,
+            [(
+                Expr::Column("a.x".to_string()),
+                Expr::Column("b.x".to_string())
+            )]
+        );
+        assert_eq!(rest.unwrap().to_string(), "b.y > a.id");
+
+        let join = |memory_limit| {
+            let mut rows = Rowset::default();
+            hash_join(
+                &left,
+                &right,
+                &keys,
+                &Functions::default(),
+                &Collations::default(),
+                memory_limit,
+                &mut rows,
+            )
+            .unwrap();
+            let mut rows = rows
+                .rows()
+                .map(|row| {
+                    let values = row.unwrap().values();
+                    values
+                        .iter()
+                        .map(|value| value.as_i64())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            rows.sort();
+            rows
+        };
+        let joined = vec![
+            vec![Some(1), Some(10), Some(10), Some(2)],
+            vec![Some(3), Some(30), Some(30), Some(1)],
+            vec![Some(3), Some(30), Some(30), Some(4)],
+        ];
+        assert_eq!(join(DEFAULT_JOIN_MEMORY_LIMIT), joined);
+        // Partitioned into files, the join has the same rows
+        assert_eq!(join(0), joined);
+        // Without keys, every pair of rows
+        let mut rows = Rowset::default();
+        hash_join(
+            &left,
+            &right,
+            &[],
+            &Functions::default(),
+            &Collations::default(),
+            0,
+            &mut rows,
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 12);
+
+        assert_eq!(
+            columns.resolve(&Expr::Column("x".to_string())),
+            Err(Error::AmbiguousColumn("x".to_string()))
+        );
+        assert_eq!(
+            columns.resolve(&Expr::QualifiedColumn {
+                table: "c".to_string(),
+                column: "x".to_string()
+            }),
+            Err(Error::NoSuchColumn("c.x".to_string()))
+        );
+    }
+}
@@ -1,6 +1,32 @@
+pub mod affinity;
+pub mod catalog;
+pub mod clock;
+pub mod collation;
+pub mod connection;
+pub mod csv;
+pub mod cursor;
 pub mod database;
+pub mod datetime;
+pub mod dbstat;
+pub mod error;
+pub mod explain;
+pub mod expr;
+pub mod freelist;
+pub mod fts;
+pub mod functions;
+pub mod integrity;
+pub mod join;
+pub mod log;
+pub mod output;
 pub mod page;
+pub mod pager;
 pub mod record;
+pub mod rowset;
 pub mod sql;
 pub mod sqlite_schema;
+pub mod stats;
+#[cfg(test)]
+mod test_support;
 pub mod varint;
+pub mod wal;
+pub mod window;
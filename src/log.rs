@@ -0,0 +1,58 @@
+//! Diagnostics on stderr for following what the engine does, like the `log` crate's macros.
+//! Nothing is printed, or even formatted, unless a level is enabled with `--verbose`.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Off,
+    /// How queries are planned, such as the index a lookup uses
+    Debug,
+    /// Every page that is read
+    Trace,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Off as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level != Level::Off && level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            eprintln!("debug: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Trace) {
+            eprintln!("trace: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use {debug, trace};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_include_the_ones_before() {
+        assert!(!enabled(Level::Debug));
+        set_level(Level::Trace);
+        assert!(enabled(Level::Debug) && enabled(Level::Trace));
+        set_level(Level::Debug);
+        assert!(enabled(Level::Debug) && !enabled(Level::Trace));
+        assert!(!enabled(Level::Off));
+        set_level(Level::Off);
+    }
+}
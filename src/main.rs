@@ -1,58 +1,371 @@
-use std::io::stdout;
+use std::fs::File;
+use std::io::{stdin, stdout, BufWriter, Read, Write};
 
 use anyhow::{bail, Result};
-use sqlite_starter_rust::{database::Database, sql};
+use sqlite_starter_rust::catalog::MAIN_SCHEMA;
+use sqlite_starter_rust::connection::{Connection, DEFAULT_RECURSION_LIMIT};
+use sqlite_starter_rust::functions;
+use sqlite_starter_rust::log::{self, Level};
+use sqlite_starter_rust::output::{self, OutputMode};
+use sqlite_starter_rust::record::ColumnValue;
+use sqlite_starter_rust::sql::{self, SQLCommand};
+use sqlite_starter_rust::sqlite_schema::{SQLiteSchemaRow, SCHEMA_TABLE_NAME};
+
+struct Settings {
+    mode: OutputMode,
+    headers: bool,
+    output: Box<dyn Write>,
+    threads: usize,
+    recursion_limit: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mode: OutputMode::default(),
+            headers: false,
+            output: Box::new(BufWriter::new(stdout())),
+            threads: 1,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+        }
+    }
+}
+
+impl Settings {
+    fn set_mode(&mut self, mode: &str) -> Result<()> {
+        self.mode = mode.parse()?;
+        // Like sqlite3, column mode is hard to read without headers
+        if self.mode == OutputMode::Column {
+            self.headers = true;
+        }
+        Ok(())
+    }
+
+    /// Redirects query results to the given file, or back to stdout if there is none. Results
+    /// are buffered, and the formatters flush them at the end of each statement.
+    fn set_output(&mut self, path: Option<&str>) -> Result<()> {
+        self.output = match path {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(BufWriter::new(stdout())),
+        };
+        Ok(())
+    }
+}
 
 fn main() -> Result<()> {
     // Parse arguments
-    let args = std::env::args().collect::<Vec<_>>();
-    match args.len() {
-        0 | 1 => bail!("Missing <database path> and <command>"),
-        2 => bail!("Missing <command>"),
-        _ => {}
+    let mut settings = Settings::default();
+    let mut positional = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                let mode = args
+                    .next()
+                    .ok_or(anyhow::anyhow!("Missing value for --mode"))?;
+                settings.set_mode(&mode)?;
+            }
+            "--output" => {
+                let path = args
+                    .next()
+                    .ok_or(anyhow::anyhow!("Missing value for --output"))?;
+                settings.set_output(Some(&path))?;
+            }
+            "--threads" => {
+                settings.threads = args
+                    .next()
+                    .ok_or(anyhow::anyhow!("Missing value for --threads"))?
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("--threads needs a positive number"))?;
+            }
+            "--recursion-limit" => {
+                settings.recursion_limit = args
+                    .next()
+                    .ok_or(anyhow::anyhow!("Missing value for --recursion-limit"))?
+                    .parse()
+                    .map_err(|_| {
+                        anyhow::anyhow!("--recursion-limit needs a non-negative number")
+                    })?;
+            }
+            "--seed" => functions::seed_random(
+                args.next()
+                    .ok_or(anyhow::anyhow!("Missing value for --seed"))?
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("--seed needs a non-negative number"))?,
+            ),
+            // Once shows how queries are planned, twice also every page that is read
+            "--verbose" => log::set_level(if log::enabled(Level::Debug) {
+                Level::Trace
+            } else {
+                Level::Debug
+            }),
+            "--json" => settings.set_mode("json")?,
+            "--header" | "--headers" => settings.headers = true,
+            "--noheader" | "--noheaders" => settings.headers = false,
+            _ => positional.push(arg),
+        }
     }
 
-    {}
+    if positional.is_empty() {
+        bail!("Missing <database path> and <command>");
+    }
+
+    // Without a command, the commands are read from stdin
+    let script = if positional.len() == 1 {
+        let mut script = String::new();
+        stdin().read_to_string(&mut script)?;
+        Some(script)
+    } else {
+        None
+    };
+
+    // Like sqlite3, a database that doesn't exist yet is created, but only by commands that
+    // write to it, so that a mistyped path doesn't leave an empty database behind
+    let path = &positional[0];
+    let mut connection = if std::path::Path::new(path).exists() {
+        Connection::open(path)?
+    } else if match &script {
+        Some(script) => script_writes(script),
+        None => positional[1..].iter().any(|command| writes(command)),
+    } {
+        Connection::create(path)?
+    } else {
+        bail!("Unable to open database \"{path}\": the file doesn't exist");
+    };
+    connection.set_threads(settings.threads);
+    connection.set_recursion_limit(settings.recursion_limit);
+
+    if let Some(script) = script {
+        return run_script(&mut connection, &mut settings, &script);
+    }
+
+    for command in &positional[1..] {
+        run_command(&mut connection, &mut settings, command)?;
+    }
+
+    Ok(())
+}
+
+/// Whether a command given on the command line writes to the database: `.import`, or a
+/// statement that creates something or inserts rows.
+fn writes(command: &str) -> bool {
+    if command.trim_start().starts_with('.') {
+        return command.split_whitespace().next() == Some(".import");
+    }
+    sql::split_statements(command).into_iter().any(|statement| {
+        matches!(
+            sql::parse_statement(statement),
+            Ok(SQLCommand::CreateTable(_)
+                | SQLCommand::CreateIndex(_)
+                | SQLCommand::CreateView(_)
+                | SQLCommand::CreateVirtualTable(_)
+                | SQLCommand::Insert(_))
+        )
+    })
+}
+
+/// Like [`writes`], for a script whose dot-commands are on lines of their own.
+fn script_writes(script: &str) -> bool {
+    let (commands, statements): (Vec<_>, Vec<_>) = script
+        .lines()
+        .partition(|line| line.trim_start().starts_with('.'));
+    commands.into_iter().any(writes) || writes(&statements.join("\n"))
+}
+
+/// Runs a script of SQL statements and dot-commands. Dot-commands are only recognized at the
+/// start of a line outside of a statement.
+fn run_script(connection: &mut Connection, settings: &mut Settings, script: &str) -> Result<()> {
+    let mut statement_number = 0;
+    let mut pending = String::new();
+
+    let mut run_pending = |connection: &mut Connection, settings: &mut Settings, pending: &str| {
+        for statement in sql::split_statements(pending) {
+            statement_number += 1;
+            run_statement(connection, settings, statement)
+                .map_err(|e| anyhow::anyhow!("Error in statement {}: {}", statement_number, e))?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    for line in script.lines() {
+        if pending.trim().is_empty() && line.trim_start().starts_with('.') {
+            run_command(connection, settings, line.trim())?;
+            continue;
+        }
+
+        pending.push_str(line);
+        pending.push('\n');
+        if sql::is_complete(&pending) {
+            run_pending(connection, settings, &pending)?;
+            pending.clear();
+        }
+    }
 
-    let mut database = Database::open(&args[1])?;
+    run_pending(connection, settings, &pending)
+}
 
+fn run_command(connection: &mut Connection, settings: &mut Settings, command: &str) -> Result<()> {
     // Parse command and act accordingly
-    let command = &args[2];
-    match command.as_str() {
+    let mut words = command.split_whitespace();
+    match words.next().unwrap_or_default() {
         ".dbinfo" => {
+            let database = connection.database();
             println!("database page size: {}", database.header.page_size);
-            println!(
-                "number of tables: {}",
-                database.schema.user_tables().count()
-            );
-        }
-
-        ".tables" => database
-            .schema
-            .table_names
-            .iter()
-            .for_each(|name| println!("{}", name)),
-
-        query_string => {
-            let (_, query) = sql::parse(query_string.as_bytes())
-                .map_err(|_e| anyhow::anyhow!("Failed to parse query"))?;
-
-            match query {
-                sql::SQLCommand::Select(sql::SelectStatement::Count(table)) => {
-                    let row = database
-                        .schema
-                        .find_table(&table)
-                        .ok_or(anyhow::anyhow!("Table not found: {}", table))?;
-                    let page = database.get_page(row.rootpage - 1)?;
-                    println!("{}", page.header.number_of_cells);
+            println!("number of tables: {}", database.schema().tables().count());
+            // After the lines the challenge checks, so that they keep their positions
+            println!("freelist page count: {}", database.header.freelist_count);
+        }
+
+        // Tables of attached databases are qualified with their schema name
+        ".tables" => {
+            let mut names = vec![];
+            for (schema, database) in connection.catalog().databases() {
+                let tables = database.schema().tables().map(|table| &table.name);
+                let views = database.schema().views().map(|view| &view.name);
+                let virtual_tables = database.schema().virtual_tables().map(|table| &table.name);
+                for name in tables.chain(views).chain(virtual_tables) {
+                    names.push(match schema {
+                        MAIN_SCHEMA => name.clone(),
+                        schema => format!("{}.{}", schema, name),
+                    });
                 }
-                sql::SQLCommand::Select(sql::SelectStatement::Fields(command)) => {
-                    database.select_fields(&command, &mut stdout())?;
+            }
+            names.sort();
+            for line in output::name_columns(&names) {
+                println!("{}", line);
+            }
+        }
+
+        ".databases" => {
+            for (schema, database) in connection.catalog().databases() {
+                println!("{}: {}", schema, database.pager.path());
+            }
+        }
+
+        // The CREATE statements of the main database, or of one table with its indexes and
+        // triggers
+        ".schema" => {
+            let table = words.next();
+            for row in connection.database().scan(SCHEMA_TABLE_NAME)? {
+                let row = SQLiteSchemaRow::try_from(row?)?;
+                if !table.map_or(true, |table| row.tbl_name.eq_ignore_ascii_case(table)) {
+                    continue;
+                }
+                // Like sqlite3, automatic indexes aren't listed since they have no SQL
+                if let Some(sql) = &row.sql {
+                    println!("{};", sql);
                 }
-                _ => bail!("Unsupported command: {}", query_string),
+            }
+        }
+
+        ".indexes" => {
+            let table = words.next();
+            connection
+                .database()
+                .schema()
+                .indexes(table)
+                .iter()
+                .for_each(|index| println!("{}", index.name));
+        }
+
+        // The header, cell pointers and decoded cells of a b-tree page
+        ".btree-inspect" => {
+            let number = words
+                .next()
+                .and_then(|number| number.parse().ok())
+                .ok_or(anyhow::anyhow!("Usage: .btree-inspect PAGE"))?;
+            for line in connection.inspect_page(number)? {
+                println!("{}", line);
+            }
+        }
+
+        // The bytes of a page, labelled with the structures they belong to
+        ".pagehex" => {
+            let number = words
+                .next()
+                .and_then(|number| number.parse().ok())
+                .ok_or(anyhow::anyhow!("Usage: .pagehex PAGE"))?;
+            for line in connection.hexdump_page(number)? {
+                println!("{}", line);
+            }
+        }
+
+        // Like a query result, so that it's formatted in the current mode
+        ".stats" => {
+            let columns = [
+                "name", "type", "depth", "pages", "interior", "leaf", "overflow", "entries",
+                "payload", "unused",
+            ]
+            .map(String::from);
+            let mut out = settings
+                .mode
+                .formatter(&mut settings.output, settings.headers);
+            out.begin(&columns)?;
+            for usage in connection.space_usage()? {
+                let kind = if usage.is_index { "index" } else { "table" };
+                out.row(&[
+                    ColumnValue::Text(usage.name.as_bytes().into()),
+                    ColumnValue::Text(kind.as_bytes().into()),
+                    ColumnValue::I64(usage.depth as i64),
+                    ColumnValue::I64(usage.pages() as i64),
+                    ColumnValue::I64(usage.interior_pages as i64),
+                    ColumnValue::I64(usage.leaf_pages as i64),
+                    ColumnValue::I64(usage.overflow_pages as i64),
+                    ColumnValue::I64(usage.entries as i64),
+                    ColumnValue::I64(usage.payload as i64),
+                    ColumnValue::I64(usage.unused as i64),
+                ])?;
+            }
+            out.finish()?;
+        }
+
+        ".checkpoint" => {
+            connection.checkpoint()?;
+        }
+
+        ".mode" => {
+            let mode = words.next().ok_or(anyhow::anyhow!("Usage: .mode MODE"))?;
+            settings.set_mode(mode)?;
+        }
+
+        ".read" => {
+            let path = words.next().ok_or(anyhow::anyhow!("Usage: .read FILE"))?;
+            let script = std::fs::read_to_string(path)?;
+            run_script(connection, settings, &script)?;
+        }
+
+        ".import" => {
+            let (Some(path), Some(table)) = (words.next(), words.next()) else {
+                bail!("Usage: .import FILE TABLE");
             };
+            connection.import_csv(path, table)?;
+        }
+
+        ".output" => settings.set_output(words.next())?,
+
+        ".headers" => match words.next() {
+            Some("on") => settings.headers = true,
+            Some("off") => settings.headers = false,
+            _ => bail!("Usage: .headers on|off"),
+        },
+
+        _ => {
+            for statement in sql::split_statements(command) {
+                run_statement(connection, settings, statement)?;
+            }
         }
     }
 
     Ok(())
 }
+
+fn run_statement(
+    connection: &mut Connection,
+    settings: &mut Settings,
+    statement: &str,
+) -> Result<()> {
+    let mut out = settings
+        .mode
+        .formatter(&mut settings.output, settings.headers);
+    connection.execute(statement, out.as_mut())
+}
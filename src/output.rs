@@ -0,0 +1,704 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::Result;
+
+use crate::record::{format_real_digits, ColumnValue};
+
+/// Formats query results. `begin` is called once per statement with the names of the result
+/// columns, followed by a `row` call for every result row and a final `finish`.
+pub trait OutputFormatter {
+    fn begin(&mut self, columns: &[String]) -> Result<()>;
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    #[default]
+    List,
+    Csv,
+    Json,
+    Column,
+    Markdown,
+    Quote,
+}
+
+impl FromStr for OutputMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "list" => Ok(Self::List),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "column" => Ok(Self::Column),
+            "markdown" => Ok(Self::Markdown),
+            "quote" => Ok(Self::Quote),
+            _ => Err(anyhow::anyhow!(
+                "Unknown mode: {}. Use one of: list csv json column markdown quote",
+                s
+            )),
+        }
+    }
+}
+
+impl OutputMode {
+    /// Creates a formatter for this mode. `headers` controls whether the list, csv, column and
+    /// quote modes print the column names; json and markdown always include them.
+    pub fn formatter<'out, W: Write + 'out>(
+        self,
+        out: W,
+        headers: bool,
+    ) -> Box<dyn OutputFormatter + 'out> {
+        match self {
+            OutputMode::List => Box::new(Separated::new(out, "|", headers)),
+            OutputMode::Csv => Box::new(Csv::new(out, headers)),
+            OutputMode::Json => Box::new(JsonLines::new(out)),
+            OutputMode::Column => Box::new(Column::new(out, headers)),
+            OutputMode::Markdown => Box::new(Markdown::new(out)),
+            OutputMode::Quote => Box::new(Quote::new(out, headers)),
+        }
+    }
+}
+
+/// One row per line with the values joined by a separator. NULL is empty, like the default
+/// `.nullvalue` of sqlite3.
+pub struct Separated<W: Write> {
+    out: W,
+    separator: &'static str,
+    headers: bool,
+}
+
+impl<W: Write> Separated<W> {
+    pub fn new(out: W, separator: &'static str, headers: bool) -> Self {
+        Self {
+            out,
+            separator,
+            headers,
+        }
+    }
+}
+
+impl<W: Write> OutputFormatter for Separated<W> {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        if self.headers {
+            writeln!(self.out, "{}", columns.join(self.separator))?;
+        }
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                write!(self.out, "{}", self.separator)?;
+            }
+            if !matches!(value, ColumnValue::Null) {
+                write!(self.out, "{}", value)?;
+            }
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// RFC 4180 CSV: fields containing separators, quotes or line breaks are quoted, records are
+/// terminated by CRLF and NULL is written as an empty field.
+pub struct Csv<W: Write> {
+    out: W,
+    headers: bool,
+    /// Holds the formatted value of a field, reused across fields to avoid allocating
+    field: String,
+}
+
+impl<W: Write> Csv<W> {
+    pub fn new(out: W, headers: bool) -> Self {
+        Self {
+            out,
+            headers,
+            field: String::new(),
+        }
+    }
+
+    fn write_field(out: &mut W, field: &str) -> Result<()> {
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(out, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputFormatter for Csv<W> {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        if self.headers {
+            for (i, column) in columns.iter().enumerate() {
+                if i > 0 {
+                    self.out.write_all(b",")?;
+                }
+                Self::write_field(&mut self.out, column)?;
+            }
+            self.out.write_all(b"\r\n")?;
+        }
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                self.out.write_all(b",")?;
+            }
+            self.field.clear();
+            if !matches!(value, ColumnValue::Null) {
+                std::fmt::Write::write_fmt(&mut self.field, format_args!("{}", value))?;
+            }
+            Self::write_field(&mut self.out, &self.field)?;
+        }
+        self.out.write_all(b"\r\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// One JSON object per row, keyed by column name.
+pub struct JsonLines<W: Write> {
+    out: W,
+    columns: Vec<String>,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            columns: vec![],
+        }
+    }
+}
+
+impl<W: Write> OutputFormatter for JsonLines<W> {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        self.columns = columns.to_vec();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        write!(self.out, "{{")?;
+        for (i, (column, value)) in self.columns.iter().zip(values).enumerate() {
+            if i > 0 {
+                write!(self.out, ",")?;
+            }
+            write_json_string(&mut self.out, column)?;
+            write!(self.out, ":")?;
+            write_json_value(&mut self.out, value)?;
+        }
+        writeln!(self.out, "}}")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes a value with its natural JSON type: numbers as numbers, NULL as null, text as a
+/// string and blobs as a base64 encoded string. JSON has no infinite numbers, so they're null.
+fn write_json_value(out: &mut impl Write, value: &ColumnValue) -> Result<()> {
+    match value {
+        ColumnValue::Null => write!(out, "null")?,
+        ColumnValue::F64(n) if !n.is_finite() => write!(out, "null")?,
+        ColumnValue::Text(content) => write_json_string(out, &String::from_utf8_lossy(content))?,
+        ColumnValue::Blob(content) => write_json_string(out, &base64(content))?,
+        number => write!(out, "{}", number)?,
+    }
+    Ok(())
+}
+
+fn write_json_string(out: &mut impl Write, value: &str) -> Result<()> {
+    write!(out, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")?;
+    Ok(())
+}
+
+/// Encodes bytes as base64 with padding, in the standard alphabet.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len() / 3 * 4 + 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Buffers all rows to left-align every column to its widest value.
+pub struct Column<W: Write> {
+    out: W,
+    headers: bool,
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl<W: Write> Column<W> {
+    pub fn new(out: W, headers: bool) -> Self {
+        Self {
+            out,
+            headers,
+            columns: vec![],
+            rows: vec![],
+        }
+    }
+}
+
+impl<W: Write> OutputFormatter for Column<W> {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        self.columns = columns
+            .iter()
+            .map(|column| escape_line_breaks(column))
+            .collect();
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        self.rows.push(
+            values
+                .iter()
+                .map(|value| escape_line_breaks(&cell_text(value)))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let widths = column_widths(&self.columns, &self.rows);
+        let separator = widths.iter().map(|width| "-".repeat(*width)).collect();
+        let header = if self.headers {
+            vec![&self.columns, &separator]
+        } else {
+            vec![]
+        };
+
+        for row in header.into_iter().chain(&self.rows) {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| pad(value, *width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(self.out, "{}", line.trim_end())?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Buffers all rows to render a GitHub flavoured markdown table.
+pub struct Markdown<W: Write> {
+    out: W,
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl<W: Write> Markdown<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            columns: vec![],
+            rows: vec![],
+        }
+    }
+}
+
+impl<W: Write> OutputFormatter for Markdown<W> {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        self.columns = columns
+            .iter()
+            .map(|column| escape_markdown(column))
+            .collect();
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        self.rows.push(
+            values
+                .iter()
+                .map(|value| escape_markdown(&cell_text(value)))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let widths = column_widths(&self.columns, &self.rows);
+        let separator = widths.iter().map(|width| "-".repeat(*width)).collect();
+
+        for row in [&self.columns, &separator].into_iter().chain(&self.rows) {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| pad(value, *width))
+                .collect::<Vec<_>>()
+                .join(" | ");
+            writeln!(self.out, "| {} |", line)?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Values as SQL literals separated by commas, like `.mode quote` of sqlite3. Unlike the other
+/// modes, the output can be read back without losing blobs or the types of values.
+pub struct Quote<W: Write> {
+    out: W,
+    headers: bool,
+}
+
+impl<W: Write> Quote<W> {
+    pub fn new(out: W, headers: bool) -> Self {
+        Self { out, headers }
+    }
+
+    fn write_text(out: &mut W, text: &[u8]) -> Result<()> {
+        out.write_all(b"'")?;
+        for (i, part) in text.split(|&c| c == b'\'').enumerate() {
+            if i > 0 {
+                out.write_all(b"''")?;
+            }
+            out.write_all(part)?;
+        }
+        out.write_all(b"'")?;
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputFormatter for Quote<W> {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        if self.headers {
+            for (i, column) in columns.iter().enumerate() {
+                if i > 0 {
+                    self.out.write_all(b",")?;
+                }
+                Self::write_text(&mut self.out, column.as_bytes())?;
+            }
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                self.out.write_all(b",")?;
+            }
+            match value {
+                ColumnValue::Text(text) => Self::write_text(&mut self.out, text)?,
+                // More digits than usual, so that the real reads back as the same number
+                ColumnValue::F64(real) => write!(self.out, "{}", format_real_digits(*real, 20))?,
+                value => write!(self.out, "{}", value)?,
+            }
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// The text of a value in a table. NULL is empty, like the default `.nullvalue` of sqlite3.
+fn cell_text(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Escapes line breaks, which would split a row of a table over several lines.
+fn escape_line_breaks(text: &str) -> String {
+    text.replace('\r', "\\r").replace('\n', "\\n")
+}
+
+/// Escapes line breaks and the pipes that separate the cells of a markdown table.
+fn escape_markdown(text: &str) -> String {
+    escape_line_breaks(text).replace('|', "\\|")
+}
+
+fn column_widths(columns: &[String], rows: &[Vec<String>]) -> Vec<usize> {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(std::iter::once(column.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+fn pad(value: &str, width: usize) -> String {
+    let padding = width.saturating_sub(value.chars().count());
+    format!("{}{}", value, " ".repeat(padding))
+}
+
+/// Lays out names in as many columns as fit in 80 characters, filling them top to bottom, like
+/// sqlite3 prints `.tables`. Every name is padded to the width of the longest one.
+pub fn name_columns(names: &[String]) -> Vec<String> {
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let columns = (80 / (width + 2)).max(1);
+    let rows = (names.len() + columns - 1) / columns;
+    (0..rows)
+        .map(|row| {
+            names
+                .iter()
+                .skip(row)
+                .step_by(rows)
+                .map(|name| pad(name, width))
+                .collect::<Vec<_>>()
+                .join("  ")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(mode: OutputMode, headers: bool) -> String {
+        let mut out = Vec::new();
+        {
+            let mut formatter = mode.formatter(&mut out, headers);
+            formatter
+                .begin(&["id".to_string(), "name".to_string()])
+                .unwrap();
+            formatter
+                .row(&[
+                    ColumnValue::I8(1),
+                    ColumnValue::Text(b"Granny Smith"[..].into()),
+                ])
+                .unwrap();
+            formatter
+                .row(&[ColumnValue::I16(300), ColumnValue::Null])
+                .unwrap();
+            formatter.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn names_in_columns() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            name_columns(&names(&["apples", "b", "c", "d"])),
+            ["apples  b       c       d     "]
+        );
+        let long = "verylongtablename_abcdefghijkl";
+        assert_eq!(
+            name_columns(&names(&["apples", "b", "c", "d", "e", long])),
+            [
+                format!("apples{}  d{}", " ".repeat(24), " ".repeat(29)),
+                format!("b{}  e{}", " ".repeat(29), " ".repeat(29)),
+                format!("c{}  {long}", " ".repeat(29)),
+            ]
+        );
+        assert!(name_columns(&[]).is_empty());
+    }
+
+    #[test]
+    fn list_mode() {
+        assert_eq!(render(OutputMode::List, false), "1|Granny Smith\n300|\n");
+        assert_eq!(
+            render(OutputMode::List, true),
+            "id|name\n1|Granny Smith\n300|\n"
+        );
+    }
+
+    #[test]
+    fn csv_mode() {
+        assert_eq!(render(OutputMode::Csv, false), "1,Granny Smith\r\n300,\r\n");
+        assert_eq!(
+            render(OutputMode::Csv, true),
+            "id,name\r\n1,Granny Smith\r\n300,\r\n"
+        );
+    }
+
+    #[test]
+    fn csv_escaping() {
+        let mut out = Vec::new();
+        {
+            let mut formatter = OutputMode::Csv.formatter(&mut out, false);
+            formatter
+                .begin(&["a".to_string(), "b".to_string()])
+                .unwrap();
+            formatter
+                .row(&[
+                    ColumnValue::Text(b"Smith, \"Granny\""[..].into()),
+                    ColumnValue::Text(b"two\nlines"[..].into()),
+                ])
+                .unwrap();
+            formatter.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"Smith, \"\"Granny\"\"\",\"two\nlines\"\r\n"
+        );
+    }
+
+    #[test]
+    fn json_mode() {
+        assert_eq!(
+            render(OutputMode::Json, false),
+            "{\"id\":1,\"name\":\"Granny Smith\"}\n{\"id\":300,\"name\":null}\n"
+        );
+    }
+
+    #[test]
+    fn json_value_types() {
+        let mut out = Vec::new();
+        {
+            let mut formatter = OutputMode::Json.formatter(&mut out, false);
+            let columns = ["real", "blob", "text"].map(String::from);
+            formatter.begin(&columns).unwrap();
+            formatter
+                .row(&[
+                    ColumnValue::F64(1.5),
+                    ColumnValue::Blob(b"\x00\xffhi"[..].into()),
+                    ColumnValue::Text(b"say \"hi\""[..].into()),
+                ])
+                .unwrap();
+            formatter.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"real\":1.5,\"blob\":\"AP9oaQ==\",\"text\":\"say \\\"hi\\\"\"}\n"
+        );
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn column_mode() {
+        assert_eq!(
+            render(OutputMode::Column, true),
+            "id   name\n---  ------------\n1    Granny Smith\n300\n"
+        );
+        assert_eq!(
+            render(OutputMode::Column, false),
+            "1    Granny Smith\n300\n"
+        );
+    }
+
+    #[test]
+    fn quote_mode() {
+        assert_eq!(
+            render(OutputMode::Quote, true),
+            "'id','name'\n1,'Granny Smith'\n300,NULL\n"
+        );
+
+        let mut out = Vec::new();
+        {
+            let mut formatter = OutputMode::Quote.formatter(&mut out, false);
+            let columns = ["real", "blob", "text"].map(String::from);
+            formatter.begin(&columns).unwrap();
+            formatter
+                .row(&[
+                    ColumnValue::F64(0.1),
+                    ColumnValue::Blob(b"\x00\xffhi"[..].into()),
+                    ColumnValue::Text(b"it's"[..].into()),
+                ])
+                .unwrap();
+            formatter.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0.10000000000000000555,X'00ff6869','it''s'\n"
+        );
+    }
+
+    #[test]
+    fn markdown_mode() {
+        assert_eq!(
+            render(OutputMode::Markdown, false),
+            "| id  | name         |\n| --- | ------------ |\n| 1   | Granny Smith |\n| 300 |              |\n"
+        );
+    }
+
+    #[test]
+    fn tables_keep_rows_on_one_line() {
+        let render = |mode: OutputMode| {
+            let mut out = Vec::new();
+            {
+                let mut formatter = mode.formatter(&mut out, true);
+                formatter
+                    .begin(&["a|b".to_string(), "c".to_string()])
+                    .unwrap();
+                formatter
+                    .row(&[
+                        ColumnValue::Text(b"two\nlines"[..].into()),
+                        ColumnValue::Text(b"x|y\r\n"[..].into()),
+                    ])
+                    .unwrap();
+                formatter.finish().unwrap();
+            }
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            render(OutputMode::Column),
+            "a|b         c\n----------  -------\ntwo\\nlines  x|y\\r\\n\n"
+        );
+        assert_eq!(
+            render(OutputMode::Markdown),
+            "| a\\|b       | c        |\n| ---------- | -------- |\n| two\\nlines | x\\|y\\r\\n |\n"
+        );
+    }
+}
@@ -1,9 +1,13 @@
-use anyhow::Result;
-use std::{fs::File, io::prelude::*};
+use std::sync::Arc;
 
+use anyhow::{bail, Result};
+
+use crate::error::Error;
+use crate::record::{Record, Value};
+use crate::sql::Literal;
 use crate::varint;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PageKind {
     InteriorIndex,
     LeafIndex,
@@ -20,12 +24,63 @@ impl<'page> PageKind {
         matches!(self, Self::LeafIndex | Self::LeafTable)
     }
 
-    pub fn read_cell(&self, data: &'page [u8]) -> Cell<'page> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::InteriorIndex => "interior index",
+            Self::LeafIndex => "leaf index",
+            Self::InteriorTable => "interior table",
+            Self::LeafTable => "leaf table",
+        }
+    }
+
+    /// The kind of the interior pages of the same b-tree.
+    pub fn interior(&self) -> PageKind {
+        match self {
+            Self::LeafTable | Self::InteriorTable => Self::InteriorTable,
+            Self::LeafIndex | Self::InteriorIndex => Self::InteriorIndex,
+        }
+    }
+
+    pub fn read_cell(&self, data: &'page [u8], usable_size: usize) -> Cell<'page> {
         match self {
-            PageKind::InteriorIndex => Cell::read_interior_index(data),
-            PageKind::LeafIndex => Cell::read_leaf_index(data),
+            PageKind::InteriorIndex => Cell::read_interior_index(data, usable_size),
+            PageKind::LeafIndex => Cell::read_leaf_index(data, usable_size),
             PageKind::InteriorTable => Cell::read_interior_table(data),
-            PageKind::LeafTable => Cell::read_leaf_table(data),
+            PageKind::LeafTable => Cell::read_leaf_table(data, usable_size),
+        }
+    }
+
+    /// Number of payload bytes stored on the page itself, before spilling onto overflow pages.
+    ///
+    /// See "B-tree Pages" in https://www.sqlite.org/fileformat.html for the derivation of the
+    /// maximum (X) and minimum (M) local payload sizes.
+    pub fn local_payload_size(&self, payload_size: usize, usable_size: usize) -> usize {
+        let max_local = match self {
+            PageKind::LeafTable | PageKind::InteriorTable => usable_size - 35,
+            PageKind::LeafIndex | PageKind::InteriorIndex => ((usable_size - 12) * 64 / 255) - 23,
+        };
+
+        if payload_size <= max_local {
+            return payload_size;
+        }
+
+        let min_local = ((usable_size - 12) * 32 / 255) - 23;
+        let local = min_local + ((payload_size - min_local) % (usable_size - 4));
+        if local <= max_local {
+            local
+        } else {
+            min_local
+        }
+    }
+}
+
+impl From<&PageKind> for u8 {
+    fn from(kind: &PageKind) -> Self {
+        match kind {
+            PageKind::InteriorIndex => 0x02,
+            PageKind::InteriorTable => 0x05,
+            PageKind::LeafIndex => 0x0a,
+            PageKind::LeafTable => 0x0d,
         }
     }
 }
@@ -39,7 +94,7 @@ impl TryFrom<u8> for PageKind {
             0x05 => Ok(Self::InteriorTable),
             0x0a => Ok(Self::LeafIndex),
             0x0d => Ok(Self::LeafTable),
-            _ => Err(anyhow::anyhow!("Invalid page kind")),
+            _ => Err(Error::Corrupt(format!("invalid page type {}", value)).into()),
         }
     }
 }
@@ -59,7 +114,7 @@ pub enum Cell<'page> {
     },
     InteriorTable {
         left_child_page: u32,
-        key: u64,
+        key: i64,
     },
     LeafTable {
         size: u64,
@@ -70,7 +125,7 @@ pub enum Cell<'page> {
 }
 
 impl<'page> Cell<'page> {
-    fn read_interior_index(data: &'page [u8]) -> Cell {
+    fn read_interior_index(data: &'page [u8], usable_size: usize) -> Cell<'page> {
         let left_child_page = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
 
         let mut cursor = 4;
@@ -78,58 +133,44 @@ impl<'page> Cell<'page> {
         let size = size as u64;
         cursor += offset;
 
-        let (overflow_page, end) = if size > data[cursor..].len() as u64 {
-            let end = data.len() - 4;
-            (
-                u32::from_be_bytes([data[end], data[end + 1], data[end + 2], data[end + 3]]),
-                end,
-            )
-        } else {
-            (0, cursor + size as usize)
-        };
+        let (payload, overflow_page) =
+            Self::split_payload(&PageKind::InteriorIndex, &data[cursor..], size, usable_size);
 
         Cell::InteriorIndex {
-            left_child_page: left_child_page as u32,
+            left_child_page,
             size,
-            payload: &data[cursor..end],
+            payload,
             overflow_page,
         }
     }
 
-    fn read_leaf_index(data: &'page [u8]) -> Cell {
+    fn read_leaf_index(data: &'page [u8], usable_size: usize) -> Cell<'page> {
         let mut cursor = 0;
-        let (size, offset) = varint::read(&data[..]);
+        let (size, offset) = varint::read(data);
         let size = size as u64;
         cursor += offset;
 
-        let (overflow_page, end) = if size > data[cursor..].len() as u64 {
-            let end = data.len() - 4;
-            (
-                u32::from_be_bytes([data[end], data[end + 1], data[end + 2], data[end + 3]]),
-                end,
-            )
-        } else {
-            (0, cursor + size as usize)
-        };
+        let (payload, overflow_page) =
+            Self::split_payload(&PageKind::LeafIndex, &data[cursor..], size, usable_size);
 
         Cell::LeafIndex {
             size,
-            payload: &data[cursor..end],
+            payload,
             overflow_page,
         }
     }
 
-    fn read_interior_table(data: &'page [u8]) -> Cell {
+    fn read_interior_table(data: &'page [u8]) -> Cell<'page> {
         let left_child_page = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
         let (key, _) = varint::read(&data[4..]);
 
         Cell::InteriorTable {
-            left_child_page: left_child_page as u32,
-            key: key as u64,
+            left_child_page,
+            key,
         }
     }
 
-    fn read_leaf_table(data: &'page [u8]) -> Cell {
+    fn read_leaf_table(data: &'page [u8], usable_size: usize) -> Cell<'page> {
         let mut cursor = 0;
         let (payload_size, offset) = varint::read(data);
         let payload_size = payload_size as u64;
@@ -138,61 +179,111 @@ impl<'page> Cell<'page> {
         let (rowid, offset) = varint::read(&data[cursor..]);
         cursor += offset;
 
-        let (overflow_page, end) = if payload_size > data[cursor..].len() as u64 {
-            let end = data.len() - 4;
-            (
-                u32::from_be_bytes([data[end], data[end + 1], data[end + 2], data[end + 3]]),
-                end,
-            )
-        } else {
-            (0, cursor + payload_size as usize)
-        };
+        let (payload, overflow_page) = Self::split_payload(
+            &PageKind::LeafTable,
+            &data[cursor..],
+            payload_size,
+            usable_size,
+        );
 
         Cell::LeafTable {
             size: payload_size,
-            rowid: rowid as i64,
-            payload: &data[cursor..end],
+            rowid,
+            payload,
             overflow_page,
         }
     }
+
+    /// Splits the cell content into the locally stored payload and the first overflow page
+    /// number (0 if the payload fits on the page).
+    fn split_payload(
+        kind: &PageKind,
+        data: &'page [u8],
+        payload_size: u64,
+        usable_size: usize,
+    ) -> (&'page [u8], u32) {
+        let local = kind.local_payload_size(payload_size as usize, usable_size);
+        if local as u64 == payload_size {
+            return (&data[..local], 0);
+        }
+
+        let overflow_page = u32::from_be_bytes([
+            data[local],
+            data[local + 1],
+            data[local + 2],
+            data[local + 3],
+        ]);
+        (&data[..local], overflow_page)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PageHeader {
     pub kind: PageKind,
     pub first_freeblock_start: u16,
     pub number_of_cells: u16,
-    pub content_start_offset: u16,
+    pub content_start_offset: u32,
     pub fragment_free_bytes: u8,
     pub right_child_page_number: Option<u32>,
 }
 
-#[derive(Debug)]
+/// The data of a page for changing it, which copies it first if it's shared.
+fn make_mut(data: &mut Arc<[u8]>) -> &mut [u8] {
+    if Arc::get_mut(data).is_none() {
+        *data = Arc::from(&data[..]);
+    }
+    Arc::get_mut(data).expect("the data was just copied")
+}
+
+/// A b-tree page. Clones of a page share its data, so that a page can be kept cheaply, e.g.
+/// for a cell on it, while the b-tree is traversed further. Changing a page copies its data
+/// first if it's shared.
+#[derive(Debug, Clone)]
 pub struct Page {
     pub header: PageHeader,
+    /// Positions of the cells in `data`
     pub cell_pointers: Vec<u16>,
-    pub data: Vec<u8>,
+    pub data: Arc<[u8]>,
+    pub usable_size: usize,
+    /// Bytes at the start of the page that aren't part of `data`, i.e. the database header of
+    /// the first page. Cell pointers in the file are relative to the start of the page.
+    pub offset: u16,
 }
 
 impl Page {
-    pub fn read(file: &mut File, page_size: u16) -> Result<Self> {
-        Self::read_with_offset(file, page_size, 0)
-    }
-
-    pub fn read_with_offset(file: &mut File, page_size: u16, offset: u16) -> Result<Self> {
-        let mut page = vec![0; page_size as usize];
-        file.read_exact(&mut page)?;
-
+    /// Parses a page, checking that the header, the cell pointers and the cells lie within
+    /// the usable part of the page, so that reading the cells can't go out of bounds.
+    pub fn parse(page: impl Into<Arc<[u8]>>, usable_size: usize, offset: u16) -> Result<Self> {
+        let page = page.into();
+        // Cells sizes are derived from the usable size, which is at least 480 in a valid file
+        if usable_size < 480 {
+            bail!(Error::Corrupt(format!(
+                "usable size {} is too small",
+                usable_size
+            )));
+        }
+        // The end of the usable area and the positions of cells in `page`
+        let end = usable_size.saturating_sub(offset as usize).min(page.len());
+        if end < 12 {
+            bail!(Error::Corrupt("page is too small".to_string()));
+        }
         let kind = PageKind::try_from(u8::from_be_bytes([page[0]]))?;
         let first_freeblock_start = u16::from_be_bytes([page[1], page[2]]);
         let number_of_cells = u16::from_be_bytes([page[3], page[4]]);
-        let content_start_offset = u16::from_be_bytes([page[5], page[6]]);
+        // Zero is interpreted as 65536, for pages of that size with no cells.
+        let content_start_offset = match u16::from_be_bytes([page[5], page[6]]) {
+            0 => 65536,
+            n => n as u32,
+        };
         let fragment_free_bytes = page[7];
         let (header_size, right_child_page_number) = if kind.is_interior() {
-            (
-                12,
-                Some(u32::from_be_bytes([page[8], page[9], page[10], page[11]])),
-            )
+            let right_child = u32::from_be_bytes([page[8], page[9], page[10], page[11]]);
+            if right_child == 0 {
+                bail!(Error::Corrupt(
+                    "interior page without right child".to_string()
+                ));
+            }
+            (12, Some(right_child))
         } else {
             (8, None)
         };
@@ -206,22 +297,610 @@ impl Page {
             right_child_page_number,
         };
 
-        let cell_pointers: Vec<u16> = page[header_size..]
+        let cells_start = header_size + 2 * number_of_cells as usize;
+        if cells_start > end {
+            bail!(Error::Corrupt(format!(
+                "{} cells don't fit on the page",
+                number_of_cells
+            )));
+        }
+        let content_start = content_start_offset as usize;
+        if content_start < cells_start + offset as usize || content_start > usable_size {
+            bail!(Error::Corrupt(format!(
+                "cell content area starts at {} out of range",
+                content_start
+            )));
+        }
+        let cell_pointers = page[header_size..cells_start]
             .chunks_exact(2)
-            .take(header.number_of_cells.into())
-            .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]) - offset as u16)
-            .collect();
+            .map(|chunk| {
+                let pointer = u16::from_be_bytes([chunk[0], chunk[1]]) as usize;
+                match pointer.checked_sub(offset as usize) {
+                    // The smallest cell is 4 bytes
+                    Some(pointer) if pointer >= cells_start && pointer + 4 <= end => {
+                        Ok(pointer as u16)
+                    }
+                    _ => Err(Error::Corrupt(format!(
+                        "cell pointer {} out of range",
+                        pointer
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<u16>, _>>()?;
 
-        Ok(Self {
+        let page = Self {
             header,
             cell_pointers,
             data: page,
+            usable_size,
+            offset,
+        };
+        for &pointer in &page.cell_pointers {
+            let pointer = pointer as usize;
+            if pointer + page.cell_len(pointer) > end {
+                bail!(Error::Corrupt(format!(
+                    "cell at {} extends past the end of the page",
+                    pointer + offset as usize
+                )));
+            }
+            if kind.is_interior() && page.data[pointer..pointer + 4] == [0; 4] {
+                bail!(Error::Corrupt("cell without child page".to_string()));
+            }
+        }
+        Ok(page)
+    }
+
+    pub fn cell(&self, index: usize) -> Option<Cell<'_>> {
+        let pointer = *self.cell_pointers.get(index)?;
+        Some(
+            self.header
+                .kind
+                .read_cell(&self.data[pointer as usize..], self.usable_size),
+        )
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = Cell<'_>> {
+        self.cell_pointers.iter().map(move |pointer| {
+            self.header
+                .kind
+                .read_cell(&self.data[*pointer as usize..], self.usable_size)
         })
     }
 
-    pub fn cells(&self) -> impl Iterator<Item = Cell> {
+    /// A page without cells, to be filled with [`Page::set_cells`].
+    pub fn empty(kind: PageKind, page_size: u32, usable_size: usize, offset: u16) -> Self {
+        let mut data = vec![0; page_size as usize - offset as usize];
+        data[0] = u8::from(&kind);
+        data[5..7].copy_from_slice(&(usable_size as u16).to_be_bytes());
+        Self {
+            header: PageHeader {
+                right_child_page_number: kind.is_interior().then_some(0),
+                kind,
+                first_freeblock_start: 0,
+                number_of_cells: 0,
+                content_start_offset: usable_size as u32,
+                fragment_free_bytes: 0,
+            },
+            cell_pointers: vec![],
+            data: data.into(),
+            usable_size,
+            offset,
+        }
+    }
+
+    fn header_size(&self) -> usize {
+        if self.header.kind.is_interior() {
+            12
+        } else {
+            8
+        }
+    }
+
+    /// The encoded cells of the page, including their overflow page numbers.
+    pub fn raw_cells(&self) -> Vec<Vec<u8>> {
         self.cell_pointers
             .iter()
-            .map(move |pointer| self.header.kind.read_cell(&self.data[*pointer as usize..]))
+            .map(|&pointer| {
+                let pointer = pointer as usize;
+                self.data[pointer..pointer + self.cell_len(pointer)].to_vec()
+            })
+            .collect()
+    }
+
+    /// Length of the cell at `pointer` in `data`.
+    pub fn cell_len(&self, pointer: usize) -> usize {
+        let data = &self.data[pointer..];
+        let (header_len, payload_size) = match self.header.kind {
+            PageKind::InteriorTable => return 4 + varint::read(&data[4..]).1,
+            PageKind::LeafTable => {
+                let (payload_size, size_len) = varint::read(data);
+                let (_, rowid_len) = varint::read(&data[size_len..]);
+                (size_len + rowid_len, payload_size)
+            }
+            PageKind::LeafIndex => {
+                let (payload_size, size_len) = varint::read(data);
+                (size_len, payload_size)
+            }
+            PageKind::InteriorIndex => {
+                let (payload_size, size_len) = varint::read(&data[4..]);
+                (4 + size_len, payload_size)
+            }
+        };
+        let payload_size = payload_size as usize;
+        let local = self
+            .header
+            .kind
+            .local_payload_size(payload_size, self.usable_size);
+        let overflow_page_len = if local < payload_size { 4 } else { 0 };
+        header_len + local + overflow_page_len
+    }
+
+    /// Whether `cells` fit on this page, ignoring its current content.
+    pub fn fits(&self, cells: &[Vec<u8>]) -> bool {
+        let size: usize = cells.iter().map(|cell| cell.len() + 2).sum();
+        self.header_size() + size <= self.usable_size - self.offset as usize
+    }
+
+    /// Replaces the content of the page with `cells`, packed at the end of the page. Interior
+    /// pages also get a new right child.
+    pub fn set_cells(&mut self, cells: &[Vec<u8>], right_child: Option<u32>) -> Result<()> {
+        if !self.fits(cells) {
+            bail!("cells don't fit on the page");
+        }
+
+        let header_size = self.header_size();
+        let end = self.usable_size - self.offset as usize;
+        // A page that is shared, e.g. by the cursor that found it, is copied first
+        let data = make_mut(&mut self.data);
+        data[1..end].fill(0);
+
+        let mut content_start = end;
+        self.cell_pointers.clear();
+        for cell in cells {
+            content_start -= cell.len();
+            data[content_start..content_start + cell.len()].copy_from_slice(cell);
+            self.cell_pointers.push(content_start as u16);
+        }
+
+        self.header.first_freeblock_start = 0;
+        self.header.fragment_free_bytes = 0;
+        self.header.number_of_cells = cells.len() as u16;
+        self.header.content_start_offset = (content_start + self.offset as usize) as u32;
+        data[3..5].copy_from_slice(&self.header.number_of_cells.to_be_bytes());
+        data[5..7].copy_from_slice(&(self.header.content_start_offset as u16).to_be_bytes());
+        if self.header.kind.is_interior() {
+            let right_child = right_child.unwrap_or_default();
+            self.header.right_child_page_number = Some(right_child);
+            data[8..12].copy_from_slice(&right_child.to_be_bytes());
+        }
+        self.write_cell_pointers(header_size);
+        Ok(())
+    }
+
+    fn write_cell_pointers(&mut self, header_size: usize) {
+        let data = make_mut(&mut self.data);
+        for (i, pointer) in self.cell_pointers.iter().enumerate() {
+            let stored = *pointer + self.offset;
+            data[header_size + 2 * i..header_size + 2 * i + 2]
+                .copy_from_slice(&stored.to_be_bytes());
+        }
+    }
+
+    /// Bytes between the cell pointer array and the cell content area. Space in freeblocks
+    /// isn't reused.
+    pub fn free_space(&self) -> usize {
+        let pointers_end = self.header_size() + 2 * self.cell_pointers.len();
+        let content_start = self.header.content_start_offset as usize - self.offset as usize;
+        content_start.saturating_sub(pointers_end)
+    }
+
+    /// Describes the header, the cell pointers and the cells of the page, one line each, for
+    /// debugging the file format. `number` is the page number, counting from 1. Offsets are
+    /// from the start of the page, like in the file.
+    pub fn inspect(&self, number: u32) -> Vec<String> {
+        let header = &self.header;
+        let mut lines = vec![
+            format!("page {number}: {}", header.kind.name()),
+            format!("first freeblock: {}", header.first_freeblock_start),
+            format!("number of cells: {}", header.number_of_cells),
+            format!("cell content area: {}", header.content_start_offset),
+            format!("fragmented free bytes: {}", header.fragment_free_bytes),
+        ];
+        if let Some(right_child) = header.right_child_page_number {
+            lines.push(format!("right child: page {right_child}"));
+        }
+        let pointers = self
+            .cell_pointers
+            .iter()
+            .map(|pointer| (pointer + self.offset).to_string())
+            .collect::<Vec<_>>();
+        lines.push(format!("cell pointers: {}", pointers.join(" ")));
+
+        for (i, (pointer, cell)) in self.cell_pointers.iter().zip(self.cells()).enumerate() {
+            let description = match cell {
+                Cell::InteriorTable {
+                    left_child_page,
+                    key,
+                } => format!("left child page {left_child_page}, key {key}"),
+                Cell::LeafTable {
+                    size,
+                    rowid,
+                    payload,
+                    overflow_page,
+                } => format!(
+                    "rowid {rowid}, {}",
+                    describe_payload(size, payload, overflow_page)
+                ),
+                Cell::InteriorIndex {
+                    left_child_page,
+                    size,
+                    payload,
+                    overflow_page,
+                } => format!(
+                    "left child page {left_child_page}, {}",
+                    describe_payload(size, payload, overflow_page)
+                ),
+                Cell::LeafIndex {
+                    size,
+                    payload,
+                    overflow_page,
+                } => describe_payload(size, payload, overflow_page),
+            };
+            lines.push(format!(
+                "cell {i} at {}: {description}",
+                pointer + self.offset
+            ));
+        }
+        lines
+    }
+
+    /// A hexdump of the page in which each structure starts on a new line that names it: the
+    /// database header, the page header, the cell pointer array, the unallocated space, the
+    /// cells, freeblocks and fragments in the cell content area, and the reserved space.
+    /// `database_header` holds the bytes before the page data, which only the first page has.
+    pub fn hexdump(&self, number: u32, database_header: &[u8]) -> Vec<String> {
+        let offset = self.offset as usize;
+        let header_size = self.header_size();
+        let pointers_end = header_size + 2 * self.cell_pointers.len();
+        let content_start = (self.header.content_start_offset as usize)
+            .saturating_sub(offset)
+            .clamp(pointers_end, self.data.len());
+        let usable_end = self.usable_size.saturating_sub(offset).min(self.data.len());
+
+        // The cells and freeblocks in the content area, by position in `data`
+        let mut content = self
+            .cell_pointers
+            .iter()
+            .enumerate()
+            .map(|(i, &pointer)| {
+                let pointer = pointer as usize;
+                (pointer, self.cell_len(pointer), format!("cell {i}"))
+            })
+            .collect::<Vec<_>>();
+        let mut freeblock = self.header.first_freeblock_start as usize;
+        while freeblock != 0 && content.len() <= self.data.len() / 4 {
+            let Some(position) = freeblock.checked_sub(offset) else {
+                break;
+            };
+            let Some(block) = self.data.get(position..position + 4) else {
+                break;
+            };
+            let size = u16::from_be_bytes([block[2], block[3]]) as usize;
+            content.push((position, size.max(4), "freeblock".to_string()));
+            freeblock = u16::from_be_bytes([block[0], block[1]]) as usize;
+        }
+        content.sort_by_key(|&(start, _, _)| start);
+
+        let mut lines = vec![format!(
+            "page {number}: {}, {} bytes",
+            self.header.kind.name(),
+            offset + self.data.len()
+        )];
+        if !database_header.is_empty() {
+            hexdump(0, database_header, "database header", &mut lines);
+        }
+        let mut region = |start: usize, end: usize, label: &str| {
+            if start < end {
+                hexdump(offset + start, &self.data[start..end], label, &mut lines);
+            }
+        };
+        region(0, header_size, "page header");
+        region(header_size, pointers_end, "cell pointer array");
+        region(pointers_end, content_start, "unallocated");
+        let mut position = content_start;
+        for (start, len, label) in content {
+            let end = (start + len).min(usable_end);
+            // Bytes that belong to no cell or freeblock are fragments
+            region(position, start.min(usable_end), "fragment");
+            region(start.max(position), end, &label);
+            position = position.max(end);
+        }
+        region(position, usable_end, "fragment");
+        region(usable_end, self.data.len(), "reserved space");
+        lines
+    }
+
+    /// Bytes of the usable part of the page that belong to neither the header, the cell pointer
+    /// array nor a cell: the unallocated space, freeblocks and fragments.
+    pub fn unused_bytes(&self) -> usize {
+        let cells = self
+            .cell_pointers
+            .iter()
+            .map(|&pointer| self.cell_len(pointer as usize))
+            .sum::<usize>();
+        let used = self.header_size() + 2 * self.cell_pointers.len() + cells;
+        (self.usable_size - self.offset as usize).saturating_sub(used)
+    }
+
+    /// Adds an encoded cell so that it becomes the cell at `index`. The cell content is placed
+    /// at the start of the content area, which grows towards the cell pointer array.
+    pub fn insert_cell(&mut self, index: usize, cell: &[u8]) -> Result<()> {
+        if cell.len() + 2 > self.free_space() {
+            bail!("not enough space on the page for the cell");
+        }
+
+        let content_start = self.header.content_start_offset as usize - cell.len();
+        let position = content_start - self.offset as usize;
+        let data = make_mut(&mut self.data);
+        data[position..position + cell.len()].copy_from_slice(cell);
+        self.cell_pointers.insert(index, position as u16);
+
+        self.header.number_of_cells += 1;
+        self.header.content_start_offset = content_start as u32;
+        data[3..5].copy_from_slice(&self.header.number_of_cells.to_be_bytes());
+        data[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+
+        self.write_cell_pointers(self.header_size());
+        Ok(())
+    }
+}
+
+/// Adds lines with 16 bytes each, starting at position `start` of the page, to `lines`. The
+/// first line is labelled. Like `hexdump`, repeated lines are replaced by a single `*`.
+pub fn hexdump(start: usize, bytes: &[u8], label: &str, lines: &mut Vec<String>) {
+    let mut previous: Option<&[u8]> = None;
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        if previous == Some(chunk) {
+            if lines.last().map_or(true, |line| line != "*") {
+                lines.push("*".to_string());
+            }
+            continue;
+        }
+        previous = Some(chunk);
+        let hex = chunk
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let label = if i == 0 { label } else { "" };
+        let line = format!("{:04x}  {hex:<47}  {label}", start + 16 * i);
+        lines.push(line.trim_end().to_string());
+    }
+    if lines.last().map_or(false, |line| line == "*") {
+        lines.push(format!("{:04x}", start + bytes.len()));
+    }
+}
+
+/// The size of a payload and its values, unless part of it is on overflow pages.
+fn describe_payload(size: u64, payload: &[u8], overflow_page: u32) -> String {
+    if overflow_page != 0 {
+        return format!(
+            "payload {size} bytes, {} on the page, overflow page {overflow_page}",
+            payload.len()
+        );
+    }
+    match Record::read(0, payload) {
+        Ok(record) => {
+            // Control characters are escaped so that each cell stays on one line
+            let values = record
+                .values()
+                .iter()
+                .map(|value| {
+                    let literal = Literal::from(Value::from(value)).to_string();
+                    literal
+                        .chars()
+                        .flat_map(|c| {
+                            if c.is_control() {
+                                c.escape_default().collect()
+                            } else {
+                                vec![c]
+                            }
+                        })
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>();
+            format!("payload {size} bytes ({})", values.join(", "))
+        }
+        Err(error) => format!("payload {size} bytes, {error}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_payloads_are_stored_locally() {
+        assert_eq!(PageKind::LeafTable.local_payload_size(100, 4096), 100);
+        assert_eq!(PageKind::LeafTable.local_payload_size(4061, 4096), 4061);
+        assert_eq!(PageKind::LeafIndex.local_payload_size(1002, 4096), 1002);
+    }
+
+    #[test]
+    fn table_leaf_payload_spills_past_max_local() {
+        // U = 4096: X = 4061, M = 489
+        assert_eq!(PageKind::LeafTable.local_payload_size(10000, 4096), 1816);
+        // K = 4062 > X, so only the minimum is kept locally
+        assert_eq!(PageKind::LeafTable.local_payload_size(4062, 4096), 489);
+    }
+
+    fn empty_leaf(size: usize, offset: u16) -> Page {
+        let mut data = vec![0; size - offset as usize];
+        data[0] = 0x0d;
+        data[5..7].copy_from_slice(&(size as u16).to_be_bytes());
+        Page::parse(data, size, offset).unwrap()
+    }
+
+    #[test]
+    fn insert_cells_into_leaf() {
+        for offset in [0, 100] {
+            let mut page = empty_leaf(512, offset);
+            page.insert_cell(0, &[2, 2, 2, 9]).unwrap();
+            page.insert_cell(0, &[2, 1, 2, 9]).unwrap();
+
+            let reparsed = Page::parse(page.data.clone(), 512, offset).unwrap();
+            assert_eq!(reparsed.header.number_of_cells, 2);
+            assert_eq!(reparsed.header.content_start_offset, 512 - 8);
+            let rowids = reparsed
+                .cells()
+                .map(|cell| match cell {
+                    Cell::LeafTable { rowid, .. } => rowid,
+                    _ => panic!("not a table leaf cell"),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(rowids, vec![1, 2]);
+        }
+    }
+
+    #[test]
+    fn clones_share_data_until_changed() {
+        let mut page = empty_leaf(512, 0);
+        page.insert_cell(0, &[2, 1, 2, 9]).unwrap();
+        let kept = page.clone();
+        assert!(Arc::ptr_eq(&page.data, &kept.data));
+
+        // Changing the page leaves the clone, and the cells read from it, as they were
+        page.insert_cell(1, &[2, 2, 2, 8]).unwrap();
+        assert!(!Arc::ptr_eq(&page.data, &kept.data));
+        let Some(Cell::LeafTable { payload, .. }) = kept.cell(0) else {
+            panic!("not a table leaf cell");
+        };
+        drop(page);
+        assert_eq!(
+            Record::read(1, payload).unwrap().to_owned_record().values,
+            [Value::Integer(1)]
+        );
+        assert_eq!(kept.cells().count(), 1);
+    }
+
+    #[test]
+    fn inspect_pages() {
+        let mut page = empty_leaf(512, 100);
+        // A record of 'ab' and 7, then one whose payload claims to be larger than the page
+        page.insert_cell(0, &[6, 3, 3, 17, 1, b'a', b'b', 7])
+            .unwrap();
+        let mut overflowing = vec![0x84, 0x00, 9];
+        overflowing.extend([0; 39]);
+        overflowing.extend(4u32.to_be_bytes());
+        page.insert_cell(1, &overflowing).unwrap();
+        assert_eq!(
+            page.inspect(1),
+            [
+                "page 1: leaf table",
+                "first freeblock: 0",
+                "number of cells: 2",
+                "cell content area: 458",
+                "fragmented free bytes: 0",
+                "cell pointers: 504 458",
+                "cell 0 at 504: rowid 3, payload 6 bytes ('ab', 7)",
+                "cell 1 at 458: rowid 9, payload 512 bytes, 39 on the page, overflow page 4",
+            ]
+        );
+    }
+
+    #[test]
+    fn hexdump_pages() {
+        let mut page = empty_leaf(512, 0);
+        page.insert_cell(0, &[3, 1, 2, 15, b'x']).unwrap();
+        page.insert_cell(1, &[3, 2, 2, 15, b'y']).unwrap();
+        // Free the first cell, except for a byte that becomes a fragment
+        let mut data = page.data.to_vec();
+        data[1..3].copy_from_slice(&507u16.to_be_bytes());
+        data[3..5].copy_from_slice(&1u16.to_be_bytes());
+        data[8..10].copy_from_slice(&502u16.to_be_bytes());
+        data[507..511].copy_from_slice(&[0, 0, 0, 4]);
+        let page = Page::parse(data, 512, 0).unwrap();
+        assert_eq!(
+            page.hexdump(2, &[]),
+            [
+                "page 2: leaf table, 512 bytes",
+                "0000  0d 01 fb 00 01 01 f6 00                          page header",
+                "0008  01 f6                                            cell pointer array",
+                "000a  01 f6 00 00 00 00 00 00 00 00 00 00 00 00 00 00  unallocated",
+                "001a  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+                "*",
+                "01ea  00 00 00 00 00 00 00 00 00 00 00 00",
+                "01f6  03 02 02 0f 79                                   cell 0",
+                "01fb  00 00 00 04                                      freeblock",
+                "01ff  78                                               fragment",
+            ]
+        );
+    }
+
+    #[test]
+    fn insert_into_full_page_fails() {
+        let mut page = empty_leaf(512, 0);
+        let cell = [0; 100];
+        for _ in 0..4 {
+            page.insert_cell(0, &cell).unwrap();
+        }
+        assert_eq!(page.free_space(), 512 - 400 - 8 - 8);
+        assert!(page.insert_cell(0, &cell).is_err());
+    }
+
+    #[test]
+    fn index_payload_spills_past_max_local() {
+        // U = 4096: X = 1002, M = 489
+        assert_eq!(PageKind::LeafIndex.local_payload_size(4600, 4096), 508);
+        assert_eq!(PageKind::LeafIndex.local_payload_size(1003, 4096), 489);
+        assert_eq!(PageKind::InteriorIndex.local_payload_size(1003, 4096), 489);
+    }
+
+    #[test]
+    fn corrupt_pages_are_errors() {
+        let leaf = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut data = empty_leaf(512, 0).data.to_vec();
+            data[3..5].copy_from_slice(&1u16.to_be_bytes());
+            data[5..7].copy_from_slice(&508u16.to_be_bytes());
+            data[8..10].copy_from_slice(&508u16.to_be_bytes());
+            data[508..512].copy_from_slice(&[2, 1, 2, 9]);
+            edit(&mut data);
+            Page::parse(data, 512, 0)
+                .map(|_| ())
+                .map_err(|error| error.to_string())
+        };
+        assert_eq!(leaf(&|_| {}), Ok(()));
+
+        let pointer_past_end = leaf(&|data| data[8..10].copy_from_slice(&510u16.to_be_bytes()));
+        assert_eq!(
+            pointer_past_end.unwrap_err(),
+            "database disk image is malformed: cell pointer 510 out of range"
+        );
+        let cell_past_end = leaf(&|data| data[508] = 100);
+        assert_eq!(
+            cell_past_end.unwrap_err(),
+            "database disk image is malformed: cell at 508 extends past the end of the page"
+        );
+        let too_many_cells = leaf(&|data| data[3..5].copy_from_slice(&300u16.to_be_bytes()));
+        assert_eq!(
+            too_many_cells.unwrap_err(),
+            "database disk image is malformed: 300 cells don't fit on the page"
+        );
+        let bad_kind = leaf(&|data| data[0] = 3);
+        assert_eq!(
+            bad_kind.unwrap_err(),
+            "database disk image is malformed: invalid page type 3"
+        );
+
+        // Pointers of the first page can't point into the database header
+        let mut data = empty_leaf(512, 100).data.to_vec();
+        data[3..5].copy_from_slice(&1u16.to_be_bytes());
+        data[8..10].copy_from_slice(&50u16.to_be_bytes());
+        assert!(Page::parse(data, 512, 100).is_err());
+
+        assert_eq!(
+            Page::parse(vec![0x0d; 32], 32, 0).unwrap_err().to_string(),
+            "database disk image is malformed: usable size 32 is too small"
+        );
     }
 }
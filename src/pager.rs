@@ -0,0 +1,719 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, Cursor, ErrorKind, SeekFrom};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use anyhow::{bail, Result};
+
+use crate::clock;
+use crate::error::Error;
+use crate::log;
+use crate::wal::Wal;
+
+/// Magic number at the start of a rollback journal.
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+/// The journal header is padded to the sector size, the page records start after it.
+const SECTOR_SIZE: u32 = 512;
+/// The number of pages read at once when pages are read in order, see
+/// [`Pager::set_read_ahead`].
+pub const DEFAULT_READ_AHEAD: u32 = 16;
+
+/// Reads and writes pages of the database file. Pages are numbered from 0 here, one less than
+/// the page numbers stored in the file.
+///
+/// Writes between [`Pager::begin`] and [`Pager::commit`] are atomic: the original content of
+/// every page is copied to a rollback journal next to the database before the page is first
+/// changed. The journal uses the format of SQLite, so both this pager and SQLite restore the
+/// database from a journal that was left behind by an interrupted write.
+///
+/// For databases in WAL mode, pages with a committed frame in the `-wal` file are read from
+/// there instead of the database file.
+///
+/// Scans tend to read pages in order, as tables that were written in order or vacuumed have
+/// the leaves of a b-tree next to each other. When a page is read right after the one before
+/// it, the pager reads the pages after it too, with one read instead of one per page.
+///
+/// Pages can be read through a shared pager, so that queries can run on several threads at
+/// once. Pages are read with positioned reads, which don't move a shared file position, so
+/// reads of different threads don't wait for each other. Writes need the pager to themselves.
+///
+/// A database that isn't on the local file system, like one on a web server, is read through
+/// a [`RemoteFile`], and can't be written.
+///
+/// An in-memory pager keeps the pages and the journal in buffers instead of files, and its
+/// content is gone when it's dropped. It doesn't need a file system or a clock, so it also
+/// works on targets without them, like WebAssembly in a browser.
+#[derive(Debug)]
+pub struct Pager {
+    file: Storage,
+    page_size: u32,
+    writable: bool,
+    path: String,
+    journal: Option<Journal>,
+    wal: Option<Wal>,
+    read_ahead: u32,
+    ahead: Mutex<ReadAhead>,
+}
+
+/// The state of reading ahead, see [`Pager::set_read_ahead`].
+#[derive(Debug, Default)]
+struct ReadAhead {
+    /// The pages of the last read-ahead that haven't been changed since: the number of the
+    /// first one and their content.
+    pages: Option<(u32, Vec<u8>)>,
+    /// The number of the page that was read last, to notice pages being read in order.
+    last_read: Option<u32>,
+}
+
+/// The rollback journal of the open transaction.
+#[derive(Debug)]
+struct Journal {
+    file: Storage,
+    nonce: u32,
+    /// The number of pages in the database when the transaction started. Pages after them are
+    /// new and don't need to be journaled, rolling back truncates the file.
+    initial_size: u32,
+    pages: HashSet<u32>,
+}
+
+impl Pager {
+    /// Opens the file for reading and writing, or only for reading if it can't be written to.
+    /// A journal left behind by an interrupted transaction is rolled back.
+    pub fn open(path: &str) -> Result<Self> {
+        let (file, writable) = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => (file, true),
+            Err(_) => (File::open(path)?, false),
+        };
+
+        let mut pager = Self {
+            file: Storage::File(file),
+            page_size: 0,
+            writable,
+            path: path.to_string(),
+            journal: None,
+            wal: None,
+            read_ahead: DEFAULT_READ_AHEAD,
+            ahead: Mutex::default(),
+        };
+        pager.recover()?;
+        if let Ok(file) = File::open(pager.wal_path()) {
+            pager.wal = Wal::read(file)?;
+        }
+        Ok(pager)
+    }
+
+    /// Creates a new, empty file. Fails if the file exists.
+    pub fn create(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+
+        Ok(Self {
+            file: Storage::File(file),
+            page_size: 0,
+            writable: true,
+            path: path.to_string(),
+            journal: None,
+            wal: None,
+            read_ahead: DEFAULT_READ_AHEAD,
+            ahead: Mutex::default(),
+        })
+    }
+
+    /// Creates an empty database that only lives in memory.
+    pub fn memory() -> Self {
+        Self::from_bytes(vec![])
+    }
+
+    /// Opens the content of a database file that was read into memory, e.g. one that was
+    /// downloaded. Writes only change the copy in memory.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            file: Storage::Memory(Cursor::new(bytes)),
+            page_size: 0,
+            writable: true,
+            path: String::new(),
+            journal: None,
+            wal: None,
+            read_ahead: DEFAULT_READ_AHEAD,
+            ahead: Mutex::default(),
+        }
+    }
+
+    /// Opens a database file that isn't on the local file system for reading. There's no
+    /// journal or WAL next to it.
+    pub fn remote(path: &str, file: Box<dyn RemoteFile>) -> Self {
+        Self {
+            file: Storage::Remote(Mutex::new(file)),
+            page_size: 0,
+            writable: false,
+            path: path.to_string(),
+            journal: None,
+            wal: None,
+            read_ahead: DEFAULT_READ_AHEAD,
+            ahead: Mutex::default(),
+        }
+    }
+
+    pub fn is_memory(&self) -> bool {
+        matches!(self.file, Storage::Memory(_))
+    }
+
+    /// The path of the database file, which is empty for an in-memory database.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Flushes all writes to the disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn set_page_size(&mut self, page_size: u32) {
+        self.page_size = page_size;
+        self.forget_read_ahead();
+    }
+
+    /// Sets how many pages are read at once when pages are read in order. With 1 or 0, every
+    /// page is read by itself.
+    pub fn set_read_ahead(&mut self, pages: u32) {
+        self.read_ahead = pages;
+        self.forget_read_ahead();
+    }
+
+    /// Reads `len` bytes at the start of the file, for the database header.
+    pub fn read_header(&self, len: usize) -> Result<Vec<u8>> {
+        if let Some(wal) = &self.wal {
+            if let Some(page) = wal.read_page(0, 0)? {
+                return Ok(page[..len].to_vec());
+            }
+        }
+        let mut header = vec![0; len];
+        self.file.read_exact_at(&mut header, 0)?;
+        Ok(header)
+    }
+
+    /// The number of pages in the file.
+    pub fn page_count(&self) -> Result<u32> {
+        if let Some(wal) = &self.wal {
+            return Ok(wal.database_size());
+        }
+        Ok((self.file.len()? / self.page_size as u64) as u32)
+    }
+
+    /// Reads a page, skipping the first `offset` bytes. The first page starts with the 100 byte
+    /// database header, which isn't part of the b-tree page. The page is read straight into
+    /// shared memory, so that pages and the cells on them can be passed around without copies.
+    pub fn read_page(&self, number: u32, offset: u16) -> Result<Arc<[u8]>> {
+        if let Some(wal) = &self.wal {
+            if let Some(page) = wal.read_page(number, offset)? {
+                return Ok(page);
+            }
+        }
+        let mut ahead = lock(&self.ahead);
+        let in_order = number > 0 && ahead.last_read == Some(number - 1);
+        ahead.last_read = Some(number);
+        let page_size = self.page_size as usize;
+        if let Some((first, pages)) = &ahead.pages {
+            if let Some(index) = number.checked_sub(*first) {
+                let start = index as usize * page_size;
+                if let Some(page) = pages.get(start + offset as usize..start + page_size) {
+                    return Ok(Arc::from(page));
+                }
+            }
+        }
+
+        if in_order && self.read_ahead > 1 {
+            // Only pages that are in the file are read ahead, as reading past its end fails
+            let pages_in_file = (self.file.len()? / self.page_size as u64) as u32;
+            let count = self.read_ahead.min(pages_in_file.saturating_sub(number));
+            if count > 1 {
+                log::trace!("reading pages {} to {} at once", number + 1, number + count);
+                let mut pages = vec![0; count as usize * page_size];
+                self.file.read_exact_at(&mut pages, self.position(number))?;
+                let page = Arc::from(&pages[offset as usize..page_size]);
+                ahead.pages = Some((number, pages));
+                return Ok(page);
+            }
+        }
+        drop(ahead);
+
+        let mut page = zeroed(page_size - offset as usize);
+        match self.file.read_exact_at(
+            Arc::get_mut(&mut page).expect("page isn't shared yet"),
+            self.position(number) + offset as u64,
+        ) {
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => bail!(Error::Corrupt(
+                format!("page {} is past the end of the file", number + 1)
+            )),
+            result => result?,
+        }
+        Ok(page)
+    }
+
+    /// Writes a page read with [`Pager::read_page`] back to the file.
+    pub fn write_page(&mut self, number: u32, offset: u16, data: &[u8]) -> Result<()> {
+        self.write(self.position(number) + offset as u64, data)
+    }
+
+    /// Writes bytes at a position in the file, e.g. to update a field of the database header.
+    /// Inside a transaction the pages that are touched for the first time are journaled first.
+    pub fn write(&mut self, position: u64, bytes: &[u8]) -> Result<()> {
+        if !self.writable {
+            bail!("attempt to write a readonly database");
+        }
+        self.forget_read_ahead();
+        if self.journal.is_some() && !bytes.is_empty() {
+            let first = (position / self.page_size as u64) as u32;
+            let last = ((position + bytes.len() as u64 - 1) / self.page_size as u64) as u32;
+            self.journal_pages(first..=last)?;
+        }
+        self.file.seek(SeekFrom::Start(position))?;
+        self.file.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Starts a transaction by creating the rollback journal.
+    pub fn begin(&mut self) -> Result<()> {
+        if !self.writable {
+            bail!("attempt to write a readonly database");
+        }
+        if self.wal.is_some() {
+            bail!("writing to a database with a write-ahead log is not supported");
+        }
+        if self.journal.is_some() {
+            bail!("cannot start a transaction within a transaction");
+        }
+
+        // The nonce tells the records of this journal apart from stale ones of an older journal
+        // in the same file. In-memory journals always start empty, so they don't need one.
+        let time = clock::now();
+        let (mut file, nonce) = match self.file {
+            Storage::File(_) => (
+                Storage::File(
+                    OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(self.journal_path())?,
+                ),
+                time.subsec_nanos() ^ time.as_secs() as u32,
+            ),
+            Storage::Memory(_) => (Storage::Memory(Cursor::new(vec![])), 0),
+            Storage::Remote(_) => unreachable!("remote files are opened readonly"),
+        };
+        let initial_size = self.page_count()?;
+
+        let mut header = vec![0; SECTOR_SIZE as usize];
+        header[0..8].copy_from_slice(&JOURNAL_MAGIC);
+        // A record count of -1 tells SQLite to compute it from the size of the journal, since
+        // records are appended while the transaction runs.
+        header[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        header[12..16].copy_from_slice(&nonce.to_be_bytes());
+        header[16..20].copy_from_slice(&initial_size.to_be_bytes());
+        header[20..24].copy_from_slice(&SECTOR_SIZE.to_be_bytes());
+        header[24..28].copy_from_slice(&self.page_size.to_be_bytes());
+        file.write_all(&header)?;
+
+        self.journal = Some(Journal {
+            file,
+            nonce,
+            initial_size,
+            pages: HashSet::new(),
+        });
+        Ok(())
+    }
+
+    /// Makes the writes of the transaction durable and empties the journal, which ends the
+    /// transaction.
+    pub fn commit(&mut self) -> Result<()> {
+        let Some(mut journal) = self.journal.take() else {
+            bail!("cannot commit - no transaction is active");
+        };
+        self.file.sync_all()?;
+        journal.file.set_len(0)?;
+        journal.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Restores the pages changed by the transaction from the journal and ends the
+    /// transaction.
+    pub fn rollback(&mut self) -> Result<()> {
+        let Some(mut journal) = self.journal.take() else {
+            bail!("cannot rollback - no transaction is active");
+        };
+        self.play_back(&mut journal.file)?;
+        journal.file.set_len(0)?;
+        journal.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Copies the original content of pages that weren't journaled yet to the journal and
+    /// syncs it, so they can be restored after the database is written.
+    fn journal_pages(&mut self, pages: impl Iterator<Item = u32>) -> Result<()> {
+        let mut records = Vec::new();
+        for number in pages {
+            let journal = self.journal.as_mut().expect("transaction is active");
+            if number >= journal.initial_size || !journal.pages.insert(number) {
+                continue;
+            }
+            let nonce = journal.nonce;
+            let data = self.read_page(number, 0)?;
+            records.extend_from_slice(&(number + 1).to_be_bytes());
+            records.extend_from_slice(&data);
+            records.extend_from_slice(&checksum(nonce, &data).to_be_bytes());
+        }
+
+        if !records.is_empty() {
+            let journal = self.journal.as_mut().expect("transaction is active");
+            journal.file.seek(SeekFrom::End(0))?;
+            journal.file.write_all(&records)?;
+            journal.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Rolls back a journal left behind by a transaction that didn't finish.
+    fn recover(&mut self) -> Result<()> {
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .write(self.writable)
+            .open(self.journal_path())
+        {
+            Ok(file) => Storage::File(file),
+            Err(_) => return Ok(()),
+        };
+        let mut magic = [0; 8];
+        if file.read_exact(&mut magic).is_err() || magic != JOURNAL_MAGIC {
+            return Ok(());
+        }
+        if !self.writable {
+            bail!("cannot roll back the hot journal of a readonly database");
+        }
+
+        self.play_back(&mut file)?;
+        drop(file);
+        fs::remove_file(self.journal_path())?;
+        Ok(())
+    }
+
+    /// Writes the pages stored in a journal back to the database and truncates it to the size
+    /// it had when the journal was created. Records with a wrong checksum were not completely
+    /// written and end the playback.
+    fn play_back(&mut self, journal: &mut Storage) -> Result<()> {
+        self.forget_read_ahead();
+        let mut bytes = Vec::new();
+        journal.seek(SeekFrom::Start(0))?;
+        journal.read_to_end(&mut bytes)?;
+        if bytes.len() < 28 || bytes[0..8] != JOURNAL_MAGIC {
+            bail!("journal is malformed");
+        }
+        let field = |offset: usize| {
+            u32::from_be_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+        };
+        let (records, nonce, initial_size) = (field(8), field(12), field(16));
+        let (sector_size, page_size) = (field(20) as usize, field(24) as usize);
+        if page_size == 0 {
+            bail!("journal is malformed");
+        }
+
+        let record_size = page_size + 8;
+        let available = bytes.len().saturating_sub(sector_size) / record_size;
+        let records = if records == u32::MAX {
+            available
+        } else {
+            available.min(records as usize)
+        };
+        for record in bytes[sector_size.min(bytes.len())..]
+            .chunks_exact(record_size)
+            .take(records)
+        {
+            let number = u32::from_be_bytes(record[0..4].try_into().expect("4 bytes"));
+            let data = &record[4..page_size + 4];
+            let stored = u32::from_be_bytes(record[page_size + 4..].try_into().expect("4 bytes"));
+            if number == 0 || stored != checksum(nonce, data) {
+                break;
+            }
+            self.file
+                .seek(SeekFrom::Start((number as u64 - 1) * page_size as u64))?;
+            self.file.write_all(data)?;
+        }
+        self.file.set_len(initial_size as u64 * page_size as u64)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Copies the pages of the committed transactions in the WAL to the database file and
+    /// removes the WAL, so the database file is complete by itself. Returns the number of pages
+    /// that were copied.
+    pub fn checkpoint(&mut self) -> Result<usize> {
+        if !self.writable {
+            bail!("attempt to write a readonly database");
+        }
+        let Some(wal) = self.wal.take() else {
+            return Ok(0);
+        };
+
+        self.forget_read_ahead();
+        let pages = wal.pages().collect::<Vec<_>>();
+        let page_size = wal.page_size() as u64;
+        for &number in &pages {
+            let data = wal.read_page(number, 0)?.expect("page is in the WAL");
+            self.file.seek(SeekFrom::Start(number as u64 * page_size))?;
+            self.file.write_all(&data)?;
+        }
+        self.file.set_len(wal.database_size() as u64 * page_size)?;
+        self.file.sync_all()?;
+
+        // The WAL index in the shared memory file describes the removed WAL
+        drop(wal);
+        fs::remove_file(self.wal_path())?;
+        match fs::remove_file(format!("{}-shm", self.path)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error)?,
+            _ => {}
+        }
+        Ok(pages.len())
+    }
+
+    fn journal_path(&self) -> String {
+        format!("{}-journal", self.path)
+    }
+
+    fn wal_path(&self) -> String {
+        format!("{}-wal", self.path)
+    }
+
+    fn position(&self, number: u32) -> u64 {
+        number as u64 * self.page_size as u64
+    }
+
+    fn forget_read_ahead(&mut self) {
+        self.ahead
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pages = None;
+    }
+}
+
+/// A read-only database file somewhere else than on the local file system, like on a web server
+/// or in an object store.
+pub trait RemoteFile: std::fmt::Debug + Send + Sync {
+    /// The size of the file in bytes.
+    fn size(&self) -> u64;
+
+    /// Fills `buf` with the bytes at `position`, or fails with
+    /// [`ErrorKind::UnexpectedEof`] if the file ends before.
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// Where the pages of a database or a journal are kept.
+#[derive(Debug)]
+enum Storage {
+    File(File),
+    Memory(Cursor<Vec<u8>>),
+    /// Behind a lock, as reading a remote file changes its state, like a connection
+    Remote(Mutex<Box<dyn RemoteFile>>),
+}
+
+impl Storage {
+    /// Fills `buf` with the bytes at `position`. This doesn't use or change the position of
+    /// [`Read`] and [`Seek`], so it only needs a shared reference.
+    fn read_exact_at(&self, buf: &mut [u8], position: u64) -> io::Result<()> {
+        match self {
+            Storage::File(file) => read_exact_at(file, buf, position),
+            Storage::Memory(buffer) => {
+                let bytes = usize::try_from(position)
+                    .ok()
+                    .and_then(|start| buffer.get_ref().get(start..start.checked_add(buf.len())?))
+                    .ok_or(ErrorKind::UnexpectedEof)?;
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+            Storage::Remote(file) => lock(file).read_at(position, buf),
+        }
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        match self {
+            Storage::File(file) => Ok(file.metadata()?.len()),
+            Storage::Memory(buffer) => Ok(buffer.get_ref().len() as u64),
+            Storage::Remote(file) => Ok(lock(file).size()),
+        }
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        match self {
+            Storage::File(file) => file.set_len(len),
+            Storage::Memory(buffer) => {
+                buffer.get_mut().resize(len as usize, 0);
+                Ok(())
+            }
+            Storage::Remote(_) => Err(readonly()),
+        }
+    }
+
+    fn sync_all(&mut self) -> io::Result<()> {
+        match self {
+            Storage::File(file) => file.sync_all(),
+            Storage::Memory(_) | Storage::Remote(_) => Ok(()),
+        }
+    }
+
+    fn sync_data(&mut self) -> io::Result<()> {
+        match self {
+            Storage::File(file) => file.sync_data(),
+            Storage::Memory(_) | Storage::Remote(_) => Ok(()),
+        }
+    }
+}
+
+impl Read for Storage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Storage::File(file) => file.read(buf),
+            Storage::Memory(buffer) => buffer.read(buf),
+            Storage::Remote(_) => Err(positioned_only()),
+        }
+    }
+}
+
+impl Write for Storage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Storage::File(file) => file.write(buf),
+            Storage::Memory(buffer) => buffer.write(buf),
+            Storage::Remote(_) => Err(readonly()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Storage::File(file) => file.flush(),
+            Storage::Memory(buffer) => buffer.flush(),
+            Storage::Remote(_) => Ok(()),
+        }
+    }
+}
+
+impl Seek for Storage {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            Storage::File(file) => file.seek(position),
+            Storage::Memory(buffer) => buffer.seek(position),
+            Storage::Remote(_) => Err(positioned_only()),
+        }
+    }
+}
+
+/// A buffer of `len` zero bytes to read a page into, allocated once in its final place.
+pub(crate) fn zeroed(len: usize) -> Arc<[u8]> {
+    std::iter::repeat(0).take(len).collect()
+}
+
+/// Remote files are only read with [`Storage::read_exact_at`], they're never journals.
+fn positioned_only() -> io::Error {
+    io::Error::new(
+        ErrorKind::Unsupported,
+        "remote files only support positioned reads",
+    )
+}
+
+/// Reads `buf.len()` bytes at `position` without moving the position of the file, with `pread`
+/// on Unix and `ReadFile` with an offset on Windows.
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], position: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, position)
+    }
+    #[cfg(windows)]
+    {
+        let mut done = 0;
+        while done < buf.len() {
+            match std::os::windows::fs::FileExt::seek_read(
+                file,
+                &mut buf[done..],
+                position + done as u64,
+            )? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+    // Without positioned reads, concurrent reads of the same file could interleave their
+    // seeks, but such targets like WebAssembly don't have files to read anyway
+    #[cfg(not(any(unix, windows)))]
+    {
+        let mut file = file;
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(buf)
+    }
+}
+
+fn readonly() -> io::Error {
+    io::Error::new(
+        ErrorKind::PermissionDenied,
+        "attempt to write a readonly database",
+    )
+}
+
+/// Locks a part of the pager for a read. A read that panicked leaves nothing half done, as
+/// every read seeks to where it starts, so the lock can be used after that too.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The checksum of a journal record: the nonce plus every 200th byte of the page, counting
+/// back from the end.
+fn checksum(nonce: u32, data: &[u8]) -> u32 {
+    (1..=data.len() / 200)
+        .map(|i| data.len() - i * 200)
+        .filter(|&i| i > 0)
+        .fold(nonce, |sum, i| sum.wrapping_add(data[i] as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDatabase;
+
+    #[test]
+    fn read_ahead_pages_in_order() {
+        let database_file = TempDatabase::sample("read_ahead");
+        let path = database_file.path();
+        let content = std::fs::read(path).unwrap();
+        let mut pager = Pager::open(path).unwrap();
+        pager.set_page_size(4096);
+        let page = |number: usize| content[number * 4096..(number + 1) * 4096].to_vec();
+
+        // Reading the page after the last one reads the rest of the file, which has 4 pages
+        assert_eq!(pager.read_page(0, 0).unwrap()[..], page(0));
+        assert!(pager.ahead.lock().unwrap().pages.is_none());
+        assert_eq!(pager.read_page(1, 0).unwrap()[..], page(1));
+        assert_eq!(
+            pager
+                .ahead
+                .lock()
+                .unwrap()
+                .pages
+                .as_ref()
+                .map(|(first, _)| *first),
+            Some(1)
+        );
+        assert_eq!(pager.read_page(3, 100).unwrap()[..], page(3)[100..]);
+        assert_eq!(pager.read_page(2, 0).unwrap()[..], page(2));
+
+        // Writes aren't hidden by the pages that were read before
+        pager.write_page(2, 0, &[0; 4096]).unwrap();
+        assert!(pager.ahead.lock().unwrap().pages.is_none());
+        assert_eq!(pager.read_page(2, 0).unwrap()[..], vec![0; 4096]);
+
+        pager.set_read_ahead(1);
+        pager.read_page(3, 0).unwrap();
+        assert!(pager.ahead.lock().unwrap().pages.is_none());
+    }
+}
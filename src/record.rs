@@ -1,3 +1,9 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use crate::collation::Collation;
+use crate::error::Error;
+use crate::sql::Literal;
 use crate::varint;
 
 #[derive(Debug, Clone)]
@@ -16,9 +22,11 @@ enum ColumnType {
     Text(usize),
 }
 
-impl From<u64> for ColumnType {
-    fn from(value: u64) -> Self {
-        match value {
+impl TryFrom<u64> for ColumnType {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        Ok(match value {
             0 => Self::Null,
             1 => Self::I8,
             2 => Self::I16,
@@ -29,10 +37,10 @@ impl From<u64> for ColumnType {
             7 => Self::F64,
             8 => Self::Zero,
             9 => Self::One,
-            n if n > 12 && n % 2 == 0 => Self::Blob((n as usize - 12) / 2),
-            n if n > 13 && n % 2 == 1 => Self::Text((n as usize - 13) / 2),
-            _ => unreachable!(),
-        }
+            n if n >= 12 && n % 2 == 0 => Self::Blob((n as usize - 12) / 2),
+            n if n >= 13 && n % 2 == 1 => Self::Text((n as usize - 13) / 2),
+            n => return Err(Error::Corrupt(format!("reserved serial type {}", n))),
+        })
     }
 }
 
@@ -48,8 +56,10 @@ pub enum ColumnValue<'page> {
     F64(f64),
     Zero,
     One,
-    Blob(&'page [u8]),
-    Text(&'page [u8]),
+    /// Blobs and text are borrowed from the page they're stored on, or owned if they were
+    /// computed, like the result of a CAST
+    Blob(Cow<'page, [u8]>),
+    Text(Cow<'page, [u8]>),
 }
 
 impl<'page> ColumnValue<'page> {
@@ -69,24 +79,139 @@ impl<'page> ColumnValue<'page> {
     }
 }
 
-impl Into<i64> for ColumnValue<'_> {
-    fn into(self) -> i64 {
+impl<'page> ColumnValue<'page> {
+    /// Orders values like SQLite with the BINARY collation: NULL sorts first, followed by
+    /// numbers, text and blobs. Numbers compare by value regardless of their serial type.
+    pub fn compare(&self, other: &ColumnValue) -> Ordering {
+        match (self, other) {
+            (ColumnValue::Null, ColumnValue::Null) => Ordering::Equal,
+            (ColumnValue::Null, _) => Ordering::Less,
+            (_, ColumnValue::Null) => Ordering::Greater,
+            (ColumnValue::Text(a), ColumnValue::Text(b)) => a.cmp(b),
+            (ColumnValue::Blob(a), ColumnValue::Blob(b)) => a.cmp(b),
+            (ColumnValue::Blob(_), _) => Ordering::Greater,
+            (_, ColumnValue::Blob(_)) => Ordering::Less,
+            (ColumnValue::Text(_), _) => Ordering::Greater,
+            (_, ColumnValue::Text(_)) => Ordering::Less,
+            // -0.0 and 0.0 are the same number
+            (ColumnValue::F64(a), ColumnValue::F64(b)) if a == b => Ordering::Equal,
+            (ColumnValue::F64(a), ColumnValue::F64(b)) => a.total_cmp(b),
+            (ColumnValue::F64(a), b) => {
+                compare_integer_real(b.as_i64().unwrap_or_default(), *a).reverse()
+            }
+            (a, ColumnValue::F64(b)) => compare_integer_real(a.as_i64().unwrap_or_default(), *b),
+            (a, b) => a.as_i64().cmp(&b.as_i64()),
+        }
+    }
+
+    /// The name of the storage class, as returned by `typeof()`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ColumnValue::Null => "null",
+            ColumnValue::F64(_) => "real",
+            ColumnValue::Text(_) => "text",
+            ColumnValue::Blob(_) => "blob",
+            _ => "integer",
+        }
+    }
+
+    /// The value of an integer, regardless of its serial type.
+    pub fn as_i64(&self) -> Option<i64> {
         match self {
-            ColumnValue::Null => 0,
             ColumnValue::I8(n)
             | ColumnValue::I16(n)
             | ColumnValue::I24(n)
             | ColumnValue::I32(n)
             | ColumnValue::I48(n)
-            | ColumnValue::I64(n) => n,
-            ColumnValue::F64(n) => n as i64,
-            ColumnValue::Zero => 0,
-            ColumnValue::One => 1,
-            _ => panic!("Cannot convert to i64"),
+            | ColumnValue::I64(n) => Some(*n),
+            ColumnValue::Zero => Some(0),
+            ColumnValue::One => Some(1),
+            _ => None,
+        }
+    }
+
+    /// The value of a real, or of an integer converted to a real.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ColumnValue::F64(n) => Some(*n),
+            value => value.as_i64().map(|n| n as f64),
+        }
+    }
+
+    /// The value of a text, if it is valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ColumnValue::Text(text) => std::str::from_utf8(text).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_blob(&self) -> Option<&[u8]> {
+        match self {
+            ColumnValue::Blob(blob) => Some(blob),
+            _ => None,
+        }
+    }
+
+    fn invalid_type(&self, expected: &'static str) -> Error {
+        Error::InvalidType {
+            expected,
+            found: self.type_name(),
+        }
+    }
+}
+
+impl<'a> From<&'a Literal> for ColumnValue<'a> {
+    fn from(literal: &'a Literal) -> Self {
+        match literal {
+            Literal::Null => ColumnValue::Null,
+            Literal::Integer(n) => ColumnValue::I64(*n),
+            Literal::Real(n) => ColumnValue::F64(*n),
+            Literal::Text(text) => ColumnValue::Text(text.as_bytes().into()),
+            Literal::Blob(blob) => ColumnValue::Blob(blob.into()),
         }
     }
 }
 
+impl TryFrom<ColumnValue<'_>> for i64 {
+    type Error = Error;
+
+    fn try_from(value: ColumnValue<'_>) -> Result<Self, Self::Error> {
+        value.as_i64().ok_or_else(|| value.invalid_type("integer"))
+    }
+}
+
+impl TryFrom<ColumnValue<'_>> for f64 {
+    type Error = Error;
+
+    fn try_from(value: ColumnValue<'_>) -> Result<Self, Self::Error> {
+        value.as_f64().ok_or_else(|| value.invalid_type("real"))
+    }
+}
+
+/// Text that isn't valid UTF-8 is converted lossily, like the sqlite3 shell displays it.
+impl TryFrom<ColumnValue<'_>> for String {
+    type Error = Error;
+
+    fn try_from(value: ColumnValue<'_>) -> Result<Self, Self::Error> {
+        match value {
+            ColumnValue::Text(text) => Ok(String::from_utf8_lossy(&text).into_owned()),
+            value => Err(value.invalid_type("text")),
+        }
+    }
+}
+
+impl TryFrom<ColumnValue<'_>> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(value: ColumnValue<'_>) -> Result<Self, Self::Error> {
+        value
+            .as_blob()
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| value.invalid_type("blob"))
+    }
+}
+
 impl<'page> std::fmt::Display for ColumnValue<'page> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -97,75 +222,634 @@ impl<'page> std::fmt::Display for ColumnValue<'page> {
             | ColumnValue::I32(n)
             | ColumnValue::I48(n)
             | ColumnValue::I64(n) => write!(f, "{}", n),
-            ColumnValue::F64(n) => write!(f, "{}", n),
+            ColumnValue::F64(n) => write!(f, "{}", format_real(*n)),
             ColumnValue::Zero => write!(f, "0"),
             ColumnValue::One => write!(f, "1"),
-            ColumnValue::Blob(content) => write!(f, "<BLOB {} bytes>", content.len()),
+            // Hex, like blob literals
+            ColumnValue::Blob(content) => {
+                write!(f, "X'")?;
+                for byte in content.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "'")
+            }
             ColumnValue::Text(content) => write!(f, "{}", String::from_utf8_lossy(content)),
         }
     }
 }
 
+/// Formats a real like SQLite converts it to text, with `printf("%!.15g")`: 15 significant
+/// digits, in exponential notation for very large and small numbers, and always with a
+/// fractional part.
+pub fn format_real(real: f64) -> String {
+    format_real_digits(real, 15)
+}
+
+/// Formats a real like `printf("%!.{precision}g")` in SQLite.
+pub fn format_real_digits(real: f64, precision: usize) -> String {
+    if real.is_nan() {
+        return "NaN".to_string();
+    }
+    // -0.0 isn't less than 0, so it has no sign either
+    let sign = if real < 0.0 { "-" } else { "" };
+    if real.is_infinite() {
+        return format!("{sign}Inf");
+    }
+
+    // Rounding to the precision can carry into the exponent, so it has to be done first
+    let scientific = format!("{:.*e}", precision - 1, real.abs());
+    let (digits, exponent) = scientific.split_once('e').expect("exponent");
+    let exponent = exponent.parse::<i32>().expect("exponent");
+    let digits = digits.replace('.', "");
+
+    let with_fraction = |integer: &str, fraction: &str| {
+        let fraction = fraction.trim_end_matches('0');
+        let fraction = if fraction.is_empty() { "0" } else { fraction };
+        format!("{integer}.{fraction}")
+    };
+    if !(-4..precision as i32).contains(&exponent) {
+        let mantissa = with_fraction(&digits[..1], &digits[1..]);
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!("{sign}{mantissa}e{exponent_sign}{:02}", exponent.abs())
+    } else if exponent < 0 {
+        let zeros = "0".repeat(exponent.unsigned_abs() as usize - 1);
+        format!("{sign}{}", with_fraction("0", &format!("{zeros}{digits}")))
+    } else {
+        let (integer, fraction) = digits.split_at(exponent as usize + 1);
+        format!("{sign}{}", with_fraction(integer, fraction))
+    }
+}
+
+/// Compares an integer with a real exactly. Converting the integer to a real would round
+/// integers beyond 2^53, which then compare equal to their neighbours.
+fn compare_integer_real(integer: i64, real: f64) -> Ordering {
+    // i64::MIN and i64::MAX + 1 are exactly representable as reals
+    if real.is_nan() || real < i64::MIN as f64 {
+        return Ordering::Greater;
+    }
+    if real >= i64::MAX as f64 {
+        return Ordering::Less;
+    }
+    let truncated = real.trunc();
+    integer.cmp(&(truncated as i64)).then_with(|| {
+        if real > truncated {
+            Ordering::Less
+        } else if real < truncated {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    })
+}
+
+/// A value that doesn't borrow from the page it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    /// The name of the storage class, as returned by `typeof()`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Integer(_) => "integer",
+            Value::Real(_) => "real",
+            Value::Text(_) => "text",
+            Value::Blob(_) => "blob",
+        }
+    }
+
+    /// The serial type that stores the value in the least space.
+    fn serial_type(&self) -> u64 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            Value::Integer(n) => match integer_size(*n) {
+                1 => 1,
+                2 => 2,
+                3 => 3,
+                4 => 4,
+                6 => 5,
+                _ => 6,
+            },
+            Value::Real(_) => 7,
+            Value::Text(text) => text.len() as u64 * 2 + 13,
+            Value::Blob(blob) => blob.len() as u64 * 2 + 12,
+        }
+    }
+}
+
+/// The number of bytes needed to store an integer in big-endian two's complement, as one of the
+/// sizes SQLite supports.
+fn integer_size(n: i64) -> usize {
+    [1, 2, 3, 4, 6]
+        .into_iter()
+        .find(|&bytes| {
+            let bits = bytes * 8 - 1;
+            (-(1i64 << bits)..(1i64 << bits)).contains(&n)
+        })
+        .unwrap_or(8)
+}
+
+/// Encodes values in the record format: a header with the size of the header and the serial
+/// type of each value, followed by the values.
+pub fn encode_record(values: &[Value]) -> Vec<u8> {
+    let serial_types = values
+        .iter()
+        .flat_map(|value| varint::write(value.serial_type() as i64))
+        .collect::<Vec<_>>();
+    // The header size includes the varint of the size itself
+    let mut header_size = serial_types.len() + 1;
+    if varint::write(header_size as i64).len() > 1 {
+        header_size += varint::write(header_size as i64 + 1).len() - 1;
+    }
+
+    let mut record = varint::write(header_size as i64);
+    record.extend(serial_types);
+    for value in values {
+        match value {
+            Value::Null | Value::Integer(0) | Value::Integer(1) => {}
+            Value::Integer(n) => {
+                let size = integer_size(*n);
+                record.extend(&n.to_be_bytes()[8 - size..]);
+            }
+            Value::Real(n) => record.extend(n.to_be_bytes()),
+            Value::Text(text) => record.extend(text.as_bytes()),
+            Value::Blob(blob) => record.extend(blob),
+        }
+    }
+    record
+}
+
+impl From<Literal> for Value {
+    fn from(literal: Literal) -> Self {
+        match literal {
+            Literal::Null => Value::Null,
+            Literal::Integer(n) => Value::Integer(n),
+            Literal::Real(n) => Value::Real(n),
+            Literal::Text(text) => Value::Text(text),
+            Literal::Blob(blob) => Value::Blob(blob),
+        }
+    }
+}
+
+impl From<Value> for Literal {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Literal::Null,
+            Value::Integer(n) => Literal::Integer(n),
+            Value::Real(n) => Literal::Real(n),
+            Value::Text(text) => Literal::Text(text),
+            Value::Blob(blob) => Literal::Blob(blob),
+        }
+    }
+}
+
+impl<'a> From<&'a Value> for ColumnValue<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::Null => ColumnValue::Null,
+            Value::Integer(n) => ColumnValue::I64(*n),
+            Value::Real(n) => ColumnValue::F64(*n),
+            Value::Text(text) => ColumnValue::Text(text.as_bytes().into()),
+            Value::Blob(blob) => ColumnValue::Blob(blob.into()),
+        }
+    }
+}
+
+impl From<Value> for ColumnValue<'_> {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => ColumnValue::Null,
+            Value::Integer(n) => ColumnValue::I64(n),
+            Value::Real(n) => ColumnValue::F64(n),
+            Value::Text(text) => ColumnValue::Text(text.into_bytes().into()),
+            Value::Blob(blob) => ColumnValue::Blob(blob.into()),
+        }
+    }
+}
+
+/// Orders records value by value, like the entries of an ascending index.
+pub fn compare_records(a: &[ColumnValue], b: &[ColumnValue]) -> Ordering {
+    compare_keys(a, b, &[], &[])
+}
+
+/// Compares index entries like [`compare_records`], except that the values at the positions
+/// where `descending` is true sort in reverse, and text is ordered by the collation at its
+/// position in `collations`, if there is one.
+pub fn compare_keys(
+    a: &[ColumnValue],
+    b: &[ColumnValue],
+    descending: &[bool],
+    collations: &[Collation],
+) -> Ordering {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .map(|(position, (a, b))| {
+            let ordering = match collations.get(position) {
+                Some(collation) => collation.compare(a, b),
+                None => a.compare(b),
+            };
+            match descending.get(position) {
+                Some(true) => ordering.reverse(),
+                _ => ordering,
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+impl From<&ColumnValue<'_>> for Value {
+    fn from(value: &ColumnValue<'_>) -> Self {
+        match value {
+            ColumnValue::Null => Value::Null,
+            ColumnValue::F64(n) => Value::Real(*n),
+            ColumnValue::Text(text) => Value::Text(String::from_utf8_lossy(text).into_owned()),
+            ColumnValue::Blob(blob) => Value::Blob(blob.to_vec()),
+            value => Value::Integer(value.as_i64().unwrap_or_default()),
+        }
+    }
+}
+
+/// A record whose header has been parsed, but whose values are only decoded when they're
+/// asked for, so that reading a few columns of a wide row doesn't decode all of them.
 #[derive(Debug, Clone)]
 pub struct Record<'page> {
     pub rowid: i64,
-    pub values: Vec<ColumnValue<'page>>,
+    values: RecordValues<'page>,
 }
 
-macro_rules! read_n_bytes {
-    ($t:ident, $payload:expr, $cursor:expr, $n:expr) => {{
-        let mut bytes = [0; 8];
-        bytes[(8 - $n)..].copy_from_slice(&$payload[$cursor..$cursor + $n]);
-        $cursor += $n;
-        $t::from_be_bytes(bytes)
-    }};
+#[derive(Debug, Clone)]
+enum RecordValues<'page> {
+    Encoded {
+        payload: &'page [u8],
+        /// The type of each column and the position of its value in the payload.
+        columns: Vec<(ColumnType, usize)>,
+    },
+    /// The values of an [`OwnedRecord`], which have been decoded already
+    Decoded(&'page [Value]),
 }
 
-impl<'page> Record<'page> {
-    pub fn read(rowid: i64, payload: &'page [u8]) -> Self {
-        let mut cursor = 0;
-        let (header_size, offset) = varint::read(&payload[cursor..]);
-        cursor += offset;
+/// A record that doesn't borrow from the page it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedRecord {
+    pub rowid: i64,
+    pub values: Vec<Value>,
+}
 
-        let mut remaining_bytes = header_size as usize - offset;
+impl ColumnType {
+    /// The number of bytes the value takes up in the body of the record.
+    fn size(&self) -> usize {
+        match self {
+            ColumnType::Null | ColumnType::Zero | ColumnType::One => 0,
+            ColumnType::I8 => 1,
+            ColumnType::I16 => 2,
+            ColumnType::I24 => 3,
+            ColumnType::I32 => 4,
+            ColumnType::I48 => 6,
+            ColumnType::I64 | ColumnType::F64 => 8,
+            ColumnType::Blob(size) | ColumnType::Text(size) => *size,
+        }
+    }
 
-        // Most headers are 1 byte, but some are 2 bytes, rarely 3/4
-        let mut columns = Vec::with_capacity(remaining_bytes);
+    /// Decodes a value from exactly `self.size()` bytes.
+    fn decode<'page>(&self, bytes: &'page [u8]) -> ColumnValue<'page> {
+        // Integers are stored in big-endian two's complement, so the sign bit of an integer
+        // of n bytes has to be extended to the full 8 bytes
+        let integer = || {
+            let mut buffer = [0; 8];
+            buffer[8 - bytes.len()..].copy_from_slice(bytes);
+            let shift = 64 - 8 * bytes.len() as u32;
+            i64::from_be_bytes(buffer) << shift >> shift
+        };
+        match self {
+            ColumnType::Null => ColumnValue::Null,
+            ColumnType::I8 => ColumnValue::I8(integer()),
+            ColumnType::I16 => ColumnValue::I16(integer()),
+            ColumnType::I24 => ColumnValue::I24(integer()),
+            ColumnType::I32 => ColumnValue::I32(integer()),
+            ColumnType::I48 => ColumnValue::I48(integer()),
+            ColumnType::I64 => ColumnValue::I64(integer()),
+            ColumnType::F64 => {
+                ColumnValue::F64(f64::from_be_bytes(bytes.try_into().expect("8 bytes")))
+            }
+            ColumnType::Zero => ColumnValue::Zero,
+            ColumnType::One => ColumnValue::One,
+            ColumnType::Blob(_) => ColumnValue::Blob(bytes.into()),
+            ColumnType::Text(_) => ColumnValue::Text(bytes.into()),
+        }
+    }
+}
+
+impl<'page> Record<'page> {
+    /// Parses the header of a record. The values aren't decoded yet, but they're checked to
+    /// fit in the payload, so that decoding them later can't fail.
+    pub fn read(rowid: i64, payload: &'page [u8]) -> Result<Self, Error> {
+        let (header_size, mut cursor) = varint::read(payload);
+        let header_size = header_size as usize;
+        if cursor == 0 || header_size < cursor || header_size > payload.len() {
+            return Err(Error::Corrupt("invalid record header size".to_string()));
+        }
 
-        while remaining_bytes > 0 {
-            let (column, offset) = varint::read(&payload[cursor..]);
+        // Most headers are 1 byte, but some are 2 bytes, rarely 3/4
+        let mut columns = Vec::with_capacity(header_size - cursor);
+        let mut position = header_size;
+        while cursor < header_size {
+            let (column, offset) = varint::read(&payload[cursor..header_size]);
             cursor += offset;
-            remaining_bytes -= offset;
-            columns.push(ColumnType::from(column as u64));
-        }
-
-        let mut values = Vec::with_capacity(columns.len());
-        for column in columns.iter() {
-            let value = match column {
-                ColumnType::Null => ColumnValue::Null,
-                ColumnType::I8 => ColumnValue::I8(read_n_bytes!(i64, payload, cursor, 1)),
-                ColumnType::I16 => ColumnValue::I16(read_n_bytes!(i64, payload, cursor, 2)),
-                ColumnType::I24 => ColumnValue::I24(read_n_bytes!(i64, payload, cursor, 3)),
-                ColumnType::I32 => ColumnValue::I32(read_n_bytes!(i64, payload, cursor, 4)),
-                ColumnType::I48 => ColumnValue::I48(read_n_bytes!(i64, payload, cursor, 6)),
-                ColumnType::I64 => ColumnValue::I64(read_n_bytes!(i64, payload, cursor, 8)),
-                ColumnType::F64 => ColumnValue::F64(read_n_bytes!(f64, payload, cursor, 8)),
-                ColumnType::Zero => ColumnValue::Zero,
-                ColumnType::One => ColumnValue::One,
-                ColumnType::Blob(size) => {
-                    let value = ColumnValue::Blob(&payload[cursor..(cursor + *size)]);
-                    cursor += *size;
-                    value
-                }
-                ColumnType::Text(size) => {
-                    let value = ColumnValue::Text(&payload[cursor..(cursor + *size)]);
-                    cursor += *size;
-                    value
-                }
-            };
-            values.push(value);
+            let column = ColumnType::try_from(column as u64)?;
+            let size = column.size();
+            columns.push((column, position));
+            position = position
+                .checked_add(size)
+                .filter(|&end| end <= payload.len())
+                .ok_or_else(|| Error::Corrupt("record is truncated".to_string()))?;
+        }
+
+        Ok(Record {
+            rowid,
+            values: RecordValues::Encoded { payload, columns },
+        })
+    }
+
+    /// The number of values in the record.
+    pub fn len(&self) -> usize {
+        match &self.values {
+            RecordValues::Encoded { columns, .. } => columns.len(),
+            RecordValues::Decoded(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decodes the last value, which is the rowid in the entries of an index.
+    pub fn last(&self) -> Option<ColumnValue<'page>> {
+        self.len().checked_sub(1).map(|index| self.value(index))
+    }
+
+    /// Decodes all values of the record.
+    pub fn values(&self) -> Vec<ColumnValue<'page>> {
+        (0..self.len()).map(|index| self.value(index)).collect()
+    }
+
+    /// Copies the values out of the page, so that the record can outlive it.
+    pub fn to_owned_record(&self) -> OwnedRecord {
+        OwnedRecord {
+            rowid: self.rowid,
+            values: self.values().iter().map(Value::from).collect(),
         }
+    }
+
+    /// Decodes the value of the column at `index`. Records written before columns were added
+    /// with ALTER TABLE are shorter than the table, and the missing values are NULL here.
+    /// Queries use the default value of the column instead.
+    pub fn value(&self, index: usize) -> ColumnValue<'page> {
+        match &self.values {
+            RecordValues::Encoded { payload, columns } => {
+                let Some((column, position)) = columns.get(index) else {
+                    return ColumnValue::Null;
+                };
+                column.decode(&payload[*position..*position + column.size()])
+            }
+            RecordValues::Decoded(values) => values
+                .get(index)
+                .map_or(ColumnValue::Null, ColumnValue::from),
+        }
+    }
+}
+
+impl OwnedRecord {
+    /// The record as one that expressions can be evaluated on, without encoding its values.
+    pub fn as_record(&self) -> Record<'_> {
+        Record {
+            rowid: self.rowid,
+            values: RecordValues::Decoded(&self.values),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_record() {
+        // Header of 4 bytes: an 8-bit integer, the constant 1 and text of length 2
+        let payload = [4, 1, 9, 17, 42, b'h', b'i'];
+        let record = Record::read(7, &payload).unwrap();
+
+        assert_eq!(record.rowid, 7);
+        assert_eq!(record.value(0).as_i64(), Some(42));
+        assert_eq!(record.value(1).as_i64(), Some(1));
+        assert_eq!(record.value(2).to_string(), "hi");
+        assert!(matches!(record.value(3), ColumnValue::Null));
+    }
+
+    #[test]
+    fn read_values_in_any_order() {
+        let values = vec![
+            Value::Text("x".repeat(300)),
+            Value::Integer(-70_000),
+            Value::Blob(vec![7; 20]),
+            Value::Real(0.5),
+        ];
+        let payload = encode_record(&values);
+        let record = Record::read(3, &payload).unwrap();
+
+        assert_eq!(record.len(), 4);
+        assert_eq!(record.value(3).as_f64(), Some(0.5));
+        assert_eq!(record.value(1).as_i64(), Some(-70_000));
+        assert_eq!(record.last().unwrap().as_f64(), Some(0.5));
+        assert_eq!(record.value(2).as_blob(), Some(&[7; 20][..]));
+        assert_eq!(record.value(0).as_str().map(str::len), Some(300));
+    }
+
+    #[test]
+    fn read_owned_record() {
+        let owned = OwnedRecord {
+            rowid: 5,
+            values: vec![Value::Integer(42), Value::Text("hi".to_string())],
+        };
+        let record = owned.as_record();
+
+        assert_eq!(record.rowid, 5);
+        assert_eq!(record.len(), 2);
+        assert_eq!(record.value(0).as_i64(), Some(42));
+        assert_eq!(record.last().unwrap().as_str(), Some("hi"));
+        assert!(matches!(record.value(2), ColumnValue::Null));
+        assert_eq!(record.to_owned_record(), owned);
+    }
+
+    #[test]
+    fn read_empty_text_and_blob() {
+        let record = Record::read(1, &[3, 12, 13]).unwrap();
+
+        assert!(matches!(record.value(0), ColumnValue::Blob(blob) if blob.is_empty()));
+        assert!(matches!(record.value(1), ColumnValue::Text(text) if text.is_empty()));
+    }
+
+    #[test]
+    fn read_corrupt_record() {
+        // The text is longer than the payload
+        assert!(Record::read(1, &[2, 17, b'h']).is_err());
+        // The header is longer than the payload
+        assert!(Record::read(1, &[5, 1]).is_err());
+        // Serial types 10 and 11 are reserved
+        assert!(Record::read(1, &[2, 10]).is_err());
+        assert!(Record::read(1, &[]).is_err());
+    }
+
+    #[test]
+    fn encode_then_read_record() {
+        let values = vec![
+            Value::Null,
+            Value::Integer(0),
+            Value::Integer(1),
+            Value::Integer(-2),
+            Value::Integer(1000),
+            Value::Integer(100_000),
+            Value::Integer(1 << 40),
+            Value::Integer(i64::MIN),
+            Value::Real(2.5),
+            Value::Text("hello".to_string()),
+            Value::Blob(vec![1, 2, 3]),
+            Value::Text("x".repeat(100)),
+        ];
+        let payload = encode_record(&values);
+        let record = Record::read(1, &payload).unwrap();
+
+        assert_eq!(record.to_owned_record().values, values);
+        // The serial type of the long text takes two bytes
+        assert_eq!(payload[0], 14);
+
+        // A header of more than 127 bytes has a two byte size
+        let values = vec![Value::Null; 200];
+        let payload = encode_record(&values);
+        assert_eq!(varint::read(&payload), (202, 2));
+        assert_eq!(Record::read(1, &payload).unwrap().len(), 200);
+    }
+
+    #[test]
+    fn format_reals_like_sqlite() {
+        let cases = [
+            (3.0, "3.0"),
+            (-0.0, "0.0"),
+            (0.1, "0.1"),
+            (1.0 / 3.0, "0.333333333333333"),
+            (-2.0 / 3.0, "-0.666666666666667"),
+            (0.30000000000000004, "0.3"),
+            (12345.678, "12345.678"),
+            (1e14, "100000000000000.0"),
+            (1e15, "1.0e+15"),
+            (123456789012345678.0, "1.23456789012346e+17"),
+            (1e15 - 0.125, "1.0e+15"),
+            (1.5e300, "1.5e+300"),
+            (0.0001234, "0.0001234"),
+            (1e-5, "1.0e-05"),
+            (-2.5e-7, "-2.5e-07"),
+            (5e-324, "4.94065645841247e-324"),
+            (f64::MAX, "1.79769313486232e+308"),
+            (f64::NEG_INFINITY, "-Inf"),
+        ];
+        for (real, text) in cases {
+            assert_eq!(format_real(real), text, "{real:?}");
+        }
+    }
+
+    #[test]
+    fn compare_integers_with_reals() {
+        let compare = |a: ColumnValue, b: ColumnValue| a.compare(&b);
+        assert_eq!(
+            compare(ColumnValue::I8(2), ColumnValue::F64(2.0)),
+            Ordering::Equal
+        );
+        assert_eq!(
+            compare(ColumnValue::I8(2), ColumnValue::F64(2.5)),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare(ColumnValue::F64(-2.5), ColumnValue::I8(-2)),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare(ColumnValue::Zero, ColumnValue::F64(-0.0)),
+            Ordering::Equal
+        );
+        assert_eq!(
+            compare(ColumnValue::F64(0.0), ColumnValue::F64(-0.0)),
+            Ordering::Equal
+        );
+        // 2^53 + 1 rounds to 2^53 as a real
+        let big = (1i64 << 53) + 1;
+        assert_eq!(
+            compare(ColumnValue::I64(big), ColumnValue::F64((1i64 << 53) as f64)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare(ColumnValue::I64(i64::MAX), ColumnValue::F64(9.3e18)),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare(ColumnValue::I64(i64::MIN), ColumnValue::F64(-9.3e18)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare(
+                ColumnValue::I64(i64::MIN),
+                ColumnValue::F64(i64::MIN as f64)
+            ),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn typed_accessors() {
+        assert_eq!(ColumnValue::I24(-5).as_i64(), Some(-5));
+        assert_eq!(ColumnValue::One.as_f64(), Some(1.0));
+        assert_eq!(ColumnValue::F64(1.5).as_i64(), None);
+        assert_eq!(ColumnValue::Text(b"hi"[..].into()).as_str(), Some("hi"));
+        assert_eq!(ColumnValue::Text(b"\xff"[..].into()).as_str(), None);
+        assert_eq!(ColumnValue::Blob(b"hi"[..].into()).as_str(), None);
+        assert_eq!(
+            ColumnValue::Blob(b"hi"[..].into()).as_blob(),
+            Some(&b"hi"[..])
+        );
+    }
 
-        Record { values, rowid }
+    #[test]
+    fn try_from_conversions() {
+        assert_eq!(i64::try_from(ColumnValue::I8(3)), Ok(3));
+        assert_eq!(f64::try_from(ColumnValue::F64(0.5)), Ok(0.5));
+        assert_eq!(
+            String::try_from(ColumnValue::Text(b"hi"[..].into())),
+            Ok("hi".to_string())
+        );
+        assert_eq!(
+            Vec::try_from(ColumnValue::Blob(vec![1, 2].into())),
+            Ok(vec![1, 2])
+        );
+        assert_eq!(
+            i64::try_from(ColumnValue::Text(b"3"[..].into())),
+            Err(Error::InvalidType {
+                expected: "integer",
+                found: "text"
+            })
+        );
+        assert_eq!(
+            String::try_from(ColumnValue::Null),
+            Err(Error::InvalidType {
+                expected: "text",
+                found: "null"
+            })
+        );
     }
 }
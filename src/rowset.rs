@@ -0,0 +1,261 @@
+//! The rows of a subquery, which are collected in memory so that the outer query can read them
+//! like a table.
+
+use std::cmp::Ordering;
+
+use anyhow::{bail, Result};
+
+use crate::collation::Collations;
+use crate::database::Query;
+use crate::functions::Functions;
+use crate::output::OutputFormatter;
+use crate::record::{compare_records, encode_record, ColumnValue, OwnedRecord, Record, Value};
+use crate::sql::{Literal, SelectFields};
+use crate::sqlite_schema::{Column, Table};
+
+/// The name the columns of a subquery are qualified with in error messages.
+const SUBQUERY_TABLE_NAME: &str = "subquery";
+
+/// The results of a subquery as records of a table without a b-tree. The columns of the table
+/// have no type, so like in SQLite, the values of a subquery have no affinity.
+#[derive(Debug, Clone)]
+pub struct Rowset {
+    table: Table,
+    records: Vec<(i64, Vec<u8>)>,
+}
+
+impl Default for Rowset {
+    fn default() -> Self {
+        Self {
+            table: Table {
+                name: SUBQUERY_TABLE_NAME.to_string(),
+                columns: vec![],
+                primary_key: vec![],
+                indexes: vec![],
+                rootpage: 0,
+            },
+            records: vec![],
+        }
+    }
+}
+
+impl Rowset {
+    /// The rows of a table, which keep their rowids and the types of their columns.
+    pub fn from_table(
+        table: Table,
+        rows: impl Iterator<Item = Result<OwnedRecord>>,
+    ) -> Result<Self> {
+        let records = rows
+            .map(|row| {
+                let row = row?;
+                Ok((row.rowid, encode_record(&row.values)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { table, records })
+    }
+
+    /// Runs a query with the rowset as the table it reads from.
+    pub fn select(
+        &self,
+        sql_statement: &SelectFields,
+        functions: &Functions,
+        collations: &Collations,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<()> {
+        let query = Query::new(&self.table, sql_statement, functions, collations)?;
+        out.begin(&query.columns)?;
+        let mut row = Vec::with_capacity(query.results.len());
+        for (rowid, payload) in &self.records {
+            let record = Record::read(*rowid, payload)?;
+            if !query.matches(&record)? {
+                continue;
+            }
+            query.project(&record, &mut row)?;
+            out.row(&row)?;
+        }
+        out.finish()
+    }
+
+    /// Runs a query with the rowset as the table it reads from, like [`Rowset::select`], where
+    /// the query can be different for each row: `bind` returns it for the row. The results are
+    /// named like the ones of `names`.
+    pub fn select_each(
+        &self,
+        names: &SelectFields,
+        bind: &mut dyn FnMut(&Table, &Record) -> Result<SelectFields>,
+        functions: &Functions,
+        collations: &Collations,
+        out: &mut dyn OutputFormatter,
+    ) -> Result<()> {
+        out.begin(&Query::new(&self.table, names, functions, collations)?.columns)?;
+        for (rowid, payload) in &self.records {
+            let record = Record::read(*rowid, payload)?;
+            let sql_statement = bind(&self.table, &record)?;
+            let query = Query::new(&self.table, &sql_statement, functions, collations)?;
+            if !query.matches(&record)? {
+                continue;
+            }
+            let mut row = Vec::with_capacity(query.results.len());
+            query.project(&record, &mut row)?;
+            out.row(&row)?;
+        }
+        out.finish()
+    }
+
+    /// The rows, in the order they were added.
+    pub fn rows(&self) -> impl Iterator<Item = Result<Record<'_>>> {
+        self.records
+            .iter()
+            .map(|(rowid, payload)| Ok(Record::read(*rowid, payload)?))
+    }
+
+    /// The table the rows are records of, whose columns have no type.
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    /// The number of bytes of the records of the rows.
+    pub fn size(&self) -> usize {
+        self.records.iter().map(|(_, payload)| payload.len()).sum()
+    }
+
+    /// The number of columns of the rows.
+    pub fn width(&self) -> usize {
+        self.table.columns.len()
+    }
+
+    pub fn column_names(&self) -> Vec<String> {
+        self.table
+            .columns
+            .iter()
+            .map(|column| column.name.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Renames the columns to the ones a view declares, if it declares any.
+    pub fn rename_columns(&mut self, view: &str, names: &[String]) -> Result<()> {
+        if names.is_empty() {
+            return Ok(());
+        }
+        if names.len() != self.width() {
+            bail!(
+                "expected {} columns for '{}' but got {}",
+                names.len(),
+                view,
+                self.width()
+            );
+        }
+        for (column, name) in self.table.columns.iter_mut().zip(names) {
+            column.name = name.clone();
+        }
+        Ok(())
+    }
+
+    /// Adds the rows of `other` after the rows of this rowset, like UNION ALL.
+    pub fn append(&mut self, other: Rowset) {
+        for (_, payload) in other.records {
+            let rowid = self.records.len() as i64 + 1;
+            self.records.push((rowid, payload));
+        }
+    }
+
+    /// Sorts the rows and removes duplicates, like UNION. SQLite finds the duplicates with a
+    /// temporary index, so its rows come out sorted too.
+    pub fn dedup(&mut self) -> Result<()> {
+        let records = std::mem::take(&mut self.records);
+        let mut rows = records
+            .iter()
+            .map(|(rowid, payload)| Ok(Record::read(*rowid, payload)?.values()))
+            .collect::<Result<Vec<_>>>()?;
+        rows.sort_by(|a, b| compare_records(a, b));
+        rows.dedup_by(|a, b| compare_records(a, b) == Ordering::Equal);
+        for row in rows {
+            let values = row.iter().map(Value::from).collect::<Vec<_>>();
+            let rowid = self.records.len() as i64 + 1;
+            self.records.push((rowid, encode_record(&values)));
+        }
+        Ok(())
+    }
+
+    /// Removes duplicate rows and the rows that are rows of `seen` too, which leaves the rows a
+    /// UNION with `seen` would add.
+    pub fn remove_seen(&mut self, seen: &Rowset) -> Result<()> {
+        self.dedup()?;
+        let mut seen_rows = seen
+            .records
+            .iter()
+            .map(|(rowid, payload)| Ok(Record::read(*rowid, payload)?.values()))
+            .collect::<Result<Vec<_>>>()?;
+        seen_rows.sort_by(|a, b| compare_records(a, b));
+        for (rowid, payload) in std::mem::take(&mut self.records) {
+            let values = Record::read(rowid, &payload)?.values();
+            let is_seen = seen_rows
+                .binary_search_by(|row| compare_records(row, &values))
+                .is_ok();
+            if !is_seen {
+                let rowid = self.records.len() as i64 + 1;
+                self.records.push((rowid, payload));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes all rows to `out`.
+    pub fn write(&self, out: &mut dyn OutputFormatter) -> Result<()> {
+        let columns = self
+            .table
+            .columns
+            .iter()
+            .map(|column| column.name.clone())
+            .collect::<Vec<_>>();
+        out.begin(&columns)?;
+        for (rowid, payload) in &self.records {
+            out.row(&Record::read(*rowid, payload)?.values())?;
+        }
+        out.finish()
+    }
+
+    /// The values of a subquery that is used as an expression, which has to return a single
+    /// column.
+    pub fn values(&self) -> Result<Vec<Literal>> {
+        if self.table.columns.len() != 1 {
+            bail!(
+                "sub-select returns {} columns - expected 1",
+                self.table.columns.len()
+            );
+        }
+        self.records
+            .iter()
+            .map(|(rowid, payload)| {
+                let record = Record::read(*rowid, payload)?;
+                Ok(Literal::from(Value::from(&record.value(0))))
+            })
+            .collect()
+    }
+}
+
+impl OutputFormatter for Rowset {
+    fn begin(&mut self, columns: &[String]) -> Result<()> {
+        self.table.columns = columns.iter().map(|name| Column::new(name)).collect();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        let values = values.iter().map(Value::from).collect::<Vec<_>>();
+        let rowid = self.records.len() as i64 + 1;
+        self.records.push((rowid, encode_record(&values)));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -1,404 +1,3574 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, tag_no_case, take_until, take_while1},
+    bytes::complete::{is_not, tag, tag_no_case, take_while, take_while1},
     character::{
         complete::{multispace0, multispace1},
-        is_alphanumeric, is_space,
+        is_alphanumeric,
     },
-    combinator::{map, opt},
-    multi::{many0, many1},
-    sequence::{delimited, terminated, tuple},
-    IResult,
+    combinator::{consumed, cut, map, map_opt, not, opt, recognize, verify},
+    error::{ErrorKind, ParseError},
+    multi::{many0, many1, separated_list0, separated_list1},
+    sequence::{delimited, preceded, separated_pair, terminated, tuple},
+    InputLength,
 };
 
-#[derive(Debug, PartialEq)]
-pub enum SelectStatement {
-    Fields(SelectFields),
-    Count(String),
-}
+use crate::error::Error;
 
-#[derive(Debug, PartialEq)]
-pub struct WhereClause {
-    pub field: String,
-    pub value: String,
-}
+type IResult<I, O> = nom::IResult<I, O, Failure<I>>;
 
-#[derive(Debug, PartialEq)]
-pub struct SelectFields {
-    pub fields: Vec<String>,
-    pub table: String,
-    pub where_clause: Option<WhereClause>,
+/// Why a parser failed: where, and the keywords that could have come there. When all
+/// alternatives fail, the failure that got furthest is kept, since that's usually where the
+/// mistake is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure<I> {
+    input: I,
+    expected: Vec<&'static str>,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum ColumnConstraint {
-    PrimaryKey,
+impl<I: InputLength> ParseError<I> for Failure<I> {
+    fn from_error_kind(input: I, _kind: ErrorKind) -> Self {
+        Self {
+            input,
+            expected: vec![],
+        }
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        match self.input.input_len().cmp(&other.input.input_len()) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => {
+                for expected in other.expected {
+                    if !self.expected.contains(&expected) {
+                        self.expected.push(expected);
+                    }
+                }
+                self
+            }
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Field {
-    pub name: String,
-    pub is_primary_key: bool,
+/// A statement that can't be parsed, like `SELECT a FORM t`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    /// The byte offset in the statement where parsing failed
+    pub offset: usize,
+    /// The token at the offset, or `None` at the end of the statement
+    pub near: Option<String>,
+    /// The keywords that could have come at the offset
+    pub expected: Vec<&'static str>,
 }
 
-impl Field {
-    pub fn new(name: String) -> Self {
+impl SyntaxError {
+    fn new(statement: &[u8], rest: &[u8], expected: Vec<&'static str>) -> Self {
+        let start = rest
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let rest = &rest[start..];
+        let token = match rest.first() {
+            None => None,
+            Some(&quote @ (b'\'' | b'"' | b'`')) => Some(
+                rest[1..]
+                    .iter()
+                    .position(|&c| c == quote)
+                    .map_or(rest, |end| &rest[..end + 2]),
+            ),
+            Some(&c) if is_sql_identifier(c) => {
+                Some(&rest[..rest.iter().take_while(|&&c| is_sql_identifier(c)).count()])
+            }
+            Some(_) => Some(&rest[..1]),
+        };
         Self {
-            name,
-            is_primary_key: false,
+            offset: statement.len() - rest.len(),
+            near: token.map(|token| String::from_utf8_lossy(token).into_owned()),
+            expected,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct CreateTableStatement {
-    pub table: String,
-    pub fields: Vec<Field>,
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.near {
+            Some(token) => write!(f, "near '{}' at offset {}", token, self.offset)?,
+            None => write!(f, "at the end of the statement")?,
+        }
+        let expected = self
+            .expected
+            .iter()
+            .map(|keyword| keyword.to_ascii_uppercase())
+            .collect::<Vec<_>>();
+        if let Some((last, others)) = expected.split_last() {
+            write!(f, ", expected ")?;
+            if !others.is_empty() {
+                write!(f, "{} or ", others.join(", "))?;
+            }
+            write!(f, "{}", last)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct CreateIndexStatement {
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectStatement {
+    Fields(SelectFields),
+    /// `SELECT count(*) FROM table`, optionally counting only the rows that match a WHERE
+    Count {
+        schema: Option<String>,
+        table: String,
+        where_clause: Option<Expr>,
+    },
+    /// Two SELECTs whose rows are combined. Chains like `a UNION b UNION ALL c` combine from
+    /// left to right.
+    Compound {
+        op: CompoundOperator,
+        left: Box<SelectStatement>,
+        right: Box<SelectStatement>,
+    },
+    /// `WITH name AS (SELECT ...), ... SELECT ...`, a query that can read the results of the
+    /// common table expressions like tables
+    With {
+        /// `WITH RECURSIVE`, where a common table expression can read itself
+        recursive: bool,
+        ctes: Vec<CommonTableExpression>,
+        select: Box<SelectStatement>,
+    },
+}
+
+/// A query of a WITH clause, `name [(columns)] AS (SELECT ...)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonTableExpression {
     pub name: String,
-    pub table: String,
-    pub fields: Vec<String>,
+    /// The names of the columns, or empty to use the names of the SELECT's results
+    pub columns: Vec<String>,
+    pub select: SelectStatement,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum SQLCommand {
-    Select(SelectStatement),
-    CreateTable(CreateTableStatement),
-    CreateIndex(CreateIndexStatement),
+impl CommonTableExpression {
+    /// The parts of a recursive common table expression, `initial UNION [ALL] step`, where only
+    /// the step reads the expression itself. `None` if it doesn't read itself at all.
+    pub fn recursion(
+        &self,
+    ) -> Result<Option<(CompoundOperator, &SelectStatement, &SelectStatement)>, Error> {
+        if !self.select.reads_table(&self.name) {
+            return Ok(None);
+        }
+        match &self.select {
+            SelectStatement::Compound { op, left, right } if !left.reads_table(&self.name) => {
+                Ok(Some((*op, left, right)))
+            }
+            _ => Err(Error::CircularReference(self.name.clone())),
+        }
+    }
 }
 
-pub fn parse(input: &[u8]) -> IResult<&[u8], SQLCommand> {
-    alt((
-        map(parse_creation, |c| SQLCommand::CreateTable(c)),
-        map(selection, |s| SQLCommand::Select(s)),
-        map(count_selection, |s| SQLCommand::Select(s)),
-        map(parse_index_creation, |c| SQLCommand::CreateIndex(c)),
-    ))(input)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompoundOperator {
+    /// `UNION`, which drops duplicate rows
+    Union,
+    /// `UNION ALL`, which keeps all rows
+    UnionAll,
 }
 
-fn count_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
-    let (remaining_input, (_, _, _, _, _, _, table, _)) = tuple((
-        tag_no_case("select"),
-        multispace1,
-        tag_no_case("count(*)"),
-        multispace1,
-        tag_no_case("from"),
-        multispace1,
-        identifier,
-        opt(tag(";")),
-    ))(input)?;
+impl std::fmt::Display for CompoundOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompoundOperator::Union => write!(f, "UNION"),
+            CompoundOperator::UnionAll => write!(f, "UNION ALL"),
+        }
+    }
+}
+
+impl SelectStatement {
+    /// Copies the query, replacing each parameter with the result of `f`, in the order they
+    /// appear in the statement.
+    pub fn map_parameters(&self, f: &mut impl FnMut(&Parameter) -> Expr) -> SelectStatement {
+        match self {
+            SelectStatement::Fields(select) => SelectStatement::Fields(select.map_parameters(f)),
+            SelectStatement::Compound { op, left, right } => {
+                let left = left.map_parameters(f);
+                SelectStatement::Compound {
+                    op: *op,
+                    left: Box::new(left),
+                    right: Box::new(right.map_parameters(f)),
+                }
+            }
+            SelectStatement::Count {
+                schema,
+                table,
+                where_clause,
+            } => SelectStatement::Count {
+                schema: schema.clone(),
+                table: table.clone(),
+                where_clause: where_clause.as_ref().map(|expr| expr.map_parameters(f)),
+            },
+            SelectStatement::With {
+                recursive,
+                ctes,
+                select,
+            } => {
+                let ctes = ctes
+                    .iter()
+                    .map(|cte| CommonTableExpression {
+                        name: cte.name.clone(),
+                        columns: cte.columns.clone(),
+                        select: cte.select.map_parameters(f),
+                    })
+                    .collect();
+                SelectStatement::With {
+                    recursive: *recursive,
+                    ctes,
+                    select: Box::new(select.map_parameters(f)),
+                }
+            }
+        }
+    }
 
-    Ok((remaining_input, SelectStatement::Count(table)))
+    /// Whether the query or one of its subqueries reads the table called `name`, without a
+    /// schema.
+    pub fn reads_table(&self, name: &str) -> bool {
+        let is_named = |schema: &Option<String>, table: &str| {
+            schema.is_none() && table.eq_ignore_ascii_case(name)
+        };
+        match self {
+            SelectStatement::Fields(select) => {
+                select.from.reads_table(name)
+                    || select.fields.iter().any(|field| match field {
+                        ResultColumn::All => false,
+                        ResultColumn::Expr { expr, .. } => expr.reads_table(name),
+                    })
+                    || select
+                        .where_clause
+                        .as_ref()
+                        .map_or(false, |expr| expr.reads_table(name))
+            }
+            SelectStatement::Count {
+                schema,
+                table,
+                where_clause,
+            } => {
+                is_named(schema, table)
+                    || where_clause
+                        .as_ref()
+                        .map_or(false, |expr| expr.reads_table(name))
+            }
+            SelectStatement::Compound { left, right, .. } => {
+                left.reads_table(name) || right.reads_table(name)
+            }
+            SelectStatement::With { ctes, select, .. } => {
+                for cte in ctes {
+                    if cte.select.reads_table(name) {
+                        return true;
+                    }
+                    // Later queries read the common table expression instead
+                    if cte.name.eq_ignore_ascii_case(name) {
+                        return false;
+                    }
+                }
+                select.reads_table(name)
+            }
+        }
+    }
 }
 
-fn selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
-    let (remaining_input, (_, _, fields, _, _, _, table, where_clause, _)) = tuple((
-        tag_no_case("select"),
-        multispace1,
-        identifiers,
-        multispace0,
-        tag_no_case("from"),
-        multispace1,
-        identifier,
-        parse_where_clause,
-        opt(tag(";")),
-    ))(input)?;
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
 
-    Ok((
-        remaining_input,
-        SelectStatement::Fields(SelectFields {
-            table,
-            fields,
-            where_clause,
-        }),
-    ))
+impl From<i64> for Literal {
+    fn from(value: i64) -> Self {
+        Literal::Integer(value)
+    }
 }
 
-fn identifiers(input: &[u8]) -> IResult<&[u8], Vec<String>> {
-    many1(terminated(
-        identifier,
-        opt(delimited(multispace0, tag(","), multispace0)),
-    ))(input)
+impl From<i32> for Literal {
+    fn from(value: i32) -> Self {
+        Literal::Integer(value.into())
+    }
 }
 
-fn parse_where_clause(input: &[u8]) -> IResult<&[u8], Option<WhereClause>> {
-    let (remaining_input, maybe_where) = opt(tuple((
-        multispace0,
-        tag_no_case("where"),
-        multispace0,
-        identifier,
-        multispace0,
-        tag("="),
-        multispace0,
-        tag("'"),
-        take_until("'"),
-    )))(input)?;
+impl From<f64> for Literal {
+    fn from(value: f64) -> Self {
+        Literal::Real(value)
+    }
+}
 
-    let maybe_where = if let Some((_, _, _, field, _, _, _, _, value)) = maybe_where {
-        let value = String::from_utf8(value.to_vec()).unwrap();
-        Some(WhereClause { field, value })
-    } else {
-        None
-    };
+impl From<&str> for Literal {
+    fn from(value: &str) -> Self {
+        Literal::Text(value.to_string())
+    }
+}
 
-    Ok((remaining_input, maybe_where))
+impl From<String> for Literal {
+    fn from(value: String) -> Self {
+        Literal::Text(value)
+    }
 }
 
-pub fn parse_create(input: &[u8]) -> IResult<&[u8], SQLCommand> {
-    alt((
-        map(parse_creation, |c| SQLCommand::CreateTable(c)),
-        map(parse_index_creation, |c| SQLCommand::CreateIndex(c)),
-    ))(input)
+impl<T: Into<Literal>> From<Option<T>> for Literal {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Literal::Null, Into::into)
+    }
 }
 
-pub fn parse_creation(input: &[u8]) -> IResult<&[u8], CreateTableStatement> {
-    let (remaining_input, (_, _, _, _, _, table, _, _, _, fields, _, _, _)) = tuple((
-        tag_no_case("create"),
-        multispace1,
-        tag_no_case("table"),
-        multispace1,
-        opt(tuple((tag_no_case("IF NOT EXISTS"), multispace1))),
-        identifier,
-        multispace0,
-        tag("("),
-        multispace0,
-        field_specification_list,
-        multispace0,
-        tag(")"),
-        opt(tag(";")),
-    ))(input)?;
+/// A placeholder for a value that is bound when a prepared statement is run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Parameter {
+    /// `?`, numbered one higher than the largest number used before it
+    Anonymous,
+    /// `?NNN`
+    Numbered(usize),
+    /// `:name`, `@name` or `$name`, numbered on first use. The name includes the prefix.
+    Named(String),
+}
 
-    Ok((remaining_input, CreateTableStatement { table, fields }))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    /// `text MATCH 'query'`, a full-text search
+    Match,
 }
 
-pub fn parse_index_creation(input: &[u8]) -> IResult<&[u8], CreateIndexStatement> {
-    let (remaining_input, (_, _, _, _, _, name, _, _, _, table, _, _, _, columns, _, _, _)) =
-        tuple((
-            tag_no_case("create"),
-            multispace1,
-            tag_no_case("index"),
-            multispace1,
-            opt(tuple((tag_no_case("IF NOT EXISTS"), multispace1))),
-            identifier, // index name
-            multispace1,
-            tag_no_case("ON"),
-            multispace1,
-            identifier, // table
-            multispace0,
-            tag("("),
-            multispace0,
-            many1(identifier), // columns
-            multispace0,
-            tag(")"),
-            opt(tag(";")),
-        ))(input)?;
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Column(String),
+    /// `table.column`, where the table is the name or alias of a table the statement reads
+    QualifiedColumn {
+        table: String,
+        column: String,
+    },
+    Parameter(Parameter),
+    Binary {
+        op: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Not(Box<Expr>),
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    /// `expr [NOT] IN (SELECT ...)`
+    InSubquery {
+        expr: Box<Expr>,
+        subquery: Box<SelectStatement>,
+        negated: bool,
+    },
+    /// `(SELECT ...)`, the first value of the first row of the subquery or NULL without rows
+    Subquery(Box<SelectStatement>),
+    /// `EXISTS (SELECT ...)`, whether the subquery returns any rows
+    Exists(Box<SelectStatement>),
+    /// `CAST(expr AS type)`, where the type is a declared type like the ones of columns
+    Cast {
+        expr: Box<Expr>,
+        ty: String,
+    },
+    /// `name(args...)`, a call of a scalar function like `date()`
+    Function {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// `(expr, expr, ...)`, a row value, which can only be compared with another row value of
+    /// the same size
+    Row(Vec<Expr>),
+    /// `expr [NOT] IN (expr, ...)`
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    /// The rows of an IN subquery, which the connection runs before the query it's in
+    InValues {
+        expr: Box<Expr>,
+        values: Vec<Literal>,
+        negated: bool,
+    },
+    /// `name(args) OVER (...)`, a function of the rows in the window of each result row.
+    /// `COUNT(*)` has no arguments.
+    Window {
+        name: String,
+        args: Vec<Expr>,
+        window: Window,
+    },
+}
 
-    Ok((
-        remaining_input,
-        CreateIndexStatement {
-            name,
-            table,
-            fields: columns,
-        },
-    ))
+/// `OVER (PARTITION BY ... ORDER BY ...)`: the rows with the same PARTITION BY values, in
+/// the ORDER BY order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Window {
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderingTerm>,
 }
 
-fn identifier(input: &[u8]) -> IResult<&[u8], String> {
-    let (input, name) = alt((
-        delimited(
-            tag("\""),
-            take_while1(is_sql_identifier_with_space),
-            tag("\""),
-        ),
-        take_while1(is_sql_identifier),
-    ))(input)?;
-    let name = String::from_utf8(name.to_vec()).unwrap();
+/// `expr [ASC | DESC]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+}
 
-    Ok((input, name))
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut clauses = vec![];
+        if !self.partition_by.is_empty() {
+            clauses.push(format!("PARTITION BY {}", join(&self.partition_by)));
+        }
+        if !self.order_by.is_empty() {
+            clauses.push(format!("ORDER BY {}", join(&self.order_by)));
+        }
+        write!(f, "{}", clauses.join(" "))
+    }
 }
 
-fn is_sql_identifier_with_space(chr: u8) -> bool {
-    is_alphanumeric(chr) || chr == b'_' || is_space(chr)
+impl std::fmt::Display for OrderingTerm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.descending {
+            true => write!(f, "{} DESC", self.expr),
+            false => write!(f, "{}", self.expr),
+        }
+    }
 }
 
-fn is_sql_identifier(chr: u8) -> bool {
-    is_alphanumeric(chr) || chr == b'_'
+impl std::fmt::Display for Literal {
+    /// Writes the value as an SQL literal.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Null => write!(f, "NULL"),
+            Literal::Integer(n) => write!(f, "{n}"),
+            Literal::Real(n) => write!(f, "{n:?}"),
+            Literal::Text(text) => write!(f, "'{}'", text.replace('\'', "''")),
+            Literal::Blob(blob) => {
+                write!(f, "X'")?;
+                for byte in blob {
+                    write!(f, "{byte:02X}")?;
+                }
+                write!(f, "'")
+            }
+        }
+    }
 }
 
-fn field_specification_list(input: &[u8]) -> IResult<&[u8], Vec<Field>> {
-    many1(field_specification)(input)
+impl std::fmt::Display for Parameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Parameter::Anonymous => write!(f, "?"),
+            Parameter::Numbered(number) => write!(f, "?{number}"),
+            Parameter::Named(name) => write!(f, "{name}"),
+        }
+    }
 }
 
-fn column_constraint(input: &[u8]) -> IResult<&[u8], Option<ColumnConstraint>> {
-    let not_null = map(
-        delimited(multispace0, tag_no_case("NOT NULL"), multispace0),
-        |_| None,
-    );
-    let auto_increment = map(
-        delimited(multispace0, tag_no_case("AUTOINCREMENT"), multispace0),
-        |_| None,
-    );
-    let primary_key = map(
-        delimited(multispace0, tag_no_case("PRIMARY KEY"), multispace0),
-        |_| Some(ColumnConstraint::PrimaryKey),
-    );
+impl std::fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BinaryOperator::Eq => "=",
+            BinaryOperator::NotEq => "!=",
+            BinaryOperator::Lt => "<",
+            BinaryOperator::LtEq => "<=",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::GtEq => ">=",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Or => "OR",
+            BinaryOperator::Match => "MATCH",
+        })
+    }
+}
 
-    alt((not_null, auto_increment, primary_key))(input)
+impl BinaryOperator {
+    /// How tightly the operator binds: OR binds loosest, comparisons tightest.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOperator::Or => 0,
+            BinaryOperator::And => 1,
+            _ => 2,
+        }
+    }
 }
 
-fn field_specification(input: &[u8]) -> IResult<&[u8], Field> {
-    let (remaining_input, (column, ty, constraints, _)) = tuple((
-        identifier,
-        opt(delimited(multispace0, identifier, multispace0)), // type
-        many0(column_constraint),
-        opt(delimited(multispace0, tag(","), multispace0)),
-    ))(input)?;
+impl std::fmt::Display for Expr {
+    /// Writes the expression as SQL, with parentheses only where precedence requires them.
+    /// Subqueries are abbreviated to `(SELECT ...)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let not = |negated: bool| if negated { "NOT " } else { "" };
+        match self {
+            Expr::Literal(value) => write!(f, "{value}"),
+            Expr::Column(name) => write!(f, "{name}"),
+            Expr::QualifiedColumn { table, column } => write!(f, "{table}.{column}"),
+            Expr::Parameter(parameter) => write!(f, "{parameter}"),
+            Expr::Binary { op, left, right } => {
+                let operand = |expr: &Expr, right_side: bool| match expr {
+                    Expr::Binary { op: inner, .. }
+                        if inner.precedence() < op.precedence()
+                            || (right_side && inner.precedence() == op.precedence()) =>
+                    {
+                        format!("({expr})")
+                    }
+                    expr => expr.to_string(),
+                };
+                write!(f, "{} {op} {}", operand(left, false), operand(right, true))
+            }
+            Expr::Not(expr) if matches!(expr.as_ref(), Expr::Binary { .. }) => {
+                write!(f, "NOT ({expr})")
+            }
+            Expr::Not(expr) => write!(f, "NOT {expr}"),
+            Expr::IsNull { expr, negated } => write!(f, "{expr} IS {}NULL", not(*negated)),
+            Expr::InSubquery { expr, negated, .. } => {
+                write!(f, "{expr} {}IN (SELECT ...)", not(*negated))
+            }
+            Expr::Subquery(_) => write!(f, "(SELECT ...)"),
+            Expr::Exists(_) => write!(f, "EXISTS (SELECT ...)"),
+            Expr::Cast { expr, ty } => write!(f, "CAST({expr} AS {ty})"),
+            Expr::Function { name, args } => write!(f, "{name}({})", join(args)),
+            Expr::Row(exprs) => write!(f, "({})", join(exprs)),
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => write!(f, "{expr} {}IN ({})", not(*negated), join(list)),
+            Expr::InValues {
+                expr,
+                values,
+                negated,
+            } => write!(f, "{expr} {}IN ({})", not(*negated), join(values)),
+            Expr::Window { name, args, window }
+                if args.is_empty() && name.eq_ignore_ascii_case("count") =>
+            {
+                write!(f, "{name}(*) OVER ({window})")
+            }
+            Expr::Window { name, args, window } => {
+                write!(f, "{name}({}) OVER ({window})", join(args))
+            }
+        }
+    }
+}
 
-    let is_primary_key = constraints
+/// The items separated by commas.
+fn join(items: &[impl ToString]) -> String {
+    items
         .iter()
-        .flatten()
-        .find(|c| **c == ColumnConstraint::PrimaryKey)
-        .is_some()
-        && ty
-            .map(|ty| ty.to_ascii_lowercase() == "integer")
-            .unwrap_or(false);
-
-    Ok((
-        remaining_input,
-        Field {
-            name: column,
-            is_primary_key,
-        },
-    ))
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Expr {
+    /// Returns both sides of an `expr = literal` comparison, which can be looked up in an index
+    /// of the column or expression.
+    pub fn equality(&self) -> Option<(&Expr, &Literal)> {
+        match self {
+            Expr::Binary {
+                op: BinaryOperator::Eq,
+                left,
+                right,
+            } => match (left.as_ref(), right.as_ref()) {
+                (Expr::Literal(_), Expr::Literal(_)) => None,
+                (expr, Expr::Literal(value)) | (Expr::Literal(value), expr) => Some((expr, value)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 
-    #[test]
-    fn parse_select_with_one_field() {
-        let input = b"SELECT id FROM test";
-        let (_, result) = parse(input).unwrap();
+    /// Whether every row that satisfies this condition satisfies `other` too, as far as that can
+    /// be told from the terms of the conditions: each term of `other` has to be one of the terms
+    /// that are joined with AND here, or `column IS NOT NULL` for a column that's compared here.
+    pub fn implies(&self, other: &Expr) -> bool {
+        let terms = self.terms();
+        other.terms().into_iter().all(|required| {
+            terms.contains(&required)
+                || match required {
+                    Expr::IsNull {
+                        expr,
+                        negated: true,
+                    } => terms.iter().any(|term| term.compares(expr)),
+                    _ => false,
+                }
+        })
+    }
 
-        assert_eq!(
-            result,
-            SQLCommand::Select(SelectStatement::Fields(SelectFields {
-                table: "test".to_string(),
-                fields: vec!["id".to_string()],
-                where_clause: None
-            }))
-        );
+    /// The terms of the condition that are joined with AND.
+    fn terms(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Binary {
+                op: BinaryOperator::And,
+                left,
+                right,
+            } => {
+                let mut terms = left.terms();
+                terms.extend(right.terms());
+                terms
+            }
+            expr => vec![expr],
+        }
     }
 
-    #[test]
-    fn parse_select_with_two_fields() {
-        let input = b"SELECT id, name FROM test";
-        let (_, result) = parse(input).unwrap();
+    /// Whether this is a comparison of `expr` with a value other than NULL, which can only be
+    /// true when `expr` isn't NULL.
+    fn compares(&self, expr: &Expr) -> bool {
+        match self {
+            Expr::Binary { op, left, right }
+                if !matches!(op, BinaryOperator::And | BinaryOperator::Or) =>
+            {
+                (left.as_ref() == expr || right.as_ref() == expr)
+                    && !matches!(left.as_ref(), Expr::Literal(Literal::Null))
+                    && !matches!(right.as_ref(), Expr::Literal(Literal::Null))
+            }
+            _ => false,
+        }
+    }
 
-        assert_eq!(
-            result,
-            SQLCommand::Select(SelectStatement::Fields(SelectFields {
-                table: "test".to_string(),
-                fields: vec!["id".to_string(), "name".to_string()],
-                where_clause: None
-            }))
-        );
+    /// Copies the expression, replacing each parameter with the result of `f`. Parameters are
+    /// visited in the order they appear in the statement.
+    pub fn map_parameters(&self, f: &mut impl FnMut(&Parameter) -> Expr) -> Expr {
+        match self {
+            Expr::Parameter(parameter) => f(parameter),
+            Expr::Literal(_) | Expr::Column(_) | Expr::QualifiedColumn { .. } => self.clone(),
+            Expr::Binary { op, left, right } => {
+                let left = left.map_parameters(f);
+                let right = right.map_parameters(f);
+                Expr::Binary {
+                    op: *op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }
+            }
+            Expr::Not(expr) => Expr::Not(Box::new(expr.map_parameters(f))),
+            Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: Box::new(expr.map_parameters(f)),
+                negated: *negated,
+            },
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                let expr = expr.map_parameters(f);
+                Expr::InSubquery {
+                    expr: Box::new(expr),
+                    subquery: Box::new(subquery.map_parameters(f)),
+                    negated: *negated,
+                }
+            }
+            Expr::Subquery(subquery) => Expr::Subquery(Box::new(subquery.map_parameters(f))),
+            Expr::Exists(subquery) => Expr::Exists(Box::new(subquery.map_parameters(f))),
+            Expr::Cast { expr, ty } => Expr::Cast {
+                expr: Box::new(expr.map_parameters(f)),
+                ty: ty.clone(),
+            },
+            Expr::Function { name, args } => Expr::Function {
+                name: name.clone(),
+                args: args.iter().map(|arg| arg.map_parameters(f)).collect(),
+            },
+            Expr::Row(exprs) => {
+                Expr::Row(exprs.iter().map(|expr| expr.map_parameters(f)).collect())
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let expr = expr.map_parameters(f);
+                Expr::InList {
+                    expr: Box::new(expr),
+                    list: list.iter().map(|item| item.map_parameters(f)).collect(),
+                    negated: *negated,
+                }
+            }
+            Expr::InValues {
+                expr,
+                values,
+                negated,
+            } => Expr::InValues {
+                expr: Box::new(expr.map_parameters(f)),
+                values: values.clone(),
+                negated: *negated,
+            },
+            Expr::Window { name, args, window } => {
+                let args = args.iter().map(|arg| arg.map_parameters(f)).collect();
+                let partition_by = window
+                    .partition_by
+                    .iter()
+                    .map(|expr| expr.map_parameters(f))
+                    .collect();
+                let order_by = window
+                    .order_by
+                    .iter()
+                    .map(|term| OrderingTerm {
+                        expr: term.expr.map_parameters(f),
+                        descending: term.descending,
+                    })
+                    .collect();
+                Expr::Window {
+                    name: name.clone(),
+                    args,
+                    window: Window {
+                        partition_by,
+                        order_by,
+                    },
+                }
+            }
+        }
     }
+}
 
-    #[test]
-    fn parse_select_with_where() {
-        let input = b"SELECT id, name FROM test WHERE super_name = 'test string'";
-        let (_, result) = parse(input).unwrap();
+impl Expr {
+    /// Copies the expression, replacing the expressions it's made of with the results of `f`.
+    /// Subqueries are copied as they are.
+    pub fn map_children<E>(&self, f: &mut impl FnMut(&Expr) -> Result<Expr, E>) -> Result<Expr, E> {
+        let mut map = |expr: &Expr| f(expr).map(Box::new);
+        Ok(match self {
+            Expr::Literal(_)
+            | Expr::Column(_)
+            | Expr::QualifiedColumn { .. }
+            | Expr::Parameter(_)
+            | Expr::Subquery(_)
+            | Expr::Exists(_) => self.clone(),
+            Expr::Binary { op, left, right } => Expr::Binary {
+                op: *op,
+                left: map(left)?,
+                right: map(right)?,
+            },
+            Expr::Not(expr) => Expr::Not(map(expr)?),
+            Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: map(expr)?,
+                negated: *negated,
+            },
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => Expr::InSubquery {
+                expr: map(expr)?,
+                subquery: subquery.clone(),
+                negated: *negated,
+            },
+            Expr::Cast { expr, ty } => Expr::Cast {
+                expr: map(expr)?,
+                ty: ty.clone(),
+            },
+            Expr::Function { name, args } => Expr::Function {
+                name: name.clone(),
+                args: args.iter().map(f).collect::<Result<_, _>>()?,
+            },
+            Expr::Row(exprs) => Expr::Row(exprs.iter().map(f).collect::<Result<_, _>>()?),
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: map(expr)?,
+                list: list.iter().map(f).collect::<Result<_, _>>()?,
+                negated: *negated,
+            },
+            Expr::InValues {
+                expr,
+                values,
+                negated,
+            } => Expr::InValues {
+                expr: map(expr)?,
+                values: values.clone(),
+                negated: *negated,
+            },
+            Expr::Window { name, args, window } => Expr::Window {
+                name: name.clone(),
+                args: args.iter().map(&mut *f).collect::<Result<_, _>>()?,
+                window: Window {
+                    partition_by: window
+                        .partition_by
+                        .iter()
+                        .map(&mut *f)
+                        .collect::<Result<_, _>>()?,
+                    order_by: window
+                        .order_by
+                        .iter()
+                        .map(|term| {
+                            Ok(OrderingTerm {
+                                expr: f(&term.expr)?,
+                                descending: term.descending,
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                },
+            },
+        })
+    }
 
-        assert_eq!(
-            result,
-            SQLCommand::Select(SelectStatement::Fields(SelectFields {
-                table: "test".to_string(),
-                fields: vec!["id".to_string(), "name".to_string()],
-                where_clause: Some(WhereClause {
-                    field: "super_name".to_string(),
-                    value: "test string".to_string()
+    /// Whether the expression calls a window function, outside of subqueries.
+    pub fn has_window_function(&self) -> bool {
+        match self {
+            Expr::Window { .. } => true,
+            expr => expr
+                .map_children(&mut |child| match child.has_window_function() {
+                    true => Err(()),
+                    false => Ok(child.clone()),
                 })
-            }))
-        );
+                .is_err(),
+        }
     }
 
-    #[test]
-    fn parse_select_with_count() {
-        let input = b"SELECT COUNT(*) FROM test";
-        let (_, result) = parse(input).unwrap();
+    /// Whether a subquery of the expression reads the table called `name`.
+    fn reads_table(&self, name: &str) -> bool {
+        match self {
+            Expr::Subquery(subquery) | Expr::Exists(subquery) => subquery.reads_table(name),
+            Expr::InSubquery { expr, subquery, .. } => {
+                expr.reads_table(name) || subquery.reads_table(name)
+            }
+            expr => expr
+                .map_children(&mut |child| match child.reads_table(name) {
+                    true => Err(()),
+                    false => Ok(child.clone()),
+                })
+                .is_err(),
+        }
+    }
 
-        assert_eq!(
-            result,
-            SQLCommand::Select(SelectStatement::Count("test".to_string()))
-        );
+    /// Copies the expression with the columns qualified with `qualifier` unqualified. Columns
+    /// qualified with other names are an error, since the expression only reads one table.
+    fn unqualify(&self, qualifier: Option<&str>) -> Result<Expr, Error> {
+        match self {
+            Expr::QualifiedColumn { table, column }
+                if qualifier.map_or(false, |name| name.eq_ignore_ascii_case(table)) =>
+            {
+                Ok(Expr::Column(column.clone()))
+            }
+            Expr::QualifiedColumn { table, column } => {
+                Err(Error::NoSuchColumn(format!("{table}.{column}")))
+            }
+            expr => expr.map_children(&mut |expr| expr.unqualify(qualifier)),
+        }
     }
+}
 
-    #[test]
-    fn parse_create_table_with_one_entry() {
+/// A value a SELECT returns for each row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    /// `*`, every column of the table
+    All,
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+        /// The expression as written, which names the result if it has no alias and isn't a
+        /// column
+        text: String,
+    },
+}
+
+/// What a SELECT reads its rows from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableSource {
+    /// A table, optionally qualified with the database it's in, like `aux.companies`
+    Table {
+        schema: Option<String>,
+        name: String,
+    },
+    /// `(SELECT ...)`, whose rows are computed before the outer query reads them
+    Subquery(Box<SelectStatement>),
+    /// The rows of two tables that go together
+    Join(Box<Join>),
+}
+
+impl TableSource {
+    /// Copies the source, replacing each parameter with the result of `f`, in the order they
+    /// appear in the statement.
+    fn map_parameters(&self, f: &mut impl FnMut(&Parameter) -> Expr) -> TableSource {
+        match self {
+            TableSource::Table { .. } => self.clone(),
+            TableSource::Subquery(select) => {
+                TableSource::Subquery(Box::new(select.map_parameters(f)))
+            }
+            TableSource::Join(join) => {
+                let left = join.left.map_parameters(f);
+                let right = join.right.map_parameters(f);
+                TableSource::Join(Box::new(Join {
+                    left,
+                    left_alias: join.left_alias.clone(),
+                    right,
+                    right_alias: join.right_alias.clone(),
+                    on: join.on.as_ref().map(|on| on.map_parameters(f)),
+                }))
+            }
+        }
+    }
+
+    /// Whether the source or one of its subqueries reads the table called `name`, without a
+    /// schema.
+    fn reads_table(&self, name: &str) -> bool {
+        match self {
+            TableSource::Table {
+                schema,
+                name: table,
+            } => schema.is_none() && table.eq_ignore_ascii_case(name),
+            TableSource::Subquery(subquery) => subquery.reads_table(name),
+            TableSource::Join(join) => {
+                join.left.reads_table(name)
+                    || join.right.reads_table(name)
+                    || join.on.as_ref().map_or(false, |on| on.reads_table(name))
+            }
+        }
+    }
+}
+
+/// `left [INNER] JOIN right [ON expr]` or `left CROSS JOIN right`: each row of `left` with
+/// each row of `right` for which the ON condition is true. Joins of more tables are joins
+/// whose left side is a join.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub left: TableSource,
+    pub left_alias: Option<String>,
+    pub right: TableSource,
+    pub right_alias: Option<String>,
+    pub on: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectFields {
+    pub fields: Vec<ResultColumn>,
+    pub from: TableSource,
+    /// The name given to the table or subquery with `AS`
+    pub alias: Option<String>,
+    pub where_clause: Option<Expr>,
+}
+
+impl SelectFields {
+    /// The name of the table the query reads, unless it reads a subquery.
+    pub fn table(&self) -> Option<&str> {
+        match &self.from {
+            TableSource::Table { name, .. } => Some(name),
+            TableSource::Subquery(_) | TableSource::Join(_) => None,
+        }
+    }
+
+    /// Whether a result of the query calls a window function.
+    pub fn has_window_functions(&self) -> bool {
+        self.fields.iter().any(|field| match field {
+            ResultColumn::All => false,
+            ResultColumn::Expr { expr, .. } => expr.has_window_function(),
+        })
+    }
+
+    /// The name columns can be qualified with: the alias, or else the name of the table.
+    pub fn qualifier(&self) -> Option<&str> {
+        self.alias.as_deref().or(self.table())
+    }
+
+    /// Copies the query with the qualified columns of its results and condition unqualified,
+    /// which is how they're resolved against the table. Subqueries have to be run first.
+    pub fn unqualify_columns(&self) -> Result<SelectFields, Error> {
+        let qualifier = self.qualifier();
+        self.map_expressions(&mut |expr| expr.unqualify(qualifier))
+    }
+
+    /// Copies the query, replacing the expressions of its results and its condition with the
+    /// results of `f`.
+    pub fn map_expressions<E>(
+        &self,
+        f: &mut impl FnMut(&Expr) -> Result<Expr, E>,
+    ) -> Result<SelectFields, E> {
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| match field {
+                ResultColumn::All => Ok(ResultColumn::All),
+                ResultColumn::Expr { expr, alias, text } => Ok(ResultColumn::Expr {
+                    expr: f(expr)?,
+                    alias: alias.clone(),
+                    text: text.clone(),
+                }),
+            })
+            .collect::<Result<_, E>>()?;
+        Ok(SelectFields {
+            fields,
+            from: self.from.clone(),
+            alias: self.alias.clone(),
+            where_clause: self.where_clause.as_ref().map(f).transpose()?,
+        })
+    }
+
+    /// Copies the query, replacing each parameter with the result of `f`, in the order they
+    /// appear in the statement.
+    pub fn map_parameters(&self, f: &mut impl FnMut(&Parameter) -> Expr) -> SelectFields {
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| match field {
+                ResultColumn::All => ResultColumn::All,
+                ResultColumn::Expr { expr, alias, text } => ResultColumn::Expr {
+                    expr: expr.map_parameters(f),
+                    alias: alias.clone(),
+                    text: text.clone(),
+                },
+            })
+            .collect();
+        let from = self.from.map_parameters(f);
+        SelectFields {
+            fields,
+            from,
+            alias: self.alias.clone(),
+            where_clause: self
+                .where_clause
+                .as_ref()
+                .map(|where_clause| where_clause.map_parameters(f)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnConstraint {
+    PrimaryKey { descending: bool },
+    NotNull,
+    Unique,
+    Default(String),
+    Collate(String),
+    AutoIncrement,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    /// The declared type, as written in the CREATE TABLE statement
+    pub ty: Option<String>,
+    pub is_primary_key: bool,
+    /// INTEGER PRIMARY KEY columns are stored as NULL and alias the rowid
+    pub is_rowid_alias: bool,
+    pub not_null: bool,
+    pub unique: bool,
+    /// The source text of the DEFAULT expression
+    pub default: Option<String>,
+    pub collation: Option<String>,
+    /// Rowids of INTEGER PRIMARY KEY AUTOINCREMENT columns are never reused
+    pub autoincrement: bool,
+}
+
+impl Field {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            ty: None,
+            is_primary_key: false,
+            is_rowid_alias: false,
+            not_null: false,
+            unique: false,
+            default: None,
+            collation: None,
+            autoincrement: false,
+        }
+    }
+
+    /// Only a primary key declared exactly as INTEGER aliases the rowid, not INT or BIGINT.
+    fn has_integer_type(&self) -> bool {
+        self.ty
+            .as_ref()
+            .map_or(false, |ty| ty.eq_ignore_ascii_case("integer"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraint {
+    PrimaryKey(Vec<String>),
+    Unique(Vec<String>),
+    ForeignKey {
+        columns: Vec<String>,
+        foreign_table: String,
+        foreign_columns: Vec<String>,
+    },
+    /// The source text of the CHECK expression
+    Check(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTableStatement {
+    pub schema: Option<String>,
+    pub table: String,
+    /// `CREATE TEMP TABLE`, which only exists until the connection is closed
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub fields: Vec<Field>,
+    pub constraints: Vec<TableConstraint>,
+}
+
+impl CreateTableStatement {
+    /// The columns of the indexes SQLite creates for the PRIMARY KEY and UNIQUE constraints, in
+    /// the order they're written, which numbers them: `sqlite_autoindex_<table>_1` and so on. A
+    /// primary key that aliases the rowid needs no index, and a constraint on the same columns
+    /// as an earlier one shares its index.
+    pub fn automatic_indexes(&self) -> Vec<Vec<String>> {
+        let has_key_constraint = self
+            .constraints
+            .iter()
+            .any(|constraint| matches!(constraint, TableConstraint::PrimaryKey(_)));
+        let mut indexes: Vec<Vec<String>> = vec![];
+        let mut add = |columns: Vec<String>| {
+            if !indexes.contains(&columns) {
+                indexes.push(columns);
+            }
+        };
+        for field in &self.fields {
+            if field.is_primary_key && !field.is_rowid_alias && !has_key_constraint {
+                add(vec![field.name.clone()]);
+            }
+            if field.unique {
+                add(vec![field.name.clone()]);
+            }
+        }
+        for constraint in &self.constraints {
+            match constraint {
+                TableConstraint::PrimaryKey(columns) => {
+                    let aliases_rowid = self.fields.iter().any(|field| {
+                        field.is_rowid_alias && columns == std::slice::from_ref(&field.name)
+                    });
+                    if !aliases_rowid {
+                        add(columns.clone());
+                    }
+                }
+                TableConstraint::Unique(columns) => add(columns.clone()),
+                _ => {}
+            }
+        }
+        indexes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndexStatement {
+    /// The database of the index and its table, which is written before the index name
+    pub schema: Option<String>,
+    pub name: String,
+    pub table: String,
+    /// `CREATE UNIQUE INDEX`, which allows no two rows with the same values, unless one of them
+    /// is NULL
+    pub unique: bool,
+    /// The indexed columns. Expressions are named with their SQL, like `lower(name)`.
+    pub fields: Vec<String>,
+    /// The expression of each field that isn't a column, for indexes on expressions
+    pub expressions: Vec<Option<Expr>>,
+    /// The collation each of the fields names with `COLLATE`, if any
+    pub collations: Vec<Option<String>>,
+    /// Whether each of the fields is sorted in descending order, with `DESC`
+    pub descending: Vec<bool>,
+    /// The condition of a partial index, which only has entries for the rows that satisfy it
+    pub where_clause: Option<Expr>,
+    pub if_not_exists: bool,
+}
+
+/// `CREATE VIEW name AS SELECT ...`. A view stores no rows, queries of it run its SELECT.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateViewStatement {
+    pub schema: Option<String>,
+    pub name: String,
+    /// `CREATE TEMP VIEW`, which only exists until the connection is closed
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    /// The names of the columns, when they're given instead of taken from the SELECT
+    pub columns: Vec<String>,
+    pub select: SelectStatement,
+}
+
+/// `CREATE VIRTUAL TABLE name USING module(arguments)`. The rows of a virtual table come from
+/// its module, which keeps them in ordinary "shadow" tables like `name_content`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateVirtualTableStatement {
+    pub schema: Option<String>,
+    pub name: String,
+    pub if_not_exists: bool,
+    pub module: String,
+    /// The arguments of the module as they're written, which only the module knows how to read
+    pub arguments: Vec<String>,
+}
+
+/// `INSERT INTO table (columns) VALUES (...), (...)`. `columns` is empty when the statement
+/// doesn't name them, in which case each row has a value for every column of the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertStatement {
+    pub schema: Option<String>,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Expr>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PragmaStatement {
+    pub name: String,
+    pub argument: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SQLCommand {
+    Select(SelectStatement),
+    CreateTable(CreateTableStatement),
+    CreateIndex(CreateIndexStatement),
+    CreateView(CreateViewStatement),
+    CreateVirtualTable(CreateVirtualTableStatement),
+    Pragma(PragmaStatement),
+    Insert(InsertStatement),
+    Vacuum,
+    /// `ANALYZE`, optionally of a single table or index
+    Analyze(Option<String>),
+    /// `ATTACH DATABASE 'path' AS schema`
+    Attach {
+        path: String,
+        schema: String,
+    },
+    /// `DETACH DATABASE schema`
+    Detach(String),
+    /// `EXPLAIN SELECT ...`, which lists the steps of the query instead of running it
+    Explain(SelectStatement),
+}
+
+impl SQLCommand {
+    /// Copies the command, replacing each parameter with the result of `f`, in the order they
+    /// appear in the statement.
+    pub fn map_parameters(&self, f: &mut impl FnMut(&Parameter) -> Expr) -> SQLCommand {
+        match self {
+            SQLCommand::Select(select) => SQLCommand::Select(select.map_parameters(f)),
+            SQLCommand::Insert(insert) => SQLCommand::Insert(InsertStatement {
+                rows: insert
+                    .rows
+                    .iter()
+                    .map(|row| row.iter().map(|value| value.map_parameters(f)).collect())
+                    .collect(),
+                ..insert.clone()
+            }),
+            command => command.clone(),
+        }
+    }
+}
+
+pub fn parse(input: &[u8]) -> IResult<&[u8], SQLCommand> {
+    alt((
+        map(pragma, SQLCommand::Pragma),
+        map(parse_creation, SQLCommand::CreateTable),
+        map(compound_selection, SQLCommand::Select),
+        map(parse_index_creation, SQLCommand::CreateIndex),
+        map(parse_view_creation, SQLCommand::CreateView),
+        map(parse_virtual_table_creation, SQLCommand::CreateVirtualTable),
+        map(insertion, SQLCommand::Insert),
+        map(vacuum, |_| SQLCommand::Vacuum),
+        map(analyze, SQLCommand::Analyze),
+        attach,
+        map(detach, SQLCommand::Detach),
+        map(explain, SQLCommand::Explain),
+    ))(input)
+}
+
+/// Parses a single statement. Unlike [`parse`], the whole input has to be the statement.
+pub fn parse_statement(input: &str) -> Result<SQLCommand, SyntaxError> {
+    let input = input.as_bytes();
+    match parse(input) {
+        Ok((rest, command)) if rest.iter().all(u8::is_ascii_whitespace) => Ok(command),
+        Ok((rest, _)) => Err(SyntaxError::new(input, rest, vec![])),
+        Err(nom::Err::Error(failure) | nom::Err::Failure(failure)) => {
+            Err(SyntaxError::new(input, failure.input, failure.expected))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(SyntaxError::new(input, &[], vec![])),
+    }
+}
+
+/// `ATTACH [DATABASE] 'path' AS schema`. The path is a string literal, not an expression.
+fn attach(input: &[u8]) -> IResult<&[u8], SQLCommand> {
+    let (remaining_input, (_, _, path, _, _, _, schema, _, _)) = tuple((
+        keyword("attach"),
+        opt(tuple((multispace1, keyword("database")))),
+        preceded(multispace1, string_literal),
+        multispace1,
+        keyword("as"),
+        multispace1,
+        identifier,
+        multispace0,
+        opt(tag(";")),
+    ))(input)?;
+    let path = String::from_utf8_lossy(&path[1..path.len() - 1]).replace("''", "'");
+    Ok((remaining_input, SQLCommand::Attach { path, schema }))
+}
+
+/// `DETACH [DATABASE] schema`.
+fn detach(input: &[u8]) -> IResult<&[u8], String> {
+    let (remaining_input, (_, _, _, schema, _, _)) = tuple((
+        keyword("detach"),
+        opt(tuple((multispace1, keyword("database")))),
+        multispace1,
+        identifier,
+        multispace0,
+        opt(tag(";")),
+    ))(input)?;
+    Ok((remaining_input, schema))
+}
+
+/// `EXPLAIN` followed by a query.
+fn explain(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+    preceded(
+        tuple((keyword("explain"), multispace1)),
+        cut(compound_selection),
+    )(input)
+}
+
+/// `VACUUM`, optionally naming the main schema. Attached databases can't be vacuumed.
+fn vacuum(input: &[u8]) -> IResult<&[u8], ()> {
+    let (remaining_input, _) = tuple((
+        keyword("vacuum"),
+        opt(preceded(multispace1, keyword("main"))),
+        multispace0,
+        not(keyword("into")),
+        opt(tag(";")),
+    ))(input)?;
+    Ok((remaining_input, ()))
+}
+
+/// `ANALYZE`, optionally followed by the name of a table or an index.
+fn analyze(input: &[u8]) -> IResult<&[u8], Option<String>> {
+    let (remaining_input, (_, name, _, _)) = tuple((
+        keyword("analyze"),
+        opt(preceded(multispace1, identifier)),
+        multispace0,
+        opt(tag(";")),
+    ))(input)?;
+    Ok((remaining_input, name))
+}
+
+fn insertion(input: &[u8]) -> IResult<&[u8], InsertStatement> {
+    let row = delimited(
+        tuple((tag("("), multispace0)),
+        separated_list1(tuple((multispace0, tag(","), multispace0)), expr),
+        tuple((multispace0, tag(")"))),
+    );
+    let (remaining_input, (_, _, _, _, table, columns, _, _, _, rows, _, _)) = tuple((
+        keyword("insert"),
+        multispace1,
+        keyword("into"),
+        multispace1,
+        qualified_name,
+        opt(column_list),
+        multispace0,
+        keyword("values"),
+        multispace0,
+        separated_list1(tuple((multispace0, tag(","), multispace0)), row),
+        multispace0,
+        opt(tag(";")),
+    ))(input)?;
+
+    let (schema, table) = table;
+    Ok((
+        remaining_input,
+        InsertStatement {
+            schema,
+            table,
+            columns: columns.unwrap_or_default(),
+            rows,
+        },
+    ))
+}
+
+fn pragma(input: &[u8]) -> IResult<&[u8], PragmaStatement> {
+    let (remaining_input, (_, _, name, argument, _, _)) = tuple((
+        keyword("PRAGMA"),
+        multispace1,
+        identifier,
+        opt(alt((
+            delimited(
+                tuple((multispace0, tag("("), multispace0)),
+                identifier,
+                tuple((multispace0, tag(")"))),
+            ),
+            preceded(tuple((multispace0, tag("="), multispace0)), identifier),
+        ))),
+        multispace0,
+        opt(tag(";")),
+    ))(input)?;
+
+    Ok((remaining_input, PragmaStatement { name, argument }))
+}
+
+/// A query, which may start with a WITH clause.
+fn compound_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+    let (input, (recursive, ctes)) = match with_clause(input) {
+        Ok(parsed) => parsed,
+        Err(nom::Err::Error(_)) => return union_selection(input),
+        Err(error) => return Err(error),
+    };
+    let (input, select) = preceded(multispace0, cut(union_selection))(input)?;
+    Ok((
+        input,
+        SelectStatement::With {
+            recursive,
+            ctes,
+            select: Box::new(select),
+        },
+    ))
+}
+
+/// `WITH [RECURSIVE]` and the common table expressions separated by commas. Returns whether
+/// RECURSIVE was given.
+fn with_clause(input: &[u8]) -> IResult<&[u8], (bool, Vec<CommonTableExpression>)> {
+    let cte = map(
+        tuple((
+            identifier,
+            opt(preceded(multispace0, column_list)),
+            tuple((multispace0, keyword("AS"), multispace0)),
+            subquery,
+        )),
+        |(name, columns, _, select)| CommonTableExpression {
+            name,
+            columns: columns.unwrap_or_default(),
+            select,
+        },
+    );
+    preceded(
+        tuple((keyword("WITH"), multispace1)),
+        cut(tuple((
+            map(
+                opt(tuple((keyword("RECURSIVE"), multispace1))),
+                |recursive| recursive.is_some(),
+            ),
+            separated_list1(tuple((multispace0, tag(","), multispace0)), cte),
+        ))),
+    )(input)
+}
+
+/// A SELECT, or SELECTs combined with UNION and UNION ALL.
+fn union_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+    let operator = || {
+        map(
+            tuple((keyword("UNION"), opt(preceded(multispace1, keyword("ALL"))))),
+            |(_, all)| match all {
+                Some(_) => CompoundOperator::UnionAll,
+                None => CompoundOperator::Union,
+            },
+        )
+    };
+    let (mut input, mut select) = alt((selection, count_selection))(input)?;
+    while let Ok((remaining_input, op)) = preceded(multispace0, operator())(input) {
+        // A SELECT has to follow, so its errors are the ones to report
+        let (remaining_input, right) =
+            preceded(multispace1, cut(alt((selection, count_selection))))(remaining_input)?;
+        select = SelectStatement::Compound {
+            op,
+            left: Box::new(select),
+            right: Box::new(right),
+        };
+        input = remaining_input;
+    }
+    Ok((input, select))
+}
+
+fn count_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+    let (remaining_input, (_, _, _, _, _, _, table, where_clause, _)) = tuple((
+        keyword("SELECT"),
+        multispace1,
+        tag_no_case("count(*)"),
+        multispace1,
+        tag_no_case("from"),
+        multispace1,
+        qualified_name,
+        parse_where_clause,
+        opt(tag(";")),
+    ))(input)?;
+
+    let (schema, table) = table;
+    Ok((
+        remaining_input,
+        SelectStatement::Count {
+            schema,
+            table,
+            where_clause,
+        },
+    ))
+}
+
+fn selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+    let (remaining_input, (_, _, fields, _, _, _, from, where_clause, _)) = tuple((
+        keyword("SELECT"),
+        multispace1,
+        result_columns,
+        multispace0,
+        keyword("from"),
+        multispace0,
+        joined_tables,
+        parse_where_clause,
+        opt(tag(";")),
+    ))(input)?;
+
+    let (from, alias) = from;
+    Ok((
+        remaining_input,
+        SelectStatement::Fields(SelectFields {
+            fields,
+            from,
+            alias,
+            where_clause,
+        }),
+    ))
+}
+
+/// `*` or expressions with an optional `AS alias`, separated by commas.
+fn result_columns(input: &[u8]) -> IResult<&[u8], Vec<ResultColumn>> {
+    let alias = preceded(tuple((multispace1, keyword("AS"), multispace1)), identifier);
+    let expression = map(
+        tuple((consumed(expr), opt(alias))),
+        |((text, expr), alias)| ResultColumn::Expr {
+            expr,
+            alias,
+            text: String::from_utf8_lossy(text).into_owned(),
+        },
+    );
+    separated_list1(
+        tuple((multispace0, tag(","), multispace0)),
+        alt((map(tag("*"), |_| ResultColumn::All), expression)),
+    )(input)
+}
+
+/// Words that follow the table of a SELECT, which can't be its alias.
+const ALIAS_KEYWORDS: &[&str] = &[
+    "WHERE",
+    "UNION",
+    "EXCEPT",
+    "INTERSECT",
+    "GROUP",
+    "HAVING",
+    "WINDOW",
+    "ORDER",
+    "LIMIT",
+    "JOIN",
+    "INNER",
+    "LEFT",
+    "CROSS",
+    "NATURAL",
+    "ON",
+    "USING",
+];
+
+/// A table name or a parenthesized SELECT, with the alias it may have.
+fn table_source(input: &[u8]) -> IResult<&[u8], (TableSource, Option<String>)> {
+    let alias = preceded(
+        tuple((multispace0, opt(terminated(keyword("AS"), multispace0)))),
+        verify(identifier, |alias: &str| {
+            !ALIAS_KEYWORDS
+                .iter()
+                .any(|keyword| keyword.eq_ignore_ascii_case(alias))
+        }),
+    );
+    tuple((
+        alt((
+            map(subquery, |select| TableSource::Subquery(Box::new(select))),
+            map(qualified_name, |(schema, name)| TableSource::Table {
+                schema,
+                name,
+            }),
+        )),
+        opt(alias),
+    ))(input)
+}
+
+/// A table source, or joins of table sources. A join has no alias.
+fn joined_tables(input: &[u8]) -> IResult<&[u8], (TableSource, Option<String>)> {
+    let operator = tuple((
+        opt(terminated(
+            alt((keyword("INNER"), keyword("CROSS"))),
+            multispace1,
+        )),
+        keyword("JOIN"),
+    ));
+    let on = preceded(
+        tuple((multispace1, keyword("ON"))),
+        cut(preceded(multispace1, expr)),
+    );
+    let mut join = tuple((
+        preceded(multispace1, operator),
+        cut(preceded(multispace1, table_source)),
+        opt(on),
+    ));
+    let (mut input, mut source) = table_source(input)?;
+    loop {
+        let (remaining_input, (_, (right, right_alias), on)) = match join(input) {
+            Ok(parsed) => parsed,
+            Err(nom::Err::Error(_)) => break,
+            Err(error) => return Err(error),
+        };
+        let (left, left_alias) = source;
+        source = (
+            TableSource::Join(Box::new(Join {
+                left,
+                left_alias,
+                right,
+                right_alias,
+                on,
+            })),
+            None,
+        );
+        input = remaining_input;
+    }
+    Ok((input, source))
+}
+
+/// A parenthesized SELECT.
+fn subquery(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+    delimited(
+        tuple((tag("("), multispace0)),
+        compound_selection,
+        tuple((multispace0, tag(")"))),
+    )(input)
+}
+
+fn parse_where_clause(input: &[u8]) -> IResult<&[u8], Option<Expr>> {
+    opt(preceded(
+        tuple((multispace0, keyword("where"), multispace0)),
+        cut(expr),
+    ))(input)
+}
+
+/// Parses an expression. Operators bind from loosest to tightest: OR, AND, NOT, comparisons.
+fn expr(input: &[u8]) -> IResult<&[u8], Expr> {
+    binary_chain(and_expr, keyword("OR"), BinaryOperator::Or)(input)
+}
+
+fn and_expr(input: &[u8]) -> IResult<&[u8], Expr> {
+    binary_chain(not_expr, keyword("AND"), BinaryOperator::And)(input)
+}
+
+/// Parses `operand (operator operand)*` into a left associative chain of binary expressions.
+fn binary_chain<'a>(
+    mut operand: impl FnMut(&'a [u8]) -> IResult<&'a [u8], Expr>,
+    mut operator: impl FnMut(&'a [u8]) -> IResult<&'a [u8], &'a [u8]>,
+    op: BinaryOperator,
+) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Expr> {
+    move |input| {
+        let (mut input, mut left) = operand(input)?;
+        while let Ok((remaining_input, _)) = tuple((multispace0, &mut operator, multispace0))(input)
+        {
+            let (remaining_input, right) = operand(remaining_input)?;
+            left = Expr::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+            input = remaining_input;
+        }
+        Ok((input, left))
+    }
+}
+
+fn not_expr(input: &[u8]) -> IResult<&[u8], Expr> {
+    alt((
+        map(
+            preceded(tuple((keyword("NOT"), multispace0)), not_expr),
+            |e| Expr::Not(Box::new(e)),
+        ),
+        comparison,
+    ))(input)
+}
+
+fn comparison(input: &[u8]) -> IResult<&[u8], Expr> {
+    let (input, left) = primary_expr(input)?;
+
+    let is_null = alt((
+        map(
+            tuple((
+                keyword("IS"),
+                multispace1,
+                keyword("NOT"),
+                multispace1,
+                keyword("NULL"),
+            )),
+            |_| true,
+        ),
+        map(tuple((keyword("IS"), multispace1, keyword("NULL"))), |_| {
+            false
+        }),
+        map(keyword("NOTNULL"), |_| true),
+        map(keyword("ISNULL"), |_| false),
+    ));
+    if let Ok((input, negated)) = preceded(multispace0, is_null)(input) {
+        let expr = Expr::IsNull {
+            expr: Box::new(left),
+            negated,
+        };
+        return Ok((input, expr));
+    }
+
+    let in_subquery = tuple((
+        multispace0,
+        opt(terminated(keyword("NOT"), multispace1)),
+        keyword("IN"),
+        multispace0,
+    ))(input);
+    if let Ok((input, (_, not, _, _))) = in_subquery {
+        let negated = not.is_some();
+        let list = delimited(
+            tuple((tag("("), multispace0)),
+            separated_list0(tuple((multispace0, tag(","), multispace0)), expr),
+            tuple((multispace0, tag(")"))),
+        );
+        let left = Box::new(left);
+        return cut(alt((
+            map(subquery, |subquery| Expr::InSubquery {
+                expr: left.clone(),
+                subquery: Box::new(subquery),
+                negated,
+            }),
+            map(list, |list| Expr::InList {
+                expr: left.clone(),
+                list,
+                negated,
+            }),
+        )))(input);
+    }
+
+    // Longer operators go first so that "<=" isn't parsed as "<"
+    let operator = alt((
+        map(tag("=="), |_| BinaryOperator::Eq),
+        map(tag("="), |_| BinaryOperator::Eq),
+        map(tag("!="), |_| BinaryOperator::NotEq),
+        map(tag("<>"), |_| BinaryOperator::NotEq),
+        map(tag("<="), |_| BinaryOperator::LtEq),
+        map(tag(">="), |_| BinaryOperator::GtEq),
+        map(tag("<"), |_| BinaryOperator::Lt),
+        map(tag(">"), |_| BinaryOperator::Gt),
+        map(keyword("MATCH"), |_| BinaryOperator::Match),
+    ));
+    let operator: IResult<&[u8], BinaryOperator> = preceded(multispace0, operator)(input);
+    match operator {
+        Ok((input, op)) => {
+            let (input, right) = preceded(multispace0, cut(primary_expr))(input)?;
+            let expr = Expr::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+            Ok((input, expr))
+        }
+        Err(_) => Ok((input, left)),
+    }
+}
+
+fn primary_expr(input: &[u8]) -> IResult<&[u8], Expr> {
+    let qualified_column = map(
+        separated_pair(identifier, tag("."), identifier),
+        |(table, column)| Expr::QualifiedColumn { table, column },
+    );
+    alt((
+        map(subquery, |select| Expr::Subquery(Box::new(select))),
+        map(
+            preceded(tuple((keyword("EXISTS"), multispace0)), subquery),
+            |select| Expr::Exists(Box::new(select)),
+        ),
+        cast,
+        window_call,
+        function_call,
+        map(
+            delimited(
+                tuple((tag("("), multispace0)),
+                separated_list1(tuple((multispace0, tag(","), multispace0)), expr),
+                tuple((multispace0, tag(")"))),
+            ),
+            |mut exprs| match exprs.len() {
+                1 => exprs.remove(0),
+                _ => Expr::Row(exprs),
+            },
+        ),
+        map(literal, Expr::Literal),
+        map(parameter, Expr::Parameter),
+        qualified_column,
+        map(identifier, Expr::Column),
+    ))(input)
+}
+
+/// `CAST(expr AS type)`.
+fn cast(input: &[u8]) -> IResult<&[u8], Expr> {
+    let (remaining_input, (_, _, _, _, expr, _, _, _, ty, _)) = tuple((
+        keyword("CAST"),
+        multispace0,
+        tag("("),
+        multispace0,
+        expr,
+        multispace1,
+        keyword("AS"),
+        multispace1,
+        type_name,
+        tag(")"),
+    ))(input)?;
+    let expr = Expr::Cast {
+        expr: Box::new(expr),
+        ty,
+    };
+    Ok((remaining_input, expr))
+}
+
+/// `name(args...) OVER (window)`. Unlike other calls, `COUNT(*)` can be a window function.
+fn window_call(input: &[u8]) -> IResult<&[u8], Expr> {
+    let (remaining_input, (name, _, args, _, _, _, window)) = tuple((
+        identifier,
+        multispace0,
+        delimited(
+            tuple((tag("("), multispace0)),
+            alt((
+                map(tag("*"), |_| vec![]),
+                separated_list0(tuple((multispace0, tag(","), multispace0)), expr),
+            )),
+            tuple((multispace0, tag(")"))),
+        ),
+        multispace0,
+        keyword("OVER"),
+        multispace0,
+        cut(window_definition),
+    ))(input)?;
+    Ok((remaining_input, Expr::Window { name, args, window }))
+}
+
+/// `([PARTITION BY expr, ...] [ORDER BY expr [ASC | DESC], ...])`.
+fn window_definition(input: &[u8]) -> IResult<&[u8], Window> {
+    let comma = || tuple((multispace0, tag(","), multispace0));
+    let direction = alt((
+        map(keyword("ASC"), |_| false),
+        map(keyword("DESC"), |_| true),
+    ));
+    let ordering_term = map(
+        tuple((expr, opt(preceded(multispace1, direction)))),
+        |(expr, descending)| OrderingTerm {
+            expr,
+            descending: descending.unwrap_or(false),
+        },
+    );
+    let partition_by = preceded(
+        tuple((
+            keyword("PARTITION"),
+            multispace1,
+            keyword("BY"),
+            multispace1,
+        )),
+        separated_list1(comma(), expr),
+    );
+    let order_by = preceded(
+        tuple((keyword("ORDER"), multispace1, keyword("BY"), multispace1)),
+        separated_list1(comma(), ordering_term),
+    );
+    map(
+        delimited(
+            tuple((tag("("), multispace0)),
+            tuple((
+                opt(terminated(partition_by, multispace0)),
+                opt(terminated(order_by, multispace0)),
+            )),
+            tag(")"),
+        ),
+        |(partition_by, order_by)| Window {
+            partition_by: partition_by.unwrap_or_default(),
+            order_by: order_by.unwrap_or_default(),
+        },
+    )(input)
+}
+
+/// `name(args...)`. `COUNT(*)` isn't a call, it's parsed as a [`SelectStatement::Count`], and
+/// neither is a CAST without a type.
+fn function_call(input: &[u8]) -> IResult<&[u8], Expr> {
+    let (remaining_input, (name, _, args)) = tuple((
+        verify(identifier, |name: &str| !name.eq_ignore_ascii_case("CAST")),
+        multispace0,
+        delimited(
+            tuple((tag("("), multispace0)),
+            separated_list0(tuple((multispace0, tag(","), multispace0)), expr),
+            tuple((multispace0, tag(")"))),
+        ),
+    ))(input)?;
+    Ok((remaining_input, Expr::Function { name, args }))
+}
+
+fn parameter(input: &[u8]) -> IResult<&[u8], Parameter> {
+    let numbered = map_opt(
+        preceded(tag("?"), take_while1(|c: u8| c.is_ascii_digit())),
+        |number| {
+            let number = std::str::from_utf8(number).ok()?.parse().ok()?;
+            (number > 0).then_some(Parameter::Numbered(number))
+        },
+    );
+    let named = map(
+        recognize(tuple((
+            alt((tag(":"), tag("@"), tag("$"))),
+            take_while1(is_sql_identifier),
+        ))),
+        |name| Parameter::Named(String::from_utf8_lossy(name).into_owned()),
+    );
+
+    alt((numbered, map(tag("?"), |_| Parameter::Anonymous), named))(input)
+}
+
+/// The text SQLite stores in the schema table for a CREATE statement: the leading keywords are
+/// normalized, IF NOT EXISTS and the schema name are dropped and the rest is kept as written.
+pub fn schema_sql(statement: &str) -> Option<String> {
+    let statement = statement.trim();
+    let (remaining_input, (_, _, _, unique, kind, _, _, _)) = tuple((
+        keyword("CREATE"),
+        multispace1,
+        opt(terminated(
+            alt((keyword("TEMPORARY"), keyword("TEMP"))),
+            multispace1,
+        )),
+        opt(terminated(keyword("UNIQUE"), multispace1)),
+        alt((keyword("TABLE"), keyword("INDEX"), keyword("VIEW"))),
+        multispace1,
+        opt(tuple((
+            keyword("IF"),
+            multispace1,
+            keyword("NOT"),
+            multispace1,
+            keyword("EXISTS"),
+            multispace1,
+        ))),
+        opt(tuple((identifier, multispace0, tag("."), multispace0))),
+    ))(statement.as_bytes())
+    .ok()?;
+
+    let rest = &statement[statement.len() - remaining_input.len()..];
+    Some(format!(
+        "CREATE {}{} {}",
+        if unique.is_some() { "UNIQUE " } else { "" },
+        String::from_utf8_lossy(kind).to_ascii_uppercase(),
+        rest.trim_end_matches(|c: char| c == ';' || c.is_whitespace())
+    ))
+}
+
+/// Parses a single literal, such as the text of a column's DEFAULT clause.
+pub fn parse_literal(input: &str) -> Option<Literal> {
+    let input = input.trim();
+    let input = input
+        .strip_prefix('(')
+        .and_then(|input| input.strip_suffix(')'))
+        .unwrap_or(input)
+        .trim();
+    match literal(input.as_bytes()) {
+        Ok((b"", literal)) => Some(literal),
+        _ => None,
+    }
+}
+
+fn literal(input: &[u8]) -> IResult<&[u8], Literal> {
+    let text = map(string_literal, |quoted| {
+        let quoted = String::from_utf8_lossy(&quoted[1..quoted.len() - 1]);
+        Literal::Text(quoted.replace("''", "'"))
+    });
+    let null = map(keyword("NULL"), |_| Literal::Null);
+
+    alt((text, blob_literal, null, numeric_literal))(input)
+}
+
+/// `X'53514C'`: an even number of hex digits, in either case.
+fn blob_literal(input: &[u8]) -> IResult<&[u8], Literal> {
+    let hex = verify(
+        delimited(
+            tuple((alt((tag("x"), tag("X"))), tag("'"))),
+            take_while(|c: u8| c.is_ascii_hexdigit()),
+            tag("'"),
+        ),
+        |digits: &[u8]| digits.len() % 2 == 0,
+    );
+    map(hex, |digits: &[u8]| {
+        let blob = digits
+            .chunks(2)
+            .map(|pair| {
+                let pair = std::str::from_utf8(pair).expect("hex digits");
+                u8::from_str_radix(pair, 16).expect("hex digits")
+            })
+            .collect();
+        Literal::Blob(blob)
+    })(input)
+}
+
+fn numeric_literal(input: &[u8]) -> IResult<&[u8], Literal> {
+    let (remaining_input, number) = recognize(tuple((
+        opt(alt((tag("+"), tag("-")))),
+        take_while1(|c: u8| c.is_ascii_digit() || c == b'.'),
+        opt(tuple((
+            alt((tag("e"), tag("E"))),
+            opt(alt((tag("+"), tag("-")))),
+            take_while1(|c: u8| c.is_ascii_digit()),
+        ))),
+    )))(input)?;
+
+    let number = String::from_utf8_lossy(number);
+    let literal = match number.parse::<i64>() {
+        Ok(n) => Literal::Integer(n),
+        Err(_) => match number.parse::<f64>() {
+            Ok(n) => Literal::Real(n),
+            Err(_) => {
+                return Err(nom::Err::Error(Failure::from_error_kind(
+                    input,
+                    ErrorKind::Float,
+                )))
+            }
+        },
+    };
+    Ok((remaining_input, literal))
+}
+
+pub fn parse_create(input: &[u8]) -> IResult<&[u8], SQLCommand> {
+    alt((
+        map(parse_creation, SQLCommand::CreateTable),
+        map(parse_index_creation, SQLCommand::CreateIndex),
+        map(parse_view_creation, SQLCommand::CreateView),
+        map(parse_virtual_table_creation, SQLCommand::CreateVirtualTable),
+    ))(input)
+}
+
+pub fn parse_creation(input: &[u8]) -> IResult<&[u8], CreateTableStatement> {
+    let (
+        remaining_input,
+        (_, _, temporary, _, if_not_exists, table, _, _, mut fields, constraints, _, _, _, _),
+    ) = tuple((
+        keyword("create"),
+        multispace1,
+        opt(terminated(
+            alt((keyword("TEMPORARY"), keyword("TEMP"))),
+            multispace1,
+        )),
+        tuple((keyword("table"), multispace1)),
+        opt(tuple((
+            keyword("IF"),
+            multispace1,
+            keyword("NOT"),
+            multispace1,
+            keyword("EXISTS"),
+            multispace1,
+        ))),
+        terminated(created_name, multispace0),
+        tag("("),
+        multispace0,
+        field_specification_list,
+        many0(table_constraint),
+        multispace0,
+        tag(")"),
+        many0(table_option),
+        opt(preceded(multispace0, tag(";"))),
+    ))(input)?;
+
+    for constraint in &constraints {
+        if let TableConstraint::PrimaryKey(columns) = constraint {
+            for field in fields
+                .iter_mut()
+                .filter(|field| columns.contains(&field.name))
+            {
+                field.is_primary_key = true;
+                field.is_rowid_alias = columns.len() == 1 && field.has_integer_type();
+            }
+        }
+    }
+
+    let (schema, table) = table;
+    Ok((
+        remaining_input,
+        CreateTableStatement {
+            schema,
+            table,
+            temporary: temporary.is_some(),
+            if_not_exists: if_not_exists.is_some(),
+            fields,
+            constraints,
+        },
+    ))
+}
+
+/// A name, optionally qualified with the name of the database it's in, like `aux.users`.
+fn qualified_name(input: &[u8]) -> IResult<&[u8], (Option<String>, String)> {
+    tuple((
+        opt(terminated(
+            identifier,
+            tuple((multispace0, tag("."), multispace0)),
+        )),
+        identifier,
+    ))(input)
+}
+
+/// The name of a table being created. Besides identifiers, SQLite accepts a string here, which
+/// is how virtual table modules name their shadow tables, like `CREATE TABLE 'docs_data'(...)`.
+fn created_name(input: &[u8]) -> IResult<&[u8], (Option<String>, String)> {
+    tuple((
+        opt(terminated(
+            identifier,
+            tuple((multispace0, tag("."), multispace0)),
+        )),
+        alt((identifier, quoted_identifier("'", "''"))),
+    ))(input)
+}
+
+/// `WITHOUT ROWID` or `STRICT` after the column definitions, optionally separated by commas.
+fn table_option(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    preceded(
+        tuple((multispace0, opt(tag(",")), multispace0)),
+        alt((
+            recognize(tuple((keyword("WITHOUT"), multispace1, keyword("ROWID")))),
+            keyword("STRICT"),
+        )),
+    )(input)
+}
+
+pub fn parse_index_creation(input: &[u8]) -> IResult<&[u8], CreateIndexStatement> {
+    let (
+        remaining_input,
+        (_, _, unique, _, _, if_not_exists, name, _, _, table, columns, where_clause, _, _),
+    ) = tuple((
+        keyword("create"),
+        multispace1,
+        opt(terminated(keyword("UNIQUE"), multispace1)),
+        keyword("index"),
+        multispace1,
+        opt(tuple((
+            keyword("IF"),
+            multispace1,
+            keyword("NOT"),
+            multispace1,
+            keyword("EXISTS"),
+            multispace1,
+        ))),
+        terminated(qualified_name, multispace0), // index name
+        keyword("ON"),
+        multispace0,
+        identifier, // table
+        indexed_column_list,
+        parse_where_clause,
+        multispace0,
+        opt(tag(";")),
+    ))(input)?;
+
+    let (schema, name) = name;
+    let collations = columns
+        .iter()
+        .map(|column| column.collation.clone())
+        .collect();
+    let descending = columns.iter().map(|column| column.descending).collect();
+    let (fields, expressions) = columns
+        .into_iter()
+        .map(|column| match column.column {
+            Expr::Column(name) => (name, None),
+            expr => (expr.to_string(), Some(expr)),
+        })
+        .unzip();
+    Ok((
+        remaining_input,
+        CreateIndexStatement {
+            schema,
+            name,
+            table,
+            unique: unique.is_some(),
+            fields,
+            expressions,
+            collations,
+            descending,
+            where_clause,
+            if_not_exists: if_not_exists.is_some(),
+        },
+    ))
+}
+
+pub fn parse_view_creation(input: &[u8]) -> IResult<&[u8], CreateViewStatement> {
+    let (remaining_input, (_, _, temporary, _, if_not_exists, name, columns, _, select)) =
+        tuple((
+            keyword("create"),
+            multispace1,
+            opt(terminated(
+                alt((keyword("TEMPORARY"), keyword("TEMP"))),
+                multispace1,
+            )),
+            tuple((keyword("view"), multispace1)),
+            opt(tuple((
+                keyword("IF"),
+                multispace1,
+                keyword("NOT"),
+                multispace1,
+                keyword("EXISTS"),
+                multispace1,
+            ))),
+            qualified_name,
+            opt(column_list),
+            tuple((multispace0, keyword("AS"), multispace1)),
+            // Only a SELECT can follow AS, so its errors are the ones to report
+            cut(compound_selection),
+        ))(input)?;
+
+    let (schema, name) = name;
+    Ok((
+        remaining_input,
+        CreateViewStatement {
+            schema,
+            name,
+            temporary: temporary.is_some(),
+            if_not_exists: if_not_exists.is_some(),
+            columns: columns.unwrap_or_default(),
+            select,
+        },
+    ))
+}
+
+pub fn parse_virtual_table_creation(input: &[u8]) -> IResult<&[u8], CreateVirtualTableStatement> {
+    let (remaining_input, (_, _, if_not_exists, name, _, module, arguments, _)) = tuple((
+        keyword("create"),
+        tuple((
+            multispace1,
+            keyword("virtual"),
+            multispace1,
+            keyword("table"),
+            multispace1,
+        )),
+        opt(tuple((
+            keyword("IF"),
+            multispace1,
+            keyword("NOT"),
+            multispace1,
+            keyword("EXISTS"),
+            multispace1,
+        ))),
+        created_name,
+        tuple((multispace1, keyword("USING"), multispace1)),
+        identifier,
+        opt(preceded(multispace0, module_arguments)),
+        opt(preceded(multispace0, tag(";"))),
+    ))(input)?;
+
+    let (schema, name) = name;
+    Ok((
+        remaining_input,
+        CreateVirtualTableStatement {
+            schema,
+            name,
+            if_not_exists: if_not_exists.is_some(),
+            module,
+            arguments: arguments.unwrap_or_default(),
+        },
+    ))
+}
+
+/// The parenthesized arguments of a virtual table module, split at the commas that aren't
+/// nested in parentheses or quotes.
+fn module_arguments(input: &[u8]) -> IResult<&[u8], Vec<String>> {
+    let (input, _) = tag("(")(input)?;
+    let mut arguments = vec![];
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, &chr) in input.iter().enumerate() {
+        match (quote, chr) {
+            (Some(q), chr) if chr == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'\'' | b'"' | b'`') => quote = Some(chr),
+            (None, b'[') => quote = Some(b']'),
+            (None, b'(') => depth += 1,
+            (None, b')') if depth > 0 => depth -= 1,
+            (None, b',' | b')') => {
+                let argument = String::from_utf8_lossy(&input[start..i]);
+                if chr == b',' || !argument.trim().is_empty() || !arguments.is_empty() {
+                    arguments.push(argument.trim().to_string());
+                }
+                start = i + 1;
+                if chr == b')' {
+                    return Ok((&input[i + 1..], arguments));
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(nom::Err::Error(Failure::from_error_kind(
+        &input[input.len()..],
+        ErrorKind::Char,
+    )))
+}
+
+/// An identifier, bare or quoted in any of SQLite's styles: `"name"`, `` `name` `` or `[name]`.
+/// Quoted identifiers may contain any character, and a doubled quote stands for the quote itself.
+fn identifier(input: &[u8]) -> IResult<&[u8], String> {
+    alt((
+        quoted_identifier("\"", "\"\""),
+        quoted_identifier("`", "``"),
+        map(delimited(tag("["), is_not("]"), tag("]")), |name| {
+            String::from_utf8_lossy(name).into_owned()
+        }),
+        map(take_while1(is_sql_identifier), |name| {
+            String::from_utf8_lossy(name).into_owned()
+        }),
+    ))(input)
+}
+
+fn quoted_identifier<'a>(
+    quote: &'static str,
+    escaped_quote: &'static str,
+) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], String> {
+    map(
+        delimited(
+            tag(quote),
+            recognize(many1(alt((tag(escaped_quote), is_not(quote))))),
+            tag(quote),
+        ),
+        move |name| String::from_utf8_lossy(name).replace(escaped_quote, quote),
+    )
+}
+
+fn is_sql_identifier(chr: u8) -> bool {
+    is_alphanumeric(chr) || chr == b'_'
+}
+
+fn field_specification_list(input: &[u8]) -> IResult<&[u8], Vec<Field>> {
+    many1(preceded(not(table_constraint_start), field_specification))(input)
+}
+
+/// Matches a keyword as a whole word, so `unique` doesn't match the start of `uniquely`.
+/// A keyword, in any case. Parse errors list the keywords that were expected.
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+    move |input| {
+        let result: IResult<&[u8], &[u8]> =
+            terminated(tag_no_case(word), not(take_while1(is_sql_identifier)))(input);
+        result.map_err(|e| {
+            e.map(|_| Failure {
+                input,
+                expected: vec![word],
+            })
+        })
+    }
+}
+
+fn table_constraint_start(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    alt((
+        keyword("CONSTRAINT"),
+        keyword("PRIMARY"),
+        keyword("UNIQUE"),
+        keyword("CHECK"),
+        keyword("FOREIGN"),
+    ))(input)
+}
+
+fn table_constraint(input: &[u8]) -> IResult<&[u8], TableConstraint> {
+    let primary_key = map(
+        preceded(
+            tuple((tag_no_case("PRIMARY"), multispace1, tag_no_case("KEY"))),
+            column_list,
+        ),
+        TableConstraint::PrimaryKey,
+    );
+    let unique = map(
+        preceded(tag_no_case("UNIQUE"), column_list),
+        TableConstraint::Unique,
+    );
+    let check = map(
+        preceded(tuple((tag_no_case("CHECK"), multispace0)), parenthesized),
+        |expression| {
+            let expression = &expression[1..expression.len() - 1];
+            TableConstraint::Check(String::from_utf8_lossy(expression).into_owned())
+        },
+    );
+    let foreign_key = map(
+        tuple((
+            tag_no_case("FOREIGN"),
+            multispace1,
+            tag_no_case("KEY"),
+            column_list,
+            foreign_key_clause,
+        )),
+        |(_, _, _, columns, (foreign_table, foreign_columns))| TableConstraint::ForeignKey {
+            columns,
+            foreign_table,
+            foreign_columns,
+        },
+    );
+
+    delimited(
+        tuple((
+            multispace0,
+            opt(tuple((
+                tag_no_case("CONSTRAINT"),
+                multispace1,
+                identifier,
+                multispace1,
+            ))),
+        )),
+        alt((primary_key, unique, check, foreign_key)),
+        tuple((
+            opt(conflict_clause),
+            opt(delimited(multispace0, tag(","), multispace0)),
+        )),
+    )(input)
+}
+
+/// A parenthesized list of column names. Sort orders and collations are ignored.
+fn column_list(input: &[u8]) -> IResult<&[u8], Vec<String>> {
+    map(
+        |input| sorted_list(identifier, input),
+        |columns| columns.into_iter().map(|column| column.column).collect(),
+    )(input)
+}
+
+/// The columns of an index, which can be expressions of the columns of the table.
+fn indexed_column_list(input: &[u8]) -> IResult<&[u8], Vec<SortedColumn<Expr>>> {
+    sorted_list(expr, input)
+}
+
+/// A column of an index or key, with its collation and sort order.
+struct SortedColumn<O> {
+    column: O,
+    collation: Option<String>,
+    descending: bool,
+}
+
+/// A parenthesized list of the columns of an index or key, each with its `COLLATE` name and
+/// whether it's sorted in descending order.
+fn sorted_list<'a, O>(
+    column: impl FnMut(&'a [u8]) -> IResult<&'a [u8], O>,
+    input: &'a [u8],
+) -> IResult<&'a [u8], Vec<SortedColumn<O>>> {
+    let indexed_column = tuple((
+        column,
+        opt(preceded(
+            tuple((multispace1, tag_no_case("COLLATE"), multispace1)),
+            identifier,
+        )),
+        opt(preceded(
+            multispace1,
+            alt((
+                map(keyword("ASC"), |_| false),
+                map(keyword("DESC"), |_| true),
+            )),
+        )),
+    ));
+
+    delimited(
+        tuple((multispace0, tag("("), multispace0)),
+        separated_list1(
+            tuple((multispace0, tag(","), multispace0)),
+            map(indexed_column, |(column, collation, descending)| {
+                SortedColumn {
+                    column,
+                    collation,
+                    descending: descending.unwrap_or(false),
+                }
+            }),
+        ),
+        tuple((multispace0, tag(")"))),
+    )(input)
+}
+
+/// `REFERENCES table (columns)` followed by any ON DELETE/ON UPDATE/MATCH/DEFERRABLE clauses.
+fn foreign_key_clause(input: &[u8]) -> IResult<&[u8], (String, Vec<String>)> {
+    let action = alt((
+        recognize(tuple((
+            tag_no_case("ON"),
+            multispace1,
+            alt((tag_no_case("DELETE"), tag_no_case("UPDATE"))),
+            multispace1,
+            alt((
+                recognize(tuple((tag_no_case("SET"), multispace1, identifier))),
+                recognize(tuple((
+                    tag_no_case("NO"),
+                    multispace1,
+                    tag_no_case("ACTION"),
+                ))),
+                tag_no_case("CASCADE"),
+                tag_no_case("RESTRICT"),
+            )),
+        ))),
+        recognize(tuple((tag_no_case("MATCH"), multispace1, identifier))),
+        recognize(tuple((
+            opt(tuple((tag_no_case("NOT"), multispace1))),
+            tag_no_case("DEFERRABLE"),
+            opt(tuple((
+                multispace1,
+                tag_no_case("INITIALLY"),
+                multispace1,
+                identifier,
+            ))),
+        ))),
+    ));
+
+    let (remaining_input, (_, _, _, foreign_table, foreign_columns, _)) = tuple((
+        multispace0,
+        tag_no_case("REFERENCES"),
+        multispace1,
+        identifier,
+        opt(column_list),
+        many0(preceded(multispace1, action)),
+    ))(input)?;
+
+    Ok((
+        remaining_input,
+        (foreign_table, foreign_columns.unwrap_or_default()),
+    ))
+}
+
+fn column_constraint(input: &[u8]) -> IResult<&[u8], Option<ColumnConstraint>> {
+    let constraint_name = map(
+        delimited(
+            tuple((multispace0, tag_no_case("CONSTRAINT"), multispace1)),
+            identifier,
+            multispace0,
+        ),
+        |_| None,
+    );
+    let not_null = map(
+        delimited(
+            multispace0,
+            tuple((keyword("NOT"), multispace1, keyword("NULL"))),
+            terminated(opt(conflict_clause), multispace0),
+        ),
+        |_| Some(ColumnConstraint::NotNull),
+    );
+    let null = map(delimited(multispace0, keyword("NULL"), multispace0), |_| {
+        None
+    });
+    let unique = map(
+        delimited(
+            multispace0,
+            tag_no_case("UNIQUE"),
+            terminated(opt(conflict_clause), multispace0),
+        ),
+        |_| Some(ColumnConstraint::Unique),
+    );
+    let collate = map(
+        delimited(
+            tuple((multispace0, tag_no_case("COLLATE"), multispace1)),
+            identifier,
+            multispace0,
+        ),
+        |collation| Some(ColumnConstraint::Collate(collation)),
+    );
+    let default = map(
+        delimited(
+            tuple((multispace0, tag_no_case("DEFAULT"), multispace0)),
+            default_value,
+            multispace0,
+        ),
+        |value| Some(ColumnConstraint::Default(value)),
+    );
+    let auto_increment = map(
+        delimited(multispace0, tag_no_case("AUTOINCREMENT"), multispace0),
+        |_| Some(ColumnConstraint::AutoIncrement),
+    );
+    let primary_key = map(
+        delimited(
+            tuple((multispace0, keyword("PRIMARY"), multispace1, keyword("KEY"))),
+            opt(preceded(
+                multispace1,
+                alt((tag_no_case("ASC"), tag_no_case("DESC"))),
+            )),
+            tuple((opt(conflict_clause), multispace0)),
+        ),
+        |order| {
+            Some(ColumnConstraint::PrimaryKey {
+                descending: order.map_or(false, |order| order.eq_ignore_ascii_case(b"DESC")),
+            })
+        },
+    );
+
+    let check = map(
+        delimited(
+            tuple((multispace0, keyword("CHECK"), multispace0)),
+            parenthesized,
+            multispace0,
+        ),
+        |_| None,
+    );
+    let generated = map(
+        delimited(
+            tuple((
+                multispace0,
+                opt(tuple((
+                    keyword("GENERATED"),
+                    multispace1,
+                    keyword("ALWAYS"),
+                    multispace1,
+                ))),
+                keyword("AS"),
+                multispace0,
+            )),
+            parenthesized,
+            tuple((
+                opt(preceded(
+                    multispace1,
+                    alt((keyword("STORED"), keyword("VIRTUAL"))),
+                )),
+                multispace0,
+            )),
+        ),
+        |_| None,
+    );
+
+    let references = map(terminated(foreign_key_clause, multispace0), |_| None);
+
+    alt((
+        constraint_name,
+        references,
+        not_null,
+        null,
+        unique,
+        default,
+        collate,
+        auto_increment,
+        primary_key,
+        check,
+        generated,
+        map(unknown_constraint, |_| None),
+    ))(input)
+}
+
+/// Skips a token of a constraint that isn't understood, so that schemas using newer or
+/// unusual syntax can still be read. Commas and closing parentheses end the column definition
+/// and are never skipped.
+fn unknown_constraint(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    delimited(
+        multispace0,
+        alt((
+            parenthesized,
+            string_literal,
+            recognize(identifier),
+            take_while1(|c: u8| !c.is_ascii_whitespace() && !b",()'\"`[".contains(&c)),
+        )),
+        multispace0,
+    )(input)
+}
+
+/// `ON CONFLICT <resolution>`, which only matters when writing.
+fn conflict_clause(input: &[u8]) -> IResult<&[u8], String> {
+    preceded(
+        tuple((
+            multispace1,
+            tag_no_case("ON"),
+            multispace1,
+            tag_no_case("CONFLICT"),
+            multispace1,
+        )),
+        identifier,
+    )(input)
+}
+
+/// The source text of a DEFAULT value: a literal, a keyword like CURRENT_TIMESTAMP or a
+/// parenthesized expression (without the outer parentheses).
+fn default_value(input: &[u8]) -> IResult<&[u8], String> {
+    let (remaining_input, value) = alt((
+        map(parenthesized, |group: &[u8]| &group[1..group.len() - 1]),
+        string_literal,
+        recognize(tuple((alt((tag("x"), tag("X"))), string_literal))),
+        recognize(tuple((
+            opt(alt((tag("+"), tag("-")))),
+            multispace0,
+            numeric_literal,
+        ))),
+        take_while1(is_sql_identifier),
+    ))(input)?;
+
+    Ok((remaining_input, String::from_utf8_lossy(value).into_owned()))
+}
+
+/// A single quoted string literal including its quotes, with '' as an escaped quote.
+fn string_literal(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    recognize(delimited(
+        tag("'"),
+        many0(alt((tag("''"), is_not("'")))),
+        tag("'"),
+    ))(input)
+}
+
+/// A balanced parenthesized group, including the parentheses. Parentheses inside string
+/// literals and quoted identifiers are ignored.
+fn parenthesized(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (_, _) = tag("(")(input)?;
+
+    let mut depth = 0;
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok((&input[i + 1..], &input[..i + 1]));
+                }
+            }
+            b'\'' => {
+                let (rest, _) = string_literal(&input[i..])?;
+                i = input.len() - rest.len();
+                continue;
+            }
+            b'"' | b'`' | b'[' => {
+                let (rest, _) = identifier(&input[i..])?;
+                i = input.len() - rest.len();
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    Err(nom::Err::Error(Failure::from_error_kind(
+        input,
+        ErrorKind::Char,
+    )))
+}
+
+/// Words that end a type name and start a column constraint.
+const CONSTRAINT_KEYWORDS: &[&str] = &[
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+    "AUTOINCREMENT",
+];
+
+/// A declared column type, which may consist of several words and a size, e.g.
+/// `UNSIGNED BIG INT` or `DECIMAL(10, 5)`.
+fn type_name(input: &[u8]) -> IResult<&[u8], String> {
+    let word = alt((
+        verify(identifier, |word: &str| {
+            !CONSTRAINT_KEYWORDS
+                .iter()
+                .any(|keyword| keyword.eq_ignore_ascii_case(word))
+        }),
+        // SQLite also accepts a string as the type
+        quoted_identifier("'", "''"),
+    ));
+    let (remaining_input, (words, size)) =
+        tuple((many1(terminated(word, multispace0)), opt(parenthesized)))(input)?;
+
+    let mut ty = words.join(" ");
+    if let Some(size) = size {
+        ty.push_str(&String::from_utf8_lossy(size));
+    }
+    Ok((remaining_input, ty))
+}
+
+fn field_specification(input: &[u8]) -> IResult<&[u8], Field> {
+    let (remaining_input, (column, ty, constraints, _)) = tuple((
+        identifier,
+        opt(preceded(multispace1, type_name)),
+        many0(column_constraint),
+        opt(delimited(multispace0, tag(","), multispace0)),
+    ))(input)?;
+
+    let mut field = Field::new(column);
+    let mut descending = false;
+    for constraint in constraints.into_iter().flatten() {
+        match constraint {
+            ColumnConstraint::PrimaryKey {
+                descending: is_descending,
+            } => {
+                field.is_primary_key = true;
+                descending = is_descending;
+            }
+            ColumnConstraint::NotNull => field.not_null = true,
+            ColumnConstraint::Unique => field.unique = true,
+            ColumnConstraint::Default(value) => field.default = Some(value),
+            ColumnConstraint::Collate(collation) => field.collation = Some(collation),
+            ColumnConstraint::AutoIncrement => field.autoincrement = true,
+        }
+    }
+    field.ty = ty;
+    // A quirk of SQLite: INTEGER PRIMARY KEY DESC on the column doesn't alias the rowid, but
+    // PRIMARY KEY (column DESC) on the table does
+    field.is_rowid_alias = field.is_primary_key && !descending && field.has_integer_type();
+
+    Ok((remaining_input, field))
+}
+
+/// Splits a script into its statements at semicolons that aren't part of a string literal,
+/// quoted identifier or comment. The terminating semicolons and empty statements are dropped.
+pub fn split_statements(input: &str) -> Vec<&str> {
+    let mut statements = vec![];
+    let mut start = 0;
+    for end in statement_ends(input) {
+        statements.push(&input[start..end]);
+        start = end + 1;
+    }
+    statements.push(&input[start..]);
+
+    statements
+        .into_iter()
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+/// Parses a script of statements separated by semicolons, see [`split_statements`]. Each
+/// statement is returned with its source without leading comments, which is what CREATE
+/// statements store. Fails with the number of the first statement that can't be parsed.
+pub fn parse_script(input: &str) -> Result<Vec<(&str, SQLCommand)>, Error> {
+    split_statements(input)
+        .into_iter()
+        .map(skip_leading_comments)
+        .filter(|statement| !statement.is_empty())
+        .enumerate()
+        .map(|(position, statement)| {
+            parse_statement(&strip_comments(statement))
+                .map(|command| (statement, command))
+                .map_err(|error| Error::ScriptSyntax {
+                    statement: position + 1,
+                    error,
+                })
+        })
+        .collect()
+}
+
+/// Skips the whitespace and comments at the start of a statement.
+fn skip_leading_comments(mut input: &str) -> &str {
+    loop {
+        input = input.trim_start();
+        if let Some(rest) = input.strip_prefix("--") {
+            input = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if let Some(rest) = input.strip_prefix("/*") {
+            input = rest.find("*/").map_or("", |end| &rest[end + 2..]);
+        } else {
+            return input;
+        }
+    }
+}
+
+/// Returns true if the input doesn't end in the middle of a statement, i.e. everything but
+/// whitespace is terminated by a semicolon.
+pub fn is_complete(input: &str) -> bool {
+    let rest = match statement_ends(input).last() {
+        Some(end) => &input[end + 1..],
+        None => input,
+    };
+    rest.trim().is_empty()
+}
+
+/// Replaces each comment with a space. String literals and quoted identifiers are kept as they
+/// are, even if they contain comment markers.
+pub fn strip_comments(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        let (closing, is_comment) = match c {
+            '\'' => ("'", false),
+            '"' => ("\"", false),
+            '`' => ("`", false),
+            '[' => ("]", false),
+            '-' if rest.starts_with("--") => ("\n", true),
+            '/' if rest.starts_with("/*") => ("*/", true),
+            c => {
+                output.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+        };
+
+        let end = rest[1..]
+            .find(closing)
+            .map_or(rest.len(), |pos| 1 + pos + closing.len());
+        if is_comment {
+            output.push(' ');
+        } else {
+            output.push_str(&rest[..end]);
+        }
+        rest = &rest[end..];
+    }
+    output
+}
+
+/// Byte offsets of the semicolons terminating statements.
+fn statement_ends(input: &str) -> Vec<usize> {
+    let bytes = input.as_bytes();
+    let mut ends = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let closing = match bytes[i] {
+            b'\'' => Some(&b"'"[..]),
+            b'"' => Some(&b"\""[..]),
+            b'`' => Some(&b"`"[..]),
+            b'[' => Some(&b"]"[..]),
+            b'-' if bytes.get(i + 1) == Some(&b'-') => Some(&b"\n"[..]),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => Some(&b"*/"[..]),
+            b';' => {
+                ends.push(i);
+                None
+            }
+            _ => None,
+        };
+
+        match closing {
+            Some(closing) => {
+                let from = i + 1;
+                i = bytes[from..]
+                    .windows(closing.len())
+                    .position(|window| window == closing)
+                    .map_or(bytes.len(), |pos| from + pos + closing.len());
+            }
+            None => i += 1,
+        }
+    }
+    ends
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str) -> ResultColumn {
+        ResultColumn::Expr {
+            expr: Expr::Column(name.to_string()),
+            alias: None,
+            text: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_select_with_one_field() {
+        let input = b"SELECT id FROM test";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Fields(SelectFields {
+                from: TableSource::Table {
+                    schema: None,
+                    name: "test".to_string()
+                },
+                fields: vec![column("id")],
+                alias: None,
+                where_clause: None
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_select_with_two_fields() {
+        let input = b"SELECT id, name FROM test";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Fields(SelectFields {
+                from: TableSource::Table {
+                    schema: None,
+                    name: "test".to_string()
+                },
+                fields: vec![column("id"), column("name")],
+                alias: None,
+                where_clause: None
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_select_star() {
+        let input = b"SELECT * FROM sqlite_schema";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Fields(SelectFields {
+                from: TableSource::Table {
+                    schema: None,
+                    name: "sqlite_schema".to_string()
+                },
+                fields: vec![ResultColumn::All],
+                alias: None,
+                where_clause: None
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_select_with_where() {
+        let input = b"SELECT id, name FROM test WHERE super_name = 'test string'";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Fields(SelectFields {
+                from: TableSource::Table {
+                    schema: None,
+                    name: "test".to_string()
+                },
+                fields: vec![column("id"), column("name")],
+                alias: None,
+                where_clause: Some(Expr::Binary {
+                    op: BinaryOperator::Eq,
+                    left: Box::new(Expr::Column("super_name".to_string())),
+                    right: Box::new(Expr::Literal(Literal::Text("test string".to_string())))
+                })
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_where_literals() {
+        let parse_value = |input: &[u8]| match parse(input).unwrap().1 {
+            SQLCommand::Select(SelectStatement::Fields(select)) => {
+                select.where_clause.unwrap().equality().unwrap().1.clone()
+            }
+            _ => panic!("not a select"),
+        };
+
+        assert_eq!(
+            parse_value(b"SELECT a FROM t WHERE b = 5"),
+            Literal::Integer(5)
+        );
+        assert_eq!(
+            parse_value(b"SELECT a FROM t WHERE b = -2.5"),
+            Literal::Real(-2.5)
+        );
+        assert_eq!(
+            parse_value(b"SELECT a FROM t WHERE b = 1e3"),
+            Literal::Real(1000.0)
+        );
+        assert_eq!(
+            parse_value(b"SELECT a FROM t WHERE b = NULL"),
+            Literal::Null
+        );
+        assert_eq!(
+            parse_value(b"SELECT a FROM t WHERE b = 'it''s'"),
+            Literal::Text("it's".to_string())
+        );
+        assert_eq!(
+            parse_value(b"SELECT a FROM t WHERE b = X'53514c'"),
+            Literal::Blob(b"SQL".to_vec())
+        );
+        assert_eq!(
+            parse_value(b"SELECT a FROM t WHERE b = x''"),
+            Literal::Blob(vec![])
+        );
+        assert!(blob_literal(b"X'5'").is_err());
+    }
+
+    #[test]
+    fn parse_where_expressions() {
+        let parse_where = |input: &[u8]| match parse(input).unwrap().1 {
+            SQLCommand::Select(SelectStatement::Fields(select)) => select.where_clause.unwrap(),
+            _ => panic!("not a select"),
+        };
+        let column = |name: &str| Box::new(Expr::Column(name.to_string()));
+        let integer = |n| Box::new(Expr::Literal(Literal::Integer(n)));
+
+        assert_eq!(
+            parse_where(b"SELECT a FROM t WHERE b IS NULL"),
+            Expr::IsNull {
+                expr: column("b"),
+                negated: false
+            }
+        );
+        assert_eq!(
+            parse_where(b"SELECT a FROM t WHERE b is not null"),
+            Expr::IsNull {
+                expr: column("b"),
+                negated: true
+            }
+        );
+        // AND binds tighter than OR
+        assert_eq!(
+            parse_where(b"SELECT a FROM t WHERE a = 1 OR b <> 2 AND NOT c >= 3"),
+            Expr::Binary {
+                op: BinaryOperator::Or,
+                left: Box::new(Expr::Binary {
+                    op: BinaryOperator::Eq,
+                    left: column("a"),
+                    right: integer(1)
+                }),
+                right: Box::new(Expr::Binary {
+                    op: BinaryOperator::And,
+                    left: Box::new(Expr::Binary {
+                        op: BinaryOperator::NotEq,
+                        left: column("b"),
+                        right: integer(2)
+                    }),
+                    right: Box::new(Expr::Not(Box::new(Expr::Binary {
+                        op: BinaryOperator::GtEq,
+                        left: column("c"),
+                        right: integer(3)
+                    })))
+                })
+            }
+        );
+        assert_eq!(
+            parse_where(b"SELECT a FROM t WHERE (a = 1 OR a = 2) AND b"),
+            Expr::Binary {
+                op: BinaryOperator::And,
+                left: Box::new(Expr::Binary {
+                    op: BinaryOperator::Or,
+                    left: Box::new(Expr::Binary {
+                        op: BinaryOperator::Eq,
+                        left: column("a"),
+                        right: integer(1)
+                    }),
+                    right: Box::new(Expr::Binary {
+                        op: BinaryOperator::Eq,
+                        left: column("a"),
+                        right: integer(2)
+                    })
+                }),
+                right: column("b")
+            }
+        );
+    }
+
+    #[test]
+    fn parse_parameters() {
+        let parse_where = |input: &[u8]| match parse(input).unwrap().1 {
+            SQLCommand::Select(SelectStatement::Fields(select)) => select.where_clause.unwrap(),
+            _ => panic!("not a select"),
+        };
+        let parameters = |input: &[u8]| {
+            let mut parameters = vec![];
+            parse_where(input).map_parameters(&mut |parameter| {
+                parameters.push(parameter.clone());
+                Expr::Parameter(parameter.clone())
+            });
+            parameters
+        };
+
+        assert_eq!(
+            parameters(
+                b"SELECT a FROM t WHERE a = ? AND b = ?12 OR c = :name AND d = @x AND e = $y"
+            ),
+            vec![
+                Parameter::Anonymous,
+                Parameter::Numbered(12),
+                Parameter::Named(":name".to_string()),
+                Parameter::Named("@x".to_string()),
+                Parameter::Named("$y".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_insert() {
+        let (remaining, result) =
+            parse(b"INSERT INTO main.t (a, \"b c\") VALUES (1, 'x'), (-2.5, NULL);").unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(
+            result,
+            SQLCommand::Insert(InsertStatement {
+                schema: Some("main".to_string()),
+                table: "t".to_string(),
+                columns: vec!["a".to_string(), "b c".to_string()],
+                rows: vec![
+                    vec![Expr::Literal(1.into()), Expr::Literal("x".into())],
+                    vec![Expr::Literal((-2.5).into()), Expr::Literal(Literal::Null)],
+                ],
+            })
+        );
+
+        let (_, result) = parse(b"insert into t values (?, :b)").unwrap();
+        let SQLCommand::Insert(insert) = result else {
+            panic!("not an insert");
+        };
+        assert!(insert.columns.is_empty());
+        assert_eq!(
+            insert.rows,
+            vec![vec![
+                Expr::Parameter(Parameter::Anonymous),
+                Expr::Parameter(Parameter::Named(":b".to_string())),
+            ]]
+        );
+        assert_eq!(parse_literal("('it''s')"), Some("it's".into()));
+        assert_eq!(parse_literal("CURRENT_TIMESTAMP"), None);
+    }
+
+    #[test]
+    fn normalize_schema_sql() {
+        assert_eq!(
+            schema_sql("  create temp table if not exists main.t(x) ; ").as_deref(),
+            Some("CREATE TABLE t(x)")
+        );
+        assert_eq!(
+            schema_sql("create unique index \"i\" on t (x);").as_deref(),
+            Some("CREATE UNIQUE INDEX \"i\" on t (x)")
+        );
+        assert_eq!(schema_sql("SELECT 1"), None);
+    }
+
+    #[test]
+    fn parse_select_with_count() {
+        let input = b"SELECT COUNT(*) FROM test";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Count {
+                schema: None,
+                table: "test".to_string(),
+                where_clause: None,
+            })
+        );
+
+        let input = b"SELECT COUNT(*) FROM test WHERE color = 'Red';";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Count {
+                schema: None,
+                table: "test".to_string(),
+                where_clause: Some(Expr::Binary {
+                    op: BinaryOperator::Eq,
+                    left: Box::new(Expr::Column("color".to_string())),
+                    right: Box::new(Expr::Literal(Literal::Text("Red".to_string()))),
+                }),
+            })
+        );
+    }
+
+    fn field(name: &str, ty: &str) -> Field {
+        Field {
+            ty: Some(ty.to_string()),
+            ..Field::new(name.to_string())
+        }
+    }
+
+    fn rowid_alias(name: &str, ty: &str) -> Field {
+        Field {
+            is_primary_key: true,
+            is_rowid_alias: true,
+            ..field(name, ty)
+        }
+    }
+
+    #[test]
+    fn parse_create_table_with_one_entry() {
         let input = b"CREATE TABLE IF NOT EXISTS test (id INTEGER primary key autoincrement)";
         let (_, result) = parse(input).unwrap();
 
         assert_eq!(
             result,
             SQLCommand::CreateTable(CreateTableStatement {
+                schema: None,
                 table: "test".to_string(),
+                temporary: false,
+                if_not_exists: true,
                 fields: vec![Field {
-                    name: "id".to_string(),
-                    is_primary_key: true
-                },]
+                    autoincrement: true,
+                    ..rowid_alias("id", "INTEGER")
+                }],
+                constraints: vec![],
+            })
+        );
+
+        match parse(b"CREATE TEMP TABLE temp.scratch (a)").unwrap().1 {
+            SQLCommand::CreateTable(create) => {
+                assert!(create.temporary);
+                assert_eq!(create.schema.as_deref(), Some("temp"));
+            }
+            command => panic!("not a create table: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn parse_create_table_with_two_entries() {
+        let input = b"CREATE TABLE \"test\" (id INTEGER primary key, \"name field\" TEXT NOT NULL)";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::CreateTable(CreateTableStatement {
+                schema: None,
+                table: "test".to_string(),
+                temporary: false,
+                if_not_exists: false,
+                fields: vec![
+                    rowid_alias("id", "INTEGER"),
+                    Field {
+                        not_null: true,
+                        ..field("name field", "TEXT")
+                    }
+                ],
+                constraints: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_create_super_heroes() {
+        let input = b"CREATE TABLE IF NOT EXISTS \"superheroes\" (id integer primary key autoincrement, name text not null, eye_color text, hair_color text, appearance_count integer, first_appearance text, first_appearance_year text);";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::CreateTable(CreateTableStatement {
+                schema: None,
+                table: "superheroes".to_string(),
+                temporary: false,
+                if_not_exists: true,
+                fields: vec![
+                    Field {
+                        autoincrement: true,
+                        ..rowid_alias("id", "integer")
+                    },
+                    Field {
+                        not_null: true,
+                        ..field("name", "text")
+                    },
+                    field("eye_color", "text"),
+                    field("hair_color", "text"),
+                    field("appearance_count", "integer"),
+                    field("first_appearance", "text"),
+                    field("first_appearance_year", "text")
+                ],
+                constraints: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_quoted_identifiers() {
+        let input =
+            b"CREATE TABLE [my table] (\"first \"\"name\"\"\" text, `a``b` int, [x-y.z] int)";
+        let (_, result) = parse_creation(input).unwrap();
+
+        assert_eq!(result.table, "my table");
+        assert_eq!(
+            result
+                .fields
+                .iter()
+                .map(|field| field.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["first \"name\"", "a`b", "x-y.z"]
+        );
+
+        let (_, result) = parse(b"SELECT `a``b`, [x-y.z] FROM \"my table\"").unwrap();
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Fields(SelectFields {
+                from: TableSource::Table {
+                    schema: None,
+                    name: "my table".to_string()
+                },
+                fields: vec![
+                    ResultColumn::Expr {
+                        expr: Expr::Column("a`b".to_string()),
+                        alias: None,
+                        text: "`a``b`".to_string()
+                    },
+                    ResultColumn::Expr {
+                        expr: Expr::Column("x-y.z".to_string()),
+                        alias: None,
+                        text: "[x-y.z]".to_string()
+                    }
+                ],
+                alias: None,
+                where_clause: None
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_create_table_with_types_and_defaults() {
+        let input = b"CREATE TABLE t (a, b VARCHAR(10) DEFAULT 'it''s', c UNSIGNED BIG INT NOT NULL DEFAULT -1, d DECIMAL(10, 5) DEFAULT (round(1.5, 0)), e TEXT DEFAULT CURRENT_TIMESTAMP)";
+        let (_, result) = parse(input).unwrap();
+
+        assert_eq!(
+            result,
+            SQLCommand::CreateTable(CreateTableStatement {
+                schema: None,
+                table: "t".to_string(),
+                temporary: false,
+                if_not_exists: false,
+                fields: vec![
+                    Field::new("a".to_string()),
+                    Field {
+                        default: Some("'it''s'".to_string()),
+                        ..field("b", "VARCHAR(10)")
+                    },
+                    Field {
+                        not_null: true,
+                        default: Some("-1".to_string()),
+                        ..field("c", "UNSIGNED BIG INT")
+                    },
+                    Field {
+                        default: Some("round(1.5, 0)".to_string()),
+                        ..field("d", "DECIMAL(10, 5)")
+                    },
+                    Field {
+                        default: Some("CURRENT_TIMESTAMP".to_string()),
+                        ..field("e", "TEXT")
+                    },
+                ],
+                constraints: vec![],
             })
         );
     }
 
     #[test]
-    fn parse_create_table_with_two_entries() {
-        let input = b"CREATE TABLE \"test\" (id INTEGER primary key, \"name field\" TEXT NOT NULL)";
+    fn parse_create_table_with_constraints() {
+        let input = b"CREATE TABLE t (id INTEGER CONSTRAINT pk PRIMARY KEY ASC ON CONFLICT REPLACE, email TEXT NULL UNIQUE ON CONFLICT ABORT COLLATE NOCASE, name TEXT COLLATE rtrim NOT NULL)";
         let (_, result) = parse(input).unwrap();
 
         assert_eq!(
             result,
             SQLCommand::CreateTable(CreateTableStatement {
-                table: "test".to_string(),
+                schema: None,
+                table: "t".to_string(),
+                temporary: false,
+                if_not_exists: false,
                 fields: vec![
+                    rowid_alias("id", "INTEGER"),
                     Field {
-                        name: "id".to_string(),
-                        is_primary_key: true
+                        unique: true,
+                        collation: Some("NOCASE".to_string()),
+                        ..field("email", "TEXT")
                     },
-                    Field::new("name field".to_string())
-                ]
+                    Field {
+                        not_null: true,
+                        collation: Some("rtrim".to_string()),
+                        ..field("name", "TEXT")
+                    },
+                ],
+                constraints: vec![],
             })
         );
     }
 
     #[test]
-    fn parse_create_super_heroes() {
-        let input = b"CREATE TABLE IF NOT EXISTS \"superheroes\" (id integer primary key autoincrement, name text not null, eye_color text, hair_color text, appearance_count integer, first_appearance text, first_appearance_year text);";
+    fn automatic_indexes_of_constraints() {
+        let columns = |statement: &str| {
+            let (_, table) = parse_creation(statement.as_bytes()).unwrap();
+            table.automatic_indexes()
+        };
+        assert_eq!(
+            columns("CREATE TABLE t (id INTEGER PRIMARY KEY, code UNIQUE, a, b, UNIQUE (a, b), UNIQUE (code))"),
+            [vec!["code"], vec!["a", "b"]]
+        );
+        assert_eq!(
+            columns("CREATE TABLE t (x TEXT PRIMARY KEY, y UNIQUE)"),
+            [vec!["x"], vec!["y"]]
+        );
+        assert_eq!(
+            columns("CREATE TABLE t (x, y, z UNIQUE, PRIMARY KEY (y, x))"),
+            [vec!["z"], vec!["y", "x"]]
+        );
+        assert!(columns("CREATE TABLE t (id INTEGER, name, PRIMARY KEY (id))").is_empty());
+    }
+
+    #[test]
+    fn parse_create_table_with_table_constraints() {
+        let input = b"CREATE TABLE order_items (order_id INTEGER, product_id INTEGER REFERENCES products, quantity INTEGER, CONSTRAINT pk PRIMARY KEY (order_id, product_id DESC), UNIQUE (order_id, quantity) ON CONFLICT IGNORE, CHECK (quantity > (0)), FOREIGN KEY (order_id) REFERENCES orders(id) ON DELETE CASCADE)";
         let (_, result) = parse(input).unwrap();
 
         assert_eq!(
             result,
             SQLCommand::CreateTable(CreateTableStatement {
-                table: "superheroes".to_string(),
+                schema: None,
+                table: "order_items".to_string(),
+                temporary: false,
+                if_not_exists: false,
                 fields: vec![
                     Field {
-                        name: "id".to_string(),
                         is_primary_key: true,
+                        ..field("order_id", "INTEGER")
                     },
-                    Field::new("name".to_string()),
-                    Field::new("eye_color".to_string()),
-                    Field::new("hair_color".to_string()),
-                    Field::new("appearance_count".to_string()),
-                    Field::new("first_appearance".to_string()),
-                    Field::new("first_appearance_year".to_string())
-                ]
+                    Field {
+                        is_primary_key: true,
+                        ..field("product_id", "INTEGER")
+                    },
+                    field("quantity", "INTEGER"),
+                ],
+                constraints: vec![
+                    TableConstraint::PrimaryKey(vec![
+                        "order_id".to_string(),
+                        "product_id".to_string()
+                    ]),
+                    TableConstraint::Unique(vec!["order_id".to_string(), "quantity".to_string()]),
+                    TableConstraint::Check("quantity > (0)".to_string()),
+                    TableConstraint::ForeignKey {
+                        columns: vec!["order_id".to_string()],
+                        foreign_table: "orders".to_string(),
+                        foreign_columns: vec!["id".to_string()],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_create_table_real_world_schema() {
+        let input = strip_comments(
+            "CREATE TABLE IF NOT EXISTS main.\"orders\" (
+                id INTEGER PRIMARY KEY, -- the order id (rowid)
+                qty int NOT  NULL CHECK (qty > 0 AND (qty < 100)) DEFAULT 1,
+                note \"varchar\"(20) DEFAULT ('a, b (c)'),
+                total REAL GENERATED ALWAYS AS (qty * 2.5) STORED,
+                half AS (total / 2),
+                flags BLOB DEFAULT x'00' /* unknown: */ HIDDEN OPTION(1, 2),
+                delta int DEFAULT -1e3
+            ) WITHOUT ROWID, STRICT;",
+        );
+        let (_, result) = parse_creation(input.as_bytes()).unwrap();
+
+        assert_eq!(result.table, "orders");
+        let fields = result
+            .fields
+            .iter()
+            .map(|field| {
+                (
+                    field.name.as_str(),
+                    field.ty.as_deref(),
+                    field.default.as_deref(),
+                )
             })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![
+                ("id", Some("INTEGER"), None),
+                ("qty", Some("int"), Some("1")),
+                ("note", Some("varchar(20)"), Some("'a, b (c)'")),
+                ("total", Some("REAL"), None),
+                ("half", None, None),
+                ("flags", Some("BLOB"), Some("x'00'")),
+                ("delta", Some("int"), Some("-1e3")),
+            ]
+        );
+        assert!(result.fields[0].is_rowid_alias);
+        assert!(result.fields[1].not_null);
+    }
+
+    #[test]
+    fn rowid_aliases() {
+        let cases = [
+            ("id INTEGER PRIMARY KEY, x", true),
+            ("id integer CONSTRAINT pk PRIMARY KEY ASC, x", true),
+            ("id \"INTEGER\" PRIMARY KEY, x", true),
+            ("id 'integer' PRIMARY KEY, x", true),
+            ("id [INTEGER] PRIMARY KEY, x", true),
+            ("id INTEGER, x, PRIMARY KEY (id DESC)", true),
+            ("id INTEGER, x, PRIMARY KEY (\"id\")", true),
+            ("id INTEGER PRIMARY KEY DESC, x", false),
+            ("id INT PRIMARY KEY, x", false),
+            ("id INTEGER(10) PRIMARY KEY, x", false),
+            ("id UNSIGNED INTEGER PRIMARY KEY, x", false),
+            ("id INTEGER, x, PRIMARY KEY (id, x)", false),
+        ];
+        for (columns, is_rowid_alias) in cases {
+            let statement = format!("CREATE TABLE t ({columns})");
+            let (_, result) = parse_creation(statement.as_bytes()).unwrap();
+            assert!(result.fields[0].is_primary_key, "{statement}");
+            assert_eq!(
+                result.fields[0].is_rowid_alias, is_rowid_alias,
+                "{statement}"
+            );
+        }
+    }
+
+    #[test]
+    fn strip_comments_keeps_quoted_text() {
+        assert_eq!(
+            strip_comments("a -- comment\nb /* c */ '-- d' \"/* e */\""),
+            "a  b   '-- d' \"/* e */\""
         );
     }
+
     #[test]
     fn parse_create_index() {
         let input = b"CREATE INDEX idx_companies_country on companies (country);";
@@ -407,10 +3577,851 @@ mod tests {
         assert_eq!(
             result,
             SQLCommand::CreateIndex(CreateIndexStatement {
+                schema: None,
                 table: "companies".to_string(),
                 name: "idx_companies_country".to_string(),
+                unique: false,
                 fields: vec!["country".to_string()],
+                expressions: vec![None],
+                collations: vec![None],
+                descending: vec![false],
+                where_clause: None,
+                if_not_exists: false,
+            })
+        );
+
+        let input =
+            b"create index if not exists main.\"by name\" ON t (last COLLATE NOCASE, first DESC)";
+        let (_, result) = parse(input).unwrap();
+        assert_eq!(
+            result,
+            SQLCommand::CreateIndex(CreateIndexStatement {
+                schema: Some("main".to_string()),
+                table: "t".to_string(),
+                name: "by name".to_string(),
+                unique: false,
+                fields: vec!["last".to_string(), "first".to_string()],
+                expressions: vec![None, None],
+                collations: vec![Some("NOCASE".to_string()), None],
+                descending: vec![false, true],
+                where_clause: None,
+                if_not_exists: true,
+            })
+        );
+
+        let input = b"CREATE UNIQUE INDEX by_email ON users (email)";
+        let SQLCommand::CreateIndex(index) = parse(input).unwrap().1 else {
+            panic!("not a CREATE INDEX statement");
+        };
+        assert!(index.unique);
+        assert_eq!(index.fields, ["email"]);
+    }
+
+    #[test]
+    fn parse_expression_index() {
+        let input = b"CREATE INDEX by_day ON events (kind, date(at, 'utc') DESC, CAST(\"size\" AS INTEGER))";
+        let SQLCommand::CreateIndex(index) = parse(input).unwrap().1 else {
+            panic!("not a CREATE INDEX statement");
+        };
+        assert_eq!(
+            index.fields,
+            ["kind", "date(at, 'utc')", "CAST(size AS INTEGER)"]
+        );
+        assert_eq!(index.descending, [false, true, false]);
+        assert_eq!(index.expressions[0], None);
+        assert_eq!(
+            index.expressions[1],
+            Some(Expr::Function {
+                name: "date".to_string(),
+                args: vec![
+                    Expr::Column("at".to_string()),
+                    Expr::Literal(Literal::Text("utc".to_string()))
+                ],
+            })
+        );
+
+        let (_, query) = expr(b"date(at, 'utc') = '2024-01-02'").unwrap();
+        let (expr, value) = query.equality().unwrap();
+        assert_eq!(Some(expr), index.expressions[1].as_ref());
+        assert_eq!(value, &Literal::Text("2024-01-02".to_string()));
+    }
+
+    #[test]
+    fn parse_partial_index() {
+        let input = b"CREATE INDEX reds ON apples (name) WHERE color = 'Red' AND id > 2;";
+        let SQLCommand::CreateIndex(index) = parse(input).unwrap().1 else {
+            panic!("not a CREATE INDEX statement");
+        };
+        let condition = index.where_clause.unwrap();
+        assert_eq!(condition.to_string(), "color = 'Red' AND id > 2");
+
+        let implies = |query: &str, condition: &str| {
+            let (_, query) = expr(query.as_bytes()).unwrap();
+            let (_, condition) = expr(condition.as_bytes()).unwrap();
+            query.implies(&condition)
+        };
+        assert!(implies(
+            "id > 2 AND color = 'Red' AND name = 'x'",
+            "color = 'Red' AND id > 2"
+        ));
+        assert!(!implies("color = 'Red'", "color = 'Red' AND id > 2"));
+        assert!(!implies("color = 'Red' OR id > 2", "id > 2"));
+        assert!(implies("color = 'Red'", "color IS NOT NULL"));
+        assert!(implies("color = 'Red'", "color NOTNULL"));
+        assert!(!implies("name = 'Red'", "color IS NOT NULL"));
+        assert!(!implies("color IS NULL", "color IS NOT NULL"));
+    }
+
+    #[test]
+    fn parse_create_view() {
+        let input = b"CREATE TEMP VIEW IF NOT EXISTS reds (n) AS SELECT name FROM apples;";
+        let SQLCommand::CreateView(view) = parse(input).unwrap().1 else {
+            panic!("not a CREATE VIEW statement");
+        };
+        assert!(view.temporary && view.if_not_exists);
+        assert_eq!(
+            (view.schema, view.name.as_str(), view.columns),
+            (None, "reds", vec!["n".to_string()])
+        );
+        assert_eq!(
+            view.select,
+            parse_statement("SELECT name FROM apples")
+                .map(|command| match command {
+                    SQLCommand::Select(select) => select,
+                    _ => panic!("not a SELECT"),
+                })
+                .unwrap()
+        );
+
+        assert!(parse_statement("CREATE VIEW v AS INSERT INTO t VALUES (1)").is_err());
+        assert_eq!(
+            schema_sql("create view if not exists aux.v as select 1 from t;").unwrap(),
+            "CREATE VIEW v as select 1 from t"
+        );
+    }
+
+    #[test]
+    fn parse_create_virtual_table() {
+        let input = b"CREATE VIRTUAL TABLE docs USING fts5(title, body, tokenize = 'porter ascii')";
+        let SQLCommand::CreateVirtualTable(table) = parse(input).unwrap().1 else {
+            panic!("not a CREATE VIRTUAL TABLE statement");
+        };
+        assert_eq!(
+            (table.name.as_str(), table.module.as_str(), table.arguments),
+            (
+                "docs",
+                "fts5",
+                vec![
+                    "title".to_string(),
+                    "body".to_string(),
+                    "tokenize = 'porter ascii'".to_string()
+                ]
+            )
+        );
+        let table = parse_virtual_table_creation(b"create virtual table if not exists t using m")
+            .unwrap()
+            .1;
+        assert!(table.if_not_exists && table.arguments.is_empty());
+
+        // Modules name their shadow tables with strings
+        let (_, table) =
+            parse_creation(b"CREATE TABLE 'docs_content'(id INTEGER PRIMARY KEY, c0, c1)").unwrap();
+        assert_eq!(table.table, "docs_content");
+    }
+
+    #[test]
+    fn parse_vacuum() {
+        assert_eq!(parse(b"VACUUM").unwrap(), (&b""[..], SQLCommand::Vacuum));
+        assert_eq!(
+            parse(b"vacuum main;").unwrap(),
+            (&b""[..], SQLCommand::Vacuum)
+        );
+        assert!(parse(b"VACUUM INTO 'copy.db'").is_err());
+    }
+
+    #[test]
+    fn parse_analyze() {
+        assert_eq!(
+            parse(b"ANALYZE;").unwrap(),
+            (&b""[..], SQLCommand::Analyze(None))
+        );
+        assert_eq!(
+            parse(b"analyze apples").unwrap(),
+            (&b""[..], SQLCommand::Analyze(Some("apples".to_string())))
+        );
+    }
+
+    #[test]
+    fn parse_attach_and_schema_names() {
+        assert_eq!(
+            parse(b"ATTACH DATABASE 'other''s.db' AS aux;").unwrap(),
+            (
+                &b""[..],
+                SQLCommand::Attach {
+                    path: "other's.db".to_string(),
+                    schema: "aux".to_string()
+                }
+            )
+        );
+        assert_eq!(
+            parse(b"attach 'other.db' as \"my aux\"").unwrap().1,
+            SQLCommand::Attach {
+                path: "other.db".to_string(),
+                schema: "my aux".to_string()
+            }
+        );
+        assert_eq!(
+            parse(b"DETACH DATABASE aux").unwrap().1,
+            SQLCommand::Detach("aux".to_string())
+        );
+        assert_eq!(
+            parse(b"detach aux;").unwrap().1,
+            SQLCommand::Detach("aux".to_string())
+        );
+
+        match parse(b"SELECT name FROM aux . companies WHERE id = 1")
+            .unwrap()
+            .1
+        {
+            SQLCommand::Select(SelectStatement::Fields(select)) => {
+                assert_eq!(
+                    select.from,
+                    TableSource::Table {
+                        schema: Some("aux".to_string()),
+                        name: "companies".to_string()
+                    }
+                );
+            }
+            command => panic!("not a select: {:?}", command),
+        }
+        assert_eq!(
+            parse(b"SELECT COUNT(*) FROM aux.companies").unwrap().1,
+            SQLCommand::Select(SelectStatement::Count {
+                schema: Some("aux".to_string()),
+                table: "companies".to_string(),
+                where_clause: None,
+            })
+        );
+        match parse(b"CREATE INDEX aux.idx ON t (a)").unwrap().1 {
+            SQLCommand::CreateIndex(create) => {
+                assert_eq!(create.schema.as_deref(), Some("aux"));
+                assert_eq!(create.name, "idx");
+            }
+            command => panic!("not a create index: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn parse_subqueries_in_expressions() {
+        let (_, result) = parse(
+            b"SELECT name FROM companies WHERE id NOT IN (SELECT company_id FROM employees) \
+              AND size > (SELECT size FROM companies WHERE id = ?)",
+        )
+        .unwrap();
+        let select = |fields, table: &str, where_clause| {
+            SelectStatement::Fields(SelectFields {
+                fields,
+                from: TableSource::Table {
+                    schema: None,
+                    name: table.to_string(),
+                },
+                alias: None,
+                where_clause,
+            })
+        };
+        assert_eq!(
+            result,
+            SQLCommand::Select(select(
+                vec![column("name")],
+                "companies",
+                Some(Expr::Binary {
+                    op: BinaryOperator::And,
+                    left: Box::new(Expr::InSubquery {
+                        expr: Box::new(Expr::Column("id".to_string())),
+                        subquery: Box::new(select(vec![column("company_id")], "employees", None)),
+                        negated: true
+                    }),
+                    right: Box::new(Expr::Binary {
+                        op: BinaryOperator::Gt,
+                        left: Box::new(Expr::Column("size".to_string())),
+                        right: Box::new(Expr::Subquery(Box::new(select(
+                            vec![column("size")],
+                            "companies",
+                            Some(Expr::Binary {
+                                op: BinaryOperator::Eq,
+                                left: Box::new(Expr::Column("id".to_string())),
+                                right: Box::new(Expr::Parameter(Parameter::Anonymous))
+                            })
+                        ))))
+                    })
+                })
+            ))
+        );
+
+        let (_, result) = parse(b"SELECT id IN(SELECT COUNT(*) FROM t) FROM t").unwrap();
+        match result {
+            SQLCommand::Select(SelectStatement::Fields(select)) => {
+                assert!(matches!(
+                    &select.fields[0],
+                    ResultColumn::Expr { expr: Expr::InSubquery { negated: false, .. }, text, .. }
+                        if text == "id IN(SELECT COUNT(*) FROM t)"
+                ));
+            }
+            command => panic!("not a select: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn parse_qualified_columns_and_exists() {
+        let (_, result) = parse(
+            b"SELECT a.name FROM apples AS a WHERE NOT EXISTS (SELECT 1 FROM orders o WHERE o.apple = a.id)",
+        )
+        .unwrap();
+        let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+            panic!("not a select: {:?}", result);
+        };
+        assert_eq!(select.alias.as_deref(), Some("a"));
+        assert_eq!(select.qualifier(), Some("a"));
+        assert!(matches!(
+            &select.fields[0],
+            ResultColumn::Expr { expr: Expr::QualifiedColumn { table, column }, .. }
+                if table == "a" && column == "name"
+        ));
+        let Some(Expr::Not(exists)) = &select.where_clause else {
+            panic!("not a NOT EXISTS: {:?}", select.where_clause);
+        };
+        let Expr::Exists(subquery) = exists.as_ref() else {
+            panic!("not an EXISTS: {exists:?}");
+        };
+        let SelectStatement::Fields(subquery) = subquery.as_ref() else {
+            panic!("not a select: {subquery:?}");
+        };
+        assert_eq!(subquery.qualifier(), Some("o"));
+        assert_eq!(
+            subquery.where_clause.as_ref().unwrap().to_string(),
+            "o.apple = a.id"
+        );
+
+        // Only the columns of the query's own table can be unqualified
+        let unqualified = select.unqualify_columns().unwrap();
+        assert!(matches!(
+            &unqualified.fields[0],
+            ResultColumn::Expr { expr: Expr::Column(name), .. } if name == "name"
+        ));
+        assert_eq!(
+            subquery.unqualify_columns(),
+            Err(Error::NoSuchColumn("a.id".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_cast() {
+        let (_, result) = parse(
+            b"SELECT CAST ( size AS unsigned big int ) FROM t WHERE cast(a AS DECIMAL(10, 5)) > 1",
+        )
+        .unwrap();
+        let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+            panic!("not a select: {:?}", result);
+        };
+        assert_eq!(
+            select.fields,
+            vec![ResultColumn::Expr {
+                expr: Expr::Cast {
+                    expr: Box::new(Expr::Column("size".to_string())),
+                    ty: "unsigned big int".to_string()
+                },
+                alias: None,
+                text: "CAST ( size AS unsigned big int )".to_string()
+            }]
+        );
+        assert!(matches!(
+            select.where_clause,
+            Some(Expr::Binary { left, .. })
+                if matches!(&*left, Expr::Cast { ty, .. } if ty == "DECIMAL(10, 5)")
+        ));
+        assert!(parse(b"SELECT CAST(size) FROM t").is_err());
+    }
+
+    #[test]
+    fn parse_function_calls() {
+        let (_, result) =
+            parse(b"SELECT date( 'now' , '-7 days' ), julianday() FROM t WHERE strftime('%Y', d) = '2024'")
+                .unwrap();
+        let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+            panic!("not a select: {:?}", result);
+        };
+        assert_eq!(
+            select.fields,
+            vec![
+                ResultColumn::Expr {
+                    expr: Expr::Function {
+                        name: "date".to_string(),
+                        args: vec![
+                            Expr::Literal(Literal::Text("now".to_string())),
+                            Expr::Literal(Literal::Text("-7 days".to_string()))
+                        ]
+                    },
+                    alias: None,
+                    text: "date( 'now' , '-7 days' )".to_string()
+                },
+                ResultColumn::Expr {
+                    expr: Expr::Function {
+                        name: "julianday".to_string(),
+                        args: vec![]
+                    },
+                    alias: None,
+                    text: "julianday()".to_string()
+                }
+            ]
+        );
+        assert!(matches!(
+            select.where_clause,
+            Some(Expr::Binary { left, .. })
+                if matches!(&*left, Expr::Function { name, args } if name == "strftime" && args.len() == 2)
+        ));
+        let (_, result) = parse(b"SELECT COUNT(*) FROM t").unwrap();
+        assert!(matches!(
+            result,
+            SQLCommand::Select(SelectStatement::Count { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_row_values() {
+        let (_, result) =
+            parse(b"SELECT a FROM t WHERE (a, b) NOT IN ((1, 'x'), ( 2 , ?)) OR (a) IN ()")
+                .unwrap();
+        let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+            panic!("not a select: {:?}", result);
+        };
+        let row = |values: Vec<Expr>| Expr::Row(values);
+        let column = |name: &str| Expr::Column(name.to_string());
+        assert_eq!(
+            select.where_clause,
+            Some(Expr::Binary {
+                op: BinaryOperator::Or,
+                left: Box::new(Expr::InList {
+                    expr: Box::new(row(vec![column("a"), column("b")])),
+                    list: vec![
+                        row(vec![
+                            Expr::Literal(Literal::Integer(1)),
+                            Expr::Literal("x".into())
+                        ]),
+                        row(vec![
+                            Expr::Literal(Literal::Integer(2)),
+                            Expr::Parameter(Parameter::Anonymous)
+                        ]),
+                    ],
+                    negated: true,
+                }),
+                right: Box::new(Expr::InList {
+                    expr: Box::new(column("a")),
+                    list: vec![],
+                    negated: false,
+                }),
+            })
+        );
+        assert!(parse(b"SELECT a FROM t WHERE (a, b) = (1,)").is_err());
+    }
+
+    #[test]
+    fn parse_compound_selects() {
+        let select = |field: &str, table: &str| {
+            Box::new(SelectStatement::Fields(SelectFields {
+                fields: vec![column(field)],
+                from: TableSource::Table {
+                    schema: None,
+                    name: table.to_string(),
+                },
+                alias: None,
+                where_clause: None,
+            }))
+        };
+        let (_, result) =
+            parse(b"SELECT a FROM t UNION ALL SELECT b FROM u\nunion select c FROM v;").unwrap();
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Compound {
+                op: CompoundOperator::Union,
+                left: Box::new(SelectStatement::Compound {
+                    op: CompoundOperator::UnionAll,
+                    left: select("a", "t"),
+                    right: select("b", "u")
+                }),
+                right: select("c", "v")
+            })
+        );
+
+        // A subquery's alias can't be UNION
+        let (_, result) =
+            parse(b"SELECT x FROM (SELECT a AS x FROM t) UNION SELECT COUNT(*) FROM u").unwrap();
+        assert!(matches!(
+            result,
+            SQLCommand::Select(SelectStatement::Compound { op: CompoundOperator::Union, right, .. })
+                if matches!(*right, SelectStatement::Count { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_subquery_in_from() {
+        let (_, result) =
+            parse(b"SELECT x FROM (SELECT name AS x, id = 1 FROM apples WHERE id > 1) sub")
+                .unwrap();
+        assert_eq!(
+            result,
+            SQLCommand::Select(SelectStatement::Fields(SelectFields {
+                fields: vec![column("x")],
+                from: TableSource::Subquery(Box::new(SelectStatement::Fields(SelectFields {
+                    fields: vec![
+                        ResultColumn::Expr {
+                            expr: Expr::Column("name".to_string()),
+                            alias: Some("x".to_string()),
+                            text: "name".to_string()
+                        },
+                        ResultColumn::Expr {
+                            expr: Expr::Binary {
+                                op: BinaryOperator::Eq,
+                                left: Box::new(Expr::Column("id".to_string())),
+                                right: Box::new(Expr::Literal(Literal::Integer(1)))
+                            },
+                            alias: None,
+                            text: "id = 1".to_string()
+                        }
+                    ],
+                    from: TableSource::Table {
+                        schema: None,
+                        name: "apples".to_string()
+                    },
+                    alias: None,
+                    where_clause: Some(Expr::Binary {
+                        op: BinaryOperator::Gt,
+                        left: Box::new(Expr::Column("id".to_string())),
+                        right: Box::new(Expr::Literal(Literal::Integer(1)))
+                    })
+                }))),
+                alias: Some("sub".to_string()),
+                where_clause: None
+            }))
+        );
+
+        let (_, result) =
+            parse(b"SELECT * FROM (SELECT COUNT(*) FROM apples) AS counted WHERE 1").unwrap();
+        match result {
+            SQLCommand::Select(SelectStatement::Fields(select)) => {
+                assert_eq!(select.table(), None);
+                assert!(select.where_clause.is_some());
+            }
+            command => panic!("not a select: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn parse_common_table_expressions() {
+        let (_, result) = parse(
+            b"WITH red(apple) AS (SELECT name FROM apples WHERE color = 'Red'), \
+              named AS (SELECT * FROM red) SELECT apple FROM named",
+        )
+        .unwrap();
+        let SQLCommand::Select(SelectStatement::With {
+            recursive: false,
+            ctes,
+            select,
+        }) = result
+        else {
+            panic!("not a WITH: {:?}", result);
+        };
+        assert_eq!(ctes.len(), 2);
+        assert_eq!(ctes[0].name, "red");
+        assert_eq!(ctes[0].columns, ["apple"]);
+        assert_eq!(ctes[1].name, "named");
+        assert!(ctes[1].columns.is_empty());
+        let SelectStatement::Fields(select) = select.as_ref() else {
+            panic!("not a select: {select:?}");
+        };
+        assert_eq!(select.table(), Some("named"));
+
+        let (_, result) = parse(
+            b"WITH RECURSIVE t(id) AS (SELECT id FROM apples WHERE id = 1 \
+              UNION SELECT id FROM apples WHERE id IN (SELECT id FROM t)) SELECT * FROM t",
+        )
+        .unwrap();
+        let SQLCommand::Select(SelectStatement::With {
+            recursive: true,
+            ctes,
+            ..
+        }) = result
+        else {
+            panic!("not a WITH RECURSIVE: {:?}", result);
+        };
+        let Ok(Some((op, initial, step))) = ctes[0].recursion() else {
+            panic!("not recursive: {:?}", ctes[0]);
+        };
+        assert_eq!(op, CompoundOperator::Union);
+        assert!(!initial.reads_table("t"));
+        assert!(step.reads_table("T"));
+
+        // Only the step of a UNION can read the common table expression itself
+        let (_, result) = parse(b"WITH RECURSIVE t AS (SELECT * FROM t) SELECT * FROM t").unwrap();
+        let SQLCommand::Select(SelectStatement::With { ctes, .. }) = result else {
+            panic!("not a WITH: {:?}", result);
+        };
+        assert_eq!(
+            ctes[0].recursion(),
+            Err(Error::CircularReference("t".to_string()))
+        );
+
+        // The common table expressions have to be followed by a query
+        assert!(parse(b"WITH t AS (SELECT 1 FROM apples)").is_err());
+        assert!(parse(b"WITH t (SELECT 1 FROM apples) SELECT * FROM t").is_err());
+    }
+
+    #[test]
+    fn parse_joins() {
+        let (_, result) = parse(
+            b"SELECT a.name, o.quantity FROM apples AS a INNER JOIN orders o ON o.apple_id = a.id \
+              CROSS JOIN (SELECT 1 FROM oranges) JOIN oranges WHERE quantity > 1",
+        )
+        .unwrap();
+        let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+            panic!("not a select: {:?}", result);
+        };
+        assert_eq!(select.alias, None);
+        assert_eq!(select.table(), None);
+        assert!(select.where_clause.is_some());
+        // Joins of more tables join the joins before them
+        let TableSource::Join(oranges) = &select.from else {
+            panic!("not a join: {:?}", select.from);
+        };
+        assert_eq!(
+            oranges.right,
+            TableSource::Table {
+                schema: None,
+                name: "oranges".to_string()
+            }
+        );
+        assert_eq!(oranges.on, None);
+        let TableSource::Join(subquery) = &oranges.left else {
+            panic!("not a join: {:?}", oranges.left);
+        };
+        assert!(matches!(subquery.right, TableSource::Subquery(_)));
+        let TableSource::Join(orders) = &subquery.left else {
+            panic!("not a join: {:?}", subquery.left);
+        };
+        assert_eq!(orders.left_alias.as_deref(), Some("a"));
+        assert_eq!(orders.right_alias.as_deref(), Some("o"));
+        assert_eq!(
+            orders.on.as_ref().map(|on| on.to_string()).as_deref(),
+            Some("o.apple_id = a.id")
+        );
+        assert!(SelectStatement::Fields(select).reads_table("orders"));
+
+        assert!(parse(b"SELECT * FROM apples JOIN").is_err());
+        assert!(parse(b"SELECT * FROM apples JOIN oranges ON").is_err());
+    }
+
+    #[test]
+    fn parse_window_functions() {
+        let (_, result) = parse(
+            b"SELECT name, ROW_NUMBER() OVER (PARTITION BY color, id > 2 ORDER BY name DESC, id), \
+              count(*) OVER ( ), SUM(id) over (ORDER BY id ASC) FROM apples",
+        )
+        .unwrap();
+        let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+            panic!("not a select: {:?}", result);
+        };
+        let exprs = select
+            .fields
+            .iter()
+            .map(|field| match field {
+                ResultColumn::Expr { expr, .. } => expr,
+                ResultColumn::All => panic!("not an expression"),
+            })
+            .collect::<Vec<_>>();
+        let Expr::Window { name, args, window } = exprs[1] else {
+            panic!("not a window function: {:?}", exprs[1]);
+        };
+        assert_eq!(name, "ROW_NUMBER");
+        assert!(args.is_empty());
+        assert_eq!(window.partition_by.len(), 2);
+        assert_eq!(
+            window.order_by,
+            [
+                OrderingTerm {
+                    expr: Expr::Column("name".to_string()),
+                    descending: true
+                },
+                OrderingTerm {
+                    expr: Expr::Column("id".to_string()),
+                    descending: false
+                }
+            ]
+        );
+        assert_eq!(
+            exprs[1].to_string(),
+            "ROW_NUMBER() OVER (PARTITION BY color, id > 2 ORDER BY name DESC, id)"
+        );
+        assert_eq!(exprs[2].to_string(), "count(*) OVER ()");
+        assert_eq!(exprs[3].to_string(), "SUM(id) OVER (ORDER BY id)");
+        assert!(select.has_window_functions());
+
+        // A window has to follow OVER
+        assert!(parse(b"SELECT rank() OVER FROM apples").is_err());
+    }
+
+    #[test]
+    fn parse_pragma() {
+        let (_, result) = parse(b"PRAGMA table_info(apples)").unwrap();
+        assert_eq!(
+            result,
+            SQLCommand::Pragma(PragmaStatement {
+                name: "table_info".to_string(),
+                argument: Some("apples".to_string())
+            })
+        );
+
+        let (_, result) = parse(b"pragma page_size;").unwrap();
+        assert_eq!(
+            result,
+            SQLCommand::Pragma(PragmaStatement {
+                name: "page_size".to_string(),
+                argument: None
             })
         );
     }
+
+    #[test]
+    fn split_script_into_statements() {
+        let input =
+            "SELECT a FROM t;\n\nSELECT 'x;y' FROM \"s;t\" -- comment; here\n;; SELECT b FROM u";
+        assert_eq!(
+            split_statements(input),
+            vec![
+                "SELECT a FROM t",
+                "SELECT 'x;y' FROM \"s;t\" -- comment; here",
+                "SELECT b FROM u"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_scripts() {
+        let script = "-- migration 1\nCREATE TABLE t (a text);\n\
+            /* rows */ INSERT INTO t (a) VALUES ('x;y'), ('-- z');\n\
+            SELECT a FROM t; -- done\n";
+        let statements = parse_script(script).unwrap();
+        assert_eq!(
+            statements.iter().map(|(sql, _)| *sql).collect::<Vec<_>>(),
+            vec![
+                "CREATE TABLE t (a text)",
+                "INSERT INTO t (a) VALUES ('x;y'), ('-- z')",
+                "SELECT a FROM t"
+            ]
+        );
+        assert!(matches!(statements[0].1, SQLCommand::CreateTable(_)));
+        assert!(matches!(
+            &statements[1].1,
+            SQLCommand::Insert(insert) if insert.rows.len() == 2
+        ));
+        assert!(matches!(statements[2].1, SQLCommand::Select(_)));
+
+        assert_eq!(parse_script("  -- nothing\n;").unwrap(), vec![]);
+        assert!(matches!(
+            parse_script("SELECT a FROM t; SELEKT b FROM t").unwrap_err(),
+            Error::ScriptSyntax { statement: 2, error } if error.offset == 0
+        ));
+        assert!(matches!(
+            parse_script("SELECT a FROM t u v").unwrap_err(),
+            Error::ScriptSyntax { statement: 1, error } if error.offset == 18
+        ));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let error = |statement: &str| parse_statement(statement).unwrap_err().to_string();
+        assert_eq!(
+            error("SELECT a FORM t"),
+            "near 'FORM' at offset 9, expected FROM"
+        );
+        assert_eq!(
+            error("SELEKT a FROM t"),
+            "near 'SELEKT' at offset 0, expected PRAGMA, CREATE, SELECT, INSERT, VACUUM, \
+             ANALYZE, ATTACH, DETACH or EXPLAIN"
+        );
+        assert_eq!(
+            error("SELECT a FROM t WHERE b IN (SELECT c FROM)"),
+            "near ')' at offset 41"
+        );
+        assert_eq!(
+            error("SELECT a FROM t UNION SELEC b FROM u"),
+            "near 'SELEC' at offset 22, expected SELECT"
+        );
+        assert_eq!(
+            error("SELECT a FROM t LIMIT 1"),
+            "near 'LIMIT' at offset 16"
+        );
+        assert_eq!(
+            error("SELECT a FROM t WHERE"),
+            "at the end of the statement, expected NOT, EXISTS, CAST or NULL"
+        );
+        assert_eq!(
+            parse_statement("SELECT a FROM \"t\"\"\" WHERE b = 'it''s';  \n"),
+            Ok(parse(b"SELECT a FROM \"t\"\"\" WHERE b = 'it''s'")
+                .unwrap()
+                .1)
+        );
+    }
+
+    #[test]
+    fn parse_explain() {
+        let (_, command) = parse(b"EXPLAIN SELECT name FROM apples WHERE id = 1").unwrap();
+        let SQLCommand::Explain(SelectStatement::Fields(select)) = command else {
+            panic!("not an explained query: {command:?}");
+        };
+        assert_eq!(select.table(), Some("apples"));
+        assert_eq!(
+            parse_statement("EXPLAIN INSERT INTO t VALUES (1)")
+                .unwrap_err()
+                .to_string(),
+            "near 'INSERT' at offset 8, expected SELECT"
+        );
+    }
+
+    #[test]
+    fn display_expressions() {
+        let where_clause = |statement: &str| {
+            let Ok((_, SQLCommand::Select(SelectStatement::Fields(select)))) =
+                parse(statement.as_bytes())
+            else {
+                panic!("not a query: {statement}");
+            };
+            select.where_clause.unwrap().to_string()
+        };
+        assert_eq!(
+            where_clause("SELECT a FROM t WHERE (a = 'it''s' OR b < 1.5) AND NOT c IS NULL"),
+            "(a = 'it''s' OR b < 1.5) AND NOT c IS NULL"
+        );
+        assert_eq!(
+            where_clause(
+                "SELECT a FROM t WHERE a = 1 OR (b = X'0aFF' AND c NOT IN (SELECT d FROM u))"
+            ),
+            "a = 1 OR b = X'0AFF' AND c NOT IN (SELECT ...)"
+        );
+        assert_eq!(
+            where_clause("SELECT a FROM t WHERE date(a, '+1 day') >= CAST(:b AS text)"),
+            "date(a, '+1 day') >= CAST(:b AS text)"
+        );
+        assert_eq!(
+            where_clause("SELECT a FROM t WHERE (a,b) >= (1,2) AND c IN (3,(4))"),
+            "(a, b) >= (1, 2) AND c IN (3, 4)"
+        );
+    }
+
+    #[test]
+    fn script_completeness() {
+        assert!(is_complete(""));
+        assert!(is_complete("SELECT a FROM t;  \n"));
+        assert!(!is_complete("SELECT a FROM t"));
+        assert!(!is_complete("SELECT a FROM t WHERE b = ';"));
+        assert!(is_complete("SELECT a FROM t WHERE b = ';';"));
+    }
 }
@@ -1,41 +1,67 @@
 use std::collections::HashMap;
 
 use crate::{
-    page::{Cell, Page},
-    record::{ColumnValue, Record},
+    affinity::Affinity,
+    collation,
+    error::Error,
+    record::{OwnedRecord, Value},
     sql,
 };
 use anyhow::Result;
 
-#[derive(Debug)]
+/// The tables and indexes of a database, as defined by its schema table.
+#[derive(Debug, Default)]
 pub struct SchemaStore {
-    pub tables: HashMap<String, Table>,
-    // the tests care about the order of the tables
-    pub table_names: Vec<String>,
+    tables: HashMap<String, Table>,
+    /// The names of the user tables in the order they were created, as the tables are listed
+    /// in that order
+    table_names: Vec<String>,
+    /// The views in the order they were created
+    views: Vec<View>,
+    /// The virtual tables in the order they were created. Their modules aren't supported, so
+    /// only their shadow tables can be read.
+    virtual_tables: Vec<VirtualTable>,
+    triggers: Vec<Trigger>,
 }
 
 impl SchemaStore {
-    pub fn read(page: Page) -> Result<Self> {
-        let schema_table = SQLiteSchema::read(page)?;
-        let mut tables: HashMap<String, Table> = HashMap::new();
-        let mut table_names: Vec<String> = Vec::new();
-
+    pub fn read(schema_table: SQLiteSchema) -> Result<Self> {
+        let mut store = Self::default();
+        store
+            .tables
+            .insert(SCHEMA_TABLE_NAME.to_string(), Table::schema_table());
+
+        // The definitions of the tables, which the automatic indexes take their columns from
+        let mut definitions = HashMap::new();
         for row in schema_table.rows.iter() {
-            let (_, sql) = sql::parse_create(row.sql.as_bytes())
+            // Automatic indexes have no CREATE statement
+            let Some(row_sql) = &row.sql else {
+                continue;
+            };
+            // Triggers are kept as they're written, they never run
+            if row.kind == "trigger" {
+                store.triggers.push(Trigger {
+                    name: row.name.clone(),
+                    table_name: row.tbl_name.clone(),
+                    sql: row_sql.clone(),
+                });
+                continue;
+            }
+            let (_, sql) = sql::parse_create(sql::strip_comments(row_sql).as_bytes())
                 .map_err(|_e| anyhow::anyhow!("Failed to parse table definition"))?;
 
-            if let sql::SQLCommand::CreateTable(t) = sql {
-                let table = Table {
-                    name: t.table,
-                    columns: t.fields.iter().map(|f| Column::from(f)).collect(),
-                    indexes: vec![],
-                    rootpage: row.rootpage,
-                };
-
-                if table.is_user_table() {
-                    table_names.push(table.name.clone());
+            match sql {
+                sql::SQLCommand::CreateTable(t) => {
+                    store.add_table(&t, row.rootpage);
+                    definitions.insert(t.table.clone(), t);
                 }
-                tables.insert(table.name.clone(), table);
+                sql::SQLCommand::CreateView(v) => store.add_view(&v),
+                sql::SQLCommand::CreateVirtualTable(v) => store.virtual_tables.push(VirtualTable {
+                    name: v.name,
+                    module: v.module,
+                    sql: row_sql.clone(),
+                }),
+                _ => {}
             }
         }
 
@@ -43,58 +69,242 @@ impl SchemaStore {
         // created before the indexes are created.
         // Since the amount of tables in a typical database is small, this isn't a problem.
         // (also this is an exercise in learning rust, not a production ready database)
-        for row in schema_table.rows.iter() {
-            let (_, sql) = sql::parse_create(row.sql.as_bytes())
+        for row in schema_table.rows.iter().filter(|row| row.kind == "index") {
+            let Some(row_sql) = &row.sql else {
+                let columns = definitions
+                    .get(&row.tbl_name)
+                    .and_then(|table| automatic_index_columns(&row.name, table));
+                // Constraints this crate doesn't know about, like the primary keys of WITHOUT
+                // ROWID tables, leave their indexes unused
+                if let Some(columns) = columns {
+                    store.add_automatic_index(&row.name, &row.tbl_name, &columns, row.rootpage)?;
+                }
+                continue;
+            };
+            let (_, sql) = sql::parse_create(sql::strip_comments(row_sql).as_bytes())
                 .map_err(|_e| anyhow::anyhow!("Failed to parse table definition"))?;
 
             if let sql::SQLCommand::CreateIndex(i) = sql {
-                let index = Index {
-                    name: i.name,
-                    columns: i.fields,
-                    table_name: i.table,
-                    rootpage: row.rootpage,
-                };
-                tables
-                    .get_mut(&index.table_name)
-                    .expect("Index without table")
-                    .indexes
-                    .push(index);
+                store.add_index(&i, row.rootpage)?;
             };
         }
 
-        Ok(Self {
-            tables,
-            table_names,
-        })
+        Ok(store)
+    }
+
+    /// Adds the index defined by a CREATE INDEX statement, stored at `rootpage`.
+    pub fn add_index(&mut self, i: &sql::CreateIndexStatement, rootpage: u32) -> Result<(), Error> {
+        let table = self
+            .tables
+            .get_mut(&i.table)
+            .ok_or_else(|| Error::NoSuchTable(i.table.clone()))?;
+        let collations = table.index_collations(i);
+        table.indexes.push(Index {
+            name: i.name.clone(),
+            unique: i.unique,
+            columns: i.fields.clone(),
+            expressions: i.expressions.clone(),
+            collations,
+            descending: i.descending.clone(),
+            where_clause: i.where_clause.clone(),
+            table_name: i.table.clone(),
+            rootpage,
+        });
+        Ok(())
     }
 
-    pub fn user_tables(&self) -> impl Iterator<Item = &Table> {
-        self.tables.values().filter(|table| table.is_user_table())
+    /// Adds the index SQLite keeps for a PRIMARY KEY or UNIQUE constraint on `columns`, stored
+    /// at `rootpage`. It's a unique index without a CREATE INDEX statement.
+    pub fn add_automatic_index(
+        &mut self,
+        name: &str,
+        table_name: &str,
+        columns: &[String],
+        rootpage: u32,
+    ) -> Result<(), Error> {
+        self.add_index(
+            &sql::CreateIndexStatement {
+                schema: None,
+                name: name.to_string(),
+                table: table_name.to_string(),
+                unique: true,
+                fields: columns.to_vec(),
+                expressions: vec![None; columns.len()],
+                collations: vec![None; columns.len()],
+                descending: vec![false; columns.len()],
+                where_clause: None,
+                if_not_exists: false,
+            },
+            rootpage,
+        )
+    }
+
+    /// Adds the table defined by a CREATE TABLE statement, stored at `rootpage`.
+    pub fn add_table(&mut self, t: &sql::CreateTableStatement, rootpage: u32) {
+        let primary_key = t
+            .constraints
+            .iter()
+            .find_map(|constraint| match constraint {
+                sql::TableConstraint::PrimaryKey(columns) => Some(columns.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| {
+                t.fields
+                    .iter()
+                    .filter(|field| field.is_primary_key)
+                    .map(|field| field.name.clone())
+                    .collect()
+            });
+        let table = Table {
+            name: t.table.clone(),
+            columns: t.fields.iter().map(Column::from).collect(),
+            primary_key,
+            indexes: vec![],
+            rootpage,
+        };
+
+        if table.is_user_table() {
+            self.table_names.push(table.name.clone());
+        }
+        self.tables.insert(table.name.clone(), table);
+    }
+
+    /// Adds the view defined by a CREATE VIEW statement.
+    pub fn add_view(&mut self, v: &sql::CreateViewStatement) {
+        self.views.push(View {
+            name: v.name.clone(),
+            columns: v.columns.clone(),
+            select: v.select.clone(),
+        });
+    }
+
+    /// The views in the order they were created.
+    pub fn views(&self) -> impl Iterator<Item = &View> {
+        self.views.iter()
+    }
+
+    pub fn find_view(&self, view_name: &str) -> Option<&View> {
+        self.views.iter().find(|view| view.name == view_name)
+    }
+
+    /// The virtual tables in the order they were created.
+    pub fn virtual_tables(&self) -> impl Iterator<Item = &VirtualTable> {
+        self.virtual_tables.iter()
+    }
+
+    pub fn find_virtual_table(&self, table_name: &str) -> Option<&VirtualTable> {
+        self.virtual_tables
+            .iter()
+            .find(|table| table.name == table_name)
+    }
+
+    /// The triggers of a table, or of all tables, in the order they were created.
+    pub fn triggers(&self, table_name: Option<&str>) -> Vec<&Trigger> {
+        self.triggers
+            .iter()
+            .filter(|trigger| table_name.map_or(true, |name| trigger.table_name == name))
+            .collect()
+    }
+
+    /// The user tables, with their columns and indexes, in the order they were created. The
+    /// internal `sqlite_` tables aren't included, but they can be looked up by name.
+    pub fn tables(&self) -> impl Iterator<Item = &Table> {
+        self.table_names.iter().map(|name| &self.tables[name])
     }
 
     pub fn find_table(&self, table_name: &str) -> Option<&Table> {
-        self.user_tables().find(|table| table.name == table_name)
+        match table_name {
+            "sqlite_master" => self.tables.get(SCHEMA_TABLE_NAME),
+            name => self.tables.get(name),
+        }
     }
-}
 
-impl Default for SchemaStore {
-    fn default() -> Self {
-        Self {
-            tables: HashMap::new(),
-            table_names: vec![],
+    /// The `sqlite_sequence` table, if the database has AUTOINCREMENT tables.
+    pub fn sequence_table(&self) -> Option<&Table> {
+        self.tables.get(SEQUENCE_TABLE_NAME)
+    }
+
+    pub fn table(&self, table_name: &str) -> Result<&Table, Error> {
+        self.find_table(table_name)
+            .ok_or_else(|| match self.find_virtual_table(table_name) {
+                Some(table) => Error::NoSuchModule(table.module.clone()),
+                None => Error::NoSuchTable(table_name.to_string()),
+            })
+    }
+
+    /// Makes all indexes ascending. Files in schema formats before 4 ignore `DESC`.
+    pub(crate) fn ignore_sort_orders(&mut self) {
+        for table in self.tables.values_mut() {
+            for index in &mut table.indexes {
+                index.descending.fill(false);
+            }
         }
     }
+
+    /// Indexes of all user tables, or only of `table_name` if given, ordered by index name.
+    pub fn indexes(&self, table_name: Option<&str>) -> Vec<&Index> {
+        let mut indexes = self
+            .tables()
+            .filter(|table| table_name.is_none() || table_name == Some(table.name.as_str()))
+            .flat_map(|table| table.indexes.iter())
+            .collect::<Vec<_>>();
+        indexes.sort_by(|a, b| a.name.cmp(&b.name));
+        indexes
+    }
+}
+
+/// The name of the `number`th automatic index of a table, counting from 1.
+pub fn automatic_index_name(table_name: &str, number: usize) -> String {
+    format!("sqlite_autoindex_{table_name}_{number}")
+}
+
+/// The columns of the automatic index called `name`, which are the ones of the constraint its
+/// number refers to.
+fn automatic_index_columns(name: &str, table: &sql::CreateTableStatement) -> Option<Vec<String>> {
+    let number = name
+        .strip_prefix(&format!("sqlite_autoindex_{}_", table.table))?
+        .parse::<usize>()
+        .ok()?;
+    table
+        .automatic_indexes()
+        .into_iter()
+        .nth(number.checked_sub(1)?)
 }
 
+/// The schema table stored in the b-tree rooted at page 1. It is also known as `sqlite_master`.
+pub const SCHEMA_TABLE_NAME: &str = "sqlite_schema";
+
+/// The table that keeps the largest rowid each AUTOINCREMENT table has used. It's created with
+/// the first AUTOINCREMENT table.
+pub const SEQUENCE_TABLE_NAME: &str = "sqlite_sequence";
+
 #[derive(Debug, Clone)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    /// Names of the primary key columns, in key order
+    pub primary_key: Vec<String>,
     pub indexes: Vec<Index>,
     pub rootpage: u32,
 }
 
 impl Table {
+    /// Definition of the schema table itself, which has no CREATE TABLE statement on file.
+    pub fn schema_table() -> Self {
+        let columns = ["type", "name", "tbl_name", "rootpage", "sql"]
+            .into_iter()
+            .map(Column::new)
+            .collect();
+
+        Self {
+            name: SCHEMA_TABLE_NAME.to_string(),
+            columns,
+            primary_key: vec![],
+            indexes: vec![],
+            rootpage: 1,
+        }
+    }
+
     pub fn find_column(&self, column_name: &str) -> Option<(usize, &Column)> {
         self.columns
             .iter()
@@ -102,16 +312,66 @@ impl Table {
             .find(|(_, column)| column.name == column_name)
     }
 
+    pub fn column(&self, column_name: &str) -> Result<(usize, &Column), Error> {
+        self.find_column(column_name)
+            .ok_or_else(|| Error::NoSuchColumn(column_name.to_string()))
+    }
+
+    /// The names of the collations of the columns of an index of the table. Columns without a
+    /// COLLATE are collated like the column of the table.
+    pub fn index_collations(&self, index: &sql::CreateIndexStatement) -> Vec<Option<String>> {
+        index
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(position, field)| match index.collations.get(position) {
+                Some(Some(collation)) => Some(collation.clone()),
+                _ if matches!(index.expressions.get(position), Some(Some(_))) => None,
+                _ => self
+                    .find_column(field)
+                    .and_then(|(_, column)| column.collation.clone()),
+            })
+            .collect()
+    }
+
+    /// The name of the collation that comparisons with `expr` use, if it's a column with one.
+    pub fn collation_of(&self, expr: &sql::Expr) -> Option<&str> {
+        match expr {
+            sql::Expr::Column(name) => self
+                .find_column(name)
+                .and_then(|(_, column)| column.collation.as_deref()),
+            _ => None,
+        }
+    }
+
+    /// Whether the rowids of the table come from `sqlite_sequence`, so that they only grow.
+    pub fn is_autoincrement(&self) -> bool {
+        self.columns.iter().any(|column| column.autoincrement)
+    }
+
     pub fn is_user_table(&self) -> bool {
         !self.name.starts_with("sqlite_")
     }
 
-    pub fn find_applicable_index(&self, filter: &Option<sql::WhereClause>) -> Option<&Index> {
-        let Some(filter) = filter else { return None; };
-
-        self.indexes
-            .iter()
-            .find(|index| filter.field == index.columns[0])
+    /// An index that can look up rows by the value of `expr`, a column or an indexed expression,
+    /// for a query whose condition is `where_clause`. Partial indexes only have the rows their own condition is true for, so
+    /// they're only applicable if the query's condition implies it.
+    pub fn find_applicable_index(
+        &self,
+        expr: &sql::Expr,
+        where_clause: &sql::Expr,
+    ) -> Option<&Index> {
+        // The entries are ordered by the collation of the index, which has to be the one the
+        // comparison uses
+        let collation = self.collation_of(expr);
+        self.indexes.iter().find(|index| {
+            index.position(expr) == Some(0)
+                && collation::same_name(collation, index.collation(0))
+                && index
+                    .where_clause
+                    .as_ref()
+                    .map_or(true, |condition| where_clause.implies(condition))
+        })
     }
 }
 
@@ -120,23 +380,96 @@ impl From<Index> for Table {
         Self {
             name: index.table_name.clone(),
             columns: vec![],
+            primary_key: vec![],
             indexes: vec![index],
             rootpage: 0,
         }
     }
 }
 
+/// A view, which is queried by running its SELECT.
+#[derive(Debug, Clone)]
+pub struct View {
+    pub name: String,
+    /// The names of the columns, or empty to use the names of the SELECT's results
+    pub columns: Vec<String>,
+    pub select: sql::SelectStatement,
+}
+
+/// A trigger of a table. Triggers aren't run, so tables that have them can't be changed.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub table_name: String,
+    /// The CREATE TRIGGER statement
+    pub sql: String,
+}
+
+/// A table whose rows come from a module, like `fts5` or `rtree`. Modules aren't supported, so
+/// querying the table fails, but the ordinary tables the module keeps its data in can be read.
+#[derive(Debug, Clone)]
+pub struct VirtualTable {
+    pub name: String,
+    pub module: String,
+    /// The CREATE VIRTUAL TABLE statement
+    pub sql: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct Column {
     pub name: String,
+    pub ty: Option<String>,
     pub is_primary_key: bool,
+    pub is_rowid_alias: bool,
+    pub not_null: bool,
+    pub unique: bool,
+    /// An INTEGER PRIMARY KEY declared with AUTOINCREMENT, whose rowids are never reused
+    pub autoincrement: bool,
+    /// The source text of the DEFAULT expression
+    pub default: Option<String>,
+    pub collation: Option<String>,
+}
+
+impl Column {
+    pub fn affinity(&self) -> Affinity {
+        Affinity::from_declared_type(self.ty.as_deref())
+    }
+
+    /// The value of the column in records that were written before it was added with ALTER
+    /// TABLE, which don't store it. Such columns can only have constant defaults.
+    pub fn default_value(&self) -> sql::Literal {
+        let default = self.default.as_deref().and_then(sql::parse_literal);
+        self.affinity()
+            .apply(&default.unwrap_or(sql::Literal::Null))
+    }
+
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ty: None,
+            is_primary_key: false,
+            is_rowid_alias: false,
+            not_null: false,
+            unique: false,
+            autoincrement: false,
+            default: None,
+            collation: None,
+        }
+    }
 }
 
 impl From<&sql::Field> for Column {
     fn from(field: &sql::Field) -> Self {
         Self {
             name: field.name.clone(),
+            ty: field.ty.clone(),
             is_primary_key: field.is_primary_key,
+            is_rowid_alias: field.is_rowid_alias,
+            not_null: field.not_null,
+            unique: field.unique,
+            autoincrement: field.autoincrement,
+            default: field.default.clone(),
+            collation: field.collation.clone(),
         }
     }
 }
@@ -144,17 +477,59 @@ impl From<&sql::Field> for Column {
 #[derive(Debug, Clone)]
 pub struct Index {
     pub name: String,
+    /// Whether no two entries may have the same values in all columns, unless one of them is
+    /// NULL
+    pub unique: bool,
     pub columns: Vec<String>,
+    /// The expression of each column that isn't a column of the table. Its entries hold the
+    /// value of the expression for the row.
+    pub expressions: Vec<Option<sql::Expr>>,
+    /// The collation of each column, which orders its text values. Columns without one use
+    /// BINARY.
+    pub collations: Vec<Option<String>>,
+    /// Whether each column is sorted in descending order. The entries of such columns are
+    /// stored from the largest value to the smallest.
+    pub descending: Vec<bool>,
+    /// The condition of a partial index. Only the rows that satisfy it have entries, so the
+    /// index can only answer queries whose WHERE implies it.
+    pub where_clause: Option<sql::Expr>,
     pub table_name: String,
     pub rootpage: u32,
 }
 
 impl Index {
+    /// Whether the column at `position` in the entries sorts in descending order. The rowid
+    /// at the end of the entries always sorts ascending.
+    pub fn is_descending(&self, position: usize) -> bool {
+        self.descending.get(position).copied().unwrap_or(false)
+    }
+
+    /// The name of the collation of the column at `position`, if it isn't BINARY.
+    pub fn collation(&self, position: usize) -> Option<&str> {
+        self.collations.get(position).and_then(Option::as_deref)
+    }
+
     pub fn find_column(&self, column_name: &str) -> Option<(usize, &String)> {
         self.columns
             .iter()
             .enumerate()
-            .find(|(_, column)| *column == column_name)
+            .find(|&(position, column)| column == column_name && !self.is_expression(position))
+    }
+
+    /// Whether the column at `position` is an expression rather than a column of the table.
+    pub fn is_expression(&self, position: usize) -> bool {
+        matches!(self.expressions.get(position), Some(Some(_)))
+    }
+
+    /// The position of a column of the table, or of an indexed expression, in the entries.
+    pub fn position(&self, expr: &sql::Expr) -> Option<usize> {
+        match expr {
+            sql::Expr::Column(name) => self.find_column(name).map(|(position, _)| position),
+            expr => self
+                .expressions
+                .iter()
+                .position(|indexed| indexed.as_ref() == Some(expr)),
+        }
     }
 }
 
@@ -164,10 +539,10 @@ pub struct SQLiteSchema {
 }
 
 impl SQLiteSchema {
-    pub fn read(page: Page) -> Result<Self> {
-        let rows: Vec<SQLiteSchemaRow> = page
-            .cells()
-            .map(|cell| SQLiteSchemaRow::try_from(cell))
+    /// Reads the rows of the schema table, which may span several pages.
+    pub fn read(rows: impl Iterator<Item = Result<OwnedRecord>>) -> Result<Self> {
+        let rows = rows
+            .map(|record| SQLiteSchemaRow::try_from(record?))
             .collect::<Result<_>>()?;
 
         Ok(Self { rows })
@@ -181,77 +556,52 @@ pub struct SQLiteSchemaRow {
     pub name: String,
     pub tbl_name: String,
     pub rootpage: u32,
-    pub sql: String,
+    /// The CREATE statement, which automatic indexes don't have
+    pub sql: Option<String>,
 }
 
-impl<'page> TryFrom<Cell<'page>> for SQLiteSchemaRow {
+impl TryFrom<OwnedRecord> for SQLiteSchemaRow {
     type Error = anyhow::Error;
 
-    fn try_from(cell: Cell) -> std::result::Result<Self, Self::Error> {
-        if let Cell::LeafTable {
-            size: _,
-            rowid,
-            payload,
-            overflow_page: _,
-        } = cell
-        {
-            let record = Record::read(rowid, payload);
-
-            let mut values = record.values.into_iter();
-            let kind = values
-                .next()
-                .and_then(|v| match v {
-                    ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
-                    _ => None,
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema kind")), Ok)?;
-
-            let name = values
-                .next()
-                .and_then(|v| match v {
-                    ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
-                    _ => None,
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema name")), Ok)?;
-
-            let tbl_name = values
-                .next()
-                .and_then(|v| match v {
-                    ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
-                    _ => None,
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema table name")), Ok)?;
-
-            let rootpage = values
-                .next()
-                .and_then(|v| {
-                    if v.is_number() {
-                        let page_number: i64 = v.into();
-                        Some(page_number as u32)
-                    } else {
-                        None
-                    }
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema root page")), Ok)?;
-
-            let sql = values
-                .next()
-                .and_then(|v| match v {
-                    ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
-                    _ => None,
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema SQL")), Ok)?;
-
-            Ok(SQLiteSchemaRow {
-                rowid,
-                kind,
-                name,
-                tbl_name,
-                rootpage,
-                sql,
+    fn try_from(record: OwnedRecord) -> std::result::Result<Self, Self::Error> {
+        let mut values = record.values.into_iter();
+        let mut text = |what: &str| match values.next() {
+            Some(Value::Text(text)) => Ok(text),
+            _ => Err(anyhow::anyhow!("Invalid schema {}", what)),
+        };
+
+        let kind = text("kind")?;
+        let name = text("name")?;
+        let tbl_name = text("table name")?;
+        let rootpage = match values.next() {
+            Some(Value::Integer(page_number)) => page_number,
+            _ => return Err(anyhow::anyhow!("Invalid schema root page")),
+        };
+        // Automatic indexes are the only rows without SQL
+        let sql = match values.next() {
+            Some(Value::Text(text)) => Some(text),
+            Some(Value::Null) if kind == "index" => None,
+            _ => return Err(anyhow::anyhow!("Invalid schema SQL")),
+        };
+        // Views, triggers and virtual tables have no b-tree of their own
+        let rootpage = u32::try_from(rootpage)
+            .ok()
+            .filter(|&page_number| {
+                page_number > 0
+                    || !matches!(kind.as_str(), "table" | "index")
+                    || sql.as_ref().map_or(false, |sql| {
+                        sql::parse_virtual_table_creation(sql.as_bytes()).is_ok()
+                    })
             })
-        } else {
-            Err(anyhow::anyhow!("Invalid cell kind"))
-        }
+            .ok_or_else(|| Error::Corrupt(format!("invalid root page {} of {}", rootpage, name)))?;
+
+        Ok(SQLiteSchemaRow {
+            rowid: record.rowid,
+            kind,
+            name,
+            tbl_name,
+            rootpage,
+            sql,
+        })
     }
 }
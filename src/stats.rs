@@ -0,0 +1,204 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::database::{Database, MAX_DEPTH};
+use crate::error::Error;
+use crate::page::Cell;
+use crate::record::{ColumnValue, Record, Value};
+use crate::sqlite_schema::Index;
+
+/// The table ANALYZE stores its results in, with a row per index.
+pub const STAT1_TABLE_NAME: &str = "sqlite_stat1";
+pub const STAT1_TABLE_SQL: &str = "CREATE TABLE sqlite_stat1(tbl,idx,stat)";
+
+/// The statistics of an index, like SQLite keeps them in `sqlite_stat1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexStats {
+    /// The number of entries in the index, which is the number of rows of the table
+    pub rows: u64,
+    /// For the first column, the first two columns and so on, the average number of rows that
+    /// have the same values in these columns
+    pub average_equal: Vec<u64>,
+}
+
+impl IndexStats {
+    /// Parses the `stat` column of `sqlite_stat1`: the number of rows followed by the average
+    /// number of equal rows for each column. SQLite may append options like `unordered`.
+    pub fn parse(stat: &str) -> Option<Self> {
+        let mut numbers = stat.split_whitespace().map_while(|word| word.parse().ok());
+        let rows = numbers.next()?;
+        Some(Self {
+            rows,
+            average_equal: numbers.collect(),
+        })
+    }
+
+    /// The `stat` column of `sqlite_stat1`.
+    pub fn to_stat(&self) -> String {
+        std::iter::once(self.rows)
+            .chain(self.average_equal.iter().copied())
+            .map(|number| number.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Whether looking up rows by the first column of the index is cheaper than a full scan.
+    /// Every row the index finds costs a descent through the table b-tree, which is about
+    /// log2(rows) comparisons, while a scan reads each row once.
+    pub fn is_selective(&self) -> bool {
+        let Some(&matches) = self.average_equal.first() else {
+            return true;
+        };
+        let descent = self.rows.max(2).ilog2() as u64;
+        matches.saturating_mul(descent) < self.rows
+    }
+}
+
+/// The statistics of the indexes of a database, read from `sqlite_stat1`. Without statistics
+/// for an index, the planner assumes it is worth using.
+#[derive(Debug, Default)]
+pub struct Statistics {
+    indexes: HashMap<String, IndexStats>,
+}
+
+impl Statistics {
+    /// Reads `sqlite_stat1` if the database has it. Rows it doesn't understand are skipped,
+    /// like SQLite does.
+    pub fn read(database: &Database) -> Result<Self> {
+        let mut statistics = Self::default();
+        if database.schema.find_table(STAT1_TABLE_NAME).is_none() {
+            return Ok(statistics);
+        }
+        for row in database.scan(STAT1_TABLE_NAME)? {
+            let row = row?;
+            if let [_, Value::Text(index), Value::Text(stat), ..] = &row.values[..] {
+                if let Some(stats) = IndexStats::parse(stat) {
+                    statistics.indexes.insert(index.clone(), stats);
+                }
+            }
+        }
+        Ok(statistics)
+    }
+
+    pub fn index(&self, name: &str) -> Option<&IndexStats> {
+        self.indexes.get(name)
+    }
+
+    pub fn insert(&mut self, index: String, stats: IndexStats) {
+        self.indexes.insert(index, stats);
+    }
+}
+
+/// Computes the statistics of an index by reading all of its entries in order, counting the
+/// distinct values of each prefix of the indexed columns.
+pub fn analyze_index(database: &Database, index: &Index) -> Result<IndexStats> {
+    let mut counter = Counter {
+        rows: 0,
+        distinct: vec![0; index.columns.len()],
+        previous: None,
+    };
+    counter.walk(database, index.rootpage, 0)?;
+    let average_equal = counter
+        .distinct
+        .iter()
+        .map(|&distinct| {
+            let distinct = distinct.max(1);
+            (counter.rows + distinct - 1) / distinct
+        })
+        .collect();
+    Ok(IndexStats {
+        rows: counter.rows,
+        average_equal,
+    })
+}
+
+struct Counter {
+    rows: u64,
+    /// The number of distinct values of the first column, the first two columns and so on
+    distinct: Vec<u64>,
+    previous: Option<Vec<Value>>,
+}
+
+impl Counter {
+    fn walk(&mut self, database: &Database, number: u32, depth: usize) -> Result<()> {
+        if depth >= MAX_DEPTH {
+            bail!(Error::Corrupt("b-tree is too deep".to_string()));
+        }
+        let page = database.get_page(number - 1)?;
+        for cell in page.cells() {
+            let (size, payload, overflow_page) = match cell {
+                Cell::InteriorIndex {
+                    left_child_page,
+                    size,
+                    payload,
+                    overflow_page,
+                } => {
+                    self.walk(database, left_child_page, depth + 1)?;
+                    (size, payload, overflow_page)
+                }
+                Cell::LeafIndex {
+                    size,
+                    payload,
+                    overflow_page,
+                } => (size, payload, overflow_page),
+                Cell::InteriorTable { .. } | Cell::LeafTable { .. } => {
+                    bail!(Error::Corrupt("index contains table pages".to_string()))
+                }
+            };
+            let payload = database.payload(payload, size, overflow_page)?;
+            self.count(&Record::read(0, &payload)?);
+        }
+        if let Some(right_child) = page.header.right_child_page_number {
+            self.walk(database, right_child, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Counts an entry, which starts a new group for every prefix that includes a column
+    /// whose value differs from the previous entry.
+    fn count(&mut self, entry: &Record) {
+        let columns = self.distinct.len();
+        let values = (0..columns).map(|i| entry.value(i)).collect::<Vec<_>>();
+        let first_change = match &self.previous {
+            None => 0,
+            Some(previous) => previous
+                .iter()
+                .zip(&values)
+                .position(|(a, b)| ColumnValue::from(a).compare(b) != Ordering::Equal)
+                .unwrap_or(columns),
+        };
+        for distinct in &mut self.distinct[first_change..] {
+            *distinct += 1;
+        }
+        self.rows += 1;
+        self.previous = Some(values.iter().map(Value::from).collect());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_stat() {
+        let stats = IndexStats::parse("1000 250 2 unordered").unwrap();
+        assert_eq!(stats.rows, 1000);
+        assert_eq!(stats.average_equal, [250, 2]);
+        assert_eq!(stats.to_stat(), "1000 250 2");
+        assert_eq!(IndexStats::parse("sz=20"), None);
+    }
+
+    #[test]
+    fn selective_indexes() {
+        let stats = |rows, matches| IndexStats {
+            rows,
+            average_equal: vec![matches],
+        };
+        assert!(stats(1_000_000, 1).is_selective());
+        assert!(stats(1_000_000, 1000).is_selective());
+        assert!(!stats(1_000_000, 200_000).is_selective());
+        assert!(!stats(4, 2).is_selective());
+    }
+}
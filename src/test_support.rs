@@ -0,0 +1,85 @@
+//! Fixtures shared by the unit tests.
+
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the files of tests that run at the same time in one process.
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// A database file in the temporary directory that is deleted, together with its rollback
+/// journal and write-ahead log, when the guard is dropped.
+pub struct TempDatabase {
+    path: String,
+}
+
+impl TempDatabase {
+    /// A unique path for a database that doesn't exist yet.
+    pub fn new(name: &str) -> Self {
+        let file = format!(
+            "{}-{}-{}.db",
+            name,
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(file);
+        let database = TempDatabase {
+            path: path.to_str().unwrap().to_string(),
+        };
+        database.remove_files();
+        database
+    }
+
+    /// A copy of sample.db that the test can write to.
+    pub fn sample(name: &str) -> Self {
+        let database = Self::new(name);
+        std::fs::copy("sample.db", &database.path).unwrap();
+        database
+    }
+
+    /// A database file holding `bytes`.
+    pub fn with_bytes(name: &str, bytes: &[u8]) -> Self {
+        let database = Self::new(name);
+        std::fs::write(&database.path, bytes).unwrap();
+        database
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn remove_files(&self) {
+        for suffix in ["", "-journal", "-wal"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.path, suffix));
+        }
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        self.remove_files();
+    }
+}
+
+/// Runs an SQL script on a database with the sqlite3 shell, or with Python's sqlite3 module
+/// when the shell isn't installed, to make fixtures this crate can't write yet. Fails the test
+/// if neither is available, so that it can't pass without checking anything.
+pub fn run_sqlite3(path: &str, script: &str) {
+    let python = "import sqlite3, sys
+connection = sqlite3.connect(sys.argv[1])
+connection.executescript(sys.argv[2])
+connection.close()";
+    let output = Command::new("sqlite3")
+        .args([path, script])
+        .output()
+        .or_else(|_| {
+            Command::new("python3")
+                .args(["-c", python, path, script])
+                .output()
+        })
+        .expect("the test needs the sqlite3 shell or python3 to make its database");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -19,6 +19,37 @@ pub fn read(bytes: &[u8]) -> (i64, usize) {
     (varint, bytes_read)
 }
 
+/// Encodes a value in 1 to 9 bytes. The first 8 bytes hold 7 bits each, the 9th holds 8 bits.
+pub fn write(value: i64) -> Vec<u8> {
+    let value = value as u64;
+    if value >> 56 != 0 {
+        let mut bytes = vec![0; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest as u8 & 0b0111_1111) | 0b1000_0000;
+            rest >>= 7;
+        }
+        return bytes;
+    }
+
+    let mut bytes = vec![];
+    let mut rest = value;
+    loop {
+        bytes.push(rest as u8 & 0b0111_1111);
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    bytes.reverse();
+    let last = bytes.len() - 1;
+    bytes[..last]
+        .iter_mut()
+        .for_each(|byte| *byte |= 0b1000_0000);
+    bytes
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -39,12 +70,38 @@ mod tests {
 
     #[test]
     fn read_nine_byte_varint() {
-        assert_eq!(read(&vec![0xff; 9]), (-1, 9));
+        assert_eq!(read(&[0xff; 9]), (-1, 9));
     }
 
     #[test]
     fn read_varint_from_longer_bytes() {
-        assert_eq!(read(&vec![0x01; 10]), (1, 1));
-        assert_eq!(read(&vec![0xff; 10]), (-1, 9));
+        assert_eq!(read(&[0x01; 10]), (1, 1));
+        assert_eq!(read(&[0xff; 10]), (-1, 9));
+    }
+
+    #[test]
+    fn write_varints() {
+        assert_eq!(write(1), vec![1]);
+        assert_eq!(write(127), vec![0x7f]);
+        assert_eq!(write(128), vec![0x81, 0x00]);
+        assert_eq!(write(-1), vec![0xff; 9]);
+    }
+
+    #[test]
+    fn write_then_read() {
+        for value in [
+            0,
+            240,
+            2287,
+            67823,
+            1 << 35,
+            (1 << 56) - 1,
+            1 << 56,
+            i64::MAX,
+            i64::MIN,
+        ] {
+            let bytes = write(value);
+            assert_eq!(read(&bytes), (value, bytes.len()));
+        }
     }
 }
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{prelude::*, ErrorKind};
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::pager::{read_exact_at, zeroed};
+
+const HEADER_SIZE: u64 = 32;
+const FRAME_HEADER_SIZE: u64 = 24;
+/// Magic number of a WAL whose checksums read the content as little endian words. The magic
+/// number with the lowest bit set selects big endian words.
+const MAGIC: u32 = 0x377f0682;
+
+/// The write-ahead log of a database in WAL mode. Committed transactions append the new
+/// versions of the pages they changed to the `-wal` file, and they're only copied into the
+/// database file by a checkpoint. Until then, reads have to prefer the newest committed frame
+/// of a page over the database file.
+#[derive(Debug)]
+pub struct Wal {
+    file: File,
+    page_size: u32,
+    /// Maps page numbers, counting from 0 like the pager does, to the position of the page
+    /// content in the newest committed frame that contains the page.
+    frames: HashMap<u32, u64>,
+    /// The size of the database in pages after the last committed transaction.
+    database_size: u32,
+}
+
+impl Wal {
+    /// Reads the header and the frames of a WAL. Frames after the last valid commit frame
+    /// belong to a transaction that didn't finish and are ignored. Returns `None` if the file
+    /// doesn't have a valid header or contains no committed transaction.
+    pub fn read(mut file: File) -> Result<Option<Self>> {
+        let mut header = [0; HEADER_SIZE as usize];
+        match file.read_exact(&mut header) {
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let magic = be_u32(&header[0..4]);
+        if magic & !1 != MAGIC {
+            return Ok(None);
+        }
+        let big_endian = magic & 1 == 1;
+        let page_size = match be_u32(&header[8..12]) {
+            1 => 65536,
+            size if size.is_power_of_two() && (512..=32768).contains(&size) => size,
+            _ => return Ok(None),
+        };
+        let salt = &header[16..24];
+        let mut sum = checksum(&header[..24], big_endian, (0, 0));
+        if sum != (be_u32(&header[24..28]), be_u32(&header[28..32])) {
+            return Ok(None);
+        }
+
+        let mut frames = HashMap::new();
+        let mut pending = Vec::new();
+        let mut database_size = 0;
+        let mut frame = vec![0; FRAME_HEADER_SIZE as usize + page_size as usize];
+        let mut position = HEADER_SIZE;
+        loop {
+            match file.read_exact(&mut frame) {
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                result => result?,
+            }
+            // A frame is only valid if it has the salt of the header and continues the
+            // checksum of the frames before it
+            if &frame[8..16] != salt {
+                break;
+            }
+            sum = checksum(&frame[..8], big_endian, sum);
+            sum = checksum(&frame[FRAME_HEADER_SIZE as usize..], big_endian, sum);
+            if sum != (be_u32(&frame[16..20]), be_u32(&frame[20..24])) {
+                break;
+            }
+
+            let number = be_u32(&frame[0..4]);
+            if number == 0 {
+                break;
+            }
+            pending.push((number - 1, position + FRAME_HEADER_SIZE));
+            let commit_size = be_u32(&frame[4..8]);
+            if commit_size != 0 {
+                frames.extend(pending.drain(..));
+                database_size = commit_size;
+            }
+            position += frame.len() as u64;
+        }
+
+        if frames.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            file,
+            page_size,
+            frames,
+            database_size,
+        }))
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// The number of pages in the database, including the pages added by the WAL.
+    pub fn database_size(&self) -> u32 {
+        self.database_size
+    }
+
+    /// The pages that have a committed frame, counting from 0.
+    pub fn pages(&self) -> impl Iterator<Item = u32> + '_ {
+        self.frames.keys().copied()
+    }
+
+    /// Reads the newest committed version of a page, skipping the first `offset` bytes.
+    /// Returns `None` if the page isn't in the WAL.
+    pub fn read_page(&self, number: u32, offset: u16) -> Result<Option<Arc<[u8]>>> {
+        let Some(&position) = self.frames.get(&number) else {
+            return Ok(None);
+        };
+        let mut page = zeroed(self.page_size as usize - offset as usize);
+        let buf = Arc::get_mut(&mut page).expect("page isn't shared yet");
+        read_exact_at(&self.file, buf, position + offset as u64)?;
+        Ok(Some(page))
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().expect("4 bytes"))
+}
+
+/// Continues the checksum of the WAL over `bytes`, whose length is a multiple of 8.
+fn checksum(bytes: &[u8], big_endian: bool, (mut s0, mut s1): (u32, u32)) -> (u32, u32) {
+    let word = |bytes: &[u8]| {
+        let bytes = bytes.try_into().expect("4 bytes");
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    for pair in bytes.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&pair[0..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..8])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::sql;
+    use crate::test_support::TempDatabase;
+
+    /// Encodes a WAL with big endian checksums. Frames are (page number, content, database size
+    /// for commit frames or 0).
+    fn encode(page_size: u32, frames: &[(u32, Vec<u8>, u32)]) -> Vec<u8> {
+        let mut wal = Vec::new();
+        wal.extend_from_slice(&(MAGIC | 1).to_be_bytes());
+        wal.extend_from_slice(&3007000u32.to_be_bytes());
+        wal.extend_from_slice(&page_size.to_be_bytes());
+        wal.extend_from_slice(&[0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut sum = checksum(&wal, true, (0, 0));
+        wal.extend_from_slice(&sum.0.to_be_bytes());
+        wal.extend_from_slice(&sum.1.to_be_bytes());
+
+        for (number, content, commit) in frames {
+            let mut header = number.to_be_bytes().to_vec();
+            header.extend_from_slice(&commit.to_be_bytes());
+            sum = checksum(&header, true, sum);
+            sum = checksum(content, true, sum);
+            header.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+            header.extend_from_slice(&sum.0.to_be_bytes());
+            header.extend_from_slice(&sum.1.to_be_bytes());
+            wal.extend_from_slice(&header);
+            wal.extend_from_slice(content);
+        }
+        wal
+    }
+
+    /// Copies sample.db to `name` with a WAL that inserts three apples, which aren't in the
+    /// database file. Returns the database and the content its file has after a checkpoint.
+    fn sample_with_wal(name: &str) -> (TempDatabase, Vec<u8>) {
+        let changed_file = TempDatabase::sample(&format!("{name}-changed"));
+        let database_file = TempDatabase::sample(name);
+        let (changed, path) = (changed_file.path(), database_file.path());
+
+        let mut database = Database::open(changed).unwrap();
+        for name in ["Jazz", "Gala", "Envy"] {
+            let statement = format!("INSERT INTO apples (name) VALUES ('{name}')");
+            match sql::parse(statement.as_bytes()).unwrap().1 {
+                sql::SQLCommand::Insert(insert) => database.insert(&insert).unwrap(),
+                _ => unreachable!(),
+            }
+        }
+        let page_size = database.header.page_size;
+        let (original, expected) = (
+            std::fs::read(path).unwrap(),
+            std::fs::read(changed).unwrap(),
+        );
+        let mut frames = expected
+            .chunks(page_size as usize)
+            .zip(1..)
+            .filter(|(page, number)| {
+                original.chunks(page_size as usize).nth(number - 1) != Some(*page)
+            })
+            .map(|(page, number)| (number as u32, page.to_vec(), 0))
+            .collect::<Vec<_>>();
+        frames.last_mut().unwrap().2 = database.header.database_size;
+        // A transaction that wasn't committed is ignored
+        frames.push((2, vec![0; page_size as usize], 0));
+        std::fs::write(format!("{path}-wal"), encode(page_size, &frames)).unwrap();
+        (database_file, expected)
+    }
+
+    fn insert_fuji(database: &mut Database) -> Result<()> {
+        match sql::parse(b"INSERT INTO apples (name) VALUES ('Fuji')")
+            .unwrap()
+            .1
+        {
+            sql::SQLCommand::Insert(insert) => database.insert(&insert),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn reads_pages_from_committed_frames() {
+        let (database_file, _) = sample_with_wal("wal");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        let names = database
+            .scan("apples")
+            .unwrap()
+            .map(|row| row.unwrap().values[1].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 7);
+        assert_eq!(names[6], crate::record::Value::Text("Envy".to_string()));
+
+        assert_eq!(
+            insert_fuji(&mut database).unwrap_err().to_string(),
+            "writing to a database with a write-ahead log is not supported"
+        );
+    }
+
+    #[test]
+    fn checkpoint_copies_frames_to_database() {
+        let (database_file, expected) = sample_with_wal("checkpoint");
+        let path = database_file.path();
+
+        let mut database = Database::open(path).unwrap();
+        // The header page, the apples leaf and sqlite_sequence, as apples has AUTOINCREMENT
+        assert_eq!(database.checkpoint().unwrap(), 3);
+        assert_eq!(std::fs::read(path).unwrap(), expected);
+        assert!(!std::path::Path::new(&format!("{path}-wal")).exists());
+
+        insert_fuji(&mut database).unwrap();
+        let database = Database::open(path).unwrap();
+        assert_eq!(database.scan("apples").unwrap().count(), 8);
+    }
+
+    #[test]
+    fn ignores_frames_with_wrong_checksum() {
+        let wal_file = TempDatabase::new("wal-checksum");
+        let path = wal_file.path();
+        let mut wal = encode(512, &[(1, vec![1; 512], 1), (2, vec![2; 512], 2)]);
+        let last = wal.len() - 1;
+        wal[last] ^= 1;
+        std::fs::write(path, wal).unwrap();
+
+        let wal = Wal::read(File::open(path).unwrap()).unwrap().unwrap();
+        assert_eq!(wal.database_size(), 1);
+        assert_eq!(wal.read_page(0, 0).unwrap().as_deref(), Some(&[1; 512][..]));
+        assert_eq!(wal.read_page(1, 0).unwrap(), None);
+    }
+}
@@ -0,0 +1,473 @@
+//! Window functions, `name(args) OVER (PARTITION BY ... ORDER BY ...)`, whose value for a row
+//! of a query's result depends on the other rows in its window. The connection first collects
+//! the rows the query reads, with the values the window functions need as extra columns, then
+//! computes the window functions and runs the query's results over the rows and their values.
+//!
+//! See https://www.sqlite.org/windowfunctions.html
+
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+
+use anyhow::{bail, Result};
+
+use crate::affinity::Affinity;
+use crate::error::Error;
+use crate::output::OutputFormatter;
+use crate::record::{compare_keys, compare_records, ColumnValue, Value};
+use crate::rowset::Rowset;
+use crate::sql::{Expr, Literal, ResultColumn, SelectFields};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowFunction {
+    /// The position of the row in its partition, from 1
+    RowNumber,
+    /// The position of the first row with the same ORDER BY values in the partition, from 1
+    Rank,
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl WindowFunction {
+    /// The window function called `name` with `args` arguments. `COUNT(*)` has none.
+    pub fn find(name: &str, args: usize) -> Result<Self, Error> {
+        let (function, arguments): (Self, RangeInclusive<usize>) =
+            match name.to_ascii_lowercase().as_str() {
+                "row_number" => (Self::RowNumber, 0..=0),
+                "rank" => (Self::Rank, 0..=0),
+                "count" => (Self::Count, 0..=1),
+                "sum" => (Self::Sum, 1..=1),
+                "avg" => (Self::Avg, 1..=1),
+                "min" => (Self::Min, 1..=1),
+                "max" => (Self::Max, 1..=1),
+                _ => return Err(Error::NotWindowFunction(name.to_string())),
+            };
+        if !arguments.contains(&args) {
+            return Err(Error::WrongNumberOfArguments(name.to_string()));
+        }
+        Ok(function)
+    }
+
+    /// Whether the function aggregates the values of the rows in the window, rather than
+    /// numbering the rows.
+    fn is_aggregate(self) -> bool {
+        !matches!(self, WindowFunction::RowNumber | WindowFunction::Rank)
+    }
+}
+
+/// A window function a query calls, whose inputs are columns of the rows it reads.
+#[derive(Debug)]
+pub struct WindowCall {
+    pub function: WindowFunction,
+    /// The call as written, for EXPLAIN
+    pub text: String,
+    /// The column of the argument, if the function has one
+    arg: Option<usize>,
+    /// The columns of the PARTITION BY expressions
+    partition_by: Vec<usize>,
+    /// The columns of the ORDER BY expressions, and whether they sort in descending order
+    order_by: Vec<usize>,
+    descending: Vec<bool>,
+}
+
+impl WindowCall {
+    /// The value of the function for each row. Like in SQLite, without ORDER BY the window of
+    /// a row is its whole partition, and with ORDER BY it ends with the last row that has the
+    /// same ORDER BY values as the row.
+    pub fn values(&self, rows: &[Vec<ColumnValue>]) -> Result<Vec<Value>> {
+        let partitions = rows
+            .iter()
+            .map(|row| key(row, &self.partition_by))
+            .collect::<Vec<_>>();
+        let orders = rows
+            .iter()
+            .map(|row| key(row, &self.order_by))
+            .collect::<Vec<_>>();
+        let same_order = |a: usize, b: usize| {
+            compare_keys(&orders[a], &orders[b], &self.descending, &[]) == Ordering::Equal
+        };
+
+        // Rows with equal values keep their order
+        let mut sorted = (0..rows.len()).collect::<Vec<_>>();
+        sorted.sort_by(|&a, &b| {
+            compare_records(&partitions[a], &partitions[b])
+                .then_with(|| compare_keys(&orders[a], &orders[b], &self.descending, &[]))
+        });
+
+        let mut values = vec![Value::Null; rows.len()];
+        for partition in runs(&sorted, |&a, &b| {
+            compare_records(&partitions[a], &partitions[b]) == Ordering::Equal
+        }) {
+            let mut aggregate = Aggregate::default();
+            let mut position = 0;
+            for peers in runs(partition, |&a, &b| same_order(a, b)) {
+                if self.function.is_aggregate() {
+                    for &row in peers {
+                        aggregate.add(self.arg.map(|arg| &rows[row][arg]))?;
+                    }
+                }
+                for (offset, &row) in peers.iter().enumerate() {
+                    values[row] = match self.function {
+                        WindowFunction::RowNumber => Value::Integer((position + offset + 1) as i64),
+                        WindowFunction::Rank => Value::Integer(position as i64 + 1),
+                        function => aggregate.value(function),
+                    };
+                }
+                position += peers.len();
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Splits `items` into runs of neighbours that `same` holds for, like `slice::chunk_by`, which
+/// is newer than the Rust version the crate is built with.
+fn runs<T>(items: &[T], mut same: impl FnMut(&T, &T) -> bool) -> Vec<&[T]> {
+    let mut runs = vec![];
+    let mut start = 0;
+    for i in 1..=items.len() {
+        if i == items.len() || !same(&items[i - 1], &items[i]) {
+            runs.push(&items[start..i]);
+            start = i;
+        }
+    }
+    runs
+}
+
+/// The values of a row at `columns`.
+fn key<'a>(row: &[ColumnValue<'a>], columns: &[usize]) -> Vec<ColumnValue<'a>> {
+    columns.iter().map(|&column| row[column].clone()).collect()
+}
+
+/// The state of an aggregate window function over the rows of a window so far.
+#[derive(Default)]
+struct Aggregate {
+    /// The rows, or the ones whose argument isn't NULL
+    count: i64,
+    sum: Option<Sum>,
+    min: Option<Value>,
+    max: Option<Value>,
+}
+
+#[derive(Clone, Copy)]
+enum Sum {
+    Integer(i64),
+    Real(f64),
+}
+
+impl Aggregate {
+    /// Adds the argument of a row, or a row of `COUNT(*)` without one. NULLs are skipped.
+    fn add(&mut self, value: Option<&ColumnValue>) -> Result<()> {
+        let Some(value) = value else {
+            self.count += 1;
+            return Ok(());
+        };
+        if matches!(value, ColumnValue::Null) {
+            return Ok(());
+        }
+        self.count += 1;
+        // Like in SQLite, text that isn't a number adds 0.0
+        let number = match Affinity::Numeric.apply(&Literal::from(Value::from(value))) {
+            Literal::Integer(n) => Sum::Integer(n),
+            Literal::Real(n) => Sum::Real(n),
+            _ => Sum::Real(0.0),
+        };
+        self.sum = Some(match (self.sum, number) {
+            (None, number) => number,
+            (Some(Sum::Integer(a)), Sum::Integer(b)) => match a.checked_add(b) {
+                Some(sum) => Sum::Integer(sum),
+                None => bail!("integer overflow"),
+            },
+            (Some(a), b) => Sum::Real(a.as_f64() + b.as_f64()),
+        });
+        if self.min.as_ref().map_or(true, |min| {
+            value.compare(&ColumnValue::from(min)) == Ordering::Less
+        }) {
+            self.min = Some(Value::from(value));
+        }
+        if self.max.as_ref().map_or(true, |max| {
+            value.compare(&ColumnValue::from(max)) == Ordering::Greater
+        }) {
+            self.max = Some(Value::from(value));
+        }
+        Ok(())
+    }
+
+    fn value(&self, function: WindowFunction) -> Value {
+        match function {
+            WindowFunction::Count => Value::Integer(self.count),
+            WindowFunction::Sum => match self.sum {
+                Some(Sum::Integer(n)) => Value::Integer(n),
+                Some(Sum::Real(n)) => Value::Real(n),
+                None => Value::Null,
+            },
+            WindowFunction::Avg => match self.sum {
+                Some(sum) => Value::Real(sum.as_f64() / self.count as f64),
+                None => Value::Null,
+            },
+            WindowFunction::Min => self.min.clone().unwrap_or(Value::Null),
+            WindowFunction::Max => self.max.clone().unwrap_or(Value::Null),
+            WindowFunction::RowNumber | WindowFunction::Rank => Value::Null,
+        }
+    }
+}
+
+impl Sum {
+    fn as_f64(self) -> f64 {
+        match self {
+            Sum::Integer(n) => n as f64,
+            Sum::Real(n) => n,
+        }
+    }
+}
+
+/// A query with window functions, split into the query for the rows they read and the results
+/// that read their values.
+#[derive(Debug)]
+pub struct WindowedQuery {
+    /// The inputs of the window functions, followed by the columns of the query's table
+    pub rows: SelectFields,
+    /// How many of the columns of `rows` are inputs
+    inputs: usize,
+    pub calls: Vec<WindowCall>,
+    /// The query's results, with the calls replaced by columns of their values. `*` is the
+    /// columns of the query's table.
+    pub fields: Vec<ResultColumn>,
+}
+
+/// Splits a query whose results call window functions, or returns `None` if they don't.
+/// Window functions in the arguments or window of another one are an error.
+pub fn plan(select: &SelectFields) -> Result<Option<WindowedQuery>, Error> {
+    if !select.has_window_functions() {
+        return Ok(None);
+    }
+    let mut inputs = vec![];
+    let mut calls = vec![];
+    let fields = select
+        .fields
+        .iter()
+        .map(|field| match field {
+            ResultColumn::All => Ok(ResultColumn::All),
+            ResultColumn::Expr { expr, alias, text } => Ok(ResultColumn::Expr {
+                expr: extract_calls(expr, &mut inputs, &mut calls)?,
+                alias: alias.clone(),
+                text: text.clone(),
+            }),
+        })
+        .collect::<Result<_, Error>>()?;
+    let count = inputs.len();
+    inputs.push(ResultColumn::All);
+    Ok(Some(WindowedQuery {
+        rows: SelectFields {
+            fields: inputs,
+            from: select.from.clone(),
+            alias: select.alias.clone(),
+            where_clause: select.where_clause.clone(),
+        },
+        inputs: count,
+        calls,
+        fields,
+    }))
+}
+
+/// Replaces the window functions of an expression with the columns of their values, adding
+/// them to `calls` and their inputs to `inputs`.
+fn extract_calls(
+    expr: &Expr,
+    inputs: &mut Vec<ResultColumn>,
+    calls: &mut Vec<WindowCall>,
+) -> Result<Expr, Error> {
+    let Expr::Window { name, args, window } = expr else {
+        return expr.map_children(&mut |child| extract_calls(child, inputs, calls));
+    };
+    let function = WindowFunction::find(name, args.len())?;
+    let mut input = |expr: &Expr| {
+        inputs.push(ResultColumn::Expr {
+            expr: without_windows(expr)?,
+            alias: None,
+            text: expr.to_string(),
+        });
+        Ok::<_, Error>(inputs.len() - 1)
+    };
+    let call = WindowCall {
+        function,
+        text: expr.to_string(),
+        arg: args.first().map(&mut input).transpose()?,
+        partition_by: window
+            .partition_by
+            .iter()
+            .map(&mut input)
+            .collect::<Result<_, _>>()?,
+        order_by: window
+            .order_by
+            .iter()
+            .map(|term| input(&term.expr))
+            .collect::<Result<_, _>>()?,
+        descending: window.order_by.iter().map(|term| term.descending).collect(),
+    };
+    calls.push(call);
+    Ok(Expr::Column(value_column(calls.len() - 1)))
+}
+
+/// Copies an expression that can't call window functions.
+fn without_windows(expr: &Expr) -> Result<Expr, Error> {
+    match expr {
+        Expr::Window { name, .. } => Err(Error::WindowMisuse(name.clone())),
+        expr => expr.map_children(&mut without_windows),
+    }
+}
+
+/// The name of the column with the values of the window function at `index`. It can't be
+/// written without quotes, so it can't be the name of a column of the query's table.
+fn value_column(index: usize) -> String {
+    format!("window {}", index + 1)
+}
+
+impl WindowedQuery {
+    /// Computes the window functions for `rows`, the rows of [`WindowedQuery::rows`]. Returns
+    /// the columns of the query's table followed by the values of the window functions.
+    pub fn apply(&self, rows: &Rowset) -> Result<Rowset> {
+        let records = rows.rows().collect::<Result<Vec<_>>>()?;
+        let rows_values = records
+            .iter()
+            .map(|record| record.values())
+            .collect::<Vec<_>>();
+        let values = self
+            .calls
+            .iter()
+            .map(|call| call.values(&rows_values))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut columns = rows.column_names().split_off(self.inputs);
+        columns.extend((0..self.calls.len()).map(value_column));
+        let mut windowed = Rowset::default();
+        windowed.begin(&columns)?;
+        for (index, row) in rows_values.iter().enumerate() {
+            let mut row = row[self.inputs..].to_vec();
+            row.extend(
+                values
+                    .iter()
+                    .map(|values| ColumnValue::from(&values[index])),
+            );
+            windowed.row(&row)?;
+        }
+        windowed.finish()?;
+        Ok(windowed)
+    }
+
+    /// The results of the query, which read the columns of the rowset [`WindowedQuery::apply`]
+    /// returns, named `columns`, with `*` expanded to the columns of the query's table.
+    pub fn results(&self, columns: &[String]) -> Vec<ResultColumn> {
+        let table_columns = &columns[..columns.len() - self.calls.len()];
+        self.fields
+            .iter()
+            .flat_map(|field| match field {
+                ResultColumn::All => table_columns
+                    .iter()
+                    .map(|name| ResultColumn::Expr {
+                        expr: Expr::Column(name.clone()),
+                        alias: None,
+                        text: name.clone(),
+                    })
+                    .collect(),
+                field => vec![field.clone()],
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_values() {
+        // (color, price) rows
+        let rows = [
+            ("red", 3),
+            ("green", 1),
+            ("red", 1),
+            ("red", 3),
+            ("green", 2),
+        ]
+        .iter()
+        .map(|&(color, price)| {
+            vec![
+                ColumnValue::Text(color.as_bytes().into()),
+                ColumnValue::I64(price),
+            ]
+        })
+        .collect::<Vec<_>>();
+        let call = |function, arg, order_by: Vec<usize>| WindowCall {
+            function,
+            text: String::new(),
+            arg,
+            partition_by: vec![0],
+            descending: vec![false; order_by.len()],
+            order_by,
+        };
+        let integers = |values: &[i64]| {
+            values
+                .iter()
+                .copied()
+                .map(Value::Integer)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            call(WindowFunction::RowNumber, None, vec![1])
+                .values(&rows)
+                .unwrap(),
+            integers(&[2, 1, 1, 3, 2])
+        );
+        assert_eq!(
+            call(WindowFunction::Rank, None, vec![1])
+                .values(&rows)
+                .unwrap(),
+            integers(&[2, 1, 1, 2, 2])
+        );
+        // Without ORDER BY, the window is the whole partition
+        assert_eq!(
+            call(WindowFunction::Sum, Some(1), vec![])
+                .values(&rows)
+                .unwrap(),
+            integers(&[7, 3, 7, 7, 3])
+        );
+        // With it, the rows up to the last one with the same values
+        assert_eq!(
+            call(WindowFunction::Sum, Some(1), vec![1])
+                .values(&rows)
+                .unwrap(),
+            integers(&[7, 1, 1, 7, 3])
+        );
+        assert_eq!(
+            call(WindowFunction::Count, None, vec![])
+                .values(&rows)
+                .unwrap(),
+            integers(&[3, 2, 3, 3, 2])
+        );
+        assert_eq!(
+            call(WindowFunction::Max, Some(1), vec![])
+                .values(&rows)
+                .unwrap(),
+            integers(&[3, 2, 3, 3, 2])
+        );
+        assert_eq!(
+            call(WindowFunction::Avg, Some(1), vec![])
+                .values(&rows)
+                .unwrap(),
+            [7.0 / 3.0, 1.5, 7.0 / 3.0, 7.0 / 3.0, 1.5].map(Value::Real)
+        );
+
+        assert_eq!(
+            WindowFunction::find("upper", 1).err(),
+            Some(Error::NotWindowFunction("upper".to_string()))
+        );
+        assert_eq!(
+            WindowFunction::find("RANK", 1).err(),
+            Some(Error::WrongNumberOfArguments("RANK".to_string()))
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! JavaScript bindings for inspecting databases in the browser. The database is read into
+//! memory, so it can come from a file input or a download:
+//!
+//! ```js
+//! const database = new Database(new Uint8Array(await file.arrayBuffer()));
+//! const rows = JSON.parse(database.query("SELECT name FROM sqlite_schema"));
+//! ```
+
+use anyhow::Result;
+use sqlite_starter_rust::connection::Connection;
+use sqlite_starter_rust::output::JsonLines;
+use wasm_bindgen::prelude::*;
+
+/// The browser's clock, as `wasm32-unknown-unknown` has none of its own.
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use std::time::Duration;
+
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_namespace = Date)]
+        fn now() -> f64;
+    }
+
+    pub fn clock() -> Duration {
+        Duration::from_secs_f64(now() / 1000.0)
+    }
+}
+
+#[wasm_bindgen]
+pub struct Database {
+    connection: Connection,
+}
+
+#[wasm_bindgen]
+impl Database {
+    /// Opens the content of a database file. Changes are only made to this copy.
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: Vec<u8>) -> Result<Database, JsError> {
+        #[cfg(target_arch = "wasm32")]
+        sqlite_starter_rust::clock::set_clock(browser::clock);
+        let connection = Connection::from_bytes(bytes).map_err(to_js)?;
+        Ok(Self { connection })
+    }
+
+    /// Runs a statement and returns its rows as a JSON array of objects keyed by column name.
+    pub fn query(&mut self, sql: &str) -> Result<String, JsError> {
+        self.query_json(sql).map_err(to_js)
+    }
+}
+
+impl Database {
+    fn query_json(&mut self, sql: &str) -> Result<String> {
+        let mut lines = vec![];
+        self.connection
+            .execute(sql, &mut JsonLines::new(&mut lines))?;
+        let rows = String::from_utf8(lines)?;
+        Ok(format!("[{}]", rows.lines().collect::<Vec<_>>().join(",")))
+    }
+}
+
+fn to_js(error: anyhow::Error) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_to_json() {
+        let mut database = Database {
+            connection: Connection::from_bytes(std::fs::read("../sample.db").unwrap()).unwrap(),
+        };
+        assert_eq!(
+            database
+                .query_json("SELECT id, name FROM apples WHERE id < 3")
+                .unwrap(),
+            r#"[{"id":1,"name":"Granny Smith"},{"id":2,"name":"Fuji"}]"#
+        );
+        assert_eq!(
+            database
+                .query_json("SELECT id FROM apples WHERE id > 10")
+                .unwrap(),
+            "[]"
+        );
+    }
+}